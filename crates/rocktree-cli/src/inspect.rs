@@ -0,0 +1,483 @@
+//! Fetching resources by octant path and formatting them for display.
+
+use rocktree::{
+    BulkMetadata, BulkRequest, Cache, Client, Geodetic, Mesh, MeshLayer, Node, NodeMetadata,
+    NodeRequest, OrientedBoundingBox, Planetoid, TextureFormat,
+};
+use serde_json::{Value, json};
+use std::fmt;
+use std::path::PathBuf;
+
+/// Errors reported by the command-line tool.
+#[derive(Debug)]
+pub(crate) enum CliError {
+    /// An octant path was malformed.
+    InvalidPath {
+        /// The path as given.
+        path: String,
+        /// Why the path was rejected.
+        reason: &'static str,
+    },
+    /// A bulk or node does not exist.
+    NotFound {
+        /// What was looked up.
+        what: &'static str,
+        /// The octant path that was looked up.
+        path: String,
+    },
+    /// A node exists but has no mesh data to fetch.
+    NoData(String),
+    /// Fetching or decoding a response failed.
+    Rocktree(rocktree::Error),
+    /// Writing an output file failed.
+    Io {
+        /// The file being written.
+        path: PathBuf,
+        /// The underlying error.
+        source: std::io::Error,
+    },
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidPath { path, reason } => {
+                write!(f, "invalid octant path {path:?}: {reason}")
+            }
+            Self::NotFound { what, path } => write!(f, "{what} {path} does not exist"),
+            Self::NoData(path) => write!(f, "node {path} has no mesh data"),
+            Self::Rocktree(e) => write!(f, "{e}"),
+            Self::Io { path, source } => write!(f, "failed to write {}: {source}", path.display()),
+        }
+    }
+}
+
+impl std::error::Error for CliError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Rocktree(e) => Some(e),
+            Self::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<rocktree::Error> for CliError {
+    fn from(e: rocktree::Error) -> Self {
+        Self::Rocktree(e)
+    }
+}
+
+/// Fetches resources by octant path.
+///
+/// Bulks and nodes are addressed by path alone; their epochs and texture
+/// formats are looked up by walking the bulk hierarchy from the root.
+pub(crate) struct Inspector<C: Cache> {
+    client: Client<C>,
+}
+
+impl<C: Cache + 'static> Inspector<C> {
+    pub(crate) fn new(client: Client<C>) -> Self {
+        Self { client }
+    }
+
+    pub(crate) async fn planetoid(&self) -> Result<Planetoid, CliError> {
+        Ok(self.client.fetch_planetoid().await?)
+    }
+
+    pub(crate) async fn bulk(&self, path: &str) -> Result<BulkMetadata, CliError> {
+        let request = self.bulk_request(path).await?;
+        Ok(self.client.fetch_bulk(&request).await?)
+    }
+
+    /// Fetch a node along with its metadata from the containing bulk.
+    pub(crate) async fn node(&self, path: &str) -> Result<(NodeMetadata, Node), CliError> {
+        let metadata = self.node_metadata(path).await?;
+        let node = self.client.fetch_node(&node_request(&metadata)).await?;
+        Ok((metadata, node))
+    }
+
+    pub(crate) fn planetoid_url(&self) -> String {
+        self.client.planetoid_url()
+    }
+
+    pub(crate) async fn bulk_url(&self, path: &str) -> Result<String, CliError> {
+        let request = self.bulk_request(path).await?;
+        Ok(self.client.bulk_url(&request))
+    }
+
+    pub(crate) async fn node_url(&self, path: &str) -> Result<String, CliError> {
+        let metadata = self.node_metadata(path).await?;
+        Ok(self.client.node_url(&node_request(&metadata)))
+    }
+
+    /// Fetch a raw response, going through the client's cache.
+    pub(crate) async fn fetch_raw(&self, url: &str) -> Result<Vec<u8>, CliError> {
+        Ok(self.client.fetch_bytes_from_url(url).await?)
+    }
+
+    /// Resolve the epoch of a bulk by walking down from the root bulk.
+    async fn bulk_request(&self, path: &str) -> Result<BulkRequest, CliError> {
+        validate_path(path)?;
+        if !path.len().is_multiple_of(4) {
+            return Err(CliError::InvalidPath {
+                path: path.to_string(),
+                reason: "bulk paths have a multiple of 4 octants",
+            });
+        }
+
+        let planetoid = self.client.fetch_planetoid().await?;
+        let mut request = BulkRequest::root(planetoid.root_epoch);
+        for end in (4..=path.len()).step_by(4) {
+            let bulk = self.client.fetch_bulk(&request).await?;
+            let epoch = bulk
+                .child_bulk_paths
+                .get(&path[end - 4..end])
+                .copied()
+                .ok_or_else(|| CliError::NotFound {
+                    what: "bulk",
+                    path: path[..end].to_string(),
+                })?;
+            request = BulkRequest::new(path[..end].to_string(), epoch);
+        }
+        Ok(request)
+    }
+
+    async fn node_metadata(&self, path: &str) -> Result<NodeMetadata, CliError> {
+        validate_path(path)?;
+        if path.is_empty() {
+            return Err(CliError::InvalidPath {
+                path: String::new(),
+                reason: "node paths have at least one octant",
+            });
+        }
+
+        let bulk = self.bulk(containing_bulk(path)).await?;
+        let metadata = bulk
+            .nodes
+            .into_iter()
+            .find(|node| node.path == path)
+            .ok_or_else(|| CliError::NotFound {
+                what: "node",
+                path: path.to_string(),
+            })?;
+        if !metadata.has_data {
+            return Err(CliError::NoData(path.to_string()));
+        }
+        Ok(metadata)
+    }
+}
+
+/// Check that a path consists only of octants.
+fn validate_path(path: &str) -> Result<(), CliError> {
+    if path.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+        Ok(())
+    } else {
+        Err(CliError::InvalidPath {
+            path: path.to_string(),
+            reason: "octants are digits from 0 to 7",
+        })
+    }
+}
+
+/// Get the path of the bulk holding the metadata for the node at `path`.
+///
+/// Each bulk covers the four levels below its own path.
+fn containing_bulk(path: &str) -> &str {
+    &path[..(path.len().saturating_sub(1) / 4) * 4]
+}
+
+fn node_request(metadata: &NodeMetadata) -> NodeRequest {
+    NodeRequest::new(
+        metadata.path.clone(),
+        metadata.epoch,
+        metadata.texture_format,
+        metadata.imagery_epoch,
+    )
+}
+
+/// Statistics for one mesh of a node.
+#[derive(Debug, PartialEq)]
+struct MeshStats {
+    vertices: usize,
+    indices: usize,
+    triangles: usize,
+    /// Triangles per layer, or `None` if the mesh has no layer information.
+    layer_triangles: Option<[usize; 3]>,
+}
+
+impl MeshStats {
+    fn new(mesh: &Mesh) -> Self {
+        let triangles = |indices: &[u16]| rocktree_decode::strip_to_triangles(indices).len() / 3;
+        Self {
+            vertices: mesh.vertices.len(),
+            indices: mesh.indices.len(),
+            triangles: triangles(&mesh.indices),
+            layer_triangles: mesh.has_octant_data.then(|| {
+                MeshLayer::ALL.map(|layer| triangles(&mesh.indices[mesh.layer_range(layer)]))
+            }),
+        }
+    }
+}
+
+fn flag_names(flags: u32) -> Vec<&'static str> {
+    [
+        (NodeMetadata::FLAG_LEAF, "leaf"),
+        (NodeMetadata::FLAG_NO_DATA, "no-data"),
+        (NodeMetadata::FLAG_USE_IMAGERY_EPOCH, "imagery-epoch"),
+    ]
+    .into_iter()
+    .filter(|&(flag, _)| flags & flag != 0)
+    .map(|(_, name)| name)
+    .collect()
+}
+
+fn texture_format_name(format: TextureFormat) -> &'static str {
+    match format {
+        TextureFormat::Rgb => "rgb",
+        TextureFormat::Rgba => "rgba",
+        TextureFormat::Dxt1 => "dxt1",
+    }
+}
+
+fn display_path(path: &str) -> &str {
+    if path.is_empty() { "(root)" } else { path }
+}
+
+fn obb_json(obb: &OrientedBoundingBox) -> Value {
+    let center = Geodetic::from_ecef(obb.center);
+    json!({
+        "center": obb.center.to_array(),
+        "extents": obb.extents.to_array(),
+        "orientation": obb.orientation.to_cols_array(),
+        "latitude": center.latitude,
+        "longitude": center.longitude,
+        "height": center.height,
+    })
+}
+
+pub(crate) fn planetoid_json(planetoid: &Planetoid) -> Value {
+    json!({
+        "radius": planetoid.radius,
+        "root_epoch": planetoid.root_epoch,
+    })
+}
+
+pub(crate) fn planetoid_table(planetoid: &Planetoid) -> String {
+    format!(
+        "radius      {} m\nroot epoch  {}",
+        planetoid.radius, planetoid.root_epoch
+    )
+}
+
+pub(crate) fn bulk_json(bulk: &BulkMetadata) -> Value {
+    let mut children: Vec<_> = bulk.child_bulk_paths.iter().collect();
+    children.sort();
+    json!({
+        "path": bulk.path,
+        "epoch": bulk.epoch,
+        "nodes": bulk.nodes.iter().map(|node| json!({
+            "path": node.path,
+            "flags": node.flags,
+            "flag_names": flag_names(node.flags),
+            "has_data": node.has_data,
+            "epoch": node.epoch,
+            "texture_format": node.texture_format,
+            "imagery_epoch": node.imagery_epoch,
+            "meters_per_texel": node.meters_per_texel,
+            "obb": obb_json(&node.obb),
+        })).collect::<Vec<_>>(),
+        "child_bulks": children.into_iter().map(|(relative, epoch)| json!({
+            "path": format!("{}{relative}", bulk.path),
+            "epoch": epoch,
+        })).collect::<Vec<_>>(),
+    })
+}
+
+pub(crate) fn bulk_table(bulk: &BulkMetadata) -> String {
+    let width = bulk
+        .nodes
+        .iter()
+        .map(|node| node.path.len())
+        .max()
+        .unwrap_or(0)
+        .max("PATH".len());
+    let mut lines = vec![
+        format!(
+            "bulk {} at epoch {}: {} nodes, {} child bulks",
+            display_path(&bulk.path),
+            bulk.epoch,
+            bulk.nodes.len(),
+            bulk.child_bulk_paths.len()
+        ),
+        String::new(),
+        format!(
+            "{:width$}  {:<24}  {:>6}  {:>10}  {:>10}  {:>11}  {:>9}  EXTENTS",
+            "PATH", "FLAGS", "EPOCH", "MPT", "LAT", "LON", "HEIGHT"
+        ),
+    ];
+    lines.extend(bulk.nodes.iter().map(|node| {
+        let center = Geodetic::from_ecef(node.obb.center);
+        let flags = flag_names(node.flags);
+        format!(
+            "{:width$}  {:<24}  {:>6}  {:>10.3}  {:>10.5}  {:>11.5}  {:>9.1}  {:.1} x {:.1} x {:.1}",
+            node.path,
+            if flags.is_empty() { "-".to_string() } else { flags.join(",") },
+            node.epoch,
+            node.meters_per_texel,
+            center.latitude,
+            center.longitude,
+            center.height,
+            node.obb.extents.x,
+            node.obb.extents.y,
+            node.obb.extents.z,
+        )
+    }));
+    lines.join("\n")
+}
+
+pub(crate) fn node_json(metadata: &NodeMetadata, node: &Node) -> Value {
+    json!({
+        "path": node.path,
+        "epoch": metadata.epoch,
+        "texture_format": metadata.texture_format,
+        "imagery_epoch": metadata.imagery_epoch,
+        "meters_per_texel": node.meters_per_texel,
+        "obb": obb_json(&node.obb),
+        "meshes": node.meshes.iter().map(|mesh| {
+            let stats = MeshStats::new(mesh);
+            json!({
+                "vertices": stats.vertices,
+                "indices": stats.indices,
+                "triangles": stats.triangles,
+                "layers": stats.layer_triangles.map(|counts| {
+                    MeshLayer::ALL
+                        .iter()
+                        .zip(counts)
+                        .map(|(layer, count)| (layer.name().to_string(), json!(count)))
+                        .collect::<serde_json::Map<_, _>>()
+                }),
+                "texture": {
+                    "format": texture_format_name(mesh.texture_format),
+                    "width": mesh.texture_width,
+                    "height": mesh.texture_height,
+                    "bytes": mesh.texture_data.len(),
+                },
+            })
+        }).collect::<Vec<_>>(),
+    })
+}
+
+pub(crate) fn node_table(metadata: &NodeMetadata, node: &Node) -> String {
+    let center = Geodetic::from_ecef(node.obb.center);
+    let mut lines = vec![
+        format!(
+            "node {} at epoch {}, texture format {}, imagery epoch {}",
+            node.path,
+            metadata.epoch,
+            metadata.texture_format,
+            metadata
+                .imagery_epoch
+                .map_or_else(|| "-".to_string(), |epoch| epoch.to_string())
+        ),
+        format!("meters per texel  {:.3}", node.meters_per_texel),
+        format!(
+            "center            {:.5}, {:.5} at {:.1} m",
+            center.latitude, center.longitude, center.height
+        ),
+        String::new(),
+        format!(
+            "{:>4}  {:>8}  {:>8}  {:>9}  {:>10}  {:>11}  {:>11}  TEXTURE",
+            "MESH", "VERTICES", "INDICES", "TRIANGLES", "OVERGROUND", "BELOW_WATER", "ABOVE_WATER"
+        ),
+    ];
+    lines.extend(node.meshes.iter().enumerate().map(|(i, mesh)| {
+        let stats = MeshStats::new(mesh);
+        let layers = stats.layer_triangles.map_or(
+            ["-".to_string(), "-".to_string(), "-".to_string()],
+            |counts| counts.map(|count| count.to_string()),
+        );
+        format!(
+            "{i:>4}  {:>8}  {:>8}  {:>9}  {:>10}  {:>11}  {:>11}  {} {}x{} ({} bytes)",
+            stats.vertices,
+            stats.indices,
+            stats.triangles,
+            layers[0],
+            layers[1],
+            layers[2],
+            texture_format_name(mesh.texture_format),
+            mesh.texture_width,
+            mesh.texture_height,
+            mesh.texture_data.len(),
+        )
+    }));
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocktree::{UvTransform, Vertex};
+
+    fn mesh(indices: Vec<u16>, has_octant_data: bool, layer_bounds: [usize; 4]) -> Mesh {
+        Mesh {
+            vertices: vec![Vertex::default(); 6],
+            indices,
+            uv_transform: UvTransform::default(),
+            texture_data: vec![0; 8],
+            texture_format: TextureFormat::Dxt1,
+            texture_width: 4,
+            texture_height: 4,
+            has_octant_data,
+            layer_bounds,
+        }
+    }
+
+    #[test]
+    fn test_validate_path() {
+        assert!(validate_path("").is_ok());
+        assert!(validate_path("01234567").is_ok());
+        assert!(matches!(
+            validate_path("0128"),
+            Err(CliError::InvalidPath { .. })
+        ));
+        assert!(validate_path("02a").is_err());
+    }
+
+    #[test]
+    fn test_containing_bulk() {
+        assert_eq!(containing_bulk("0"), "");
+        assert_eq!(containing_bulk("0231"), "");
+        assert_eq!(containing_bulk("02313"), "0231");
+        assert_eq!(containing_bulk("02313012"), "0231");
+        assert_eq!(containing_bulk("023130120"), "02313012");
+    }
+
+    #[test]
+    fn test_flag_names() {
+        assert!(flag_names(0).is_empty());
+        assert_eq!(
+            flag_names(NodeMetadata::FLAG_LEAF | NodeMetadata::FLAG_USE_IMAGERY_EPOCH),
+            ["leaf", "imagery-epoch"]
+        );
+    }
+
+    #[test]
+    fn test_mesh_stats() {
+        // Two triangles overground, then one above water.
+        let indices = vec![0, 1, 2, 3, 4, 5, 0];
+        let stats = MeshStats::new(&mesh(indices.clone(), true, [0, 4, 4, 7]));
+        assert_eq!(
+            stats,
+            MeshStats {
+                vertices: 6,
+                indices: 7,
+                triangles: 5,
+                layer_triangles: Some([2, 0, 1]),
+            }
+        );
+
+        let stats = MeshStats::new(&mesh(indices, false, [0, 7, 7, 7]));
+        assert_eq!(stats.layer_triangles, None);
+    }
+}
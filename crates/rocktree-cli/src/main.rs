@@ -0,0 +1,181 @@
+//! Command-line tool for inspecting Google Earth rocktree data.
+//!
+//! ```sh
+//! cargo run -p rocktree-cli -- planetoid
+//! cargo run -p rocktree-cli -- bulk 0231 --json
+//! cargo run -p rocktree-cli -- node 02313012
+//! cargo run -p rocktree-cli -- save node 02313012 -o node.pb
+//! ```
+//!
+//! Responses go through the same caches as the viewer, so `--cache-dir`,
+//! `--archive` and `--offline` can be used to inspect data without touching
+//! the network.
+
+mod inspect;
+
+use clap::{Parser, Subcommand, ValueEnum};
+use inspect::{CliError, Inspector};
+use rocktree::{ArchiveCache, Cache, Client, DiskCache, FetchPolicy, LayeredCache, MemoryCache};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Inspect Google Earth rocktree data.
+#[derive(Debug, Parser)]
+#[command(name = "rocktree", version)]
+struct Args {
+    /// Base URL of the server. Defaults to Google Earth.
+    #[arg(long, global = true)]
+    base_url: Option<String>,
+    /// Directory for the persistent response cache.
+    #[arg(long, global = true)]
+    cache_dir: Option<PathBuf>,
+    /// Region archive to read responses from.
+    #[arg(long, global = true)]
+    archive: Option<PathBuf>,
+    /// Only use cached responses, never contacting the server.
+    #[arg(long, global = true)]
+    offline: bool,
+    /// Print JSON instead of a table.
+    #[arg(long, global = true)]
+    json: bool,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Print planetoid metadata.
+    Planetoid,
+    /// List the nodes in a bulk.
+    Bulk {
+        /// Octant path of the bulk. Defaults to the root bulk.
+        #[arg(default_value = "")]
+        path: String,
+    },
+    /// Fetch a node and print statistics for each of its meshes.
+    Node {
+        /// Octant path of the node.
+        path: String,
+    },
+    /// Save a raw response to a file.
+    Save {
+        /// Kind of response to save.
+        resource: Resource,
+        /// Octant path of the bulk or node. Defaults to the root bulk.
+        #[arg(default_value = "")]
+        path: String,
+        /// File to write the response to.
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+}
+
+/// A kind of response from the server.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Resource {
+    /// The planetoid metadata.
+    Planetoid,
+    /// The metadata of a bulk.
+    Bulk,
+    /// The data of a node.
+    Node,
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> ExitCode {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("warn")),
+        )
+        .init();
+
+    let args = Args::parse();
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<(), CliError> {
+    let inspector = Inspector::new(open_client(&args)?);
+    let output = match args.command {
+        Command::Planetoid => {
+            let planetoid = inspector.planetoid().await?;
+            if args.json {
+                inspect::planetoid_json(&planetoid).to_string()
+            } else {
+                inspect::planetoid_table(&planetoid)
+            }
+        }
+        Command::Bulk { path } => {
+            let bulk = inspector.bulk(&path).await?;
+            if args.json {
+                inspect::bulk_json(&bulk).to_string()
+            } else {
+                inspect::bulk_table(&bulk)
+            }
+        }
+        Command::Node { path } => {
+            let (metadata, node) = inspector.node(&path).await?;
+            if args.json {
+                inspect::node_json(&metadata, &node).to_string()
+            } else {
+                inspect::node_table(&metadata, &node)
+            }
+        }
+        Command::Save {
+            resource,
+            path,
+            output,
+        } => {
+            let url = match resource {
+                Resource::Planetoid => inspector.planetoid_url(),
+                Resource::Bulk => inspector.bulk_url(&path).await?,
+                Resource::Node => inspector.node_url(&path).await?,
+            };
+            let data = inspector.fetch_raw(&url).await?;
+            std::fs::write(&output, &data).map_err(|source| CliError::Io {
+                path: output.clone(),
+                source,
+            })?;
+            format!("Saved {} bytes to {}", data.len(), output.display())
+        }
+    };
+    println!("{output}");
+    Ok(())
+}
+
+/// Build a client from the cache and server options.
+fn open_client(args: &Args) -> Result<Client<Box<dyn Cache>>, CliError> {
+    let memory = MemoryCache::new();
+    let cache: Box<dyn Cache> = if let Some(path) = &args.archive {
+        let archive = ArchiveCache::open(path)?;
+        Box::new(LayeredCache::new(memory, archive))
+    } else if let Some(dir) = &args.cache_dir {
+        let disk = DiskCache::new(dir)?;
+        Box::new(LayeredCache::new(memory, disk))
+    } else {
+        Box::new(memory)
+    };
+
+    let mut client = Client::with_cache(cache);
+    if let Some(base_url) = &args.base_url {
+        // Request URLs are appended to the base URL directly.
+        let base_url = if base_url.ends_with('/') {
+            base_url.clone()
+        } else {
+            format!("{base_url}/")
+        };
+        client = client.with_base_url(base_url);
+    }
+    if args.offline {
+        client = client.with_fetch_policy(FetchPolicy::CacheOnly);
+    }
+    Ok(client)
+}
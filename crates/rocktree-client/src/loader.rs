@@ -16,41 +16,145 @@ use bevy::tasks::AsyncComputeTaskPool;
 #[cfg(not(target_family = "wasm"))]
 use bevy_tokio_tasks::TokioTasksRuntime;
 
-use rocktree::{BulkMetadata, BulkRequest, Client, MemoryCache, Planetoid};
+#[cfg(not(target_family = "wasm"))]
+use rocktree::{ArchiveCache, DiskCache, LayeredCache};
+use rocktree::{
+    BulkMetadata, BulkRequest, Cache, Client, DecodedNodeCache, FetchPolicy, MemoryCache, Planetoid,
+};
 
 /// Plugin for loading Google Earth data.
 pub struct DataLoaderPlugin;
 
 impl Plugin for DataLoaderPlugin {
     fn build(&self, app: &mut App) {
-        app.init_resource::<LoaderState>()
+        app.init_resource::<LoaderConfig>()
+            .init_resource::<LoaderState>()
             .init_resource::<LoaderChannels>()
             .add_systems(Startup, start_initial_load)
             .add_systems(Update, (poll_planetoid_task, poll_bulk_task));
     }
 }
 
+/// Command-line options for data loading.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct LoaderConfig {
+    /// Load only from the cache, never from the network.
+    pub offline: bool,
+    /// Directory for the persistent cache (native only).
+    pub cache_dir: Option<std::path::PathBuf>,
+    /// Region archive to serve responses from (native only).
+    pub archive: Option<std::path::PathBuf>,
+}
+
+impl LoaderConfig {
+    /// Parse the options from the process arguments.
+    ///
+    /// Recognizes `--offline`, `--cache-dir <DIR>` and `--archive <FILE>`.
+    /// Unknown arguments are ignored with a warning.
+    #[cfg(not(target_family = "wasm"))]
+    #[must_use]
+    pub fn from_args() -> Self {
+        let mut config = Self::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--offline" => config.offline = true,
+                "--cache-dir" => {
+                    if let Some(dir) = args.next() {
+                        config.cache_dir = Some(dir.into());
+                    } else {
+                        tracing::warn!("--cache-dir requires a directory");
+                    }
+                }
+                "--archive" => {
+                    if let Some(file) = args.next() {
+                        config.archive = Some(file.into());
+                    } else {
+                        tracing::warn!("--archive requires a file");
+                    }
+                }
+                _ => tracing::warn!("Ignoring unknown argument {arg:?}"),
+            }
+        }
+        config
+    }
+}
+
 /// State for the data loader.
 #[derive(Resource)]
 pub struct LoaderState {
     /// The HTTP client for fetching data.
-    pub client: Arc<Client<MemoryCache>>,
+    pub client: Arc<Client<Box<dyn Cache>>>,
     /// Planetoid metadata (once loaded).
     pub planetoid: Option<Planetoid>,
     /// Root bulk metadata (once loaded).
     pub root_bulk: Option<BulkMetadata>,
 }
 
-impl Default for LoaderState {
-    fn default() -> Self {
+/// Memory budget for decoded nodes, which are revisited constantly as the
+/// camera moves across LOD boundaries.
+const DECODED_NODE_CACHE_SIZE: usize = 512 * 1024 * 1024;
+
+impl FromWorld for LoaderState {
+    fn from_world(world: &mut World) -> Self {
+        let config = world.resource::<LoaderConfig>();
+        let policy = if config.offline {
+            tracing::info!("Offline mode: loading only cached data");
+            FetchPolicy::CacheOnly
+        } else {
+            FetchPolicy::CacheFirst
+        };
+        let client = Client::with_cache(response_cache(config))
+            .with_request_spans(true)
+            .with_node_cache(DecodedNodeCache::new(DECODED_NODE_CACHE_SIZE))
+            .with_fetch_policy(policy);
         Self {
-            client: Arc::new(Client::with_cache(MemoryCache::new())),
+            client: Arc::new(client),
             planetoid: None,
             root_bulk: None,
         }
     }
 }
 
+/// Build the cache for raw responses: memory in front of the region archive
+/// or the persistent cache if one is configured, otherwise memory alone.
+fn response_cache(config: &LoaderConfig) -> Box<dyn Cache> {
+    #[cfg(not(target_family = "wasm"))]
+    if let Some(file) = &config.archive {
+        match ArchiveCache::open(file) {
+            Ok(archive) => {
+                let manifest = archive.manifest();
+                tracing::info!(
+                    "Serving {} nodes from {}",
+                    manifest.node_count(),
+                    file.display()
+                );
+                if config.cache_dir.is_some() {
+                    tracing::warn!("Ignoring --cache-dir in favor of --archive");
+                }
+                return Box::new(LayeredCache::new(MemoryCache::new(), archive));
+            }
+            Err(e) => tracing::error!("Failed to open archive: {}", e),
+        }
+    }
+
+    #[cfg(not(target_family = "wasm"))]
+    if let Some(dir) = &config.cache_dir {
+        match DiskCache::new(dir) {
+            Ok(disk) => {
+                tracing::info!("Caching responses in {}", dir.display());
+                return Box::new(LayeredCache::new(MemoryCache::new(), disk));
+            }
+            Err(e) => tracing::error!("Failed to open cache directory: {}", e),
+        }
+    }
+
+    if config.offline {
+        tracing::warn!("Offline mode without a cache directory or archive has no data to load");
+    }
+    Box::new(MemoryCache::new())
+}
+
 /// Channels for receiving loaded data from background tasks.
 #[derive(Resource)]
 pub struct LoaderChannels {
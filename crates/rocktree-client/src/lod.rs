@@ -7,13 +7,18 @@
 //! determine which nodes need loading. Only nodes whose LOD metric says they
 //! need more detail are expanded, avoiding wasted bandwidth on coarse nodes.
 //!
+//! Loads go through a `rocktree::Scheduler`. Each frame the BFS hands it the
+//! full set of wanted nodes and bulks, prioritized by screen-space error, and
+//! the scheduler releases the most important ones within the concurrency
+//! limits. Loads that dropped out of the wanted set are cancelled so their
+//! bandwidth is freed immediately.
+//!
 //! Uses platform-agnostic `async_channel` for communication between async tasks
 //! and the main thread. The spawn mechanism differs by platform:
 //! - Native: `bevy-tokio-tasks` for Tokio runtime (reqwest requires it)
 //! - WASM: Bevy's built-in `AsyncComputeTaskPool` (reqwest uses browser fetch)
 
 use std::collections::{HashMap, HashSet};
-use std::sync::Arc;
 
 use bevy::prelude::*;
 #[cfg(target_family = "wasm")]
@@ -21,7 +26,11 @@ use bevy::tasks::AsyncComputeTaskPool;
 #[cfg(not(target_family = "wasm"))]
 use bevy_tokio_tasks::TokioTasksRuntime;
 use glam::DMat4;
-use rocktree::{BulkMetadata, BulkRequest, Frustum, LodMetrics, Node, NodeMetadata, NodeRequest};
+use rocktree::scheduler::{Completion, FetchOutcome, RequestKind};
+use rocktree::{
+    BulkMetadata, BulkRequest, ErrorKind, FetchRequest, Frustum, LodMetrics, Node, NodeMetadata,
+    NodeRequest, Priority, Scheduler,
+};
 use rocktree_decode::OrientedBoundingBox;
 
 use crate::loader::LoaderState;
@@ -42,8 +51,7 @@ impl Plugin for LodPlugin {
                 (
                     update_frustum,
                     update_lod_requests,
-                    poll_lod_bulk_tasks,
-                    poll_lod_node_tasks,
+                    poll_lod_tasks,
                     cull_meshes,
                 )
                     .chain(),
@@ -51,17 +59,24 @@ impl Plugin for LodPlugin {
     }
 }
 
+/// Maximum number of node loads in flight at once.
+const MAX_NODE_LOADS: usize = 20;
+
+/// Maximum number of bulk loads in flight at once.
+const MAX_BULK_LOADS: usize = 10;
+
 /// State for LOD management.
-#[derive(Resource, Default)]
+#[derive(Resource)]
 pub struct LodState {
-    /// Paths of nodes that are currently being loaded.
-    loading_nodes: HashSet<String>,
+    /// Scheduler for node and bulk loads.
+    scheduler: Scheduler,
     /// Paths of nodes that are currently loaded and rendered.
     loaded_nodes: HashSet<String>,
-    /// Paths of bulks that are currently being loaded.
-    loading_bulks: HashSet<String>,
     /// Paths of bulks that failed to load (to avoid retrying).
     failed_bulks: HashSet<String>,
+    /// Paths of nodes missing from the cache in offline mode (to avoid
+    /// retrying).
+    missing_nodes: HashSet<String>,
     /// Cached bulk metadata by path.
     bulks: HashMap<String, BulkMetadata>,
     /// Node OBBs from bulk metadata, keyed by node path.
@@ -74,6 +89,22 @@ pub struct LodState {
     lod_metrics: Option<LodMetrics>,
 }
 
+impl Default for LodState {
+    fn default() -> Self {
+        Self {
+            scheduler: Scheduler::new(MAX_BULK_LOADS, MAX_NODE_LOADS),
+            loaded_nodes: HashSet::new(),
+            failed_bulks: HashSet::new(),
+            missing_nodes: HashSet::new(),
+            bulks: HashMap::new(),
+            node_obbs: HashMap::new(),
+            node_entities: HashMap::new(),
+            frustum: None,
+            lod_metrics: None,
+        }
+    }
+}
+
 impl LodState {
     /// Get the number of loaded nodes.
     #[must_use]
@@ -84,38 +115,31 @@ impl LodState {
     /// Get the number of nodes currently being loaded.
     #[must_use]
     pub fn loading_node_count(&self) -> usize {
-        self.loading_nodes.len()
+        self.scheduler.in_flight_count(RequestKind::Node)
     }
 }
 
-/// Channels for receiving loaded data from background tasks.
+/// Channels for receiving finished loads from background tasks.
 #[derive(Resource)]
 pub struct LodChannels {
-    bulk_rx: async_channel::Receiver<(String, Result<BulkMetadata, rocktree::Error>)>,
-    bulk_tx: async_channel::Sender<(String, Result<BulkMetadata, rocktree::Error>)>,
-    node_rx: async_channel::Receiver<(String, Result<Node, rocktree::Error>)>,
-    node_tx: async_channel::Sender<(String, Result<Node, rocktree::Error>)>,
+    completion_rx: async_channel::Receiver<Completion>,
+    completion_tx: async_channel::Sender<Completion>,
 }
 
 impl Default for LodChannels {
     fn default() -> Self {
-        let (bulk_tx, bulk_rx) = async_channel::bounded(100);
-        let (node_tx, node_rx) = async_channel::bounded(100);
+        let (completion_tx, completion_rx) = async_channel::bounded(100);
         Self {
-            bulk_rx,
-            bulk_tx,
-            node_rx,
-            node_tx,
+            completion_rx,
+            completion_tx,
         }
     }
 }
 
 /// Result of the BFS traversal, containing load requests and potential sets.
 struct BfsResult {
-    /// Nodes that should be loaded (metadata + bulk path).
-    nodes_to_load: Vec<NodeMetadata>,
-    /// Bulks that should be loaded (full path + epoch).
-    bulks_to_load: Vec<(String, u32)>,
+    /// Node and bulk loads that are wanted, with their priorities.
+    requests: Vec<(FetchRequest, Priority)>,
     /// All node paths that the BFS considers potentially visible.
     potential_nodes: HashSet<String>,
     /// All bulk paths that the BFS considers potentially needed.
@@ -130,21 +154,27 @@ struct BfsResult {
 /// All access to `lod_state` during BFS is read-only. Mutations are collected
 /// into the returned `BfsResult` and applied by the caller.
 fn bfs_traversal(lod_state: &LodState, frustum: Frustum, lod_metrics: LodMetrics) -> BfsResult {
-    let mut nodes_to_load: Vec<NodeMetadata> = Vec::new();
-    let mut bulks_to_load: Vec<(String, u32)> = Vec::new();
+    let mut requests: Vec<(FetchRequest, Priority)> = Vec::new();
     let mut potential_nodes: HashSet<String> = HashSet::new();
     let mut potential_bulks: HashSet<String> = HashSet::new();
     // OBBs discovered during traversal, to be merged into lod_state after.
     let mut discovered_obbs: Vec<(String, OrientedBoundingBox)> = Vec::new();
 
-    // BFS frontier: (node_path, bulk_key) pairs.
+    // BFS frontier: (node_path, bulk_key, priority) triples. The priority of
+    // a frontier node is inherited by the child bulk rooted at it.
     // Start from root node with the root bulk.
-    let mut valid: Vec<(String, String)> = vec![(String::new(), String::new())];
+    let root_priority = Priority {
+        screen_space_error: f64::INFINITY,
+        distance: 0.0,
+        level: 0,
+    };
+    let mut valid: Vec<(String, String, Priority)> =
+        vec![(String::new(), String::new(), root_priority)];
 
     loop {
-        let mut next_valid: Vec<(String, String)> = Vec::new();
+        let mut next_valid: Vec<(String, String, Priority)> = Vec::new();
 
-        for (path, original_bulk_key) in &valid {
+        for (path, original_bulk_key, priority) in &valid {
             // At bulk boundaries (path length is a multiple of 4 and non-empty),
             // check if we need to switch to a child bulk.
             let effective_bulk_key = if !path.is_empty() && path.len() % 4 == 0 {
@@ -161,11 +191,10 @@ fn bfs_traversal(lod_state: &LodState, frustum: Frustum, lod_metrics: LodMetrics
                 potential_bulks.insert(path.clone());
 
                 if !lod_state.bulks.contains_key(path) {
-                    // Trigger download if not already loading or failed.
-                    if !lod_state.loading_bulks.contains(path)
-                        && !lod_state.failed_bulks.contains(path)
-                    {
-                        bulks_to_load.push((path.clone(), child_epoch));
+                    // Request download unless it already failed.
+                    if !lod_state.failed_bulks.contains(path) {
+                        let request = BulkRequest::new(path.clone(), child_epoch);
+                        requests.push((FetchRequest::Bulk(request), *priority));
                     }
                     continue;
                 }
@@ -209,15 +238,21 @@ fn bfs_traversal(lod_state: &LodState, frustum: Frustum, lod_metrics: LodMetrics
                     continue;
                 }
 
-                next_valid.push((nxt, effective_bulk_key.to_string()));
+                let node_priority = Priority::from_lod(
+                    &lod_metrics,
+                    node.obb.center,
+                    node.meters_per_texel,
+                    node.path.len(),
+                );
+                next_valid.push((nxt, effective_bulk_key.to_string(), node_priority));
 
-                // Track this node as potentially visible and queue for loading.
+                // Track this node as potentially visible and request loading.
                 if node.has_data {
                     potential_nodes.insert(node.path.clone());
                     if !lod_state.loaded_nodes.contains(&node.path)
-                        && !lod_state.loading_nodes.contains(&node.path)
+                        && !lod_state.missing_nodes.contains(&node.path)
                     {
-                        nodes_to_load.push((*node).clone());
+                        requests.push((FetchRequest::Node(node_request(node)), node_priority));
                     }
                 }
             }
@@ -230,15 +265,24 @@ fn bfs_traversal(lod_state: &LodState, frustum: Frustum, lod_metrics: LodMetrics
     }
 
     BfsResult {
-        nodes_to_load,
-        bulks_to_load,
+        requests,
         potential_nodes,
         potential_bulks,
         discovered_obbs,
     }
 }
 
-/// Despawn entities for nodes no longer in the potential set, and remove
+/// Build the request for loading a node's data.
+fn node_request(node: &NodeMetadata) -> NodeRequest {
+    NodeRequest::new(
+        node.path.clone(),
+        node.epoch,
+        node.texture_format,
+        node.imagery_epoch,
+    )
+}
+
+/// Despawn entities for nodes no longer in the potential set and remove
 /// obsolete bulks from the cache.
 fn unload_obsolete(
     lod_state: &mut LodState,
@@ -262,6 +306,10 @@ fn unload_obsolete(
         }
     }
 
+    lod_state
+        .missing_nodes
+        .retain(|p| potential_nodes.contains(p.as_str()));
+
     // Remove bulks no longer in the potential set (never remove the root bulk).
     let obsolete_bulks: Vec<String> = lod_state
         .bulks
@@ -343,7 +391,7 @@ fn update_frustum(
 }
 
 /// Update LOD requests using BFS traversal from root.
-#[allow(clippy::needless_pass_by_value, clippy::too_many_lines)]
+#[allow(clippy::needless_pass_by_value)]
 fn update_lod_requests(
     mut commands: Commands,
     loader_state: Res<LoaderState>,
@@ -385,68 +433,18 @@ fn update_lod_requests(
         &bfs.potential_bulks,
     );
 
-    // Limit concurrent loads.
-    let max_node_loads = 20;
-    let max_bulk_loads = 10;
-
-    // Spawn node load tasks.
-    for node_meta in bfs.nodes_to_load {
-        if lod_state.loading_nodes.len() >= max_node_loads {
-            break;
-        }
-
-        let path = node_meta.path.clone();
-        lod_state.loading_nodes.insert(path.clone());
-
-        let client = Arc::clone(&loader_state.client);
-        let request = NodeRequest::new(
-            node_meta.path.clone(),
-            node_meta.epoch,
-            node_meta.texture_format,
-            node_meta.imagery_epoch,
-        );
-
-        let tx = channels.node_tx.clone();
-        let path_clone = path.clone();
-
-        #[cfg(not(target_family = "wasm"))]
-        {
-            runtime.spawn_background_task(move |_ctx| async move {
-                let result = client.fetch_node(&request).await;
-                let _ = tx.send((path_clone, result)).await;
-            });
-        }
-
-        #[cfg(target_family = "wasm")]
-        {
-            AsyncComputeTaskPool::get()
-                .spawn(async move {
-                    let result = client.fetch_node(&request).await;
-                    let _ = tx.send((path_clone, result)).await;
-                })
-                .detach();
-        }
-    }
-
-    // Spawn bulk load tasks.
-    for (path, epoch) in bfs.bulks_to_load {
-        if lod_state.loading_bulks.len() >= max_bulk_loads {
-            break;
-        }
-
-        lod_state.loading_bulks.insert(path.clone());
+    // Hand the wanted set to the scheduler. This cancels loads that are no
+    // longer wanted; their results are ignored when they arrive.
+    lod_state.scheduler.sync(bfs.requests);
 
-        let client = Arc::clone(&loader_state.client);
-        let request = BulkRequest::new(path.clone(), epoch);
-
-        let tx = channels.bulk_tx.clone();
-        let path_clone = path.clone();
+    // Spawn the highest-priority loads that fit within the limits.
+    for dispatch in lod_state.scheduler.dispatch(&loader_state.client) {
+        let tx = channels.completion_tx.clone();
 
         #[cfg(not(target_family = "wasm"))]
         {
             runtime.spawn_background_task(move |_ctx| async move {
-                let result = client.fetch_bulk(&request).await;
-                let _ = tx.send((path_clone, result)).await;
+                let _ = tx.send(dispatch.await).await;
             });
         }
 
@@ -454,104 +452,120 @@ fn update_lod_requests(
         {
             AsyncComputeTaskPool::get()
                 .spawn(async move {
-                    let result = client.fetch_bulk(&request).await;
-                    let _ = tx.send((path_clone, result)).await;
+                    let _ = tx.send(dispatch.await).await;
                 })
                 .detach();
         }
     }
 }
 
-/// Poll bulk loading results from channel.
+/// Poll finished loads from the channel, storing bulks and spawning meshes.
 #[allow(clippy::needless_pass_by_value)]
-fn poll_lod_bulk_tasks(mut lod_state: ResMut<LodState>, channels: Res<LodChannels>) {
-    while let Ok((path, result)) = channels.bulk_rx.try_recv() {
-        lod_state.loading_bulks.remove(&path);
+fn poll_lod_tasks(
+    mut commands: Commands,
+    mut lod_state: ResMut<LodState>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<UnlitMaterial>>,
+    mut images: ResMut<Assets<Image>>,
+    channels: Res<LodChannels>,
+) {
+    while let Ok(completion) = channels.completion_rx.try_recv() {
+        // Ignore results of cancelled or superseded loads.
+        let Some((key, outcome)) = lod_state.scheduler.complete(completion) else {
+            continue;
+        };
 
-        match result {
-            Ok(bulk) => {
+        match outcome {
+            FetchOutcome::Bulk(Ok(bulk)) => {
                 tracing::info!(
                     "LOD: Loaded bulk '{}': {} nodes",
                     bulk.path,
                     bulk.nodes.len()
                 );
-                lod_state.bulks.insert(path, bulk);
+                lod_state.bulks.insert(key.path, bulk);
+            }
+            FetchOutcome::Bulk(Err(e)) => {
+                tracing::debug!("LOD: Failed to load bulk '{}': {}", key.path, e);
+                lod_state.failed_bulks.insert(key.path);
+            }
+            FetchOutcome::Node(Ok(node)) => {
+                spawn_node(
+                    &mut commands,
+                    &mut lod_state,
+                    &mut meshes,
+                    &mut materials,
+                    &mut images,
+                    &node,
+                );
+            }
+            FetchOutcome::Node(Err(e)) if e.kind() == ErrorKind::NotCached => {
+                tracing::debug!("LOD: Node '{}' is not cached", key.path);
+                lod_state.missing_nodes.insert(key.path);
             }
-            Err(e) => {
-                tracing::debug!("LOD: Failed to load bulk '{}': {}", path, e);
-                lod_state.failed_bulks.insert(path);
+            FetchOutcome::Node(Err(e)) => {
+                tracing::warn!("LOD: Failed to load node '{}': {}", key.path, e);
             }
         }
     }
 }
 
-/// Poll node loading results from channel and spawn meshes.
-#[allow(clippy::needless_pass_by_value)]
-fn poll_lod_node_tasks(
-    mut commands: Commands,
-    mut lod_state: ResMut<LodState>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<UnlitMaterial>>,
-    mut images: ResMut<Assets<Image>>,
-    channels: Res<LodChannels>,
+/// Spawn mesh entities for a loaded node and track them for later despawning.
+fn spawn_node(
+    commands: &mut Commands,
+    lod_state: &mut LodState,
+    meshes: &mut Assets<Mesh>,
+    materials: &mut Assets<UnlitMaterial>,
+    images: &mut Assets<Image>,
+    node: &Node,
 ) {
-    while let Ok((path, result)) = channels.node_rx.try_recv() {
-        lod_state.loading_nodes.remove(&path);
-
-        match result {
-            Ok(node) => {
-                // Look up the real OBB from bulk metadata.
-                let obb = lod_state
-                    .node_obbs
-                    .get(&node.path)
-                    .copied()
-                    .unwrap_or(node.obb);
-
-                tracing::debug!(
-                    "LOD: Spawning node='{}' meshes={}",
-                    node.path,
-                    node.meshes.len(),
-                );
+    // Look up the real OBB from bulk metadata.
+    let obb = lod_state
+        .node_obbs
+        .get(&node.path)
+        .copied()
+        .unwrap_or(node.obb);
+
+    tracing::debug!(
+        "LOD: Spawning node='{}' meshes={}",
+        node.path,
+        node.meshes.len(),
+    );
 
-                lod_state.loaded_nodes.insert(path.clone());
-
-                // Spawn mesh entities and track them for later despawning.
-                let entities = lod_state.node_entities.entry(path).or_default();
-                for rocktree_mesh in &node.meshes {
-                    let mesh = convert_mesh(rocktree_mesh);
-                    let texture = convert_texture(rocktree_mesh);
-
-                    let mesh_handle = meshes.add(mesh);
-                    let texture_handle = images.add(texture);
-
-                    let material = materials.add(UnlitMaterial {
-                        base_color_texture: texture_handle,
-                        octant_mask: UVec4::ZERO,
-                    });
-
-                    let (world_position, transform) =
-                        matrix_to_world_position_and_transform(&node.matrix_globe_from_mesh);
-
-                    let entity = commands
-                        .spawn((
-                            Mesh3d(mesh_handle),
-                            MeshMaterial3d(material),
-                            transform,
-                            world_position,
-                            RocktreeMeshMarker {
-                                path: node.path.clone(),
-                                meters_per_texel: node.meters_per_texel,
-                                obb,
-                            },
-                        ))
-                        .id();
-                    entities.push(entity);
-                }
-            }
-            Err(e) => {
-                tracing::warn!("LOD: Failed to load node '{}': {}", path, e);
-            }
-        }
+    lod_state.loaded_nodes.insert(node.path.clone());
+
+    let entities = lod_state
+        .node_entities
+        .entry(node.path.clone())
+        .or_default();
+    for rocktree_mesh in &node.meshes {
+        let mesh = convert_mesh(rocktree_mesh);
+        let texture = convert_texture(rocktree_mesh);
+
+        let mesh_handle = meshes.add(mesh);
+        let texture_handle = images.add(texture);
+
+        let material = materials.add(UnlitMaterial {
+            base_color_texture: texture_handle,
+            octant_mask: UVec4::ZERO,
+        });
+
+        let (world_position, transform) =
+            matrix_to_world_position_and_transform(&node.matrix_globe_from_mesh);
+
+        let entity = commands
+            .spawn((
+                Mesh3d(mesh_handle),
+                MeshMaterial3d(material),
+                transform,
+                world_position,
+                RocktreeMeshMarker {
+                    path: node.path.clone(),
+                    meters_per_texel: node.meters_per_texel,
+                    obb,
+                },
+            ))
+            .id();
+        entities.push(entity);
     }
 }
 
@@ -100,6 +100,10 @@ fn main() {
 
     let mut app = App::new();
 
+    // Native: Read the loader options from the command line.
+    #[cfg(not(target_family = "wasm"))]
+    app.insert_resource(loader::LoaderConfig::from_args());
+
     #[allow(unused_mut)]
     let mut window = Window {
         title: "rocktree-client".to_string(),
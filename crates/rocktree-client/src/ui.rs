@@ -1,7 +1,7 @@
 //! Debug UI for displaying performance metrics and camera info.
 //!
-//! Shows FPS, camera position, altitude, and loaded node count.
-//! Includes geocoding search powered by OpenStreetMap Nominatim.
+//! Shows FPS, camera position, altitude, loaded node count, and client network
+//! metrics. Includes geocoding search powered by OpenStreetMap Nominatim.
 
 use bevy::diagnostic::{DiagnosticsStore, FrameTimeDiagnosticsPlugin};
 use bevy::prelude::*;
@@ -11,10 +11,12 @@ use bevy_egui::{EguiContexts, EguiPlugin, EguiPrimaryContextPass, egui};
 #[cfg(not(target_family = "wasm"))]
 use bevy_tokio_tasks::TokioTasksRuntime;
 use glam::DVec3;
+use rocktree::{Endpoint, MetricsSnapshot};
 use serde::Deserialize;
 
 use crate::camera::{CameraSettings, FlightCamera, MAX_SPEED, MIN_SPEED};
 use crate::floating_origin::FloatingOriginCamera;
+use crate::loader::LoaderState;
 use crate::lod::LodState;
 use crate::mesh::RocktreeMeshMarker;
 
@@ -94,6 +96,7 @@ fn debug_ui_system(
     mut coord_state: ResMut<CoordinateInputState>,
     mut geocoding_state: ResMut<GeocodingState>,
     lod_state: Res<LodState>,
+    loader_state: Res<LoaderState>,
     mut camera_query: Query<(&mut FloatingOriginCamera, &mut Transform, &mut FlightCamera)>,
     mesh_query: Query<&RocktreeMeshMarker>,
     #[cfg(not(target_family = "wasm"))] runtime: ResMut<TokioTasksRuntime>,
@@ -139,6 +142,7 @@ fn debug_ui_system(
     let mesh_count = mesh_query.iter().count();
     let loaded_nodes = lod_state.loaded_node_count();
     let loading_nodes = lod_state.loading_node_count();
+    let metrics = loader_state.client.metrics().snapshot();
 
     // Track if we need to move the camera.
     let mut new_coords: Option<(f64, f64)> = None;
@@ -161,6 +165,10 @@ fn debug_ui_system(
             ));
             ui.label(format!("Meshes: {mesh_count}"));
 
+            egui::CollapsingHeader::new("Network")
+                .default_open(false)
+                .show(ui, |ui| network_metrics_ui(ui, &metrics));
+
             ui.separator();
 
             // Geocoding search.
@@ -345,6 +353,70 @@ fn debug_ui_system(
     Ok(())
 }
 
+/// Render per-endpoint request metrics from the rocktree client.
+fn network_metrics_ui(ui: &mut egui::Ui, metrics: &MetricsSnapshot) {
+    ui.label(format!(
+        "Total: {} requests, {} downloaded",
+        metrics.total_requests(),
+        format_bytes(metrics.total_bytes_downloaded())
+    ));
+
+    for endpoint in Endpoint::ALL {
+        let stats = metrics.endpoint(endpoint);
+        let hit_ratio = stats
+            .cache_hit_ratio()
+            .map_or_else(|| "-".to_string(), |r| format!("{:.0}%", r * 100.0));
+        let mean = stats
+            .latency
+            .mean()
+            .map_or_else(|| "-".to_string(), |d| format!("{} ms", d.as_millis()));
+        let p95 = stats
+            .latency
+            .quantile_upper_bound(0.95)
+            .map_or_else(|| "-".to_string(), |d| format!("<= {} ms", d.as_millis()));
+
+        ui.label(format!(
+            "{}: {} requests, {} hit, {}, {} errors",
+            endpoint.name(),
+            stats.requests,
+            hit_ratio,
+            format_bytes(stats.bytes_downloaded),
+            stats.errors
+        ));
+        ui.label(format!("  latency: mean {mean}, p95 {p95}"));
+    }
+
+    if metrics.errors.total() > 0 {
+        let errors: Vec<String> = metrics
+            .errors
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(kind, count)| format!("{} {count}", kind.name()))
+            .collect();
+        ui.colored_label(
+            egui::Color32::YELLOW,
+            format!("Errors: {}", errors.join(", ")),
+        );
+    }
+}
+
+/// Format a byte count with a binary unit suffix.
+#[allow(clippy::cast_precision_loss)]
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
 /// Poll for geocoding results from background task.
 #[allow(clippy::needless_pass_by_value)]
 fn poll_geocoding_results(mut geocoding_state: ResMut<GeocodingState>) {
@@ -40,6 +40,8 @@ pub fn unpack_path_and_flags(path_and_flags: u32) -> PathAndFlags {
 }
 
 #[cfg(test)]
+// Bit groupings in the literals mirror the packed field layout.
+#[allow(clippy::unusual_byte_groupings, clippy::identity_op)]
 mod tests {
     use super::*;
 
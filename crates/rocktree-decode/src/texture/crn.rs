@@ -89,7 +89,7 @@ mod tests {
     #[test]
     fn test_bgra_u32_to_rgba_bytes() {
         // BGRA32: 0xAARRGGBB -> to_le_bytes gives [B, G, R, A] -> output [R, G, B, A].
-        let input = vec![0x11223344u32, 0xAABBCCDDu32];
+        let input = vec![0x1122_3344_u32, 0xAABB_CCDD_u32];
         let bytes = bgra_u32_to_rgba_bytes(input);
 
         // 0x11223344: A=0x11, R=0x22, G=0x33, B=0x44 -> RGBA = [0x22, 0x33, 0x44, 0x11].
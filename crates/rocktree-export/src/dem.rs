@@ -0,0 +1,241 @@
+//! Digital elevation model export.
+//!
+//! Heights of the topmost surface are rasterized onto a latitude/longitude
+//! grid and written as a single-band `float32` GeoTIFF in WGS84 (EPSG:4326)
+//! with ellipsoidal heights in meters. Cells no surface covers hold
+//! [`NODATA`].
+
+use glam::DVec2;
+use rocktree::{Cache, Client, GeoBounds, MeshLayer, Node};
+use std::path::Path;
+use tiff::encoder::colortype;
+
+use crate::error::{Error, Result};
+use crate::geotiff::{Crs, GeoTiff};
+use crate::raster::{GridLayout, HeightGrid, METERS_PER_DEGREE, TERRAIN_LAYERS, visit_nodes};
+
+/// Value written for cells without data.
+pub const NODATA: f32 = -9999.0;
+
+/// A digital elevation model being rasterized from nodes.
+///
+/// ```ignore
+/// let mut dem = Dem::new(bounds, 0.0001)?.with_terrain_only(true);
+/// dem.add_node(&node);
+/// dem.write_geotiff("dem.tif")?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Dem {
+    bounds: GeoBounds,
+    grid: HeightGrid,
+    layers: Vec<MeshLayer>,
+}
+
+impl Dem {
+    /// Create an empty model of `bounds` with cells `resolution` degrees
+    /// across.
+    ///
+    /// The resolution is adjusted slightly so a whole number of cells fits
+    /// the bounds.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Unsupported`] if `bounds` are empty or invalid,
+    /// `resolution` is not a positive number, or the model would have more
+    /// than [`MAX_CELLS`](crate::raster::MAX_CELLS) cells.
+    pub fn new(bounds: GeoBounds, resolution: f64) -> Result<Self> {
+        let layout = GridLayout::for_resolution(&bounds, resolution, "GeoTIFF")?;
+        Ok(Self {
+            bounds,
+            grid: HeightGrid::new(layout),
+            layers: MeshLayer::ALL.to_vec(),
+        })
+    }
+
+    /// Only rasterize terrain, leaving out buildings and vegetation.
+    ///
+    /// Applies to nodes added afterwards.
+    #[must_use]
+    pub fn with_terrain_only(mut self, enabled: bool) -> Self {
+        self.layers = if enabled {
+            TERRAIN_LAYERS.to_vec()
+        } else {
+            MeshLayer::ALL.to_vec()
+        };
+        self
+    }
+
+    /// Get the area covered.
+    #[must_use]
+    pub fn bounds(&self) -> &GeoBounds {
+        &self.bounds
+    }
+
+    /// Get the rasterized heights.
+    #[must_use]
+    pub fn grid(&self) -> &HeightGrid {
+        &self.grid
+    }
+
+    /// Get the approximate size of a cell in meters, north to south.
+    #[must_use]
+    pub fn cell_size_meters(&self) -> f64 {
+        self.grid.spacing().0 * METERS_PER_DEGREE
+    }
+
+    /// Rasterize the surface of `node`.
+    ///
+    /// Where nodes overlap, the most detailed one is used, and the highest
+    /// surface among nodes at the same level.
+    pub fn add_node(&mut self, node: &Node) {
+        self.grid.add_node(node, &self.layers);
+    }
+
+    /// Encode the model as a GeoTIFF.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the grid is too large for a GeoTIFF.
+    pub fn to_geotiff(&self) -> Result<Vec<u8>> {
+        let (lat_step, lon_step) = self.grid.spacing();
+        let data: Vec<f32> = self
+            .grid
+            .heights()
+            .iter()
+            .map(|&h| if h.is_nan() { NODATA } else { h })
+            .collect();
+        GeoTiff {
+            width: self.grid.width(),
+            height: self.grid.height(),
+            origin: DVec2::new(self.bounds.west, self.bounds.north),
+            pixel_size: DVec2::new(lon_step, lat_step),
+            crs: Crs::Wgs84,
+            ellipsoidal_heights: true,
+            nodata: Some(NODATA),
+        }
+        .encode::<colortype::Gray32Float>(&data)
+    }
+
+    /// Write the model as a GeoTIFF at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encoding or writing the file fails.
+    pub fn write_geotiff(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_geotiff()?).map_err(|e| Error::io(path, e))
+    }
+}
+
+/// Rasterize the nodes covering `dem` at its resolution.
+///
+/// Nodes are fetched from the root down until their meters per texel are
+/// no coarser than the model's cells.
+///
+/// # Errors
+///
+/// Returns an error if a fetch fails.
+pub async fn export_dem<C: Cache + 'static>(client: &Client<C>, mut dem: Dem) -> Result<Dem> {
+    let (bounds, target) = (dem.bounds, dem.cell_size_meters());
+    visit_nodes(client, &bounds, target, |node| {
+        dem.add_node(node);
+        Ok(())
+    })
+    .await?;
+    Ok(dem)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::tests::terrain_node;
+    use std::io::Cursor;
+    use tiff::decoder::{Decoder, DecodingResult};
+    use tiff::tags::Tag;
+
+    fn building_node(path: &str, bounds: &GeoBounds, height: f64) -> Node {
+        let mut node = terrain_node(path, bounds, height);
+        let len = node.meshes[0].indices.len();
+        node.meshes[0].layer_bounds = [0, len, len, len];
+        node
+    }
+
+    #[test]
+    fn test_write_geotiff() {
+        let bounds = GeoBounds::new(10.0, 20.0, 10.01, 20.02);
+        let mut dem = Dem::new(bounds, 0.001).unwrap();
+        // Terrain over the western half, with a building in its middle.
+        dem.add_node(&terrain_node(
+            "01",
+            &GeoBounds::new(9.99, 19.99, 10.02, 20.009),
+            100.0,
+        ));
+        dem.add_node(&building_node(
+            "01",
+            &GeoBounds::new(10.004, 20.004, 10.006, 20.006),
+            130.0,
+        ));
+        assert_eq!((dem.grid().width(), dem.grid().height()), (20, 10));
+
+        let mut decoder = Decoder::new(Cursor::new(dem.to_geotiff().unwrap())).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (20, 10));
+        let scale = decoder.get_tag_f64_vec(Tag::ModelPixelScaleTag).unwrap();
+        assert!((scale[0] - 0.001).abs() < 1e-12 && (scale[1] - 0.001).abs() < 1e-12);
+        let tiepoint = decoder.get_tag_f64_vec(Tag::ModelTiepointTag).unwrap();
+        assert_eq!(tiepoint[3..5], [20.0, 10.01]);
+        let keys = decoder.get_tag_u16_vec(Tag::GeoKeyDirectoryTag).unwrap();
+        assert_eq!(keys[3], 5);
+        assert_eq!(keys[12..16], [2048, 0, 1, 4326]);
+        let nodata = decoder.get_tag_ascii_string(Tag::GdalNodata).unwrap();
+        assert_eq!(nodata.trim_end_matches('\0'), "-9999");
+
+        let DecodingResult::F32(heights) = decoder.read_image().unwrap() else {
+            panic!("expected float32 samples");
+        };
+        let at = |column: usize, row: usize| heights[row * 20 + column];
+        assert!((at(0, 0) - 100.0).abs() < 1.0);
+        assert!((at(4, 4) - 130.0).abs() < 1.0);
+        assert!((at(19, 9) - NODATA).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn test_terrain_only() {
+        let bounds = GeoBounds::new(10.0, 20.0, 10.01, 20.01);
+        let mut dem = Dem::new(bounds, 0.001).unwrap().with_terrain_only(true);
+        dem.add_node(&terrain_node("0", &bounds, 5.0));
+        dem.add_node(&building_node("0", &bounds, 40.0));
+        let (min, max) = dem.grid().height_range().unwrap();
+        assert!((min - 5.0).abs() < 1.0 && (max - 5.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_rejects_invalid_resolution() {
+        let bounds = GeoBounds::new(10.0, 20.0, 10.01, 20.01);
+        for resolution in [0.0, -0.001, f64::NAN, f64::INFINITY] {
+            let err = Dem::new(bounds, resolution).unwrap_err();
+            assert!(matches!(err, Error::Unsupported { .. }), "{resolution}");
+        }
+        // Too many cells to allocate.
+        let err = Dem::new(bounds, 1e-12).unwrap_err();
+        assert!(matches!(err, Error::Unsupported { .. }), "{err}");
+        let err = Dem::new(GeoBounds::WORLD, 1e-300).unwrap_err();
+        assert!(matches!(err, Error::Unsupported { .. }), "{err}");
+    }
+
+    #[test]
+    fn test_rejects_invalid_bounds() {
+        for bounds in [
+            GeoBounds::new(10.0, 20.0, 10.0, 20.01),
+            GeoBounds::new(10.01, 20.0, 10.0, 20.01),
+            GeoBounds::new(10.0, 20.0, 10.01, 20.0),
+            GeoBounds::new(-91.0, 20.0, 10.01, 20.01),
+            GeoBounds::new(f64::NAN, 20.0, 10.01, 20.01),
+        ] {
+            let err = Dem::new(bounds, 0.001).unwrap_err();
+            assert!(matches!(err, Error::Unsupported { .. }), "{bounds:?}");
+        }
+        // Bounds may cross the antimeridian.
+        let dem = Dem::new(GeoBounds::new(10.0, 179.99, 10.01, -179.99), 0.001).unwrap();
+        assert_eq!((dem.grid().width(), dem.grid().height()), (20, 10));
+    }
+}
@@ -0,0 +1,81 @@
+//! Error types for the rocktree-export crate.
+
+use std::fmt;
+use std::path::PathBuf;
+
+/// Result type for export operations.
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// Errors that can occur while exporting.
+#[derive(Debug)]
+pub enum Error {
+    /// Fetching or decoding rocktree data failed.
+    Rocktree(rocktree::Error),
+    /// A texture could not be decoded or encoded.
+    Texture {
+        /// Description of what went wrong.
+        message: String,
+    },
+    /// The input cannot be represented in the output format.
+    Unsupported {
+        /// The output format.
+        format: &'static str,
+        /// Description of what could not be represented.
+        detail: String,
+    },
+    /// Reading or writing a file failed.
+    Io {
+        /// The file being accessed.
+        path: PathBuf,
+        /// The underlying error.
+        source: std::io::Error,
+    },
+}
+
+impl Error {
+    pub(crate) fn io(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        Error::Io {
+            path: path.into(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Rocktree(e) => write!(f, "{e}"),
+            Error::Texture { message } => write!(f, "texture error: {message}"),
+            Error::Unsupported { format, detail } => {
+                write!(f, "cannot export to {format}: {detail}")
+            }
+            Error::Io { path, source } => {
+                write!(f, "failed to access {}: {source}", path.display())
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Rocktree(e) => Some(e),
+            Error::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<rocktree::Error> for Error {
+    fn from(e: rocktree::Error) -> Self {
+        Error::Rocktree(e)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Self {
+        Error::Texture {
+            message: e.to_string(),
+        }
+    }
+}
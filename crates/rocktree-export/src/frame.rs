@@ -0,0 +1,128 @@
+//! Local coordinate frames for exported geometry.
+//!
+//! Rocktree positions are ECEF coordinates millions of meters from the
+//! origin, which lose centimeter precision as `f32`. Exported positions are
+//! expressed relative to a nearby origin instead.
+
+use glam::{DMat3, DMat4, DVec3};
+use rocktree::Geodetic;
+
+/// A right-handed, Z-up coordinate frame anchored near the exported data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    kind: FrameKind,
+    origin: DVec3,
+    /// Rotation from ECEF axes to the frame's axes.
+    rotation: DMat3,
+}
+
+/// The orientation of a [`Frame`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// ECEF axes translated to the origin.
+    EcefOffset,
+    /// East, north, and up axes at the origin.
+    Enu,
+}
+
+impl FrameKind {
+    /// A short, lowercase name for this kind.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            FrameKind::EcefOffset => "ecef-offset",
+            FrameKind::Enu => "enu",
+        }
+    }
+}
+
+impl Frame {
+    /// Create a frame that keeps the ECEF axes but is centered on `origin`.
+    #[must_use]
+    pub fn ecef_offset(origin: DVec3) -> Self {
+        Self {
+            kind: FrameKind::EcefOffset,
+            origin,
+            rotation: DMat3::IDENTITY,
+        }
+    }
+
+    /// Create a local east-north-up frame tangent to the ellipsoid at
+    /// `origin`.
+    #[must_use]
+    pub fn enu(origin: Geodetic) -> Self {
+        let (sin_lat, cos_lat) = origin.latitude.to_radians().sin_cos();
+        let (sin_lon, cos_lon) = origin.longitude.to_radians().sin_cos();
+        let east = DVec3::new(-sin_lon, cos_lon, 0.0);
+        let north = DVec3::new(-sin_lat * cos_lon, -sin_lat * sin_lon, cos_lat);
+        let up = DVec3::new(cos_lat * cos_lon, cos_lat * sin_lon, sin_lat);
+        Self {
+            kind: FrameKind::Enu,
+            origin: origin.to_ecef(),
+            // Rows are the local axes, so this maps ECEF vectors onto them.
+            rotation: DMat3::from_cols(east, north, up).transpose(),
+        }
+    }
+
+    /// Get the orientation of the frame.
+    #[must_use]
+    pub fn kind(&self) -> FrameKind {
+        self.kind
+    }
+
+    /// Get the ECEF position of the frame's origin.
+    #[must_use]
+    pub fn origin(&self) -> DVec3 {
+        self.origin
+    }
+
+    /// Convert an ECEF position into the frame.
+    #[must_use]
+    pub fn to_local(&self, position: DVec3) -> DVec3 {
+        self.rotation * (position - self.origin)
+    }
+
+    /// Convert a position in the frame back to ECEF.
+    #[must_use]
+    pub fn to_ecef(&self, position: DVec3) -> DVec3 {
+        self.rotation.transpose() * position + self.origin
+    }
+
+    /// Get the transform from frame coordinates to ECEF.
+    #[must_use]
+    pub fn ecef_from_local(&self) -> DMat4 {
+        DMat4::from_translation(self.origin) * DMat4::from_mat3(self.rotation.transpose())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enu_axes() {
+        let origin = Geodetic::new(40.0, -74.0, 10.0);
+        let frame = Frame::enu(origin);
+        assert!(frame.to_local(origin.to_ecef()).length() < 1e-6);
+
+        let above = Geodetic::new(40.0, -74.0, 110.0).to_ecef();
+        assert!((frame.to_local(above) - DVec3::new(0.0, 0.0, 100.0)).length() < 1e-6);
+
+        // A small step north moves along +Y.
+        let north = frame.to_local(Geodetic::new(40.001, -74.0, 10.0).to_ecef());
+        assert!(north.y > 100.0 && north.x.abs() < 1e-6 && north.z.abs() < 0.1);
+
+        let local = DVec3::new(12.0, -3.0, 4.5);
+        let ecef = frame.to_ecef(local);
+        assert!((frame.ecef_from_local().transform_point3(local) - ecef).length() < 1e-6);
+        assert!((frame.to_local(ecef) - local).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_ecef_offset() {
+        let frame = Frame::ecef_offset(DVec3::new(1.0e6, 2.0e6, 3.0e6));
+        let local = frame.to_local(DVec3::new(1.0e6 + 1.0, 2.0e6, 3.0e6 - 2.0));
+        assert_eq!(local, DVec3::new(1.0, 0.0, -2.0));
+        assert_eq!(frame.kind().name(), "ecef-offset");
+    }
+}
@@ -0,0 +1,328 @@
+//! Triangle geometry extracted from decoded meshes.
+
+use glam::{DVec3, Vec2};
+use rocktree::{Mesh, MeshLayer, Node};
+use std::collections::{HashMap, HashSet};
+
+use crate::frame::Frame;
+
+/// Triangle-list geometry of one mesh, positioned in an export frame.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MeshGeometry {
+    /// Vertex positions in the export frame.
+    pub positions: Vec<DVec3>,
+    /// Texture coordinates, with the origin at the top-left corner of the
+    /// texture.
+    pub uvs: Vec<Vec2>,
+    /// Octant of each vertex within its node, or empty if the mesh has no
+    /// octant data.
+    pub octants: Vec<u8>,
+    /// Vertex indices, three per triangle.
+    pub triangles: Vec<u32>,
+}
+
+impl MeshGeometry {
+    /// Extract the geometry of one of a node's meshes.
+    ///
+    /// Positions are dequantized through the node's `matrix_globe_from_mesh`
+    /// and expressed in `frame`, texture coordinates have the mesh's
+    /// [`UvTransform`](rocktree::UvTransform) applied, and the triangle strip
+    /// is expanded to a list.
+    #[must_use]
+    pub fn from_mesh(node: &Node, mesh: &Mesh, frame: &Frame) -> Self {
+        let mut geometry = Self::vertices(node, mesh, frame);
+        geometry.triangles = rocktree_decode::strip_to_triangles(&mesh.indices)
+            .into_iter()
+            .map(u32::from)
+            .collect();
+        geometry
+    }
+
+    /// Extract the geometry of one of a node's meshes, keeping only the
+    /// triangles in `layers`.
+    ///
+    /// Meshes without octant data report all of their geometry as
+    /// [`MeshLayer::Overground`]. Vertices outside the selected layers are
+    /// dropped.
+    #[must_use]
+    pub fn from_mesh_layers(node: &Node, mesh: &Mesh, frame: &Frame, layers: &[MeshLayer]) -> Self {
+        let mut geometry = Self::vertices(node, mesh, frame);
+        for &layer in layers {
+            let range = mesh.layer_range(layer);
+            let start = range.start;
+            let Some(strip) = mesh.indices.get(range) else {
+                continue;
+            };
+            for triangle in rocktree_decode::strip_to_triangles(strip).chunks_exact(3) {
+                // Winding alternates along the strip, so a layer starting at
+                // an odd offset has its triangles flipped.
+                let [a, b, c] = [0, 1, 2].map(|k| u32::from(triangle[k]));
+                if start.is_multiple_of(2) {
+                    geometry.triangles.extend([a, b, c]);
+                } else {
+                    geometry.triangles.extend([a, c, b]);
+                }
+            }
+        }
+        geometry.compact();
+        geometry
+    }
+
+    /// Dequantize the vertices of a mesh, without any triangles.
+    fn vertices(node: &Node, mesh: &Mesh, frame: &Frame) -> Self {
+        let positions = mesh
+            .vertices
+            .iter()
+            .map(|v| {
+                let local = DVec3::new(f64::from(v.x), f64::from(v.y), f64::from(v.z));
+                frame.to_local(node.matrix_globe_from_mesh.transform_point3(local))
+            })
+            .collect();
+        let transform = &mesh.uv_transform;
+        let uvs = mesh
+            .vertices
+            .iter()
+            .map(|v| {
+                (Vec2::new(f32::from(v.u()), f32::from(v.v())) + transform.offset) * transform.scale
+            })
+            .collect();
+        let octants = if mesh.has_octant_data {
+            mesh.vertices.iter().map(|v| v.w & 7).collect()
+        } else {
+            Vec::new()
+        };
+        Self {
+            positions,
+            uvs,
+            octants,
+            triangles: Vec::new(),
+        }
+    }
+
+    /// Remove triangles touching the octants set in `mask`, along with the
+    /// vertices only they used.
+    ///
+    /// Bit `i` of `mask` stands for the child octant `i` of the node, as
+    /// built by [`child_octant_masks`]. Removing the octants covered by
+    /// exported children keeps parent and child geometry from overlapping.
+    /// Geometry without octant data is left unchanged.
+    pub fn mask_octants(&mut self, mask: u8) {
+        if mask == 0 || self.octants.is_empty() {
+            return;
+        }
+        let octants = &self.octants;
+        let masked = |i: u32| mask & (1 << octants[i as usize]) != 0;
+        self.triangles = self
+            .triangles
+            .chunks_exact(3)
+            .filter(|triangle| !triangle.iter().any(|&i| masked(i)))
+            .flatten()
+            .copied()
+            .collect();
+        self.compact();
+    }
+
+    /// Drop vertices no triangle uses, keeping the rest in order.
+    pub(crate) fn compact(&mut self) {
+        let mut used = vec![false; self.positions.len()];
+        for &i in &self.triangles {
+            used[i as usize] = true;
+        }
+        let mut remap = vec![0; self.positions.len()];
+        let mut positions = Vec::new();
+        let mut uvs = Vec::new();
+        let mut octants = Vec::new();
+        for (i, _) in used.iter().enumerate().filter(|&(_, &used)| used) {
+            remap[i] = u32::try_from(positions.len()).expect("vertex count fits in u32");
+            positions.push(self.positions[i]);
+            uvs.push(self.uvs[i]);
+            if let Some(&octant) = self.octants.get(i) {
+                octants.push(octant);
+            }
+        }
+        for i in &mut self.triangles {
+            *i = remap[*i as usize];
+        }
+        self.positions = positions;
+        self.uvs = uvs;
+        self.octants = octants;
+    }
+
+    /// Get the number of triangles.
+    #[must_use]
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len() / 3
+    }
+
+    /// Whether the geometry has no triangles.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    /// Get the minimum and maximum corners of the positions, or `None` if
+    /// there are no vertices.
+    #[must_use]
+    pub fn bounds(&self) -> Option<(DVec3, DVec3)> {
+        let first = *self.positions.first()?;
+        Some(
+            self.positions
+                .iter()
+                .fold((first, first), |(min, max), &p| (min.min(p), max.max(p))),
+        )
+    }
+}
+
+/// Find, for each node in a set, the child octants covered by descendants
+/// also in the set.
+///
+/// Descendants need not be direct children: a node whose nearest ancestor
+/// in the set is further up, because the nodes between them hold no data,
+/// still masks the octant of that ancestor leading down to it.
+///
+/// The result maps parent paths to a bitmask suitable for
+/// [`MeshGeometry::mask_octants`]; nodes without descendants in the set are
+/// left out.
+pub fn child_octant_masks<'a>(paths: impl IntoIterator<Item = &'a str>) -> HashMap<String, u8> {
+    let paths: HashSet<&str> = paths.into_iter().collect();
+    let mut masks = HashMap::new();
+    for path in &paths {
+        let Some(ancestor) = (0..path.len())
+            .rev()
+            .find(|&len| paths.contains(&path[..len]))
+        else {
+            continue;
+        };
+        let octant = path.as_bytes()[ancestor];
+        if (b'0'..=b'7').contains(&octant) {
+            *masks.entry(path[..ancestor].to_string()).or_default() |= 1 << (octant - b'0');
+        }
+    }
+    masks
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use glam::{DMat3, DMat4};
+    use rocktree::{OrientedBoundingBox, TextureFormat, UvTransform, Vertex};
+
+    /// Build a node holding one textured quad, 2 by 2 meters, centered on
+    /// `center` and facing +Z.
+    pub(crate) fn quad_node(path: &str, center: DVec3) -> Node {
+        let vertex = |x, y, u, v| Vertex {
+            x,
+            y,
+            z: 0,
+            w: 0,
+            u,
+            v,
+        };
+        let mesh = Mesh {
+            vertices: vec![
+                vertex(0, 0, 0, 0),
+                vertex(2, 0, 2, 0),
+                vertex(0, 2, 0, 2),
+                vertex(2, 2, 2, 2),
+            ],
+            indices: vec![0, 1, 2, 3],
+            uv_transform: UvTransform {
+                offset: Vec2::ZERO,
+                scale: Vec2::splat(0.5),
+            },
+            texture_data: [255, 0, 0, 255, 0, 255, 0, 255].repeat(2),
+            texture_format: TextureFormat::Rgba,
+            texture_width: 2,
+            texture_height: 2,
+            has_octant_data: false,
+            layer_bounds: [0, 4, 4, 4],
+        };
+        assert_eq!(mesh.layer_range(MeshLayer::Overground), 0..4);
+        Node {
+            path: path.to_string(),
+            matrix_globe_from_mesh: DMat4::from_translation(center - DVec3::new(1.0, 1.0, 0.0)),
+            meters_per_texel: 1.0,
+            obb: OrientedBoundingBox {
+                center,
+                extents: DVec3::new(1.0, 1.0, 0.1),
+                orientation: DMat3::IDENTITY,
+            },
+            meshes: vec![mesh],
+        }
+    }
+
+    #[test]
+    fn test_from_mesh() {
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let node = quad_node("0123", center);
+        let geometry = MeshGeometry::from_mesh(&node, &node.meshes[0], &Frame::ecef_offset(center));
+        assert_eq!(geometry.triangles, [0, 1, 2, 1, 3, 2]);
+        assert_eq!(geometry.triangle_count(), 2);
+        assert_eq!(geometry.positions[0], DVec3::new(-1.0, -1.0, 0.0));
+        assert_eq!(geometry.positions[3], DVec3::new(1.0, 1.0, 0.0));
+        assert_eq!(geometry.uvs[3], Vec2::new(1.0, 1.0));
+        assert_eq!(
+            geometry.bounds(),
+            Some((DVec3::new(-1.0, -1.0, 0.0), DVec3::new(1.0, 1.0, 0.0)))
+        );
+    }
+
+    #[test]
+    fn test_mask_octants() {
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let mut node = quad_node("0", center);
+        let mesh = &mut node.meshes[0];
+        mesh.has_octant_data = true;
+        mesh.vertices[0].w = 1;
+        let frame = Frame::ecef_offset(center);
+
+        let mut geometry = MeshGeometry::from_mesh(&node, &node.meshes[0], &frame);
+        geometry.mask_octants(0b100);
+        assert_eq!(geometry.triangle_count(), 2);
+
+        // Vertex 0 is in octant 1, so only the second triangle survives.
+        geometry.mask_octants(0b10);
+        assert_eq!(geometry.triangles, [0, 2, 1]);
+        assert_eq!(geometry.positions.len(), 3);
+        assert_eq!(geometry.positions[0], DVec3::new(1.0, -1.0, 0.0));
+        assert_eq!(geometry.uvs.len(), 3);
+
+        node.meshes[0].has_octant_data = false;
+        let mut geometry = MeshGeometry::from_mesh(&node, &node.meshes[0], &frame);
+        geometry.mask_octants(0xff);
+        assert_eq!(geometry.triangle_count(), 2);
+    }
+
+    #[test]
+    fn test_child_octant_masks() {
+        let masks = child_octant_masks(["0", "01", "07", "012", "3", "345", "3456", "5"]);
+        assert_eq!(masks.len(), 4);
+        assert_eq!(masks["0"], 0b1000_0010);
+        assert_eq!(masks["01"], 0b100);
+        // "34" holds no data, so "3" masks the octant leading to "345".
+        assert_eq!(masks["3"], 0b1_0000);
+        assert_eq!(masks["345"], 0b100_0000);
+    }
+
+    #[test]
+    fn test_from_mesh_layers() {
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let mut node = quad_node("0", center);
+        let mesh = &mut node.meshes[0];
+        mesh.has_octant_data = true;
+        // The first triangle is overground, the second above water.
+        mesh.indices = vec![0, 1, 2, 1, 3, 2];
+        mesh.layer_bounds = [0, 3, 3, 6];
+        let frame = Frame::ecef_offset(center);
+
+        let terrain = [MeshLayer::TerrainBelowWater, MeshLayer::TerrainAboveWater];
+        let geometry = MeshGeometry::from_mesh_layers(&node, &node.meshes[0], &frame, &terrain);
+        // Vertices 1, 2 and 3 remain, wound as in the full strip.
+        assert_eq!(geometry.triangles, [0, 1, 2]);
+        assert_eq!(geometry.positions.len(), 3);
+        assert_eq!(geometry.positions[1], DVec3::new(-1.0, 1.0, 0.0));
+
+        let all = MeshGeometry::from_mesh_layers(&node, &node.meshes[0], &frame, &MeshLayer::ALL);
+        assert_eq!(all.triangle_count(), 2);
+    }
+}
@@ -0,0 +1,113 @@
+//! GeoTIFF encoding shared by the raster exporters.
+
+use glam::DVec2;
+use std::io::Cursor;
+use tiff::encoder::colortype::ColorType;
+use tiff::encoder::compression::DeflateLevel;
+use tiff::encoder::{Compression, TiffEncoder, TiffValue};
+use tiff::tags::Tag;
+
+use crate::error::{Error, Result};
+
+/// Coordinate system of a GeoTIFF's pixel grid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Crs {
+    /// WGS84 latitude and longitude in degrees (EPSG:4326).
+    Wgs84,
+    /// Web Mercator in meters (EPSG:3857).
+    WebMercator,
+    /// A user-defined projected system in meters, such as a local frame.
+    LocalMeters,
+}
+
+/// Georeferencing of an image whose rows run from north to south.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct GeoTiff {
+    pub width: usize,
+    pub height: usize,
+    /// Coordinates of the top-left corner of the first pixel.
+    pub origin: DVec2,
+    /// Size of a pixel along each axis, both positive.
+    pub pixel_size: DVec2,
+    pub crs: Crs,
+    /// Whether samples are heights above the WGS84 ellipsoid in meters.
+    pub ellipsoidal_heights: bool,
+    /// Sample value marking pixels without data.
+    pub nodata: Option<f32>,
+}
+
+impl GeoTiff {
+    /// Get the GeoTIFF keys as (key, value) pairs.
+    fn geo_keys(&self) -> Vec<(u16, u16)> {
+        let mut keys = match self.crs {
+            // GTModelTypeGeoKey: geographic; GTRasterTypeGeoKey: pixels cover
+            // areas; GeographicTypeGeoKey: WGS84.
+            Crs::Wgs84 => vec![(1024, 2), (1025, 1), (2048, 4326)],
+            // GTModelTypeGeoKey: projected; ProjectedCSTypeGeoKey: Web
+            // Mercator.
+            Crs::WebMercator => vec![(1024, 1), (1025, 1), (3072, 3857)],
+            // GTModelTypeGeoKey: projected; ProjectedCSTypeGeoKey and
+            // ProjLinearUnitsGeoKey: user-defined, in meters.
+            Crs::LocalMeters => vec![(1024, 1), (1025, 1), (3072, 32767), (3076, 9001)],
+        };
+        if self.ellipsoidal_heights {
+            // VerticalCSTypeGeoKey: WGS84 ellipsoid; VerticalUnitsGeoKey:
+            // meters.
+            keys.extend([(4096, 5030), (4099, 9001)]);
+        }
+        keys
+    }
+
+    /// Encode `data`, laid out row by row with `C`'s samples per pixel.
+    pub(crate) fn encode<C: ColorType>(&self, data: &[C::Inner]) -> Result<Vec<u8>>
+    where
+        [C::Inner]: TiffValue,
+    {
+        let tiff_error = |e: tiff::TiffError| Error::Unsupported {
+            format: "GeoTIFF",
+            detail: e.to_string(),
+        };
+        let size_error = |e: std::num::TryFromIntError| Error::Unsupported {
+            format: "GeoTIFF",
+            detail: format!("image too large: {e}"),
+        };
+        let width = u32::try_from(self.width).map_err(size_error)?;
+        let height = u32::try_from(self.height).map_err(size_error)?;
+
+        let keys = self.geo_keys();
+        let mut directory = vec![1, 1, 0, u16::try_from(keys.len()).unwrap_or(u16::MAX)];
+        for (key, value) in keys {
+            directory.extend([key, 0, 1, value]);
+        }
+
+        let mut out = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut out)
+            .map_err(tiff_error)?
+            .with_compression(Compression::Deflate(DeflateLevel::Balanced));
+        let mut image = encoder.new_image::<C>(width, height).map_err(tiff_error)?;
+        let tags = image.encoder();
+        if C::BITS_PER_SAMPLE.len() == 4 {
+            // The fourth sample is unassociated alpha.
+            tags.write_tag(Tag::ExtraSamples, 2u16)
+                .map_err(tiff_error)?;
+        }
+        tags.write_tag(
+            Tag::ModelPixelScaleTag,
+            &[self.pixel_size.x, self.pixel_size.y, 0.0][..],
+        )
+        .map_err(tiff_error)?;
+        tags.write_tag(
+            Tag::ModelTiepointTag,
+            &[0.0, 0.0, 0.0, self.origin.x, self.origin.y, 0.0][..],
+        )
+        .map_err(tiff_error)?;
+        tags.write_tag(Tag::GeoKeyDirectoryTag, &directory[..])
+            .map_err(tiff_error)?;
+        if let Some(nodata) = self.nodata {
+            tags.write_tag(Tag::GdalNodata, nodata.to_string().as_str())
+                .map_err(tiff_error)?;
+        }
+        image.write_data(data).map_err(tiff_error)?;
+        Ok(out.into_inner())
+    }
+}
@@ -0,0 +1,468 @@
+//! Binary glTF 2.0 (GLB) export.
+//!
+//! Each rocktree node becomes a glTF node named after its octant path, with
+//! one primitive per mesh. Textures are embedded in the binary chunk and
+//! materials use `KHR_materials_unlit`, since rocktree textures already
+//! contain lighting.
+//!
+//! glTF is Y-up, so a [`Frame`]'s Z axis becomes glTF's Y axis and its Y
+//! axis becomes glTF's -Z axis.
+
+use glam::{DVec3, Vec3};
+use rocktree::Node;
+use serde_json::{Map, Value, json};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::geometry::MeshGeometry;
+use crate::merge::MergedMesh;
+use crate::simplify::Simplifier;
+use crate::texture::{TextureEncoding, mesh_image};
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_VERSION: u32 = 2;
+const CHUNK_JSON: u32 = 0x4E4F_534A;
+const CHUNK_BIN: u32 = 0x004E_4942;
+
+const ARRAY_BUFFER: u32 = 34_962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34_963;
+const UNSIGNED_SHORT: u32 = 5123;
+const UNSIGNED_INT: u32 = 5125;
+const FLOAT: u32 = 5126;
+const LINEAR: u32 = 9729;
+const CLAMP_TO_EDGE: u32 = 33_071;
+
+const UNLIT: &str = "KHR_materials_unlit";
+
+/// Builds a GLB file from decoded nodes.
+///
+/// ```ignore
+/// let mut builder = GltfBuilder::new(Frame::enu(origin));
+/// builder.add_node(&node, epoch)?;
+/// builder.write(path)?;
+/// ```
+#[derive(Debug)]
+pub struct GltfBuilder {
+    frame: Frame,
+    texture_encoding: TextureEncoding,
+    simplifier: Option<Simplifier>,
+    bin: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+    images: Vec<Value>,
+    materials: Vec<Value>,
+    meshes: Vec<Value>,
+    nodes: Vec<Value>,
+}
+
+impl GltfBuilder {
+    /// Create an empty builder that positions geometry in `frame`.
+    #[must_use]
+    pub fn new(frame: Frame) -> Self {
+        Self {
+            frame,
+            texture_encoding: TextureEncoding::default(),
+            simplifier: None,
+            bin: Vec::new(),
+            buffer_views: Vec::new(),
+            accessors: Vec::new(),
+            images: Vec::new(),
+            materials: Vec::new(),
+            meshes: Vec::new(),
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Set how textures are embedded.
+    #[must_use]
+    pub fn with_texture_encoding(mut self, encoding: TextureEncoding) -> Self {
+        self.texture_encoding = encoding;
+        self
+    }
+
+    /// Simplify each mesh of the nodes added afterwards.
+    #[must_use]
+    pub fn with_simplifier(mut self, simplifier: Simplifier) -> Self {
+        self.simplifier = Some(simplifier);
+        self
+    }
+
+    /// Get the frame geometry is positioned in.
+    #[must_use]
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Get the number of nodes added so far.
+    #[must_use]
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    /// Add a node and its meshes.
+    ///
+    /// The node's path, `epoch`, and meters per texel are recorded in the
+    /// glTF node's `extras`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a texture cannot be decoded or encoded.
+    pub fn add_node(&mut self, node: &Node, epoch: u32) -> Result<()> {
+        let mut primitives = Vec::new();
+        for mesh in &node.meshes {
+            let mut geometry = MeshGeometry::from_mesh(node, mesh, &self.frame);
+            if let Some(simplifier) = &self.simplifier {
+                simplifier.simplify(&mut geometry);
+            }
+            if geometry.is_empty() {
+                continue;
+            }
+            let texture = self.texture_encoding.encode(&mesh_image(mesh)?)?;
+            primitives.push(self.add_primitive(&geometry, &texture));
+        }
+
+        let mut gltf_node = json!({
+            "name": node.path,
+            "extras": {
+                "path": node.path,
+                "epoch": epoch,
+                "meters_per_texel": node.meters_per_texel,
+            },
+        });
+        if !primitives.is_empty() {
+            gltf_node["mesh"] = json!(self.meshes.len());
+            self.meshes.push(json!({
+                "name": node.path,
+                "primitives": primitives,
+            }));
+        }
+        self.nodes.push(gltf_node);
+        Ok(())
+    }
+
+    /// Add a merged mesh as a node named `name` with a single primitive.
+    ///
+    /// Positions are moved into this builder's frame if the mesh was
+    /// merged in another.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the atlas cannot be encoded.
+    pub fn add_merged(&mut self, name: &str, merged: &MergedMesh) -> Result<()> {
+        let texture = self.texture_encoding.encode(&merged.atlas)?;
+        let primitive = if merged.frame == self.frame {
+            self.add_primitive(&merged.geometry, &texture)
+        } else {
+            let mut geometry = merged.geometry.clone();
+            for position in &mut geometry.positions {
+                *position = self.frame.to_local(merged.frame.to_ecef(*position));
+            }
+            self.add_primitive(&geometry, &texture)
+        };
+        self.nodes.push(json!({
+            "name": name,
+            "mesh": self.meshes.len(),
+        }));
+        self.meshes.push(json!({
+            "name": name,
+            "primitives": [primitive],
+        }));
+        Ok(())
+    }
+
+    /// Write geometry and its texture, returning the glTF primitive.
+    fn add_primitive(&mut self, geometry: &MeshGeometry, texture: &[u8]) -> Value {
+        let positions: Vec<Vec3> = geometry.positions.iter().map(|&p| y_up(p)).collect();
+        let (min, max) = positions
+            .iter()
+            .fold((Vec3::INFINITY, Vec3::NEG_INFINITY), |(min, max), &p| {
+                (min.min(p), max.max(p))
+            });
+        let position = self.add_accessor(
+            &floats(positions.iter().flat_map(Vec3::to_array)),
+            ARRAY_BUFFER,
+            json!({
+                "componentType": FLOAT,
+                "count": positions.len(),
+                "type": "VEC3",
+                "min": min.to_array(),
+                "max": max.to_array(),
+            }),
+        );
+        let texcoord = self.add_accessor(
+            &floats(geometry.uvs.iter().flat_map(glam::Vec2::to_array)),
+            ARRAY_BUFFER,
+            json!({
+                "componentType": FLOAT,
+                "count": geometry.uvs.len(),
+                "type": "VEC2",
+            }),
+        );
+
+        // The largest value of an index type is reserved for primitive
+        // restart, so fall back to 32-bit indices for large meshes.
+        #[allow(clippy::cast_possible_truncation)] // Indices are below the vertex count.
+        let (data, component_type) = match u16::try_from(positions.len()) {
+            Ok(count) if count < u16::MAX => (
+                geometry
+                    .triangles
+                    .iter()
+                    .flat_map(|&i| (i as u16).to_le_bytes())
+                    .collect::<Vec<_>>(),
+                UNSIGNED_SHORT,
+            ),
+            _ => (
+                geometry
+                    .triangles
+                    .iter()
+                    .flat_map(|i| i.to_le_bytes())
+                    .collect(),
+                UNSIGNED_INT,
+            ),
+        };
+        let indices = self.add_accessor(
+            &data,
+            ELEMENT_ARRAY_BUFFER,
+            json!({
+                "componentType": component_type,
+                "count": geometry.triangles.len(),
+                "type": "SCALAR",
+            }),
+        );
+
+        let view = self.add_buffer_view(texture, None);
+        self.images.push(json!({
+            "bufferView": view,
+            "mimeType": self.texture_encoding.mime_type(),
+        }));
+        // Images, textures, and materials correspond one to one.
+        let texture = self.images.len() - 1;
+        self.materials.push(json!({
+            "pbrMetallicRoughness": {
+                "baseColorTexture": { "index": texture },
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            },
+            "extensions": { UNLIT: {} },
+        }));
+
+        json!({
+            "attributes": {
+                "POSITION": position,
+                "TEXCOORD_0": texcoord,
+            },
+            "indices": indices,
+            "material": self.materials.len() - 1,
+        })
+    }
+
+    fn add_accessor(&mut self, data: &[u8], target: u32, mut accessor: Value) -> usize {
+        accessor["bufferView"] = json!(self.add_buffer_view(data, Some(target)));
+        self.accessors.push(accessor);
+        self.accessors.len() - 1
+    }
+
+    fn add_buffer_view(&mut self, data: &[u8], target: Option<u32>) -> usize {
+        // Accessors need their data aligned to the component size.
+        self.bin.resize(self.bin.len().next_multiple_of(4), 0);
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": self.bin.len(),
+            "byteLength": data.len(),
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.bin.extend_from_slice(data);
+        self.buffer_views.push(view);
+        self.buffer_views.len() - 1
+    }
+
+    /// Build the glTF JSON document.
+    fn document(&self) -> Value {
+        let origin = self.frame.origin();
+        let mut document = Map::new();
+        document.insert(
+            "asset".into(),
+            json!({
+                "version": "2.0",
+                "generator": concat!("rocktree-export ", env!("CARGO_PKG_VERSION")),
+                "extras": {
+                    "frame": self.frame.kind().name(),
+                    "origin": origin.to_array(),
+                },
+            }),
+        );
+        document.insert("scene".into(), json!(0));
+        document.insert(
+            "scenes".into(),
+            json!([{ "nodes": (0..self.nodes.len()).collect::<Vec<_>>() }]),
+        );
+        if !self.materials.is_empty() {
+            document.insert("extensionsUsed".into(), json!([UNLIT]));
+            document.insert(
+                "samplers".into(),
+                json!([{
+                    "magFilter": LINEAR,
+                    "minFilter": LINEAR,
+                    "wrapS": CLAMP_TO_EDGE,
+                    "wrapT": CLAMP_TO_EDGE,
+                }]),
+            );
+            let textures: Vec<_> = (0..self.images.len())
+                .map(|source| json!({ "source": source, "sampler": 0 }))
+                .collect();
+            document.insert("textures".into(), json!(textures));
+        }
+        for (name, items) in [
+            ("nodes", &self.nodes),
+            ("meshes", &self.meshes),
+            ("materials", &self.materials),
+            ("images", &self.images),
+            ("accessors", &self.accessors),
+            ("bufferViews", &self.buffer_views),
+        ] {
+            // glTF does not allow empty arrays.
+            if !items.is_empty() {
+                document.insert(name.into(), json!(items));
+            }
+        }
+        if !self.bin.is_empty() {
+            document.insert("buffers".into(), json!([{ "byteLength": self.bin.len() }]));
+        }
+        Value::Object(document)
+    }
+
+    /// Encode everything added so far as a GLB file.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Unsupported`] if the file would exceed 4 GiB.
+    pub fn to_glb(&self) -> Result<Vec<u8>> {
+        let mut json = self.document().to_string().into_bytes();
+        json.resize(json.len().next_multiple_of(4), b' ');
+        let mut bin = self.bin.clone();
+        bin.resize(bin.len().next_multiple_of(4), 0);
+
+        let too_large = || Error::Unsupported {
+            format: "glb",
+            detail: "the file would exceed 4 GiB".to_string(),
+        };
+        let bin_chunk_len = if bin.is_empty() { 0 } else { 8 + bin.len() };
+        let total = u32::try_from(12 + 8 + json.len() + bin_chunk_len).map_err(|_| too_large())?;
+
+        let mut glb = Vec::with_capacity(total as usize);
+        glb.extend_from_slice(GLB_MAGIC);
+        glb.extend_from_slice(&GLB_VERSION.to_le_bytes());
+        glb.extend_from_slice(&total.to_le_bytes());
+        for (kind, data) in [(CHUNK_JSON, &json), (CHUNK_BIN, &bin)] {
+            if data.is_empty() {
+                continue;
+            }
+            let len = u32::try_from(data.len()).map_err(|_| too_large())?;
+            glb.extend_from_slice(&len.to_le_bytes());
+            glb.extend_from_slice(&kind.to_le_bytes());
+            glb.extend_from_slice(data);
+        }
+        Ok(glb)
+    }
+
+    /// Write everything added so far to a GLB file at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encoding or writing the file fails.
+    pub fn write(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_glb()?).map_err(|e| Error::io(path, e))
+    }
+}
+
+/// Convert a Z-up frame position to glTF's Y-up axes.
+#[allow(clippy::cast_possible_truncation)] // Frame coordinates are small.
+fn y_up(position: DVec3) -> Vec3 {
+    Vec3::new(position.x as f32, position.z as f32, -position.y as f32)
+}
+
+fn floats(values: impl Iterator<Item = f32>) -> Vec<u8> {
+    values.flat_map(f32::to_le_bytes).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_node;
+    use crate::merge::MeshMerger;
+
+    #[test]
+    fn test_glb_round_trip() {
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let mut builder = GltfBuilder::new(Frame::ecef_offset(center))
+            .with_texture_encoding(TextureEncoding::Png);
+        builder.add_node(&quad_node("0123", center), 7).unwrap();
+        builder
+            .add_node(&quad_node("0124", center + DVec3::X * 10.0), 8)
+            .unwrap();
+        let glb = builder.to_glb().unwrap();
+        assert_eq!(glb.len() % 4, 0);
+
+        let (document, buffers, images) = gltf::import_slice(&glb).unwrap();
+        assert_eq!(document.nodes().len(), 2);
+        assert_eq!(document.extensions_used().collect::<Vec<_>>(), [UNLIT]);
+
+        let node = document.nodes().next().unwrap();
+        assert_eq!(node.name(), Some("0123"));
+        let extras: Value = serde_json::from_str(node.extras().as_ref().unwrap().get()).unwrap();
+        assert_eq!(extras["path"], "0123");
+        assert_eq!(extras["epoch"], 7);
+
+        let primitive = node.mesh().unwrap().primitives().next().unwrap();
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions: Vec<[f32; 3]> = reader.read_positions().unwrap().collect();
+        assert_eq!(Vec3::from(positions[0]), Vec3::new(-1.0, 0.0, 1.0));
+        assert_eq!(Vec3::from(positions[3]), Vec3::new(1.0, 0.0, -1.0));
+        let uvs: Vec<[f32; 2]> = reader.read_tex_coords(0).unwrap().into_f32().collect();
+        assert_eq!(glam::Vec2::from(uvs[3]), glam::Vec2::ONE);
+        let indices: Vec<u32> = reader.read_indices().unwrap().into_u32().collect();
+        assert_eq!(indices, [0, 1, 2, 1, 3, 2]);
+
+        let material = primitive.material();
+        assert!(material.unlit());
+        let texture = material
+            .pbr_metallic_roughness()
+            .base_color_texture()
+            .unwrap()
+            .texture();
+        let image = &images[texture.source().index()];
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.pixels[4..8], [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn test_add_merged() {
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let merged = MeshMerger::new(Frame::ecef_offset(center))
+            .merge(&[&quad_node("0", center)])
+            .unwrap();
+        let mut builder = GltfBuilder::new(Frame::ecef_offset(center + DVec3::X));
+        builder.add_merged("region", &merged).unwrap();
+
+        let (document, buffers, _) = gltf::import_slice(builder.to_glb().unwrap()).unwrap();
+        let node = document.nodes().next().unwrap();
+        assert_eq!(node.name(), Some("region"));
+        let primitive = node.mesh().unwrap().primitives().next().unwrap();
+        let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+        let positions: Vec<[f32; 3]> = reader.read_positions().unwrap().collect();
+        assert_eq!(Vec3::from(positions[0]), Vec3::new(-2.0, 0.0, 1.0));
+    }
+
+    #[test]
+    fn test_empty_glb() {
+        let glb = GltfBuilder::new(Frame::ecef_offset(DVec3::ZERO))
+            .to_glb()
+            .unwrap();
+        let document = gltf::Gltf::from_slice(&glb).unwrap();
+        assert_eq!(document.nodes().len(), 0);
+    }
+}
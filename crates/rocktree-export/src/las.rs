@@ -0,0 +1,256 @@
+//! LAS 1.4 point cloud export.
+//!
+//! Points are written with point data record format 7, which holds RGB
+//! colors. Coordinates are stored as scaled integers around the center of
+//! the cloud, at millimeter resolution unless the cloud is too large for
+//! it.
+//!
+//! Clouds in a [`Frame`](crate::Frame) at the ECEF origin are tagged with
+//! the WGS84 geocentric coordinate system (EPSG:4978). Clouds in other
+//! frames have no coordinate system, as their axes are local.
+
+use glam::DVec3;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::{Error, Result};
+use crate::frame::FrameKind;
+use crate::points::PointCloud;
+
+/// Size of the LAS 1.4 header.
+const HEADER_SIZE: u16 = 375;
+
+/// Size of a variable length record header.
+const VLR_HEADER_SIZE: usize = 54;
+
+/// Point data record format with GPS time and RGB.
+const POINT_FORMAT: u8 = 7;
+
+/// Size of a format 7 point record.
+const POINT_SIZE: u16 = 36;
+
+/// Global encoding bit marking the coordinate system as WKT, required for
+/// formats 6 and above.
+const GLOBAL_ENCODING_WKT: u16 = 1 << 4;
+
+/// Record ID of the OGC WKT coordinate system record.
+const WKT_RECORD_ID: u16 = 2112;
+
+/// WKT of the WGS84 geocentric coordinate system.
+const ECEF_WKT: &str = concat!(
+    r#"GEOCCS["WGS 84",DATUM["WGS_1984",SPHEROID["WGS 84",6378137,298.257223563,"#,
+    r#"AUTHORITY["EPSG","7030"]],AUTHORITY["EPSG","6326"]],"#,
+    r#"PRIMEM["Greenwich",0,AUTHORITY["EPSG","8901"]],UNIT["metre",1,AUTHORITY["EPSG","9001"]],"#,
+    r#"AXIS["Geocentric X",OTHER],AXIS["Geocentric Y",OTHER],AXIS["Geocentric Z",NORTH],"#,
+    r#"AUTHORITY["EPSG","4978"]]"#
+);
+
+/// Write `cloud` to a LAS 1.4 file at `path`.
+///
+/// # Errors
+///
+/// Returns an error if writing the file fails.
+pub fn write_las(cloud: &PointCloud, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let (min, max) = cloud.bounds().unwrap_or((DVec3::ZERO, DVec3::ZERO));
+    let offset = ((min + max) / 2.0).round();
+    let scale = DVec3::splat(coordinate_scale((max - min).max_element() / 2.0));
+
+    let frame = cloud.frame();
+    let geocentric = frame.kind() == FrameKind::EcefOffset && frame.origin() == DVec3::ZERO;
+    let vlrs = if geocentric {
+        vec![wkt_record()]
+    } else {
+        Vec::new()
+    };
+
+    let mut header = Vec::with_capacity(usize::from(HEADER_SIZE));
+    header.extend_from_slice(b"LASF");
+    header.extend_from_slice(&0u16.to_le_bytes()); // File source ID
+    header.extend_from_slice(&GLOBAL_ENCODING_WKT.to_le_bytes());
+    header.extend_from_slice(&[0; 16]); // Project ID
+    header.extend_from_slice(&[1, 4]); // Version
+    header.extend_from_slice(&padded::<32>("EXPORT"));
+    header.extend_from_slice(&padded::<32>(&format!(
+        "rocktree-export {}",
+        env!("CARGO_PKG_VERSION")
+    )));
+    let (day, year) = creation_date();
+    header.extend_from_slice(&day.to_le_bytes());
+    header.extend_from_slice(&year.to_le_bytes());
+    header.extend_from_slice(&HEADER_SIZE.to_le_bytes());
+    let vlr_size: usize = vlrs.iter().map(Vec::len).sum();
+    let data_offset = u32::try_from(usize::from(HEADER_SIZE) + vlr_size).unwrap_or(u32::MAX);
+    header.extend_from_slice(&data_offset.to_le_bytes());
+    let vlr_count = u32::try_from(vlrs.len()).unwrap_or(u32::MAX);
+    header.extend_from_slice(&vlr_count.to_le_bytes());
+    header.push(POINT_FORMAT);
+    header.extend_from_slice(&POINT_SIZE.to_le_bytes());
+    // Legacy point counts are zero for formats 6 and above.
+    header.extend_from_slice(&[0; 4 + 5 * 4]);
+    for value in [scale, offset]
+        .into_iter()
+        .flat_map(|v| v.to_array())
+        .chain([max.x, min.x, max.y, min.y, max.z, min.z])
+    {
+        header.extend_from_slice(&value.to_le_bytes());
+    }
+    // Waveform data, extended records and their count.
+    header.extend_from_slice(&[0; 8 + 8 + 4]);
+    header.extend_from_slice(&(cloud.len() as u64).to_le_bytes());
+    // Every point is a single return.
+    header.extend_from_slice(&(cloud.len() as u64).to_le_bytes());
+    header.extend_from_slice(&[0; 14 * 8]);
+    debug_assert_eq!(header.len(), usize::from(HEADER_SIZE));
+
+    let mut writer = File::create(path)
+        .map(BufWriter::new)
+        .map_err(|e| Error::io(path, e))?;
+    let mut write = |data: &[u8]| writer.write_all(data).map_err(|e| Error::io(path, e));
+    write(&header)?;
+    for vlr in &vlrs {
+        write(vlr)?;
+    }
+    for point in cloud.points() {
+        let mut record = [0u8; POINT_SIZE as usize];
+        let scaled = ((point.position - offset) / scale).round();
+        for (k, value) in scaled.to_array().into_iter().enumerate() {
+            #[allow(clippy::cast_possible_truncation)] // The scale keeps values in range.
+            record[k * 4..k * 4 + 4].copy_from_slice(&(value as i32).to_le_bytes());
+        }
+        // Intensity stays zero; return 1 of 1; never classified.
+        record[14] = 0x11;
+        for (k, channel) in point.color.into_iter().enumerate() {
+            // Colors are 16-bit, so scale 255 up to 65535.
+            let value = u16::from(channel) * 257;
+            record[30 + k * 2..32 + k * 2].copy_from_slice(&value.to_le_bytes());
+        }
+        write(&record)?;
+    }
+    writer.flush().map_err(|e| Error::io(path, e))
+}
+
+/// Get the power of ten, at least a millimeter, at which coordinates up to
+/// `extent` from the offset fit in an `i32`.
+fn coordinate_scale(extent: f64) -> f64 {
+    let mut scale = 0.001;
+    while extent / scale >= f64::from(i32::MAX) {
+        scale *= 10.0;
+    }
+    scale
+}
+
+/// Build the variable length record holding the ECEF coordinate system.
+fn wkt_record() -> Vec<u8> {
+    // The WKT is null-terminated.
+    let mut wkt = ECEF_WKT.as_bytes().to_vec();
+    wkt.push(0);
+    let mut record = Vec::with_capacity(VLR_HEADER_SIZE + wkt.len());
+    record.extend_from_slice(&0u16.to_le_bytes()); // Reserved
+    record.extend_from_slice(&padded::<16>("LASF_Projection"));
+    record.extend_from_slice(&WKT_RECORD_ID.to_le_bytes());
+    let length = u16::try_from(wkt.len()).unwrap_or(u16::MAX);
+    record.extend_from_slice(&length.to_le_bytes());
+    record.extend_from_slice(&padded::<32>("OGC coordinate system WKT"));
+    record.extend_from_slice(&wkt);
+    record
+}
+
+/// Copy `text` into a zero-padded fixed-size field, truncating if needed.
+fn padded<const N: usize>(text: &str) -> [u8; N] {
+    let mut field = [0; N];
+    let len = text.len().min(N);
+    field[..len].copy_from_slice(&text.as_bytes()[..len]);
+    field
+}
+
+/// Get today's day of the year, counted from 1, and the year.
+fn creation_date() -> (u16, u16) {
+    let mut days = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() / 86_400);
+    let mut year: u16 = 1970;
+    loop {
+        let leap =
+            (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400);
+        let length = if leap { 366 } else { 365 };
+        if days < length {
+            break;
+        }
+        days -= length;
+        year += 1;
+    }
+    (u16::try_from(days + 1).unwrap_or(1), year)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Frame;
+    use crate::geometry::tests::quad_node;
+    use rocktree::Geodetic;
+
+    fn f64_at(data: &[u8], offset: usize) -> f64 {
+        f64::from_le_bytes(data[offset..offset + 8].try_into().unwrap())
+    }
+
+    fn u32_at(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap())
+    }
+
+    #[test]
+    fn test_write_las() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.las");
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let mut cloud = PointCloud::new(Frame::ecef_offset(DVec3::ZERO));
+        cloud.add_node(&quad_node("0", center)).unwrap();
+        write_las(&cloud, &path).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(&data[..4], b"LASF");
+        assert_eq!(data[24..26], [1, 4]);
+        assert_eq!(u32_at(&data, 100), 1); // One VLR
+        assert_eq!(data[104], 7);
+        // Bounds as max X, min X, ...
+        assert!((f64_at(&data, 179) - (center.x + 1.0)).abs() < 1e-9);
+        assert!((f64_at(&data, 187) - (center.x - 1.0)).abs() < 1e-9);
+        assert_eq!(u32_at(&data, 247), 4);
+
+        let vlr = &data[375..];
+        assert_eq!(&vlr[2..17], b"LASF_Projection");
+        assert!(vlr[54..].starts_with(b"GEOCCS[\"WGS 84\""));
+
+        let points = &data[u32_at(&data, 96) as usize..];
+        assert_eq!(points.len(), 4 * 36);
+        let scale = f64_at(&data, 131);
+        let offset = f64_at(&data, 155);
+        let x = i32::from_le_bytes(points[..4].try_into().unwrap());
+        assert!((f64::from(x) * scale + offset - (center.x - 1.0)).abs() < 1e-3);
+        assert_eq!(points[30..36], [255, 255, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_local_frame_has_no_crs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.las");
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let mut cloud = PointCloud::new(Frame::enu(Geodetic::from_ecef(center)));
+        cloud.add_node(&quad_node("0", center)).unwrap();
+        write_las(&cloud, &path).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        assert_eq!(u32_at(&data, 100), 0);
+        assert_eq!(u32_at(&data, 96), 375);
+        assert_eq!(data.len(), 375 + 4 * 36);
+    }
+
+    #[test]
+    fn test_coordinate_scale() {
+        assert!((coordinate_scale(1000.0) - 0.001).abs() < 1e-12);
+        // Planet-sized clouds need coarser units.
+        assert!((coordinate_scale(6_400_000.0) - 0.01).abs() < 1e-12);
+    }
+}
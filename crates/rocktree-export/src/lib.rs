@@ -0,0 +1,69 @@
+//! Export decoded Google Earth mesh data to common 3D formats.
+//!
+//! This crate converts the [`Node`](rocktree::Node)s fetched by the
+//! [`rocktree`] client into files other tools can read:
+//!
+//! - [`gltf`]: binary glTF 2.0 with embedded textures
+//! - [`obj`]: Wavefront OBJ with a material library and texture files
+//! - [`tiles`]: OGC 3D Tiles tilesets with GLB content
+//! - [`terrain`]: Cesium quantized-mesh terrain tiles
+//! - [`ply`] and [`las`]: point clouds sampled by [`points`]
+//! - [`dem`]: elevation models as GeoTIFF
+//! - [`ortho`]: orthophotos as GeoTIFF or PNG with a world file
+//! - [`xyz`]: Web Mercator imagery tiles as a directory tree or MBTiles
+//!
+//! [`merge`] combines the nodes of a region into one welded mesh with a
+//! texture atlas, which [`GltfBuilder::add_merged`] writes. [`simplify`]
+//! reduces the triangle count of extracted geometry.
+//!
+//! Rocktree positions are ECEF coordinates. Exporters position geometry in
+//! a [`Frame`] near the data so it survives conversion to `f32`.
+//!
+//! # Example
+//!
+//! ```ignore
+//! use rocktree::Geodetic;
+//! use rocktree_export::{Frame, GltfBuilder};
+//!
+//! let center = Geodetic::from_ecef(node.obb.center);
+//! let mut builder = GltfBuilder::new(Frame::enu(center));
+//! builder.add_node(&node, epoch)?;
+//! builder.write("node.glb")?;
+//! ```
+
+pub mod dem;
+mod error;
+pub mod frame;
+pub mod geometry;
+mod geotiff;
+pub mod gltf;
+pub mod las;
+pub mod merge;
+pub mod obj;
+pub mod ortho;
+pub mod ply;
+pub mod points;
+pub mod raster;
+pub mod simplify;
+pub mod terrain;
+pub mod texture;
+pub mod tiles;
+pub mod xyz;
+
+pub use dem::{Dem, export_dem};
+pub use error::{Error, Result};
+pub use frame::{Frame, FrameKind};
+pub use geometry::{MeshGeometry, child_octant_masks};
+pub use gltf::GltfBuilder;
+pub use las::write_las;
+pub use merge::{MergedMesh, MeshMerger, export_merged};
+pub use obj::{ObjExporter, ObjOutput};
+pub use ortho::{Orthophoto, export_orthophoto};
+pub use ply::write_ply;
+pub use points::{Point, PointCloud, PointSampling};
+pub use raster::HeightGrid;
+pub use simplify::Simplifier;
+pub use terrain::{TerrainWriter, TileId, export_terrain};
+pub use texture::{TextureEncoding, mesh_image};
+pub use tiles::{TilesetWriter, export_tileset};
+pub use xyz::{XyzTile, XyzWriter, export_xyz};
@@ -0,0 +1,373 @@
+//! Merging nodes into a single welded mesh.
+//!
+//! A region exported at a target resolution is a set of nodes from several
+//! octree levels. Merging drops the octants of each node that its
+//! descendants in the set cover, as the viewer does when rendering, positions all
+//! geometry in one [`Frame`], and welds vertices that coincide across node
+//! boundaries. The textures of all meshes are packed into one atlas.
+
+use glam::{DVec3, UVec2, Vec2};
+use image::RgbaImage;
+use rocktree::{Cache, Client, GeoBounds, Node};
+use std::collections::HashMap;
+
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::geometry::{MeshGeometry, child_octant_masks};
+use crate::raster::visit_nodes;
+use crate::texture::mesh_image;
+
+/// Largest width or height of a texture atlas, the limit of most GPUs.
+pub const MAX_ATLAS_SIZE: u32 = 16_384;
+
+/// A single mesh merged from several nodes.
+#[derive(Debug, Clone)]
+pub struct MergedMesh {
+    /// The frame positions are expressed in.
+    pub frame: Frame,
+    /// The welded geometry, with texture coordinates into `atlas` and no
+    /// octant data.
+    ///
+    /// Vertices welded together but with different texture coordinates,
+    /// as along the edges of node textures, stay separate vertices at
+    /// exactly the same position.
+    pub geometry: MeshGeometry,
+    /// The textures of all merged meshes.
+    pub atlas: RgbaImage,
+}
+
+/// Merges nodes into a single mesh with a texture atlas.
+///
+/// ```ignore
+/// let merger = MeshMerger::new(Frame::enu(origin)).with_weld_tolerance(0.005);
+/// let merged = merger.merge(&nodes)?;
+/// builder.add_merged("region", &merged)?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct MeshMerger {
+    frame: Frame,
+    weld_tolerance: f64,
+    padding: u32,
+}
+
+impl MeshMerger {
+    /// Create a merger that positions geometry in `frame`.
+    #[must_use]
+    pub fn new(frame: Frame) -> Self {
+        Self {
+            frame,
+            weld_tolerance: 0.01,
+            padding: 2,
+        }
+    }
+
+    /// Set the distance in meters within which vertices are welded.
+    ///
+    /// Defaults to 1 cm. Zero welds only identical positions.
+    #[must_use]
+    pub fn with_weld_tolerance(mut self, meters: f64) -> Self {
+        self.weld_tolerance = meters.max(0.0);
+        self
+    }
+
+    /// Set the number of texels each texture's edge is extended by in the
+    /// atlas, which keeps filtering from bleeding between textures.
+    ///
+    /// Defaults to 2.
+    #[must_use]
+    pub fn with_padding(mut self, texels: u32) -> Self {
+        self.padding = texels;
+        self
+    }
+
+    /// Merge the meshes of `nodes`.
+    ///
+    /// Each node drops the octants holding descendants that are also in
+    /// `nodes`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a texture cannot be decoded, or
+    /// [`Error::Unsupported`] if the atlas would exceed [`MAX_ATLAS_SIZE`]
+    /// texels a side.
+    pub fn merge(&self, nodes: &[&Node]) -> Result<MergedMesh> {
+        let masks = child_octant_masks(nodes.iter().map(|node| node.path.as_str()));
+        let mut parts = Vec::new();
+        for node in nodes {
+            let mask = masks.get(&node.path).copied().unwrap_or(0);
+            for mesh in &node.meshes {
+                let mut geometry = MeshGeometry::from_mesh(node, mesh, &self.frame);
+                geometry.mask_octants(mask);
+                if !geometry.is_empty() {
+                    parts.push((geometry, mesh_image(mesh)?));
+                }
+            }
+        }
+
+        let sizes: Vec<UVec2> = parts
+            .iter()
+            .map(|(_, image)| UVec2::new(image.width(), image.height()))
+            .collect();
+        let (atlas_size, offsets) = pack(&sizes, self.padding);
+        if atlas_size.max_element() > MAX_ATLAS_SIZE {
+            return Err(Error::Unsupported {
+                format: "texture atlas",
+                detail: format!(
+                    "{}x{} texels exceed the limit of {MAX_ATLAS_SIZE}",
+                    atlas_size.x, atlas_size.y
+                ),
+            });
+        }
+        let mut atlas = RgbaImage::new(atlas_size.x, atlas_size.y);
+
+        let mut welder = Welder::new(self.weld_tolerance);
+        let mut merged = MeshGeometry::default();
+        let mut vertices = HashMap::new();
+        // Welded point of each merged vertex.
+        let mut points = Vec::new();
+        for ((geometry, image), offset) in parts.iter().zip(offsets) {
+            blit_padded(&mut atlas, image, offset, self.padding);
+            let origin = (offset + self.padding).as_vec2();
+            let size = UVec2::new(image.width(), image.height()).as_vec2();
+            let remap: Vec<u32> = geometry
+                .positions
+                .iter()
+                .zip(&geometry.uvs)
+                .map(|(&position, &uv)| {
+                    let point = welder.weld(position);
+                    let uv =
+                        (origin + uv.clamp(Vec2::ZERO, Vec2::ONE) * size) / atlas_size.as_vec2();
+                    *vertices
+                        .entry((point, uv.x.to_bits(), uv.y.to_bits()))
+                        .or_insert_with(|| {
+                            merged.positions.push(welder.points[point as usize]);
+                            merged.uvs.push(uv);
+                            points.push(point);
+                            u32::try_from(merged.positions.len() - 1)
+                                .expect("vertex count fits in u32")
+                        })
+                })
+                .collect();
+            for triangle in geometry.triangles.chunks_exact(3) {
+                let corners = [0, 1, 2].map(|k| remap[triangle[k] as usize]);
+                let points = corners.map(|i| points[i as usize]);
+                // Welding can collapse small triangles.
+                if points[0] != points[1] && points[1] != points[2] && points[2] != points[0] {
+                    merged.triangles.extend(corners);
+                }
+            }
+        }
+
+        Ok(MergedMesh {
+            frame: self.frame,
+            geometry: merged,
+            atlas,
+        })
+    }
+}
+
+/// Snaps positions to the first position seen within a tolerance.
+struct Welder {
+    tolerance: f64,
+    /// Size of the cells positions are bucketed in.
+    cell: f64,
+    /// Distinct positions, in the order first seen.
+    points: Vec<DVec3>,
+    cells: HashMap<[i64; 3], Vec<u32>>,
+}
+
+impl Welder {
+    fn new(tolerance: f64) -> Self {
+        Self {
+            tolerance,
+            // Identical positions share a cell however small it is, but
+            // it must not be zero.
+            cell: tolerance.max(1e-6),
+            points: Vec::new(),
+            cells: HashMap::new(),
+        }
+    }
+
+    #[allow(clippy::cast_possible_truncation)] // Cells of frame coordinates fit in i64.
+    fn key(&self, position: DVec3) -> [i64; 3] {
+        (position / self.cell).floor().to_array().map(|v| v as i64)
+    }
+
+    /// Get the index of the point `position` welds to.
+    fn weld(&mut self, position: DVec3) -> u32 {
+        let [x, y, z] = self.key(position);
+        // Points within the tolerance are at most one cell away.
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let Some(candidates) = self.cells.get(&[x + dx, y + dy, z + dz]) else {
+                        continue;
+                    };
+                    for &i in candidates {
+                        if self.points[i as usize].distance(position) <= self.tolerance {
+                            return i;
+                        }
+                    }
+                }
+            }
+        }
+        let index = u32::try_from(self.points.len()).expect("vertex count fits in u32");
+        self.points.push(position);
+        self.cells.entry([x, y, z]).or_default().push(index);
+        index
+    }
+}
+
+/// Pack rectangles of `sizes`, each grown by `padding` on every side, onto
+/// shelves, tallest first.
+///
+/// Returns the size of the atlas and the top-left corner of each padded
+/// rectangle.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)] // Atlas sizes are far below 2^24.
+fn pack(sizes: &[UVec2], padding: u32) -> (UVec2, Vec<UVec2>) {
+    let padded: Vec<UVec2> = sizes.iter().map(|&size| size + 2 * padding).collect();
+    let area: u64 = padded.iter().map(|size| u64::from(size.x * size.y)).sum();
+    let widest = padded.iter().map(|size| size.x).max().unwrap_or(1);
+    let width = ((area as f64).sqrt().ceil() as u32)
+        .next_power_of_two()
+        .max(widest);
+
+    let mut order: Vec<usize> = (0..padded.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(padded[i].y));
+    let mut offsets = vec![UVec2::ZERO; padded.len()];
+    let (mut cursor, mut shelf_height) = (UVec2::ZERO, 0);
+    for i in order {
+        if cursor.x + padded[i].x > width {
+            cursor = UVec2::new(0, cursor.y + shelf_height);
+            shelf_height = 0;
+        }
+        offsets[i] = cursor;
+        cursor.x += padded[i].x;
+        shelf_height = shelf_height.max(padded[i].y);
+    }
+    (UVec2::new(width, (cursor.y + shelf_height).max(1)), offsets)
+}
+
+/// Copy `image` into `atlas` at `offset` plus `padding`, repeating its edge
+/// texels into the padding.
+fn blit_padded(atlas: &mut RgbaImage, image: &RgbaImage, offset: UVec2, padding: u32) {
+    if image.width() == 0 || image.height() == 0 {
+        return;
+    }
+    for y in 0..image.height() + 2 * padding {
+        let source_y = y.saturating_sub(padding).min(image.height() - 1);
+        for x in 0..image.width() + 2 * padding {
+            let source_x = x.saturating_sub(padding).min(image.width() - 1);
+            atlas.put_pixel(
+                offset.x + x,
+                offset.y + y,
+                *image.get_pixel(source_x, source_y),
+            );
+        }
+    }
+}
+
+/// Merge the nodes covering `bounds` down to a resolution of `target`
+/// meters per texel.
+///
+/// Nodes are fetched from the root down until their meters per texel are
+/// no coarser than `target`; coarser nodes keep only the octants none of
+/// their fetched descendants cover.
+///
+/// # Errors
+///
+/// Returns an error if a fetch or the merge fails.
+pub async fn export_merged<C: Cache + 'static>(
+    client: &Client<C>,
+    bounds: GeoBounds,
+    target: f64,
+    merger: &MeshMerger,
+) -> Result<MergedMesh> {
+    let mut nodes = Vec::new();
+    visit_nodes(client, &bounds, target, |node| {
+        nodes.push(node.clone());
+        Ok(())
+    })
+    .await?;
+    merger.merge(&nodes.iter().collect::<Vec<_>>())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_node;
+    use crate::texture::sample_texel;
+
+    const CENTER: DVec3 = DVec3::new(6_378_137.0, 0.0, 0.0);
+
+    #[test]
+    fn test_weld_neighbors() {
+        // Two quads sharing their edge at x = 1, the second nudged by less
+        // than the tolerance.
+        let left = quad_node("01", CENTER);
+        let right = quad_node("02", CENTER + DVec3::new(2.0, 0.0, 0.004));
+        let merged = MeshMerger::new(Frame::ecef_offset(CENTER))
+            .with_padding(1)
+            .merge(&[&left, &right])
+            .unwrap();
+
+        let geometry = &merged.geometry;
+        assert_eq!(geometry.triangle_count(), 4);
+        // Each texture keeps its own vertices, but the shared edge's
+        // positions are identical.
+        assert_eq!(geometry.positions.len(), 8);
+        let mut points: Vec<[u64; 3]> = geometry
+            .positions
+            .iter()
+            .map(|p| p.to_array().map(f64::to_bits))
+            .collect();
+        points.sort_unstable();
+        points.dedup();
+        assert_eq!(points.len(), 6);
+
+        // Two 2x2 textures padded to 4x4 fit side by side.
+        assert_eq!(merged.atlas.dimensions(), (8, 4));
+        // Textures are red at u = 0 and green at u = 1.
+        for (&position, &uv) in geometry.positions.iter().zip(&geometry.uvs) {
+            let texel = sample_texel(&merged.atlas, uv);
+            if (position.x + 1.0).abs() < 1e-9 {
+                assert_eq!(texel, [255, 0, 0, 255]);
+            } else if (position.x - 3.0).abs() < 1e-9 {
+                assert_eq!(texel, [0, 255, 0, 255]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_mask_covered_octants() {
+        let mut parent = quad_node("0", CENTER);
+        parent.meshes[0].has_octant_data = true;
+        parent.meshes[0].vertices[3].w = 5;
+        let child = quad_node("05", CENTER + DVec3::Z * 5.0);
+        let merged = MeshMerger::new(Frame::ecef_offset(CENTER))
+            .merge(&[&parent, &child])
+            .unwrap();
+        // The parent's triangle touching octant 5 is gone.
+        assert_eq!(merged.geometry.triangle_count(), 3);
+        assert!(merged.geometry.octants.is_empty());
+
+        let merged = MeshMerger::new(Frame::ecef_offset(CENTER))
+            .merge(&[&parent])
+            .unwrap();
+        assert_eq!(merged.geometry.triangle_count(), 2);
+    }
+
+    #[test]
+    fn test_pack() {
+        let sizes = [UVec2::new(4, 2), UVec2::new(2, 6), UVec2::new(6, 6)];
+        let (size, offsets) = pack(&sizes, 1);
+        // 120 padded texels need a width of at least 11, rounded up to 16.
+        assert_eq!(size, UVec2::new(16, 12));
+        assert_eq!(offsets, [UVec2::new(0, 8), UVec2::ZERO, UVec2::new(4, 0)]);
+        assert_eq!(pack(&[], 0).0, UVec2::new(1, 1));
+    }
+}
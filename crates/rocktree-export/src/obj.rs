@@ -0,0 +1,267 @@
+//! Wavefront OBJ export.
+//!
+//! Writes an `.obj` file with one object per mesh, named after the node's
+//! octant path and the mesh's index, a `.mtl` file with one material per
+//! mesh, and each texture as an image file next to them. Positions are
+//! written in the [`Frame`]'s own Z-up axes.
+
+use rocktree::Node;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::geometry::{MeshGeometry, child_octant_masks};
+use crate::simplify::Simplifier;
+use crate::texture::{TextureEncoding, mesh_image};
+
+/// Writes nodes as an OBJ file with materials and textures.
+///
+/// ```ignore
+/// let exporter = ObjExporter::new(Frame::enu(origin)).with_octant_masking(true);
+/// exporter.write(&nodes, "out", "region")?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct ObjExporter {
+    frame: Frame,
+    texture_encoding: TextureEncoding,
+    octant_masking: bool,
+    simplifier: Option<Simplifier>,
+}
+
+/// Files written by [`ObjExporter::write`].
+#[derive(Debug, Clone, Default)]
+pub struct ObjOutput {
+    /// The OBJ file.
+    pub obj: PathBuf,
+    /// The material library.
+    pub mtl: PathBuf,
+    /// Texture images, one per exported mesh.
+    pub textures: Vec<PathBuf>,
+    /// Number of triangles written.
+    pub triangles: usize,
+}
+
+impl ObjExporter {
+    /// Create an exporter that positions geometry in `frame`.
+    #[must_use]
+    pub fn new(frame: Frame) -> Self {
+        Self {
+            frame,
+            texture_encoding: TextureEncoding::default(),
+            octant_masking: false,
+            simplifier: None,
+        }
+    }
+
+    /// Set how texture files are encoded.
+    #[must_use]
+    pub fn with_texture_encoding(mut self, encoding: TextureEncoding) -> Self {
+        self.texture_encoding = encoding;
+        self
+    }
+
+    /// Leave out the parts of nodes covered by their exported children.
+    ///
+    /// When exporting several levels of detail at once, parents and children
+    /// overlap. With masking, each node drops the octants whose child node is
+    /// also being exported, as the viewer does when rendering.
+    #[must_use]
+    pub fn with_octant_masking(mut self, enabled: bool) -> Self {
+        self.octant_masking = enabled;
+        self
+    }
+
+    /// Simplify each mesh after masking.
+    #[must_use]
+    pub fn with_simplifier(mut self, simplifier: Simplifier) -> Self {
+        self.simplifier = Some(simplifier);
+        self
+    }
+
+    /// Write `nodes` to `<name>.obj` and `<name>.mtl` in `dir`, with
+    /// textures named `<name>_<path>_<mesh>.<ext>`.
+    ///
+    /// `dir` is created if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a texture cannot be decoded or encoded, or if
+    /// creating the directory or writing a file fails.
+    pub fn write(&self, nodes: &[&Node], dir: impl AsRef<Path>, name: &str) -> Result<ObjOutput> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+        let masks = if self.octant_masking {
+            child_octant_masks(nodes.iter().map(|node| node.path.as_str()))
+        } else {
+            HashMap::default()
+        };
+
+        let mut output = ObjOutput {
+            obj: dir.join(format!("{name}.obj")),
+            mtl: dir.join(format!("{name}.mtl")),
+            ..ObjOutput::default()
+        };
+        let mut obj = create(&output.obj)?;
+        let mut mtl = create(&output.mtl)?;
+        let origin = self.frame.origin();
+        let header = format!(
+            "# rocktree-export {}, {} frame at ECEF {} {} {}\n",
+            env!("CARGO_PKG_VERSION"),
+            self.frame.kind().name(),
+            origin.x,
+            origin.y,
+            origin.z
+        );
+        write_all(&mut obj, &output.obj, header.as_bytes())?;
+        write_all(&mut mtl, &output.mtl, header.as_bytes())?;
+        write_all(
+            &mut obj,
+            &output.obj,
+            format!("mtllib {name}.mtl\n").as_bytes(),
+        )?;
+
+        // OBJ indices are 1-based and count vertices across the whole file.
+        let mut base = 1;
+        for node in nodes {
+            let mask = masks.get(&node.path).copied().unwrap_or(0);
+            for (i, mesh) in node.meshes.iter().enumerate() {
+                let mut geometry = MeshGeometry::from_mesh(node, mesh, &self.frame);
+                geometry.mask_octants(mask);
+                if let Some(simplifier) = &self.simplifier {
+                    simplifier.simplify(&mut geometry);
+                }
+                if geometry.is_empty() {
+                    continue;
+                }
+
+                let label = format!("{}_{i}", node.path);
+                let texture_name = format!("{name}_{label}.{}", self.texture_encoding.extension());
+                let texture_path = dir.join(&texture_name);
+                let texture = self.texture_encoding.encode(&mesh_image(mesh)?)?;
+                std::fs::write(&texture_path, texture).map_err(|e| Error::io(&texture_path, e))?;
+                output.textures.push(texture_path);
+
+                let material = format!(
+                    "\nnewmtl m_{label}\nKa 1 1 1\nKd 1 1 1\nKs 0 0 0\nillum 0\nmap_Kd {texture_name}\n"
+                );
+                write_all(&mut mtl, &output.mtl, material.as_bytes())?;
+                write_all(
+                    &mut obj,
+                    &output.obj,
+                    object(&label, &geometry, base).as_bytes(),
+                )?;
+                base += geometry.positions.len();
+                output.triangles += geometry.triangle_count();
+            }
+        }
+
+        obj.flush().map_err(|e| Error::io(&output.obj, e))?;
+        mtl.flush().map_err(|e| Error::io(&output.mtl, e))?;
+        Ok(output)
+    }
+}
+
+/// Format one mesh as an OBJ object whose first vertex has index `base`.
+fn object(label: &str, geometry: &MeshGeometry, base: usize) -> String {
+    let mut lines = vec![format!("\no {label}"), format!("usemtl m_{label}")];
+    lines.extend(
+        geometry
+            .positions
+            .iter()
+            .map(|p| format!("v {:.4} {:.4} {:.4}", p.x, p.y, p.z)),
+    );
+    // OBJ texture coordinates start at the bottom-left corner.
+    lines.extend(
+        geometry
+            .uvs
+            .iter()
+            .map(|uv| format!("vt {:.6} {:.6}", uv.x, 1.0 - uv.y)),
+    );
+    lines.extend(geometry.triangles.chunks_exact(3).map(|triangle| {
+        let [a, b, c] = [0, 1, 2].map(|k| triangle[k] as usize + base);
+        format!("f {a}/{a} {b}/{b} {c}/{c}")
+    }));
+    lines.push(String::new());
+    lines.join("\n")
+}
+
+fn create(path: &Path) -> Result<BufWriter<File>> {
+    File::create(path)
+        .map(BufWriter::new)
+        .map_err(|e| Error::io(path, e))
+}
+
+fn write_all(writer: &mut impl Write, path: &Path, data: &[u8]) -> Result<()> {
+    writer.write_all(data).map_err(|e| Error::io(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_node;
+    use glam::DVec3;
+
+    fn lines_starting<'a>(text: &'a str, prefix: &str) -> Vec<&'a str> {
+        text.lines()
+            .filter(|line| line.starts_with(prefix))
+            .collect()
+    }
+
+    #[test]
+    fn test_write_obj() {
+        let dir = tempfile::tempdir().unwrap();
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let parent = quad_node("0", center);
+        let child = quad_node("01", center + DVec3::Z * 5.0);
+        let output = ObjExporter::new(Frame::ecef_offset(center))
+            .with_texture_encoding(TextureEncoding::Png)
+            .write(&[&parent, &child], dir.path(), "region")
+            .unwrap();
+        assert_eq!(output.triangles, 4);
+        assert_eq!(output.textures.len(), 2);
+        assert!(output.textures[1].ends_with("region_01_0.png"));
+
+        let obj = std::fs::read_to_string(&output.obj).unwrap();
+        assert_eq!(lines_starting(&obj, "o "), ["o 0_0", "o 01_0"]);
+        assert_eq!(lines_starting(&obj, "v ")[0], "v -1.0000 -1.0000 0.0000");
+        assert_eq!(lines_starting(&obj, "v ")[4], "v -1.0000 -1.0000 5.0000");
+        assert_eq!(lines_starting(&obj, "vt ")[0], "vt 0.000000 1.000000");
+        // The child's faces refer to its own vertices.
+        assert_eq!(lines_starting(&obj, "f ")[2], "f 5/5 6/6 7/7");
+
+        let mtl = std::fs::read_to_string(&output.mtl).unwrap();
+        assert!(mtl.contains("newmtl m_01_0\n"));
+        assert!(mtl.contains("map_Kd region_01_0.png\n"));
+        let texture = image::open(&output.textures[0]).unwrap();
+        assert_eq!((texture.width(), texture.height()), (2, 2));
+    }
+
+    #[test]
+    fn test_octant_masking() {
+        let dir = tempfile::tempdir().unwrap();
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let mut parent = quad_node("0", center);
+        parent.meshes[0].has_octant_data = true;
+        for vertex in &mut parent.meshes[0].vertices {
+            vertex.w = 1;
+        }
+        let child = quad_node("01", center);
+
+        let exporter = ObjExporter::new(Frame::ecef_offset(center));
+        let output = exporter
+            .write(&[&parent, &child], dir.path(), "all")
+            .unwrap();
+        assert_eq!(output.triangles, 4);
+
+        let output = exporter
+            .with_octant_masking(true)
+            .write(&[&parent, &child], dir.path(), "masked")
+            .unwrap();
+        assert_eq!(output.triangles, 2);
+        let obj = std::fs::read_to_string(&output.obj).unwrap();
+        assert_eq!(lines_starting(&obj, "o "), ["o 01_0"]);
+    }
+}
@@ -0,0 +1,609 @@
+//! Orthophoto rendering.
+//!
+//! Textured meshes are rasterized on the CPU as seen from straight above,
+//! onto a latitude/longitude grid, a Web Mercator grid, or a grid in a
+//! local [`Frame`]'s horizontal plane. A depth buffer keeps the topmost
+//! surface at each pixel, and where nodes overlap the most detailed one is
+//! used. No GPU is needed.
+//!
+//! Images are written as RGBA GeoTIFF files, or as PNG files with a world
+//! file. Pixels no surface covers are transparent.
+
+use glam::{DVec2, DVec3, Vec2};
+use image::{ImageFormat, RgbaImage};
+use rocktree::{Cache, Client, GeoBounds, Geodetic, MeshLayer, Node};
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use tiff::encoder::colortype;
+
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::geometry::MeshGeometry;
+use crate::geotiff::{Crs, GeoTiff};
+use crate::raster::{
+    GridLayout, MAX_CELLS, METERS_PER_DEGREE, TERRAIN_LAYERS, cell_counts, for_each_sample,
+    node_level, visit_nodes, wrap_degrees,
+};
+use crate::texture::{mesh_image, sample_texel};
+use crate::xyz::{MERCATOR_EXTENT, from_web_mercator, to_web_mercator};
+
+/// How pixels map to positions on the globe.
+#[derive(Debug, Clone)]
+enum Projection {
+    /// Pixels are cells of a latitude/longitude grid.
+    Geographic {
+        bounds: GeoBounds,
+        layout: GridLayout,
+    },
+    /// Pixels are squares in the Web Mercator plane (EPSG:3857).
+    WebMercator {
+        /// Top-left corner of the image in meters.
+        origin: DVec2,
+        /// Size of a pixel along X and Y in meters.
+        pixel_size: DVec2,
+    },
+    /// Pixels are squares in the X/Y plane of a frame, viewed down its Z
+    /// axis.
+    Local {
+        frame: Frame,
+        /// Top-left corner of the image, at minimum X and maximum Y.
+        origin: DVec2,
+        /// Size of a pixel along X and Y.
+        pixel_size: DVec2,
+    },
+}
+
+/// An orthophoto being rendered from nodes.
+///
+/// ```ignore
+/// let mut ortho = Orthophoto::geographic(bounds, 0.00001)?;
+/// ortho.add_node(&node)?;
+/// ortho.write_png("ortho.png")?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct Orthophoto {
+    projection: Projection,
+    image: RgbaImage,
+    /// Height of the surface at each pixel.
+    depths: Vec<f64>,
+    /// Level of the node each pixel came from, or 0 if empty.
+    levels: Vec<u8>,
+    layers: Vec<MeshLayer>,
+}
+
+impl Orthophoto {
+    /// Create an empty orthophoto of `bounds` with pixels `resolution`
+    /// degrees across.
+    ///
+    /// The resolution is adjusted slightly so a whole number of pixels fits
+    /// the bounds.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Unsupported`] if `bounds` are empty or invalid,
+    /// `resolution` is not a positive number, or the image would have more
+    /// than [`MAX_CELLS`] pixels.
+    pub fn geographic(bounds: GeoBounds, resolution: f64) -> Result<Self> {
+        let layout = GridLayout::for_resolution(&bounds, resolution, "orthophoto")?;
+        Self::new(
+            Projection::Geographic { bounds, layout },
+            layout.width,
+            layout.height,
+        )
+    }
+
+    /// Create an empty orthophoto of the area from `min` to `max` in the X/Y
+    /// plane of `frame`, with pixels `resolution` meters across.
+    ///
+    /// In an [`enu`](Frame::enu) frame this is a top-down view with north
+    /// up. The resolution is adjusted slightly so a whole number of pixels
+    /// fits the area.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Unsupported`] if the area is empty or not finite,
+    /// `resolution` is not a positive number, or the image would have more
+    /// than [`MAX_CELLS`] pixels.
+    pub fn local(frame: Frame, min: DVec2, max: DVec2, resolution: f64) -> Result<Self> {
+        let size = (max - min).abs();
+        if !(size.is_finite() && size.min_element() > 0.0) {
+            return Err(Error::Unsupported {
+                format: "orthophoto",
+                detail: format!("empty or invalid area from {min} to {max}"),
+            });
+        }
+        let (width, height) = cell_counts(size, resolution, "orthophoto")?;
+        #[allow(clippy::cast_precision_loss)] // Pixel counts are far below 2^52.
+        let pixel_size = size / DVec2::new(width as f64, height as f64);
+        let origin = DVec2::new(min.x.min(max.x), min.y.max(max.y));
+        Self::new(
+            Projection::Local {
+                frame,
+                origin,
+                pixel_size,
+            },
+            width,
+            height,
+        )
+    }
+
+    /// Create an empty orthophoto of the area from `min` to `max` in the
+    /// Web Mercator plane (EPSG:3857), `width` by `height` pixels.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Unsupported`] if the image would have more than
+    /// [`MAX_CELLS`] pixels.
+    pub fn web_mercator(min: DVec2, max: DVec2, width: u32, height: u32) -> Result<Self> {
+        let (width, height) = (width.max(1), height.max(1));
+        let size = (max - min).abs();
+        let pixel_size = size / DVec2::new(f64::from(width), f64::from(height));
+        let origin = DVec2::new(min.x.min(max.x), min.y.max(max.y));
+        Self::new(
+            Projection::WebMercator { origin, pixel_size },
+            width as usize,
+            height as usize,
+        )
+    }
+
+    fn new(projection: Projection, width: usize, height: usize) -> Result<Self> {
+        let len = width.checked_mul(height).filter(|&len| len <= MAX_CELLS);
+        let (Some(len), Ok(image_width), Ok(image_height)) =
+            (len, u32::try_from(width), u32::try_from(height))
+        else {
+            return Err(Error::Unsupported {
+                format: "orthophoto",
+                detail: format!("{width}x{height} pixels is more than {MAX_CELLS}"),
+            });
+        };
+        Ok(Self {
+            projection,
+            image: RgbaImage::new(image_width, image_height),
+            depths: vec![f64::NEG_INFINITY; len],
+            levels: vec![0; len],
+            layers: MeshLayer::ALL.to_vec(),
+        })
+    }
+
+    /// Only render terrain, leaving out buildings and vegetation.
+    ///
+    /// Applies to nodes added afterwards.
+    #[must_use]
+    pub fn with_terrain_only(mut self, enabled: bool) -> Self {
+        self.layers = if enabled {
+            TERRAIN_LAYERS.to_vec()
+        } else {
+            MeshLayer::ALL.to_vec()
+        };
+        self
+    }
+
+    /// Get the rendered image.
+    #[must_use]
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    /// Check whether no surface has been rendered.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.levels.iter().all(|&level| level == 0)
+    }
+
+    /// Get the approximate size of a pixel in meters.
+    ///
+    /// For Web Mercator orthophotos this is the ground size at the center
+    /// latitude.
+    #[must_use]
+    pub fn pixel_size_meters(&self) -> f64 {
+        match &self.projection {
+            Projection::Geographic { layout, .. } => layout.lat_step * METERS_PER_DEGREE,
+            Projection::WebMercator { origin, pixel_size } => {
+                let center = *origin + *pixel_size * self.dimensions() * DVec2::new(0.5, -0.5);
+                let (_, latitude) = from_web_mercator(center);
+                pixel_size.min_element() * latitude.to_radians().cos()
+            }
+            Projection::Local { pixel_size, .. } => pixel_size.min_element(),
+        }
+    }
+
+    /// Get the area covered on the globe.
+    ///
+    /// For local orthophotos this is the box around the corners of the
+    /// image on the frame's X/Y plane.
+    #[must_use]
+    pub fn bounds(&self) -> GeoBounds {
+        match &self.projection {
+            Projection::Geographic { bounds, .. } => *bounds,
+            Projection::WebMercator { origin, pixel_size } => {
+                let size = *pixel_size * self.dimensions();
+                let (west, north) = from_web_mercator(*origin);
+                let (east, south) = from_web_mercator(*origin + size * DVec2::new(1.0, -1.0));
+                GeoBounds::new(south, west, north, east)
+            }
+            Projection::Local {
+                frame,
+                origin,
+                pixel_size,
+            } => {
+                let size = *pixel_size * self.dimensions();
+                let corners = [(0.0, 0.0), (1.0, 0.0), (0.0, -1.0), (1.0, -1.0)].map(|(x, y)| {
+                    let corner = *origin + size * DVec2::new(x, y);
+                    Geodetic::from_ecef(frame.to_ecef(corner.extend(0.0)))
+                });
+                let (mut south, mut west) = (f64::INFINITY, f64::INFINITY);
+                let (mut north, mut east) = (f64::NEG_INFINITY, f64::NEG_INFINITY);
+                for corner in corners {
+                    south = south.min(corner.latitude);
+                    north = north.max(corner.latitude);
+                    west = west.min(corner.longitude);
+                    east = east.max(corner.longitude);
+                }
+                GeoBounds::new(south, west, north, east)
+            }
+        }
+    }
+
+    fn dimensions(&self) -> DVec2 {
+        DVec2::new(
+            f64::from(self.image.width()),
+            f64::from(self.image.height()),
+        )
+    }
+
+    /// Render the meshes of `node`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if a texture cannot be decoded.
+    pub fn add_node(&mut self, node: &Node) -> Result<()> {
+        let level = node_level(node);
+        let frame = match &self.projection {
+            Projection::Geographic { .. } | Projection::WebMercator { .. } => {
+                Frame::ecef_offset(DVec3::ZERO)
+            }
+            Projection::Local { frame, .. } => *frame,
+        };
+        for mesh in &node.meshes {
+            let geometry = MeshGeometry::from_mesh_layers(node, mesh, &frame, &self.layers);
+            if geometry.is_empty() {
+                continue;
+            }
+            let texture = mesh_image(mesh)?;
+            // Pixel coordinates and height of each vertex.
+            let vertices: Vec<(DVec2, f64)> = geometry
+                .positions
+                .iter()
+                .map(|&position| self.project(position))
+                .collect();
+            for triangle in geometry.triangles.chunks_exact(3) {
+                let corners = [0, 1, 2].map(|k| triangle[k] as usize);
+                let uvs = corners.map(|i| geometry.uvs[i]);
+                let depths = corners.map(|i| vertices[i].1);
+                for projected in self.unwrap(corners.map(|i| vertices[i].0)) {
+                    self.fill_triangle(projected, depths, uvs, &texture, level);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Get the pixel coordinates and height of a position in the geometry
+    /// frame, with pixel centers at integer coordinates.
+    fn project(&self, position: DVec3) -> (DVec2, f64) {
+        match &self.projection {
+            Projection::Geographic { .. } | Projection::WebMercator { .. } => {
+                let position = Geodetic::from_ecef(position);
+                // Sample coordinates are found per triangle by `unwrap`.
+                (
+                    DVec2::new(position.longitude, position.latitude),
+                    position.height,
+                )
+            }
+            Projection::Local {
+                origin, pixel_size, ..
+            } => {
+                let offset = (position.truncate() - *origin) * DVec2::new(1.0, -1.0);
+                (offset / *pixel_size - 0.5, position.z)
+            }
+        }
+    }
+
+    /// Convert a projected triangle to the pixel triangles to fill.
+    fn unwrap(&self, triangle: [DVec2; 3]) -> Vec<[DVec2; 3]> {
+        match &self.projection {
+            Projection::Geographic { layout, .. } => layout.project(triangle).to_vec(),
+            Projection::WebMercator { origin, pixel_size } => {
+                // Keep the triangle contiguous across the antimeridian, and
+                // also place it a full turn away if it extends beyond.
+                let first = triangle[0].x;
+                let meters = triangle.map(|position| {
+                    let longitude = first + wrap_degrees(position.x - first);
+                    to_web_mercator(longitude, position.y)
+                });
+                let to_pixels = |shift: f64| {
+                    meters.map(|position| {
+                        let offset = (position - *origin) * DVec2::new(1.0, -1.0);
+                        (offset + DVec2::new(shift, 0.0)) / *pixel_size - 0.5
+                    })
+                };
+                let turn = 2.0 * MERCATOR_EXTENT;
+                if meters.iter().any(|position| position.x > MERCATOR_EXTENT) {
+                    vec![to_pixels(0.0), to_pixels(-turn)]
+                } else if meters.iter().any(|position| position.x < -MERCATOR_EXTENT) {
+                    vec![to_pixels(0.0), to_pixels(turn)]
+                } else {
+                    vec![to_pixels(0.0)]
+                }
+            }
+            Projection::Local { .. } => vec![triangle],
+        }
+    }
+
+    fn fill_triangle(
+        &mut self,
+        triangle: [DVec2; 3],
+        depths: [f64; 3],
+        uvs: [Vec2; 3],
+        texture: &RgbaImage,
+        level: u8,
+    ) {
+        let width = self.image.width() as usize;
+        let height = self.image.height() as usize;
+        for_each_sample(triangle, width, height, |i, weights| {
+            let depth = weights[0] * depths[0] + weights[1] * depths[1] + weights[2] * depths[2];
+            let existing = self.levels[i];
+            if level < existing || (level == existing && depth <= self.depths[i]) {
+                return;
+            }
+            self.levels[i] = level;
+            self.depths[i] = depth;
+            #[allow(clippy::cast_possible_truncation)] // Weights are within [0, 1].
+            let uv = uvs[0] * weights[0] as f32
+                + uvs[1] * weights[1] as f32
+                + uvs[2] * weights[2] as f32;
+            let [red, green, blue, _] = sample_texel(texture, uv);
+            #[allow(clippy::cast_possible_truncation)] // Indices come from the image size.
+            let (x, y) = ((i % width) as u32, (i / width) as u32);
+            self.image
+                .put_pixel(x, y, image::Rgba([red, green, blue, 255]));
+        });
+    }
+
+    /// Get the georeferencing of the image.
+    fn georeference(&self) -> GeoTiff {
+        let (origin, pixel_size, crs) = match &self.projection {
+            Projection::Geographic { bounds, layout } => (
+                DVec2::new(bounds.west, bounds.north),
+                DVec2::new(layout.lon_step, layout.lat_step),
+                Crs::Wgs84,
+            ),
+            Projection::WebMercator { origin, pixel_size } => {
+                (*origin, *pixel_size, Crs::WebMercator)
+            }
+            Projection::Local {
+                origin, pixel_size, ..
+            } => (*origin, *pixel_size, Crs::LocalMeters),
+        };
+        GeoTiff {
+            width: self.image.width() as usize,
+            height: self.image.height() as usize,
+            origin,
+            pixel_size,
+            crs,
+            ellipsoidal_heights: false,
+            nodata: None,
+        }
+    }
+
+    /// Encode the image as an RGBA GeoTIFF.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the image is too large for a GeoTIFF.
+    pub fn to_geotiff(&self) -> Result<Vec<u8>> {
+        self.georeference()
+            .encode::<colortype::RGBA8>(self.image.as_raw())
+    }
+
+    /// Write the image as an RGBA GeoTIFF at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encoding or writing the file fails.
+    pub fn write_geotiff(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_geotiff()?).map_err(|e| Error::io(path, e))
+    }
+
+    /// Get the contents of a world file locating the image.
+    ///
+    /// World files give the pixel size and the position of the center of
+    /// the top-left pixel, in degrees for geographic orthophotos, in meters
+    /// for Web Mercator ones, and in frame coordinates for local ones.
+    #[must_use]
+    pub fn world_file(&self) -> String {
+        let georeference = self.georeference();
+        let size = georeference.pixel_size;
+        let center = georeference.origin + size * DVec2::new(0.5, -0.5);
+        [size.x, 0.0, 0.0, -size.y, center.x, center.y]
+            .map(|value| format!("{value}\n"))
+            .concat()
+    }
+
+    /// Write the image as a PNG at `path`, with a world file next to it
+    /// named with a `.pgw` extension.
+    ///
+    /// Returns the path of the world file.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encoding the image or writing either file fails.
+    pub fn write_png(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
+        let path = path.as_ref();
+        let mut png = Cursor::new(Vec::new());
+        self.image.write_to(&mut png, ImageFormat::Png)?;
+        std::fs::write(path, png.into_inner()).map_err(|e| Error::io(path, e))?;
+        let world = path.with_extension("pgw");
+        std::fs::write(&world, self.world_file()).map_err(|e| Error::io(&world, e))?;
+        Ok(world)
+    }
+}
+
+/// Render the nodes covering `ortho` at its resolution.
+///
+/// Nodes are fetched from the root down until their meters per texel are
+/// no coarser than the orthophoto's pixels.
+///
+/// # Errors
+///
+/// Returns an error if a fetch fails or a texture cannot be decoded.
+pub async fn export_orthophoto<C: Cache + 'static>(
+    client: &Client<C>,
+    mut ortho: Orthophoto,
+) -> Result<Orthophoto> {
+    let (bounds, target) = (ortho.bounds(), ortho.pixel_size_meters());
+    visit_nodes(client, &bounds, target, |node| ortho.add_node(node)).await?;
+    Ok(ortho)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_node;
+    use crate::raster::tests::terrain_node;
+    use tiff::decoder::{Decoder, DecodingResult};
+
+    fn solid_node(path: &str, bounds: &GeoBounds, height: f64, color: [u8; 4]) -> Node {
+        let mut node = terrain_node(path, bounds, height);
+        node.meshes[0].texture_data = color.repeat(4);
+        node
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+    const GREEN: [u8; 4] = [0, 255, 0, 255];
+
+    #[test]
+    fn test_depth_and_detail() {
+        let bounds = GeoBounds::new(10.0, 20.0, 10.01, 20.01);
+        let mut ortho = Orthophoto::geographic(bounds, 0.001).unwrap();
+        let ground = GeoBounds::new(9.99, 19.99, 10.02, 20.006);
+        let roof = GeoBounds::new(10.002, 20.002, 10.004, 20.004);
+        ortho
+            .add_node(&solid_node("01", &ground, 10.0, RED))
+            .unwrap();
+        ortho
+            .add_node(&solid_node("02", &roof, 30.0, BLUE))
+            .unwrap();
+        // A coarser node does not cover finer ones, even above them.
+        ortho
+            .add_node(&solid_node("0", &bounds, 90.0, GREEN))
+            .unwrap();
+        // A lower surface at the same level is hidden.
+        ortho
+            .add_node(&solid_node("03", &roof, 20.0, GREEN))
+            .unwrap();
+
+        let image = ortho.image();
+        assert_eq!(image.dimensions(), (10, 10));
+        assert_eq!(image.get_pixel(0, 0).0, RED);
+        // Row 6 is centered 10.0035 degrees north.
+        assert_eq!(image.get_pixel(3, 6).0, BLUE);
+        assert_eq!(image.get_pixel(9, 9).0, GREEN);
+    }
+
+    #[test]
+    fn test_local_png_and_world_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let frame = Frame::ecef_offset(center);
+        let mut ortho =
+            Orthophoto::local(frame, DVec2::splat(-2.0), DVec2::splat(2.0), 0.5).unwrap();
+        ortho.add_node(&quad_node("0", center)).unwrap();
+
+        let image = ortho.image();
+        assert_eq!(image.dimensions(), (8, 8));
+        assert_eq!(image.get_pixel(0, 0).0[3], 0);
+        // The texture is red on the left and green on the right.
+        assert_eq!(image.get_pixel(2, 3).0, RED);
+        assert_eq!(image.get_pixel(5, 3).0, GREEN);
+
+        let path = dir.path().join("ortho.png");
+        let world = ortho.write_png(&path).unwrap();
+        assert!(world.ends_with("ortho.pgw"));
+        assert_eq!(
+            std::fs::read_to_string(world).unwrap(),
+            "0.5\n0\n0\n-0.5\n-1.75\n1.75\n"
+        );
+        let png = image::open(&path).unwrap().to_rgba8();
+        assert_eq!(&png, image);
+    }
+
+    #[test]
+    fn test_web_mercator() {
+        let min = to_web_mercator(20.0, 10.0);
+        let max = to_web_mercator(20.01, 10.01);
+        let mut ortho = Orthophoto::web_mercator(min, max, 8, 8).unwrap();
+        assert!(ortho.is_empty());
+        let bounds = ortho.bounds();
+        assert!((bounds.south - 10.0).abs() < 1e-9 && (bounds.east - 20.01).abs() < 1e-9);
+        // Pixels span 0.01 / 8 degrees of longitude.
+        let across = 0.01 / 8.0 * METERS_PER_DEGREE * 10.005_f64.to_radians().cos();
+        assert!((ortho.pixel_size_meters() - across).abs() < 0.5);
+
+        let ground = GeoBounds::new(9.99, 19.99, 10.02, 20.005);
+        ortho
+            .add_node(&solid_node("0", &ground, 0.0, BLUE))
+            .unwrap();
+        assert!(!ortho.is_empty());
+        assert_eq!(ortho.image().get_pixel(0, 7).0, BLUE);
+        assert_eq!(ortho.image().get_pixel(7, 0).0[3], 0);
+
+        let mut decoder = Decoder::new(Cursor::new(ortho.to_geotiff().unwrap())).unwrap();
+        let keys = decoder
+            .get_tag_u16_vec(tiff::tags::Tag::GeoKeyDirectoryTag)
+            .unwrap();
+        assert_eq!(keys[12..16], [3072, 0, 1, 3857]);
+    }
+
+    #[test]
+    fn test_rejects_invalid_sizes() {
+        let bounds = GeoBounds::new(10.0, 20.0, 10.01, 20.01);
+        let frame = Frame::ecef_offset(DVec3::new(6_378_137.0, 0.0, 0.0));
+        let results = [
+            Orthophoto::geographic(bounds, 0.0),
+            Orthophoto::geographic(bounds, 1e-12),
+            Orthophoto::geographic(GeoBounds::new(10.0, 20.0, 10.0, 20.01), 0.001),
+            Orthophoto::local(frame, DVec2::ZERO, DVec2::ONE, f64::NAN),
+            Orthophoto::local(frame, DVec2::ZERO, DVec2::splat(1e6), 1e-6),
+            Orthophoto::local(frame, DVec2::ZERO, DVec2::new(1.0, 0.0), 0.1),
+            Orthophoto::web_mercator(DVec2::ZERO, DVec2::ONE, u32::MAX, u32::MAX),
+        ];
+        for result in results {
+            let err = result.unwrap_err();
+            assert!(matches!(err, Error::Unsupported { .. }), "{err}");
+        }
+    }
+
+    #[test]
+    fn test_geotiff() {
+        let bounds = GeoBounds::new(10.0, 20.0, 10.01, 20.02);
+        let mut ortho = Orthophoto::geographic(bounds, 0.001).unwrap();
+        ortho
+            .add_node(&solid_node("0", &bounds, 0.0, BLUE))
+            .unwrap();
+
+        let mut decoder = Decoder::new(Cursor::new(ortho.to_geotiff().unwrap())).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (20, 10));
+        let tiepoint = decoder
+            .get_tag_f64_vec(tiff::tags::Tag::ModelTiepointTag)
+            .unwrap();
+        assert_eq!(tiepoint[3..5], [20.0, 10.01]);
+        let DecodingResult::U8(pixels) = decoder.read_image().unwrap() else {
+            panic!("expected 8-bit samples");
+        };
+        assert_eq!(pixels.len(), 20 * 10 * 4);
+        assert_eq!(pixels[4 * 25..4 * 26], BLUE);
+    }
+}
@@ -0,0 +1,87 @@
+//! Binary PLY point cloud export.
+//!
+//! Points are written as little-endian `double` coordinates, so absolute
+//! ECEF positions keep millimeter precision, with `uchar` colors.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::error::{Error, Result};
+use crate::points::PointCloud;
+
+/// Write `cloud` to a binary little-endian PLY file at `path`.
+///
+/// # Errors
+///
+/// Returns an error if writing the file fails.
+pub fn write_ply(cloud: &PointCloud, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let origin = cloud.frame().origin();
+    let header = [
+        "ply".to_string(),
+        "format binary_little_endian 1.0".to_string(),
+        format!("comment rocktree-export {}", env!("CARGO_PKG_VERSION")),
+        format!(
+            "comment {} frame at ECEF {} {} {}",
+            cloud.frame().kind().name(),
+            origin.x,
+            origin.y,
+            origin.z
+        ),
+        format!("element vertex {}", cloud.len()),
+        "property double x".to_string(),
+        "property double y".to_string(),
+        "property double z".to_string(),
+        "property uchar red".to_string(),
+        "property uchar green".to_string(),
+        "property uchar blue".to_string(),
+        "end_header\n".to_string(),
+    ]
+    .join("\n");
+
+    let mut writer = File::create(path)
+        .map(BufWriter::new)
+        .map_err(|e| Error::io(path, e))?;
+    let mut write = |data: &[u8]| writer.write_all(data).map_err(|e| Error::io(path, e));
+    write(header.as_bytes())?;
+    for point in cloud.points() {
+        for value in point.position.to_array() {
+            write(&value.to_le_bytes())?;
+        }
+        write(&point.color)?;
+    }
+    writer.flush().map_err(|e| Error::io(path, e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frame::Frame;
+    use crate::geometry::tests::quad_node;
+    use glam::DVec3;
+
+    #[test]
+    fn test_write_ply() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("points.ply");
+        let mut cloud = PointCloud::new(Frame::ecef_offset(DVec3::ZERO));
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        cloud.add_node(&quad_node("0", center)).unwrap();
+        write_ply(&cloud, &path).unwrap();
+
+        let data = std::fs::read(&path).unwrap();
+        let end = b"end_header\n";
+        let body = data.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+        let header = std::str::from_utf8(&data[..body]).unwrap();
+        assert!(header.starts_with("ply\nformat binary_little_endian 1.0\n"));
+        assert!(header.contains("\nelement vertex 4\n"));
+        assert!(header.contains("\ncomment ecef-offset frame at ECEF 0 0 0\n"));
+
+        let records = &data[body..];
+        assert_eq!(records.len(), 4 * 27);
+        let x = f64::from_le_bytes(records[..8].try_into().unwrap());
+        assert!((x - (center.x - 1.0)).abs() < 1e-9);
+        assert_eq!(records[24..27], [255, 0, 0]);
+    }
+}
@@ -0,0 +1,293 @@
+//! Sampling point clouds from meshes.
+//!
+//! Points are taken either at mesh vertices or spread uniformly over the
+//! mesh surface, and colored from the mesh texture. Positions are in the
+//! [`Frame`] of the [`PointCloud`]; use `Frame::ecef_offset(DVec3::ZERO)`
+//! for plain ECEF coordinates.
+//!
+//! See [`ply`](crate::ply) and [`las`](crate::las) for writing the points.
+
+use glam::{DVec2, DVec3};
+use image::RgbaImage;
+use rocktree::Node;
+
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::geometry::MeshGeometry;
+use crate::texture::{mesh_image, sample_texel};
+
+/// Most points [`PointCloud::add_node`] samples from the surface of one mesh.
+pub const MAX_MESH_SAMPLES: u32 = 1 << 24;
+
+/// Where points are placed on a mesh.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum PointSampling {
+    /// One point per mesh vertex.
+    #[default]
+    Vertices,
+    /// Points spread uniformly over the surface.
+    Surface {
+        /// Points per square meter; must be finite and positive.
+        density: f64,
+    },
+}
+
+/// A colored point.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Point {
+    /// Position in the cloud's frame.
+    pub position: DVec3,
+    /// Color sampled from the texture.
+    pub color: [u8; 3],
+}
+
+/// Points sampled from one or more nodes.
+///
+/// ```ignore
+/// let mut cloud = PointCloud::new(Frame::enu(origin))
+///     .with_sampling(PointSampling::Surface { density: 4.0 });
+/// cloud.add_node(&node)?;
+/// rocktree_export::write_las(&cloud, "region.las")?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct PointCloud {
+    frame: Frame,
+    sampling: PointSampling,
+    points: Vec<Point>,
+    /// Fractional points owed from previous triangles.
+    carry: f64,
+    /// Index into the low-discrepancy sequence of surface samples.
+    sequence: u64,
+}
+
+impl PointCloud {
+    /// Create an empty point cloud positioned in `frame`.
+    #[must_use]
+    pub fn new(frame: Frame) -> Self {
+        Self {
+            frame,
+            sampling: PointSampling::default(),
+            points: Vec::new(),
+            carry: 0.0,
+            sequence: 0,
+        }
+    }
+
+    /// Set where points are placed on meshes added afterwards.
+    #[must_use]
+    pub fn with_sampling(mut self, sampling: PointSampling) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    /// Get the frame point positions are in.
+    #[must_use]
+    pub fn frame(&self) -> &Frame {
+        &self.frame
+    }
+
+    /// Get the points.
+    #[must_use]
+    pub fn points(&self) -> &[Point] {
+        &self.points
+    }
+
+    /// Get the number of points.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.points.len()
+    }
+
+    /// Whether the cloud has no points.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.points.is_empty()
+    }
+
+    /// Get the smallest box containing all points, or `None` if empty.
+    #[must_use]
+    pub fn bounds(&self) -> Option<(DVec3, DVec3)> {
+        let first = self.points.first()?.position;
+        Some(
+            self.points
+                .iter()
+                .fold((first, first), |(min, max), point| {
+                    (min.min(point.position), max.max(point.position))
+                }),
+        )
+    }
+
+    /// Sample points from all meshes of `node`, returning how many were
+    /// added.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Unsupported`] if the surface density is not finite
+    /// and positive or would place more than [`MAX_MESH_SAMPLES`] points on
+    /// a mesh, or an error if a texture cannot be decoded.
+    pub fn add_node(&mut self, node: &Node) -> Result<usize> {
+        if let PointSampling::Surface { density } = self.sampling
+            && !(density.is_finite() && density > 0.0)
+        {
+            return Err(Error::Unsupported {
+                format: "point cloud",
+                detail: format!("invalid surface density {density}"),
+            });
+        }
+        let before = self.points.len();
+        for mesh in &node.meshes {
+            let geometry = MeshGeometry::from_mesh(node, mesh, &self.frame);
+            if geometry.is_empty() {
+                continue;
+            }
+            let image = mesh_image(mesh)?;
+            match self.sampling {
+                PointSampling::Vertices => {
+                    self.points
+                        .extend(geometry.positions.iter().zip(&geometry.uvs).map(
+                            |(&position, &uv)| Point {
+                                position,
+                                color: rgb(sample_texel(&image, uv)),
+                            },
+                        ));
+                }
+                PointSampling::Surface { density } => {
+                    self.sample_surface(&geometry, &image, density)?;
+                }
+            }
+        }
+        Ok(self.points.len() - before)
+    }
+
+    /// Spread points over the triangles of `geometry`.
+    ///
+    /// Each triangle gets points in proportion to its area, carrying the
+    /// fractional remainder over to the next so small triangles are not
+    /// lost. Positions within a triangle follow the R2 low-discrepancy
+    /// sequence, which covers it more evenly than random samples and keeps
+    /// output reproducible.
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // Counts are non-negative.
+    fn sample_surface(
+        &mut self,
+        geometry: &MeshGeometry,
+        image: &RgbaImage,
+        density: f64,
+    ) -> Result<()> {
+        let corners = |triangle: &[u32]| [0, 1, 2].map(|k| triangle[k] as usize);
+        let area = |[pa, pb, pc]: [DVec3; 3]| (pb - pa).cross(pc - pa).length() / 2.0;
+        let total: f64 = geometry
+            .triangles
+            .chunks_exact(3)
+            .map(|triangle| area(corners(triangle).map(|i| geometry.positions[i])))
+            .sum();
+        let expected = self.carry + total * density;
+        if expected > f64::from(MAX_MESH_SAMPLES) {
+            return Err(Error::Unsupported {
+                format: "point cloud",
+                detail: format!(
+                    "density {density} would sample {expected:.0} points from one mesh, \
+                     more than {MAX_MESH_SAMPLES}"
+                ),
+            });
+        }
+
+        for triangle in geometry.triangles.chunks_exact(3) {
+            let corners = corners(triangle);
+            let [pa, pb, pc] = corners.map(|i| geometry.positions[i]);
+            let [ta, tb, tc] = corners.map(|i| geometry.uvs[i]);
+            self.carry += area([pa, pb, pc]) * density;
+            let count = self.carry.floor();
+            self.carry -= count;
+            for _ in 0..count as u64 {
+                let mut r = r2(self.sequence);
+                self.sequence += 1;
+                // Fold samples from the far half of the parallelogram back
+                // into the triangle.
+                if r.x + r.y > 1.0 {
+                    r = DVec2::ONE - r;
+                }
+                let position = pa + (pb - pa) * r.x + (pc - pa) * r.y;
+                let (s, t) = (r.x as f32, r.y as f32);
+                let uv = ta + (tb - ta) * s + (tc - ta) * t;
+                self.points.push(Point {
+                    position,
+                    color: rgb(sample_texel(image, uv)),
+                });
+            }
+        }
+        Ok(())
+    }
+}
+
+fn rgb([red, green, blue, _]: [u8; 4]) -> [u8; 3] {
+    [red, green, blue]
+}
+
+/// Get the `n`th point of the R2 sequence in the unit square.
+#[allow(clippy::cast_precision_loss)] // Only the fractional part matters.
+fn r2(n: u64) -> DVec2 {
+    // The plastic number, the 2D generalization of the golden ratio.
+    const G: f64 = 1.324_717_957_244_746;
+    let step = DVec2::new(1.0 / G, 1.0 / (G * G));
+    (DVec2::splat(0.5) + step * (n + 1) as f64).fract()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_node;
+
+    #[test]
+    fn test_vertex_sampling() {
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let mut cloud = PointCloud::new(Frame::ecef_offset(center));
+        assert_eq!(cloud.add_node(&quad_node("0", center)).unwrap(), 4);
+        let points = cloud.points();
+        assert_eq!(points[0].position, DVec3::new(-1.0, -1.0, 0.0));
+        assert_eq!(points[0].color, [255, 0, 0]);
+        assert_eq!(points[1].color, [0, 255, 0]);
+        assert_eq!(
+            cloud.bounds(),
+            Some((DVec3::new(-1.0, -1.0, 0.0), DVec3::new(1.0, 1.0, 0.0)))
+        );
+    }
+
+    #[test]
+    fn test_rejects_invalid_density() {
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let node = quad_node("0", center);
+        for density in [0.0, -1.0, f64::NAN, f64::INFINITY, 1e12] {
+            let mut cloud = PointCloud::new(Frame::ecef_offset(center))
+                .with_sampling(PointSampling::Surface { density });
+            let err = cloud.add_node(&node).unwrap_err();
+            assert!(matches!(err, Error::Unsupported { .. }), "{err}");
+            assert!(cloud.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_surface_sampling() {
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let mut cloud = PointCloud::new(Frame::ecef_offset(center))
+            .with_sampling(PointSampling::Surface { density: 25.0 });
+        // The quad covers 4 square meters.
+        assert_eq!(cloud.add_node(&quad_node("0", center)).unwrap(), 100);
+        let points = cloud.points();
+        assert!(
+            points.iter().all(|point| {
+                point.position.abs().max_element() <= 1.0 && point.position.z == 0.0
+            })
+        );
+        // Both halves of the texture are sampled, in proportion to area.
+        let red = points.iter().filter(|p| p.color == [255, 0, 0]).count();
+        assert!((40..=60).contains(&red), "{red} red points");
+        // Points spread over all four quadrants.
+        for (sx, sy) in [(-1.0, -1.0), (1.0, -1.0), (-1.0, 1.0), (1.0, 1.0)] {
+            let count = points
+                .iter()
+                .filter(|p| p.position.x * sx > 0.0 && p.position.y * sy > 0.0)
+                .count();
+            assert!((15..=35).contains(&count), "{count} points in quadrant");
+        }
+    }
+}
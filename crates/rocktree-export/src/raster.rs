@@ -0,0 +1,559 @@
+//! Rasterizing mesh heights onto latitude/longitude grids.
+
+use glam::{DVec2, DVec3};
+use rocktree::{
+    Cache, Client, GeoBounds, Geodetic, MeshLayer, Node, NodeRequest, WalkStep, walk_region,
+};
+
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::geometry::MeshGeometry;
+
+/// The layers holding terrain rather than buildings and vegetation.
+pub const TERRAIN_LAYERS: [MeshLayer; 2] =
+    [MeshLayer::TerrainBelowWater, MeshLayer::TerrainAboveWater];
+
+/// Approximate meters per degree of latitude.
+pub(crate) const METERS_PER_DEGREE: f64 = 111_320.0;
+
+/// Most cells in a raster sized by its resolution, such as a [`Dem`](crate::Dem).
+pub const MAX_CELLS: usize = 1 << 28;
+
+/// Placement of samples at regularly spaced latitudes and longitudes.
+///
+/// Rows run from north to south and columns from west to east.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct GridLayout {
+    /// Latitude of the first row.
+    pub north: f64,
+    /// Longitude of the first column, possibly beyond 180 degrees.
+    pub west: f64,
+    pub lat_step: f64,
+    pub lon_step: f64,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl GridLayout {
+    /// Place the outermost samples on the edges of `bounds`.
+    #[allow(clippy::cast_precision_loss)] // Grid sizes are far below 2^52.
+    pub(crate) fn edge_samples(bounds: &GeoBounds, width: usize, height: usize) -> Self {
+        let (width, height) = (width.max(2), height.max(2));
+        Self {
+            north: bounds.north,
+            west: bounds.west,
+            lat_step: (bounds.north - bounds.south) / (height - 1) as f64,
+            lon_step: longitude_span(bounds) / (width - 1) as f64,
+            width,
+            height,
+        }
+    }
+
+    /// Divide `bounds` into cells with a sample at the center of each.
+    #[allow(clippy::cast_precision_loss)] // Grid sizes are far below 2^52.
+    pub(crate) fn cell_samples(bounds: &GeoBounds, width: usize, height: usize) -> Self {
+        let (width, height) = (width.max(1), height.max(1));
+        let lat_step = (bounds.north - bounds.south) / height as f64;
+        let lon_step = longitude_span(bounds) / width as f64;
+        Self {
+            north: bounds.north - lat_step / 2.0,
+            west: bounds.west + lon_step / 2.0,
+            lat_step,
+            lon_step,
+            width,
+            height,
+        }
+    }
+
+    /// Divide `bounds` into cells about `resolution` degrees across,
+    /// checking that the bounds are not empty and the grid is not too large.
+    ///
+    /// Errors are reported as [`Error::Unsupported`] for `format`.
+    pub(crate) fn for_resolution(
+        bounds: &GeoBounds,
+        resolution: f64,
+        format: &'static str,
+    ) -> Result<Self> {
+        let edges = [bounds.south, bounds.west, bounds.north, bounds.east];
+        if !(edges.iter().all(|edge| edge.is_finite())
+            && -90.0 <= bounds.south
+            && bounds.south < bounds.north
+            && bounds.north <= 90.0
+            && longitude_span(bounds) > 0.0)
+        {
+            return Err(Error::Unsupported {
+                format,
+                detail: format!("empty or invalid bounds {bounds:?}"),
+            });
+        }
+        let span = DVec2::new(longitude_span(bounds), bounds.north - bounds.south);
+        let (width, height) = cell_counts(span, resolution, format)?;
+        Ok(Self::cell_samples(bounds, width, height))
+    }
+
+    /// Get the latitude and longitude of a sample in degrees.
+    #[allow(clippy::cast_precision_loss)] // Grid sizes are far below 2^52.
+    pub(crate) fn position(&self, column: usize, row: usize) -> (f64, f64) {
+        let longitude = self.west + column as f64 * self.lon_step;
+        (
+            self.north - row as f64 * self.lat_step,
+            if longitude > 180.0 {
+                longitude - 360.0
+            } else {
+                longitude
+            },
+        )
+    }
+
+    /// Convert a triangle's longitudes and latitudes to sample coordinates.
+    ///
+    /// Returns the triangle twice: measured eastward from the first column,
+    /// and shifted a full turn west, as a triangle near 360 degrees east of
+    /// the first column may also overlap the start of the grid.
+    pub(crate) fn project(&self, vertices: [DVec2; 3]) -> [[DVec2; 3]; 2] {
+        // Keep the triangle contiguous across the antimeridian.
+        let first = (vertices[0].x - self.west).rem_euclid(360.0);
+        let grid = vertices.map(|position| {
+            let offset = first + wrap_degrees(position.x - vertices[0].x);
+            DVec2::new(
+                offset / self.lon_step,
+                (self.north - position.y) / self.lat_step,
+            )
+        });
+        let shift = DVec2::new(-360.0 / self.lon_step, 0.0);
+        [grid, grid.map(|p| p + shift)]
+    }
+}
+
+/// Call `visit` with the index and barycentric weights of each sample of a
+/// `width` by `height` grid inside `triangle`.
+///
+/// Samples lie at integer coordinates, with the index counting row by row.
+/// Samples on an edge shared by two triangles are visited for both.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)] // Sample ranges are clamped to the grid.
+pub(crate) fn for_each_sample(
+    triangle: [DVec2; 3],
+    width: usize,
+    height: usize,
+    mut visit: impl FnMut(usize, [f64; 3]),
+) {
+    let min = triangle[0].min(triangle[1]).min(triangle[2]);
+    let max = triangle[0].max(triangle[1]).max(triangle[2]);
+    if width == 0
+        || height == 0
+        || max.x < 0.0
+        || max.y < 0.0
+        || min.x > (width - 1) as f64
+        || min.y > (height - 1) as f64
+    {
+        return;
+    }
+    let [a, b, c] = triangle;
+    let area = cross(b - a, c - a);
+    if area.abs() < f64::EPSILON {
+        return;
+    }
+
+    let columns = min.x.ceil().max(0.0) as usize..=(max.x.floor() as usize).min(width - 1);
+    let rows = min.y.ceil().max(0.0) as usize..=(max.y.floor() as usize).min(height - 1);
+    for row in rows {
+        for column in columns.clone() {
+            let p = DVec2::new(column as f64, row as f64);
+            let weights = [
+                cross(c - b, p - b) / area,
+                cross(a - c, p - c) / area,
+                cross(b - a, p - a) / area,
+            ];
+            if weights.iter().all(|&w| w >= -1e-9) {
+                visit(row * width + column, weights);
+            }
+        }
+    }
+}
+
+/// Get the detail level of a node for resolving overlaps, at least 1.
+pub(crate) fn node_level(node: &Node) -> u8 {
+    u8::try_from(node.path.len()).unwrap_or(u8::MAX).max(1)
+}
+
+/// A grid of heights sampled at regularly spaced latitudes and longitudes.
+///
+/// Rows run from north to south and columns from west to east. Each sample
+/// holds the height of the topmost surface above it, taken from the most
+/// detailed node covering it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeightGrid {
+    layout: GridLayout,
+    heights: Vec<f32>,
+    /// Level of the node each sample came from, or 0 for no data.
+    levels: Vec<u8>,
+}
+
+impl HeightGrid {
+    /// Create a grid whose outermost samples lie on the edges of `bounds`.
+    ///
+    /// Neighboring grids created this way share their edge samples.
+    #[must_use]
+    pub fn with_edge_samples(bounds: &GeoBounds, width: usize, height: usize) -> Self {
+        Self::new(GridLayout::edge_samples(bounds, width, height))
+    }
+
+    /// Create a grid dividing `bounds` into cells, with a sample at the
+    /// center of each.
+    #[must_use]
+    pub fn with_cell_samples(bounds: &GeoBounds, width: usize, height: usize) -> Self {
+        Self::new(GridLayout::cell_samples(bounds, width, height))
+    }
+
+    pub(crate) fn new(layout: GridLayout) -> Self {
+        let len = layout.width * layout.height;
+        Self {
+            layout,
+            heights: vec![f32::NAN; len],
+            levels: vec![0; len],
+        }
+    }
+
+    /// Get the number of columns.
+    #[must_use]
+    pub fn width(&self) -> usize {
+        self.layout.width
+    }
+
+    /// Get the number of rows.
+    #[must_use]
+    pub fn height(&self) -> usize {
+        self.layout.height
+    }
+
+    /// Get the latitude and longitude of a sample in degrees.
+    #[must_use]
+    pub fn position(&self, column: usize, row: usize) -> (f64, f64) {
+        self.layout.position(column, row)
+    }
+
+    /// Get the spacing between samples in degrees of latitude and longitude.
+    #[must_use]
+    pub fn spacing(&self) -> (f64, f64) {
+        (self.layout.lat_step, self.layout.lon_step)
+    }
+
+    /// Get the height of a sample in meters above the ellipsoid, or `None`
+    /// if no surface covers it.
+    #[must_use]
+    pub fn get(&self, column: usize, row: usize) -> Option<f32> {
+        let height = self.heights[row * self.layout.width + column];
+        (!height.is_nan()).then_some(height)
+    }
+
+    /// Get all samples in row-major order, with `NaN` for missing data.
+    #[must_use]
+    pub fn heights(&self) -> &[f32] {
+        &self.heights
+    }
+
+    /// Whether no sample has data.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.levels.iter().all(|&level| level == 0)
+    }
+
+    /// Whether every sample has data.
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.levels.iter().all(|&level| level != 0)
+    }
+
+    /// Get the lowest and highest sampled heights, or `None` if the grid is
+    /// empty.
+    #[must_use]
+    pub fn height_range(&self) -> Option<(f32, f32)> {
+        self.heights
+            .iter()
+            .filter(|height| !height.is_nan())
+            .fold(None, |range, &height| match range {
+                None => Some((height, height)),
+                Some((min, max)) => Some((height.min(min), height.max(max))),
+            })
+    }
+
+    /// Rasterize the triangles of a node's meshes in `layers`.
+    ///
+    /// Samples already taken from a more detailed node, one with a longer
+    /// path, are kept; samples from a less detailed one are replaced. Among
+    /// nodes of the same level the highest surface wins.
+    pub fn add_node(&mut self, node: &Node, layers: &[MeshLayer]) {
+        let level = node_level(node);
+        let ecef = Frame::ecef_offset(DVec3::ZERO);
+        for mesh in &node.meshes {
+            let geometry = MeshGeometry::from_mesh_layers(node, mesh, &ecef, layers);
+            let vertices: Vec<Geodetic> = geometry
+                .positions
+                .iter()
+                .map(|&position| Geodetic::from_ecef(position))
+                .collect();
+            for triangle in geometry.triangles.chunks_exact(3) {
+                let corners = [0, 1, 2].map(|k| vertices[triangle[k] as usize]);
+                let heights = corners.map(|corner| corner.height);
+                let lon_lat = corners.map(|corner| DVec2::new(corner.longitude, corner.latitude));
+                for projected in self.layout.project(lon_lat) {
+                    let (width, height) = (self.layout.width, self.layout.height);
+                    for_each_sample(projected, width, height, |i, weights| {
+                        #[allow(clippy::cast_possible_truncation)] // Heights fit in f32.
+                        let height = (weights[0] * heights[0]
+                            + weights[1] * heights[1]
+                            + weights[2] * heights[2]) as f32;
+                        let existing = self.levels[i];
+                        if level > existing || (level == existing && height > self.heights[i]) {
+                            self.levels[i] = level;
+                            self.heights[i] = height;
+                        }
+                    });
+                }
+            }
+        }
+    }
+
+    /// Fill samples without data from their neighbors.
+    ///
+    /// Gaps are filled from the outside in, each sample taking the mean of
+    /// the filled samples next to it. Empty grids are left unchanged.
+    pub fn fill_gaps(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        let (width, height) = (self.layout.width, self.layout.height);
+        while !self.is_complete() {
+            let mut filled = Vec::new();
+            for row in 0..height {
+                for column in 0..width {
+                    if self.levels[row * width + column] != 0 {
+                        continue;
+                    }
+                    let neighbors: Vec<f32> = [(-1, 0), (1, 0), (0, -1), (0, 1)]
+                        .into_iter()
+                        .filter_map(|(dx, dy)| {
+                            let x = column.checked_add_signed(dx)?;
+                            let y = row.checked_add_signed(dy)?;
+                            (x < width && y < height).then_some(())?;
+                            self.get(x, y)
+                        })
+                        .collect();
+                    if !neighbors.is_empty() {
+                        #[allow(clippy::cast_precision_loss)] // At most four neighbors.
+                        let mean = neighbors.iter().sum::<f32>() / neighbors.len() as f32;
+                        filled.push((row * width + column, mean));
+                    }
+                }
+            }
+            for (i, height) in filled {
+                self.heights[i] = height;
+                // Filled samples are less trustworthy than any node.
+                self.levels[i] = 1;
+            }
+        }
+    }
+}
+
+/// Fetch the nodes within `bounds` needed to rasterize at `target` meters
+/// per sample, passing each to `visit`.
+///
+/// The octree is walked with [`walk_region`], descending into nodes until
+/// their meters per texel are no coarser than `target`. Every node reached
+/// is visited, coarse ones included, so areas without detailed nodes are
+/// still covered; rasterizers let finer nodes win.
+pub(crate) async fn visit_nodes<C: Cache + 'static>(
+    client: &Client<C>,
+    bounds: &GeoBounds,
+    target: f64,
+    mut visit: impl FnMut(&Node) -> Result<()>,
+) -> Result<()> {
+    walk_region(
+        client,
+        bounds,
+        |metadata| f64::from(metadata.meters_per_texel) > target,
+        async |step| match step {
+            WalkStep::Node(metadata) if metadata.has_data => {
+                let node = client
+                    .fetch_node(&NodeRequest::new(
+                        metadata.path.clone(),
+                        metadata.epoch,
+                        metadata.texture_format,
+                        metadata.imagery_epoch,
+                    ))
+                    .await?;
+                visit(&node)
+            }
+            _ => Ok(()),
+        },
+    )
+    .await
+}
+
+/// Get the longitude span of bounds, handling the antimeridian.
+/// Count the cells about `resolution` across that fit `span`, rejecting
+/// invalid resolutions and grids of more than [`MAX_CELLS`] cells as
+/// [`Error::Unsupported`] for `format`.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // Counts are checked first.
+#[allow(clippy::cast_precision_loss)] // MAX_CELLS is far below 2^52.
+pub(crate) fn cell_counts(
+    span: DVec2,
+    resolution: f64,
+    format: &'static str,
+) -> Result<(usize, usize)> {
+    if !(resolution.is_finite() && resolution > 0.0) {
+        return Err(Error::Unsupported {
+            format,
+            detail: format!("invalid resolution {resolution}"),
+        });
+    }
+    let count = |span: f64| {
+        let cells = (span / resolution).round().max(1.0);
+        (cells <= MAX_CELLS as f64).then_some(cells as usize)
+    };
+    count(span.x)
+        .zip(count(span.y))
+        .filter(|&(width, height)| width.checked_mul(height).is_some_and(|n| n <= MAX_CELLS))
+        .ok_or_else(|| Error::Unsupported {
+            format,
+            detail: format!("resolution {resolution} gives more than {MAX_CELLS} cells"),
+        })
+}
+
+fn longitude_span(bounds: &GeoBounds) -> f64 {
+    if bounds.west <= bounds.east {
+        bounds.east - bounds.west
+    } else {
+        bounds.east + 360.0 - bounds.west
+    }
+}
+
+/// Wrap an angle difference into `[-180, 180)`.
+pub(crate) fn wrap_degrees(angle: f64) -> f64 {
+    (angle + 180.0).rem_euclid(360.0) - 180.0
+}
+
+fn cross(a: DVec2, b: DVec2) -> f64 {
+    a.x * b.y - a.y * b.x
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use glam::{DMat3, DMat4, Vec2};
+    use rocktree::{Mesh, OrientedBoundingBox, TextureFormat, UvTransform, Vertex};
+
+    /// Build a node with one terrain mesh: a flat 3 by 3 vertex grid
+    /// covering `bounds` at `height` meters.
+    pub(crate) fn terrain_node(path: &str, bounds: &GeoBounds, height: f64) -> Node {
+        let corner = Geodetic::new(bounds.south, bounds.west, height).to_ecef();
+        let east = Geodetic::new(bounds.south, bounds.east, height).to_ecef() - corner;
+        let north = Geodetic::new(bounds.north, bounds.west, height).to_ecef() - corner;
+        // Vertices span 0 to 2 along the mesh's X and Y axes.
+        let matrix = DMat4::from_cols(
+            (east / 2.0).extend(0.0),
+            (north / 2.0).extend(0.0),
+            east.cross(north).normalize().extend(0.0),
+            corner.extend(1.0),
+        );
+        let vertices = (0..3)
+            .flat_map(|y| (0..3).map(move |x| (x, y)))
+            .map(|(x, y)| Vertex {
+                x,
+                y,
+                z: 0,
+                w: 0,
+                u: u16::from(x),
+                v: u16::from(2 - y),
+            })
+            .collect();
+        // One strip per row of quads, joined by degenerate triangles.
+        let indices = vec![0, 3, 1, 4, 2, 5, 5, 3, 3, 6, 4, 7, 5, 8];
+        let len = indices.len();
+        Node {
+            path: path.to_string(),
+            matrix_globe_from_mesh: matrix,
+            meters_per_texel: 1.0,
+            obb: OrientedBoundingBox {
+                center: corner + (east + north) / 2.0,
+                extents: DVec3::splat(east.length().max(north.length())),
+                orientation: DMat3::IDENTITY,
+            },
+            meshes: vec![Mesh {
+                vertices,
+                indices,
+                uv_transform: UvTransform {
+                    offset: Vec2::ZERO,
+                    scale: Vec2::splat(0.5),
+                },
+                texture_data: vec![128; 4 * 4],
+                texture_format: TextureFormat::Rgba,
+                texture_width: 2,
+                texture_height: 2,
+                has_octant_data: true,
+                layer_bounds: [0, 0, 0, len],
+            }],
+        }
+    }
+
+    #[test]
+    fn test_rasterize_flat_terrain() {
+        let bounds = GeoBounds::new(10.0, 20.0, 10.01, 20.01);
+        let node = terrain_node("0", &GeoBounds::new(9.99, 19.99, 10.02, 20.02), 100.0);
+        let mut grid = HeightGrid::with_edge_samples(&bounds, 5, 5);
+        grid.add_node(&node, &TERRAIN_LAYERS);
+        assert!(grid.is_complete());
+        let (min, max) = grid.height_range().unwrap();
+        assert!((min - 100.0).abs() < 0.5 && (max - 100.0).abs() < 0.5);
+        assert_eq!(grid.position(4, 4), (10.0, 20.01));
+
+        // The mesh has no overground geometry.
+        let mut grid = HeightGrid::with_edge_samples(&bounds, 5, 5);
+        grid.add_node(&node, &[MeshLayer::Overground]);
+        assert!(grid.is_empty());
+    }
+
+    #[test]
+    fn test_detail_and_height_priority() {
+        let bounds = GeoBounds::new(10.0, 20.0, 10.01, 20.01);
+        let mut grid = HeightGrid::with_cell_samples(&bounds, 4, 4);
+        grid.add_node(&terrain_node("01", &bounds, 50.0), &TERRAIN_LAYERS);
+        // A coarser node does not replace finer samples, even if higher.
+        grid.add_node(&terrain_node("0", &bounds, 80.0), &TERRAIN_LAYERS);
+        assert!((grid.get(1, 1).unwrap() - 50.0).abs() < 0.5);
+        // A higher surface at the same level wins.
+        grid.add_node(&terrain_node("02", &bounds, 60.0), &TERRAIN_LAYERS);
+        assert!((grid.get(1, 1).unwrap() - 60.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_fill_gaps() {
+        let bounds = GeoBounds::new(10.0, 20.0, 10.01, 20.01);
+        let west = GeoBounds::new(9.99, 19.99, 10.02, 20.006);
+        let mut grid = HeightGrid::with_edge_samples(&bounds, 5, 3);
+        grid.add_node(&terrain_node("0", &west, 10.0), &TERRAIN_LAYERS);
+        assert!(!grid.is_complete());
+        assert!(grid.get(2, 1).is_some());
+        assert!(grid.get(3, 1).is_none());
+        grid.fill_gaps();
+        assert!(grid.is_complete());
+        assert!((grid.get(4, 1).unwrap() - 10.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn test_antimeridian() {
+        let bounds = GeoBounds::new(0.002, 179.99, 0.008, -179.99);
+        let node = terrain_node("0", &GeoBounds::new(0.0, 179.993, 0.01, -179.993), 5.0);
+        let mut grid = HeightGrid::with_edge_samples(&bounds, 5, 2);
+        grid.add_node(&node, &TERRAIN_LAYERS);
+        assert!(grid.get(0, 0).is_none());
+        assert!(grid.get(1, 0).is_some());
+        assert!(grid.get(3, 1).is_some());
+        assert!(grid.get(4, 1).is_none());
+        assert!((grid.position(4, 0).1 + 179.99).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,495 @@
+//! Quadric error mesh simplification.
+//!
+//! Triangles are removed by collapsing edges, cheapest first, where the
+//! cost of a collapse is the squared distance of the moved vertex from the
+//! planes of the triangles it has absorbed. A vertex always collapses onto
+//! one of its neighbors, so every remaining vertex keeps its original
+//! position and texture coordinates.
+//!
+//! Vertices on a boundary, such as the edge of a node or of a hole left by
+//! octant masking, and vertices on a texture seam, where the same position
+//! appears with different texture coordinates, never move. Node edges stay
+//! where neighboring nodes expect them and textures stay intact.
+
+use glam::{DVec3, Vec2};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::geometry::MeshGeometry;
+
+/// Simplifies [`MeshGeometry`] to a triangle count or error bound.
+///
+/// ```ignore
+/// let simplifier = Simplifier::new().with_max_error(0.25);
+/// let mut geometry = MeshGeometry::from_mesh(&node, &node.meshes[0], &frame);
+/// simplifier.simplify(&mut geometry);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Simplifier {
+    target_triangles: usize,
+    max_error: f64,
+}
+
+impl Default for Simplifier {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Simplifier {
+    /// Create a simplifier that removes as many triangles as boundaries and
+    /// seams allow.
+    ///
+    /// Set a target triangle count, an error bound, or both, to stop
+    /// earlier.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            target_triangles: 0,
+            max_error: f64::INFINITY,
+        }
+    }
+
+    /// Stop once at most `count` triangles remain.
+    #[must_use]
+    pub fn with_target_triangles(mut self, count: usize) -> Self {
+        self.target_triangles = count;
+        self
+    }
+
+    /// Only collapse edges that move the surface by about `meters` or less.
+    #[must_use]
+    pub fn with_max_error(mut self, meters: f64) -> Self {
+        self.max_error = meters.max(0.0);
+        self
+    }
+
+    /// Simplify `geometry` in place, dropping the vertices no triangle uses
+    /// any more.
+    pub fn simplify(&self, geometry: &mut MeshGeometry) {
+        if geometry.triangle_count() <= self.target_triangles {
+            return;
+        }
+        let mut mesh = Collapser::new(geometry);
+        let max_cost = self.max_error * self.max_error;
+        let mut heap: BinaryHeap<Candidate> = (0..mesh.positions.len())
+            .filter_map(|v| mesh.best_collapse(v))
+            .collect();
+        while mesh.live_triangles > self.target_triangles {
+            let Some(candidate) = heap.pop() else {
+                break;
+            };
+            let from = candidate.from as usize;
+            if mesh.removed[from] || candidate.version != mesh.versions[from] {
+                continue;
+            }
+            // Candidates come out cheapest first.
+            if candidate.cost > max_cost {
+                break;
+            }
+            for v in mesh.collapse(from, candidate.to as usize) {
+                heap.extend(mesh.best_collapse(v));
+            }
+        }
+        let triangles = mesh
+            .triangles
+            .iter()
+            .zip(&mesh.live)
+            .filter(|&(_, &live)| live)
+            .flat_map(|(triangle, _)| *triangle)
+            .collect();
+        geometry.triangles = triangles;
+        geometry.compact();
+    }
+}
+
+/// A symmetric 4x4 matrix summing squared distances to planes.
+#[derive(Debug, Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// Get the quadric of the plane through `point` with unit `normal`.
+    fn plane(normal: DVec3, point: DVec3) -> Self {
+        let [a, b, c] = normal.to_array();
+        let d = -normal.dot(point);
+        Self([
+            a * a,
+            a * b,
+            a * c,
+            a * d,
+            b * b,
+            b * c,
+            b * d,
+            c * c,
+            c * d,
+            d * d,
+        ])
+    }
+
+    fn add(&mut self, other: &Quadric) {
+        for (value, other) in self.0.iter_mut().zip(other.0) {
+            *value += other;
+        }
+    }
+
+    /// Get the sum of squared distances from `p` to the planes.
+    fn error(&self, p: DVec3) -> f64 {
+        let [aa, ab, ac, ad, bb, bc, bd, cc, cd, dd] = self.0;
+        let (x, y, z) = (p.x, p.y, p.z);
+        aa * x * x
+            + 2.0 * ab * x * y
+            + 2.0 * ac * x * z
+            + 2.0 * ad * x
+            + bb * y * y
+            + 2.0 * bc * y * z
+            + 2.0 * bd * y
+            + cc * z * z
+            + 2.0 * cd * z
+            + dd
+    }
+}
+
+/// A possible collapse of vertex `from` onto its neighbor `to`.
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    cost: f64,
+    from: u32,
+    to: u32,
+    /// Version of `from` the candidate was computed for.
+    version: u32,
+}
+
+impl PartialEq for Candidate {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Candidate {}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Candidate {
+    /// Order by cost reversed, so a max-heap yields the cheapest first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .total_cmp(&self.cost)
+            .then_with(|| other.from.cmp(&self.from))
+    }
+}
+
+/// Mutable connectivity for edge collapses.
+struct Collapser<'a> {
+    positions: &'a [DVec3],
+    uvs: &'a [Vec2],
+    triangles: Vec<[u32; 3]>,
+    live: Vec<bool>,
+    live_triangles: usize,
+    /// Triangles using each vertex, including some no longer live.
+    incident: Vec<Vec<u32>>,
+    quadrics: Vec<Quadric>,
+    locked: Vec<bool>,
+    removed: Vec<bool>,
+    /// Incremented whenever a vertex's neighborhood changes.
+    versions: Vec<u32>,
+}
+
+impl<'a> Collapser<'a> {
+    fn new(geometry: &'a MeshGeometry) -> Self {
+        let count = geometry.positions.len();
+        let triangles: Vec<[u32; 3]> = geometry
+            .triangles
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .collect();
+        let mut incident = vec![Vec::new(); count];
+        let mut quadrics = vec![Quadric::default(); count];
+        for (t, triangle) in triangles.iter().enumerate() {
+            let [a, b, c] = triangle.map(|i| geometry.positions[i as usize]);
+            let normal = (b - a).cross(c - a).normalize_or_zero();
+            let quadric = Quadric::plane(normal, a);
+            for &i in triangle {
+                incident[i as usize].push(u32::try_from(t).expect("triangle count fits in u32"));
+                quadrics[i as usize].add(&quadric);
+            }
+        }
+        Self {
+            positions: &geometry.positions,
+            uvs: &geometry.uvs,
+            live: vec![true; triangles.len()],
+            live_triangles: triangles.len(),
+            locked: locked_vertices(&geometry.positions, &triangles),
+            triangles,
+            incident,
+            quadrics,
+            removed: vec![false; count],
+            versions: vec![0; count],
+        }
+    }
+
+    fn live_incident(&self, v: usize) -> impl Iterator<Item = usize> + '_ {
+        self.incident[v]
+            .iter()
+            .map(|&t| t as usize)
+            .filter(|&t| self.live[t])
+    }
+
+    /// Find the cheapest valid collapse of `v` onto a neighbor.
+    fn best_collapse(&self, v: usize) -> Option<Candidate> {
+        if self.locked[v] || self.removed[v] {
+            return None;
+        }
+        let mut neighbors: Vec<usize> = self
+            .live_incident(v)
+            .flat_map(|t| self.triangles[t])
+            .map(|i| i as usize)
+            .filter(|&i| i != v)
+            .collect();
+        neighbors.sort_unstable();
+        neighbors.dedup();
+
+        let mut best: Option<(f64, usize)> = None;
+        for u in neighbors {
+            let mut quadric = self.quadrics[v];
+            quadric.add(&self.quadrics[u]);
+            let cost = quadric.error(self.positions[u]).max(0.0);
+            if best.is_none_or(|(best, _)| cost < best) && self.is_valid(v, u) {
+                best = Some((cost, u));
+            }
+        }
+        best.map(|(cost, u)| Candidate {
+            cost,
+            from: u32::try_from(v).expect("vertex count fits in u32"),
+            to: u32::try_from(u).expect("vertex count fits in u32"),
+            version: self.versions[v],
+        })
+    }
+
+    /// Check that moving `from` onto `to` neither flips nor collapses any
+    /// triangle that survives, in space or in texture coordinates.
+    fn is_valid(&self, from: usize, to: usize) -> bool {
+        self.live_incident(from).all(|t| {
+            let before = self.triangles[t].map(|i| i as usize);
+            if before.contains(&to) {
+                return true;
+            }
+            let moved = before.map(|i| if i == from { to } else { i });
+            let normal = |corners: [usize; 3]| {
+                let [a, b, c] = corners.map(|i| self.positions[i]);
+                (b - a).cross(c - a)
+            };
+            let uv_area = |corners: [usize; 3]| {
+                let [a, b, c] = corners.map(|i| self.uvs[i]);
+                (b - a).perp_dot(c - a)
+            };
+            let (old, new) = (normal(before), normal(moved));
+            new.length_squared() > 1e-12 * old.length_squared()
+                && old.dot(new) > 0.0
+                && uv_area(before) * uv_area(moved) > 0.0
+        })
+    }
+
+    /// Collapse `v` onto `u`, returning the vertices whose candidates need
+    /// updating.
+    fn collapse(&mut self, v: usize, u: usize) -> Vec<usize> {
+        let target = u32::try_from(u).expect("vertex count fits in u32");
+        let triangles: Vec<usize> = self.live_incident(v).collect();
+        for t in triangles {
+            let triangle = &mut self.triangles[t];
+            if triangle.contains(&target) {
+                self.live[t] = false;
+                self.live_triangles -= 1;
+            } else {
+                for i in triangle.iter_mut().filter(|i| **i as usize == v) {
+                    *i = target;
+                }
+                self.incident[u].push(u32::try_from(t).expect("triangle count fits in u32"));
+            }
+        }
+        self.removed[v] = true;
+        let quadric = self.quadrics[v];
+        self.quadrics[u].add(&quadric);
+
+        let mut affected: Vec<usize> = self
+            .live_incident(u)
+            .flat_map(|t| self.triangles[t])
+            .map(|i| i as usize)
+            .collect();
+        affected.sort_unstable();
+        affected.dedup();
+        for &i in &affected {
+            self.versions[i] += 1;
+        }
+        affected
+    }
+}
+
+/// Find the vertices that must not move: those on boundary or
+/// non-manifold edges, and those sharing their position with another
+/// vertex, as along texture seams.
+fn locked_vertices(positions: &[DVec3], triangles: &[[u32; 3]]) -> Vec<bool> {
+    let mut locked = vec![false; positions.len()];
+    // Identify vertices by position, so edges along seams match up.
+    let mut groups: HashMap<[u64; 3], u32> = HashMap::new();
+    let mut group_of = Vec::with_capacity(positions.len());
+    let mut first_vertex: Vec<usize> = Vec::new();
+    for (i, position) in positions.iter().enumerate() {
+        let key = position.to_array().map(f64::to_bits);
+        let next = u32::try_from(groups.len()).expect("vertex count fits in u32");
+        let group = *groups.entry(key).or_insert(next);
+        if group == next {
+            first_vertex.push(i);
+        } else {
+            locked[i] = true;
+            locked[first_vertex[group as usize]] = true;
+        }
+        group_of.push(group);
+    }
+
+    let mut edges: HashMap<(u32, u32), u32> = HashMap::new();
+    for triangle in triangles {
+        for k in 0..3 {
+            let a = group_of[triangle[k] as usize];
+            let b = group_of[triangle[(k + 1) % 3] as usize];
+            *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+        }
+    }
+    for triangle in triangles {
+        for k in 0..3 {
+            let (a, b) = (triangle[k], triangle[(k + 1) % 3]);
+            let (ga, gb) = (group_of[a as usize], group_of[b as usize]);
+            if edges[&(ga.min(gb), ga.max(gb))] != 2 {
+                locked[a as usize] = true;
+                locked[b as usize] = true;
+            }
+        }
+    }
+    locked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a grid of `n` by `n` vertices one meter apart, with heights
+    /// from `height` and texture coordinates spanning the texture.
+    #[allow(clippy::cast_precision_loss)] // Grid sizes are tiny.
+    fn grid(n: u32, height: impl Fn(u32, u32) -> f64) -> MeshGeometry {
+        let mut geometry = MeshGeometry::default();
+        for y in 0..n {
+            for x in 0..n {
+                geometry
+                    .positions
+                    .push(DVec3::new(f64::from(x), f64::from(y), height(x, y)));
+                geometry
+                    .uvs
+                    .push(Vec2::new(x as f32, y as f32) / (n - 1) as f32);
+            }
+        }
+        for y in 0..n - 1 {
+            for x in 0..n - 1 {
+                let i = y * n + x;
+                geometry
+                    .triangles
+                    .extend([i, i + 1, i + n, i + 1, i + n + 1, i + n]);
+            }
+        }
+        geometry
+    }
+
+    #[allow(clippy::float_cmp)] // Positions are copied, not computed.
+    fn on_boundary(position: DVec3, n: u32) -> bool {
+        let last = f64::from(n - 1);
+        position.x == 0.0 || position.y == 0.0 || position.x == last || position.y == last
+    }
+
+    #[test]
+    fn test_flat_grid_keeps_boundary() {
+        let mut geometry = grid(5, |_, _| 0.0);
+        Simplifier::new()
+            .with_max_error(1e-6)
+            .simplify(&mut geometry);
+        // Every interior vertex goes, leaving a fan over the 16 boundary
+        // vertices.
+        assert_eq!(geometry.triangle_count(), 14);
+        // All 16 boundary vertices remain.
+        assert_eq!(
+            geometry
+                .positions
+                .iter()
+                .filter(|&&p| on_boundary(p, 5))
+                .count(),
+            16
+        );
+        // The surface still covers the whole square.
+        let area: f64 = geometry
+            .triangles
+            .chunks_exact(3)
+            .map(|t| {
+                let [a, b, c] = [0, 1, 2].map(|k| geometry.positions[t[k] as usize]);
+                (b - a).cross(c - a).z / 2.0
+            })
+            .sum();
+        assert!((area - 16.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_error_bound_keeps_peak() {
+        let mut geometry = grid(7, |x, y| if (x, y) == (3, 3) { 1.0 } else { 0.0 });
+        Simplifier::new()
+            .with_max_error(0.1)
+            .simplify(&mut geometry);
+        assert!(geometry.triangle_count() < 72);
+        assert!(geometry.positions.iter().any(|p| (p.z - 1.0).abs() < 1e-9));
+
+        // Without a bound the peak may go.
+        let mut geometry = grid(7, |x, y| if (x, y) == (3, 3) { 1.0 } else { 0.0 });
+        Simplifier::new().simplify(&mut geometry);
+        assert!(geometry.positions.iter().all(|p| p.z.abs() < 1e-9));
+    }
+
+    #[test]
+    fn test_target_triangles() {
+        let mut geometry = grid(9, |x, y| f64::from((x * 7 + y * 3) % 5) * 0.1);
+        Simplifier::new()
+            .with_target_triangles(60)
+            .simplify(&mut geometry);
+        assert!(geometry.triangle_count() <= 60);
+        assert_eq!(geometry.uvs.len(), geometry.positions.len());
+        assert!(
+            geometry
+                .triangles
+                .iter()
+                .all(|&i| (i as usize) < geometry.positions.len())
+        );
+    }
+
+    #[test]
+    fn test_seams_stay() {
+        // Split the grid along x = 2 into two texture charts.
+        let mut geometry = grid(5, |_, _| 0.0);
+        for y in 0..5 {
+            geometry.positions.push(DVec3::new(2.0, f64::from(y), 0.0));
+            #[allow(clippy::cast_precision_loss)] // Grid sizes are tiny.
+            geometry.uvs.push(Vec2::new(0.0, y as f32 / 4.0));
+        }
+        for triangle in geometry.triangles.chunks_exact_mut(3) {
+            let right = triangle.iter().any(|&i| i % 5 > 2);
+            for i in triangle.iter_mut().filter(|i| **i % 5 == 2 && right) {
+                *i = 25 + *i / 5;
+            }
+        }
+        Simplifier::new().simplify(&mut geometry);
+        let seam = geometry
+            .positions
+            .iter()
+            .filter(|p| (p.x - 2.0).abs() < 1e-9)
+            .count();
+        assert_eq!(seam, 10);
+    }
+}
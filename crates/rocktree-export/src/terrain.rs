@@ -0,0 +1,840 @@
+//! Cesium quantized-mesh terrain export.
+//!
+//! Terrain is resampled from the terrain layers of rocktree meshes onto a
+//! regular grid per tile, then written as `quantized-mesh-1.0` tiles on the
+//! geographic tiling scheme (EPSG:4326, two tiles at level 0). Tiles are
+//! named `<level>/<x>/<y>.terrain` with TMS rows counted from the south, and
+//! a `layer.json` lists the available tiles.
+//!
+//! Neighboring tiles sample their shared edges at the same positions, so
+//! terrain is continuous across tiles without skirts.
+
+use glam::DVec3;
+use rocktree::{Cache, Client, GeoBounds, Geodetic, Node, NodeRequest, WalkStep, walk_region};
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::PathBuf;
+
+use crate::error::{Error, Result};
+use crate::raster::{HeightGrid, TERRAIN_LAYERS};
+
+/// Largest quantized coordinate.
+const QUANTIZED_MAX: u16 = 32767;
+
+/// Extension ID of oct-encoded per-vertex normals.
+const EXTENSION_OCT_NORMALS: u8 = 1;
+
+/// WGS84 ellipsoid radii, for the horizon occlusion point.
+const WGS84_RADII: DVec3 = DVec3::new(6_378_137.0, 6_378_137.0, 6_356_752.314_245_179);
+
+/// Magnitude, in ellipsoid radii, of the horizon occlusion point of tiles
+/// that have none, far enough out that it is only hidden from cameras on
+/// the far side of the globe.
+const FALLBACK_OCCLUSION_MAGNITUDE: f64 = 1_000.0;
+
+/// How many octree levels deeper than a terrain tile [`export_terrain`]
+/// fetches nodes for it.
+///
+/// A rocktree node at level `n` is roughly as wide as a terrain tile at
+/// level `n - 1`; the extra level gives about one mesh vertex per sample.
+pub const NODE_LEVEL_OFFSET: usize = 2;
+
+/// Deepest level whose tile columns fit in a `u32`.
+pub const MAX_LEVEL: u32 = 30;
+
+/// A tile in the geographic tiling scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TileId {
+    /// Zoom level; level 0 has two tiles, west and east of the prime
+    /// meridian.
+    ///
+    /// Levels deeper than [`MAX_LEVEL`] are not supported.
+    pub level: u32,
+    /// Column, counted from the antimeridian eastward.
+    pub x: u32,
+    /// Row, counted from the south pole northward.
+    pub y: u32,
+}
+
+impl TileId {
+    /// Create a tile ID.
+    #[must_use]
+    pub fn new(level: u32, x: u32, y: u32) -> Self {
+        Self { level, x, y }
+    }
+
+    /// Get the size of tiles at `level` in degrees.
+    #[must_use]
+    pub fn size(level: u32) -> f64 {
+        180.0 / f64::from(1u32 << level)
+    }
+
+    /// Get the area covered by the tile.
+    #[must_use]
+    pub fn bounds(&self) -> GeoBounds {
+        let size = Self::size(self.level);
+        let west = -180.0 + f64::from(self.x) * size;
+        let south = -90.0 + f64::from(self.y) * size;
+        GeoBounds::new(south, west, south + size, west + size)
+    }
+
+    /// Get the tiles at `level` that intersect `bounds`.
+    #[must_use]
+    pub fn covering(bounds: &GeoBounds, level: u32) -> Vec<TileId> {
+        // Split bounds crossing the antimeridian into two.
+        let spans = if bounds.west <= bounds.east {
+            vec![(bounds.west, bounds.east)]
+        } else {
+            vec![(bounds.west, 180.0), (-180.0, bounds.east)]
+        };
+        let columns = 2u32 << level;
+        let rows = 1u32 << level;
+        let rows = tile_range(bounds.south + 90.0, bounds.north + 90.0, level, rows);
+        let mut tiles = Vec::new();
+        for (west, east) in spans {
+            for x in tile_range(west + 180.0, east + 180.0, level, columns) {
+                tiles.extend(rows.clone().map(|y| TileId::new(level, x, y)));
+            }
+        }
+        tiles.sort();
+        tiles.dedup();
+        tiles
+    }
+}
+
+/// Get the indices of tiles at `level` overlapping `[start, end]` degrees
+/// from the origin of the scheme.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // Clamped to `count`.
+fn tile_range(start: f64, end: f64, level: u32, count: u32) -> std::ops::Range<u32> {
+    let size = TileId::size(level);
+    let first = (start / size).floor().clamp(0.0, f64::from(count - 1)) as u32;
+    let last = (end / size)
+        .ceil()
+        .clamp(f64::from(first + 1), f64::from(count)) as u32;
+    first..last
+}
+
+/// Writes a quantized-mesh terrain tileset from rocktree nodes.
+///
+/// ```ignore
+/// let mut writer = TerrainWriter::create("terrain")?.with_normals(true);
+/// writer.write_tile(TileId::new(12, 4301, 2903), &nodes)?;
+/// writer.finish()?;
+/// ```
+#[derive(Debug)]
+pub struct TerrainWriter {
+    dir: PathBuf,
+    grid_size: usize,
+    normals: bool,
+    available: BTreeSet<TileId>,
+}
+
+impl TerrainWriter {
+    /// Create a writer for a tileset in `dir`, which is created if it does
+    /// not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created.
+    pub fn create(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+        Ok(Self {
+            dir,
+            grid_size: 65,
+            normals: false,
+            available: BTreeSet::new(),
+        })
+    }
+
+    /// Set the number of samples along each edge of a tile.
+    ///
+    /// Defaults to 65. Values are clamped to between 2 and 256.
+    #[must_use]
+    pub fn with_grid_size(mut self, size: usize) -> Self {
+        self.grid_size = size.clamp(2, 256);
+        self
+    }
+
+    /// Include oct-encoded per-vertex normals for lighting.
+    #[must_use]
+    pub fn with_normals(mut self, enabled: bool) -> Self {
+        self.normals = enabled;
+        self
+    }
+
+    /// Get the number of tiles written so far.
+    #[must_use]
+    pub fn tile_count(&self) -> usize {
+        self.available.len()
+    }
+
+    /// Resample the terrain of `nodes` within `tile` and write it.
+    ///
+    /// Where nodes overlap, the most detailed one is used. Samples no
+    /// terrain covers are filled from their neighbors. Returns `false`
+    /// without writing anything if no terrain covers the tile.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing the tile fails.
+    pub fn write_tile(&mut self, tile: TileId, nodes: &[&Node]) -> Result<bool> {
+        let mut grid =
+            HeightGrid::with_edge_samples(&tile.bounds(), self.grid_size, self.grid_size);
+        for node in nodes {
+            grid.add_node(node, &TERRAIN_LAYERS);
+        }
+        if grid.is_empty() {
+            return Ok(false);
+        }
+        grid.fill_gaps();
+
+        let dir = self
+            .dir
+            .join(tile.level.to_string())
+            .join(tile.x.to_string());
+        std::fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+        let path = dir.join(format!("{}.terrain", tile.y));
+        std::fs::write(&path, encode_tile(&grid, self.normals)).map_err(|e| Error::io(&path, e))?;
+        self.available.insert(tile);
+        Ok(true)
+    }
+
+    /// Write `layer.json` and return its path.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing `layer.json` fails.
+    pub fn finish(self) -> Result<PathBuf> {
+        let max_level = self.available.iter().map(|tile| tile.level).max();
+        let available: Vec<Value> = (0..=max_level.unwrap_or(0))
+            .map(|level| {
+                let tiles = self.available.iter().filter(|tile| tile.level == level);
+                Value::Array(available_ranges(tiles))
+            })
+            .collect();
+        let mut layer = json!({
+            "tilejson": "2.1.0",
+            "name": "rocktree",
+            "description": format!("Exported by rocktree-export {}", env!("CARGO_PKG_VERSION")),
+            "version": "1.0.0",
+            "format": "quantized-mesh-1.0",
+            "scheme": "tms",
+            "tiles": ["{z}/{x}/{y}.terrain?v={version}"],
+            "projection": "EPSG:4326",
+            "bounds": [-180.0, -90.0, 180.0, 90.0],
+            "minzoom": 0,
+            "maxzoom": max_level.unwrap_or(0),
+            "available": available,
+        });
+        if self.normals {
+            layer["extensions"] = json!(["octvertexnormals"]);
+        }
+
+        let path = self.dir.join("layer.json");
+        let text = serde_json::to_string_pretty(&layer).map_err(|e| Error::Unsupported {
+            format: "quantized-mesh",
+            detail: e.to_string(),
+        })?;
+        std::fs::write(&path, text).map_err(|e| Error::io(&path, e))?;
+        Ok(path)
+    }
+}
+
+/// Describe tiles of one level as rectangles of runs along rows, merging
+/// runs that repeat on consecutive rows.
+fn available_ranges<'a>(tiles: impl Iterator<Item = &'a TileId>) -> Vec<Value> {
+    // Runs of columns per row, as (start x, end x) keyed by row.
+    let mut rows: BTreeMap<u32, Vec<(u32, u32)>> = BTreeMap::new();
+    for tile in tiles {
+        let runs = rows.entry(tile.y).or_default();
+        match runs.last_mut() {
+            Some((_, end)) if *end + 1 == tile.x => *end = tile.x,
+            _ => runs.push((tile.x, tile.x)),
+        }
+    }
+
+    // Rectangles as (start x, end x, start y, end y).
+    let mut rectangles: Vec<(u32, u32, u32, u32)> = Vec::new();
+    for (y, runs) in rows {
+        for (start, end) in runs {
+            let extends = rectangles
+                .iter_mut()
+                .find(|r| r.0 == start && r.1 == end && r.3 + 1 == y);
+            match extends {
+                Some(rectangle) => rectangle.3 = y,
+                None => rectangles.push((start, end, y, y)),
+            }
+        }
+    }
+    rectangles
+        .into_iter()
+        .map(|(start_x, end_x, start_y, end_y)| {
+            json!({"startX": start_x, "startY": start_y, "endX": end_x, "endY": end_y})
+        })
+        .collect()
+}
+
+/// Encode a complete height grid as a quantized-mesh tile.
+fn encode_tile(grid: &HeightGrid, normals: bool) -> Vec<u8> {
+    let (width, height) = (grid.width(), grid.height());
+    let (min_height, max_height) = grid.height_range().unwrap_or((0.0, 0.0));
+
+    let grid_index =
+        |column: usize, row_from_south: usize| (height - 1 - row_from_south) * width + column;
+    let (order, numbers, triangles) = grid_triangles(width, height);
+
+    let positions: Vec<DVec3> = order
+        .iter()
+        .map(|&i| {
+            let (latitude, longitude) = grid.position(i % width, i / width);
+            let elevation = f64::from(grid.heights()[i]);
+            Geodetic::new(latitude, longitude, elevation).to_ecef()
+        })
+        .collect();
+    #[allow(clippy::cast_precision_loss)] // Grids have at most 256 columns.
+    let quantized: Vec<[u16; 3]> = order
+        .iter()
+        .map(|&i| {
+            let (column, row) = (i % width, height - 1 - i / width);
+            [
+                quantize(column as f64, (width - 1) as f64),
+                quantize(row as f64, (height - 1) as f64),
+                quantize(
+                    f64::from(grid.heights()[i] - min_height),
+                    f64::from(max_height - min_height),
+                ),
+            ]
+        })
+        .collect();
+
+    let mut out = Vec::new();
+    write_header(&mut out, &positions, min_height, max_height);
+    let vertex_count = u32::try_from(positions.len()).unwrap_or(u32::MAX);
+    out.extend_from_slice(&vertex_count.to_le_bytes());
+    for k in 0..3 {
+        let mut previous = 0i32;
+        for vertex in &quantized {
+            let value = i32::from(vertex[k]);
+            out.extend_from_slice(&zigzag(value - previous).to_le_bytes());
+            previous = value;
+        }
+    }
+
+    // Indices are 32-bit when more than 64Ki vertices are needed, aligned
+    // to their size.
+    let wide = positions.len() > 65536;
+    let write_index = |out: &mut Vec<u8>, index: u32| {
+        if wide {
+            out.extend_from_slice(&index.to_le_bytes());
+        } else {
+            #[allow(clippy::cast_possible_truncation)] // At most 64Ki vertices.
+            out.extend_from_slice(&(index as u16).to_le_bytes());
+        }
+    };
+    let alignment = if wide { 4 } else { 2 };
+    out.resize(out.len().next_multiple_of(alignment), 0);
+
+    let triangle_count = u32::try_from(triangles.len() / 3).unwrap_or(u32::MAX);
+    out.extend_from_slice(&triangle_count.to_le_bytes());
+    let mut highest = 0;
+    for &index in &triangles {
+        write_index(&mut out, highest - index);
+        if index == highest {
+            highest += 1;
+        }
+    }
+
+    // Edge vertices: west, south, east, then north.
+    let edges = [
+        (0..height)
+            .map(|row| grid_index(0, row))
+            .collect::<Vec<_>>(),
+        (0..width).map(|column| grid_index(column, 0)).collect(),
+        (0..height).map(|row| grid_index(width - 1, row)).collect(),
+        (0..width)
+            .map(|column| grid_index(column, height - 1))
+            .collect(),
+    ];
+    for edge in edges {
+        let count = u32::try_from(edge.len()).unwrap_or(u32::MAX);
+        out.extend_from_slice(&count.to_le_bytes());
+        for i in edge {
+            write_index(&mut out, numbers[i]);
+        }
+    }
+
+    if normals {
+        let normals = vertex_normals(&positions, &triangles);
+        out.push(EXTENSION_OCT_NORMALS);
+        let length = u32::try_from(normals.len() * 2).unwrap_or(u32::MAX);
+        out.extend_from_slice(&length.to_le_bytes());
+        for normal in normals {
+            out.extend_from_slice(&oct_encode(normal));
+        }
+    }
+    out
+}
+
+/// Triangulate a grid with two counter-clockwise triangles per cell.
+///
+/// Vertices are numbered in order of first use, as high-water mark encoding
+/// requires. Returns the grid index of each vertex, the vertex number of
+/// each grid index, and the triangles.
+fn grid_triangles(width: usize, height: usize) -> (Vec<usize>, Vec<u32>, Vec<u32>) {
+    // Rows are counted from the south, while grids store them from the north.
+    let grid_index =
+        |column: usize, row_from_south: usize| (height - 1 - row_from_south) * width + column;
+    let mut order = Vec::with_capacity(width * height);
+    let mut numbers = vec![u32::MAX; width * height];
+    let mut triangles = Vec::with_capacity((width - 1) * (height - 1) * 6);
+    for row in 0..height - 1 {
+        for column in 0..width - 1 {
+            let sw = grid_index(column, row);
+            let se = grid_index(column + 1, row);
+            let ne = grid_index(column + 1, row + 1);
+            let nw = grid_index(column, row + 1);
+            for i in [sw, se, ne, sw, ne, nw] {
+                if numbers[i] == u32::MAX {
+                    numbers[i] = u32::try_from(order.len()).unwrap_or(u32::MAX);
+                    order.push(i);
+                }
+                triangles.push(numbers[i]);
+            }
+        }
+    }
+    (order, numbers, triangles)
+}
+
+/// Write the tile header: center, height range, bounding sphere and
+/// horizon occlusion point.
+fn write_header(out: &mut Vec<u8>, positions: &[DVec3], min_height: f32, max_height: f32) {
+    let (center, radius) = bounding_sphere(positions);
+    for value in center.to_array() {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out.extend_from_slice(&min_height.to_le_bytes());
+    out.extend_from_slice(&max_height.to_le_bytes());
+    for value in center.to_array() {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out.extend_from_slice(&radius.to_le_bytes());
+    for value in horizon_occlusion_point(center, positions).to_array() {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+/// Map `value` in `[0, max]` onto `[0, 32767]`.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // Clamped to the range.
+fn quantize(value: f64, max: f64) -> u16 {
+    if max <= 0.0 {
+        return 0;
+    }
+    (value / max * f64::from(QUANTIZED_MAX))
+        .round()
+        .clamp(0.0, f64::from(QUANTIZED_MAX)) as u16
+}
+
+/// Zig-zag encode a delta so small magnitudes of either sign stay small.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // Deltas fit in 16 bits.
+fn zigzag(value: i32) -> u16 {
+    ((value << 1) ^ (value >> 31)) as u16
+}
+
+/// Get the center and radius of a sphere around `positions`, centered on
+/// their bounding box.
+fn bounding_sphere(positions: &[DVec3]) -> (DVec3, f64) {
+    let min = positions.iter().fold(DVec3::INFINITY, |a, &b| a.min(b));
+    let max = positions.iter().fold(DVec3::NEG_INFINITY, |a, &b| a.max(b));
+    let center = (min + max) / 2.0;
+    let radius = positions
+        .iter()
+        .map(|p| p.distance(center))
+        .fold(0.0, f64::max);
+    (center, radius)
+}
+
+/// Compute the horizon occlusion point of a tile, in the ellipsoid's scaled
+/// space.
+///
+/// The point lies along the direction to `center` and is far enough out
+/// that whenever it is below the horizon, so are all of `positions`. This
+/// follows Cesium's `EllipsoidalOccluder`.
+///
+/// Where a position is below the horizon of every point along that
+/// direction, which happens for tiles spanning a quarter of the globe or
+/// more, Cesium has no occlusion point. Quantized-mesh requires one, so
+/// such tiles get a point [`FALLBACK_OCCLUSION_MAGNITUDE`] radii out,
+/// which errs towards drawing them.
+fn horizon_occlusion_point(center: DVec3, positions: &[DVec3]) -> DVec3 {
+    let direction = (center / WGS84_RADII).normalize();
+    let magnitude = positions
+        .iter()
+        .map(|&position| {
+            let scaled = position / WGS84_RADII;
+            let length_squared = scaled.length_squared().max(1.0);
+            let length = length_squared.sqrt();
+            let to_position = scaled.normalize();
+            let cos_alpha = to_position.dot(direction);
+            let sin_alpha = to_position.cross(direction).length();
+            let cos_beta = 1.0 / length;
+            let sin_beta = (length_squared - 1.0).sqrt() * cos_beta;
+            1.0 / (cos_alpha * cos_beta - sin_alpha * sin_beta)
+        })
+        .try_fold(1.0, |max: f64, magnitude| {
+            (magnitude.is_finite() && magnitude > 0.0).then(|| max.max(magnitude))
+        });
+    direction * magnitude.unwrap_or(FALLBACK_OCCLUSION_MAGNITUDE)
+}
+
+/// Compute area-weighted vertex normals from counter-clockwise triangles.
+fn vertex_normals(positions: &[DVec3], triangles: &[u32]) -> Vec<DVec3> {
+    let mut normals = vec![DVec3::ZERO; positions.len()];
+    for triangle in triangles.chunks_exact(3) {
+        let [a, b, c] = [0, 1, 2].map(|k| triangle[k] as usize);
+        let normal = (positions[b] - positions[a]).cross(positions[c] - positions[a]);
+        for i in [a, b, c] {
+            normals[i] += normal;
+        }
+    }
+    normals
+        .into_iter()
+        .zip(positions)
+        .map(|(normal, position)| normal.try_normalize().unwrap_or(position.normalize()))
+        .collect()
+}
+
+/// Oct-encode a unit vector into two bytes.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // Clamped to a byte.
+fn oct_encode(normal: DVec3) -> [u8; 2] {
+    let p = normal / (normal.x.abs() + normal.y.abs() + normal.z.abs());
+    let (x, y) = if p.z < 0.0 {
+        (
+            (1.0 - p.y.abs()) * sign_not_zero(p.x),
+            (1.0 - p.x.abs()) * sign_not_zero(p.y),
+        )
+    } else {
+        (p.x, p.y)
+    };
+    [x, y].map(|value| ((value.clamp(-1.0, 1.0) * 0.5 + 0.5) * 255.0).round() as u8)
+}
+
+fn sign_not_zero(value: f64) -> f64 {
+    if value < 0.0 { -1.0 } else { 1.0 }
+}
+
+/// Export the terrain within `bounds` as tiles from level 0 to `max_level`
+/// written by `writer`, then finish the tileset.
+///
+/// Nodes intersecting `bounds` down to octree level `max_level +`
+/// [`NODE_LEVEL_OFFSET`] are fetched first, keeping only their geometry.
+/// Each tile is then resampled from the nodes at most
+/// [`NODE_LEVEL_OFFSET`] levels deeper than it.
+///
+/// Returns the path of the written `layer.json`.
+///
+/// # Errors
+///
+/// Returns [`Error::Unsupported`] if `max_level` is deeper than
+/// [`MAX_LEVEL`], or an error if a fetch fails or a tile cannot be written.
+pub async fn export_terrain<C: Cache + 'static>(
+    client: &Client<C>,
+    bounds: GeoBounds,
+    max_level: u32,
+    mut writer: TerrainWriter,
+) -> Result<PathBuf> {
+    check_max_level(max_level)?;
+    let max_depth = max_level as usize + NODE_LEVEL_OFFSET;
+    let mut nodes = Vec::new();
+    walk_region(
+        client,
+        &bounds,
+        |metadata| metadata.path.len() < max_depth,
+        async |step| -> Result<()> {
+            if let WalkStep::Node(metadata) = step
+                && metadata.has_data
+            {
+                let mut node = client
+                    .fetch_node(&NodeRequest::new(
+                        metadata.path.clone(),
+                        metadata.epoch,
+                        metadata.texture_format,
+                        metadata.imagery_epoch,
+                    ))
+                    .await?;
+                for mesh in &mut node.meshes {
+                    mesh.texture_data = Vec::new();
+                }
+                nodes.push(node);
+            }
+            Ok(())
+        },
+    )
+    .await?;
+
+    for level in 0..=max_level {
+        let depth = level as usize + NODE_LEVEL_OFFSET;
+        for tile in TileId::covering(&bounds, level) {
+            let tile_bounds = tile.bounds();
+            let tile_nodes: Vec<&Node> = nodes
+                .iter()
+                .filter(|node| node.path.len() <= depth && tile_bounds.intersects_obb(&node.obb))
+                .collect();
+            if !tile_nodes.is_empty() {
+                writer.write_tile(tile, &tile_nodes)?;
+            }
+        }
+    }
+    writer.finish()
+}
+
+/// Check that the tiles of every level up to `max_level` can be indexed.
+fn check_max_level(max_level: u32) -> Result<()> {
+    if max_level <= MAX_LEVEL {
+        return Ok(());
+    }
+    Err(Error::Unsupported {
+        format: "quantized-mesh",
+        detail: format!("level {max_level} is deeper than {MAX_LEVEL}"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raster::tests::terrain_node;
+
+    /// A decoded quantized-mesh tile.
+    struct Decoded {
+        min_height: f32,
+        max_height: f32,
+        vertices: Vec<[u16; 3]>,
+        triangles: Vec<u32>,
+        edges: Vec<Vec<u32>>,
+        normals: Option<Vec<[u8; 2]>>,
+    }
+
+    /// Reads little-endian values from a tile.
+    struct Reader<'a> {
+        data: &'a [u8],
+        pos: usize,
+    }
+
+    impl Reader<'_> {
+        fn take(&mut self, n: usize) -> &[u8] {
+            self.pos += n;
+            &self.data[self.pos - n..self.pos]
+        }
+
+        fn u16(&mut self) -> u16 {
+            u16::from_le_bytes(self.take(2).try_into().unwrap())
+        }
+
+        fn u32(&mut self) -> u32 {
+            u32::from_le_bytes(self.take(4).try_into().unwrap())
+        }
+
+        fn f32(&mut self) -> f32 {
+            f32::from_le_bytes(self.take(4).try_into().unwrap())
+        }
+    }
+
+    fn decode(data: &[u8]) -> Decoded {
+        let mut reader = Reader { data, pos: 24 };
+        let min_height = reader.f32();
+        let max_height = reader.f32();
+        reader.take(56);
+
+        let count = reader.u32() as usize;
+        let mut vertices = vec![[0u16; 3]; count];
+        for k in 0..3 {
+            let mut value = 0i32;
+            for vertex in &mut vertices {
+                let zigzag = i32::from(reader.u16());
+                value += (zigzag >> 1) ^ -(zigzag & 1);
+                vertex[k] = u16::try_from(value).unwrap();
+            }
+        }
+
+        let triangle_count = reader.u32() as usize;
+        let mut highest = 0;
+        let triangles = (0..triangle_count * 3)
+            .map(|_| {
+                let index = highest - u32::from(reader.u16());
+                if index == highest {
+                    highest += 1;
+                }
+                index
+            })
+            .collect();
+        let edges = (0..4)
+            .map(|_| {
+                let n = reader.u32() as usize;
+                (0..n).map(|_| u32::from(reader.u16())).collect()
+            })
+            .collect();
+        let normals = (reader.pos < data.len()).then(|| {
+            assert_eq!(reader.take(1)[0], EXTENSION_OCT_NORMALS);
+            let length = reader.u32() as usize;
+            reader
+                .take(length)
+                .chunks_exact(2)
+                .map(|pair| [pair[0], pair[1]])
+                .collect()
+        });
+        Decoded {
+            min_height,
+            max_height,
+            vertices,
+            triangles,
+            edges,
+            normals,
+        }
+    }
+
+    #[test]
+    fn test_tile_ids() {
+        let tile = TileId::new(1, 2, 1);
+        assert_eq!(tile.bounds(), GeoBounds::new(0.0, 0.0, 90.0, 90.0));
+
+        let bounds = GeoBounds::new(10.0, -100.0, 20.0, 10.0);
+        let tiles = TileId::covering(&bounds, 1);
+        assert_eq!(
+            tiles,
+            [
+                TileId::new(1, 0, 1),
+                TileId::new(1, 1, 1),
+                TileId::new(1, 2, 1)
+            ]
+        );
+        // Bounds crossing the antimeridian cover both ends of the row.
+        let bounds = GeoBounds::new(-10.0, 170.0, 10.0, -170.0);
+        let columns: Vec<u32> = TileId::covering(&bounds, 0)
+            .iter()
+            .map(|tile| tile.x)
+            .collect();
+        assert_eq!(columns, [0, 1]);
+    }
+
+    #[test]
+    fn test_write_tile() {
+        let dir = tempfile::tempdir().unwrap();
+        let tile = TileId::covering(&GeoBounds::new(10.001, 20.001, 10.002, 20.002), 14)[0];
+        let bounds = tile.bounds();
+        let covering = GeoBounds::new(
+            bounds.south - 0.001,
+            bounds.west - 0.001,
+            bounds.north + 0.001,
+            bounds.east + 0.001,
+        );
+        let node = terrain_node("0", &covering, 250.0);
+
+        let mut writer = TerrainWriter::create(dir.path())
+            .unwrap()
+            .with_grid_size(5)
+            .with_normals(true);
+        assert!(writer.write_tile(tile, &[&node]).unwrap());
+        // Tiles without terrain are skipped.
+        assert!(!writer.write_tile(TileId::new(14, 0, 0), &[&node]).unwrap());
+        assert_eq!(writer.tile_count(), 1);
+
+        let path = dir.path().join(format!("14/{}/{}.terrain", tile.x, tile.y));
+        let decoded = decode(&std::fs::read(path).unwrap());
+        assert!((decoded.min_height - 250.0).abs() < 1.0);
+        assert!((decoded.max_height - 250.0).abs() < 1.0);
+        assert_eq!(decoded.vertices.len(), 25);
+        assert_eq!(decoded.triangles.len(), 32 * 3);
+        assert_eq!(decoded.vertices[0][..2], [0, 0]);
+
+        // Edges hold the vertices along each side of the tile.
+        let [west, south, east, north] = [0, 1, 2, 3].map(|k| &decoded.edges[k]);
+        assert!(west.iter().all(|&i| decoded.vertices[i as usize][0] == 0));
+        assert!(south.iter().all(|&i| decoded.vertices[i as usize][1] == 0));
+        assert!(
+            east.iter()
+                .all(|&i| decoded.vertices[i as usize][0] == 32767)
+        );
+        assert!(
+            north
+                .iter()
+                .all(|&i| decoded.vertices[i as usize][1] == 32767)
+        );
+        assert_eq!(west.len(), 5);
+
+        // Triangles wind counter-clockwise in (u, v).
+        for triangle in decoded.triangles.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| {
+                let vertex = decoded.vertices[triangle[k] as usize];
+                (i64::from(vertex[0]), i64::from(vertex[1]))
+            });
+            assert!((b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0) > 0);
+        }
+
+        let normals = decoded.normals.unwrap();
+        assert_eq!(normals.len(), 25);
+
+        let layer: Value =
+            serde_json::from_str(&std::fs::read_to_string(writer.finish().unwrap()).unwrap())
+                .unwrap();
+        assert_eq!(layer["format"], "quantized-mesh-1.0");
+        assert_eq!(layer["extensions"], json!(["octvertexnormals"]));
+        assert_eq!(layer["available"].as_array().unwrap().len(), 15);
+        assert_eq!(
+            layer["available"][14],
+            json!([{"startX": tile.x, "startY": tile.y, "endX": tile.x, "endY": tile.y}])
+        );
+    }
+
+    #[test]
+    fn test_check_max_level() {
+        assert!(check_max_level(MAX_LEVEL).is_ok());
+        let err = check_max_level(MAX_LEVEL + 1).unwrap_err();
+        assert!(matches!(err, Error::Unsupported { .. }), "{err}");
+        // The deepest supported level does not overflow.
+        let tiles = TileId::covering(&GeoBounds::new(90.0, 180.0, 90.0, 180.0), MAX_LEVEL);
+        assert_eq!(
+            tiles,
+            [TileId::new(MAX_LEVEL, u32::MAX >> 1, u32::MAX >> 2)]
+        );
+    }
+
+    #[test]
+    fn test_available_ranges() {
+        let tiles = [(0, 0), (1, 0), (0, 1), (1, 1), (3, 1)].map(|(x, y)| TileId::new(3, x, y));
+        assert_eq!(
+            available_ranges(tiles.iter()),
+            [
+                json!({"startX": 0, "startY": 0, "endX": 1, "endY": 1}),
+                json!({"startX": 3, "startY": 1, "endX": 3, "endY": 1}),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_horizon_occlusion_point() {
+        let radius = WGS84_RADII.x;
+        let center = DVec3::new(radius, 0.0, 0.0);
+        let near = [
+            DVec3::new(radius, 1_000.0, 0.0),
+            DVec3::new(radius + 100.0, -1_000.0, 0.0),
+        ];
+        let point = horizon_occlusion_point(center, &near);
+        assert!(point.y.abs() < 1e-12 && point.z.abs() < 1e-12);
+        assert!(point.x > 1.0 && point.x < 1.001, "{point}");
+
+        // A vertex past the horizon of the direction has a negative
+        // magnitude, which no point along it can cover.
+        let angle = 100f64.to_radians();
+        let mut far = near.to_vec();
+        far.push(DVec3::new(angle.cos(), angle.sin(), 0.0) * radius);
+        let point = horizon_occlusion_point(center, &far);
+        assert_eq!(point, DVec3::X * FALLBACK_OCCLUSION_MAGNITUDE);
+    }
+
+    #[test]
+    fn test_oct_encode() {
+        assert_eq!(oct_encode(DVec3::Z), [128, 128]);
+        assert_eq!(oct_encode(DVec3::X), [255, 128]);
+        assert_eq!(oct_encode(DVec3::NEG_Z), [255, 255]);
+    }
+}
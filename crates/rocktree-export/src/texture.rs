@@ -0,0 +1,158 @@
+//! Texture conversion for exported meshes.
+
+use glam::Vec2;
+use image::{DynamicImage, ImageFormat, RgbaImage};
+use rocktree::{Mesh, TextureFormat};
+use std::io::Cursor;
+
+use crate::error::{Error, Result};
+
+/// Image format for exported textures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureEncoding {
+    /// Lossless PNG.
+    Png,
+    /// JPEG at the given quality, from 1 to 100.
+    Jpeg {
+        /// Encoder quality; higher is larger and more faithful.
+        quality: u8,
+    },
+}
+
+impl Default for TextureEncoding {
+    fn default() -> Self {
+        TextureEncoding::Jpeg { quality: 90 }
+    }
+}
+
+impl TextureEncoding {
+    /// Get the MIME type of encoded images.
+    #[must_use]
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            TextureEncoding::Png => "image/png",
+            TextureEncoding::Jpeg { .. } => "image/jpeg",
+        }
+    }
+
+    /// Get the file extension for encoded images, without the dot.
+    #[must_use]
+    pub fn extension(self) -> &'static str {
+        match self {
+            TextureEncoding::Png => "png",
+            TextureEncoding::Jpeg { .. } => "jpg",
+        }
+    }
+
+    /// Encode an image.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the encoder fails.
+    pub fn encode(self, image: &RgbaImage) -> Result<Vec<u8>> {
+        let mut data = Cursor::new(Vec::new());
+        match self {
+            TextureEncoding::Png => image.write_to(&mut data, ImageFormat::Png)?,
+            TextureEncoding::Jpeg { quality } => {
+                // JPEG has no alpha channel, and rocktree textures are opaque.
+                let rgb = DynamicImage::ImageRgba8(image.clone()).into_rgb8();
+                let encoder = image::codecs::jpeg::JpegEncoder::new_with_quality(
+                    &mut data,
+                    quality.clamp(1, 100),
+                );
+                rgb.write_with_encoder(encoder)?;
+            }
+        }
+        Ok(data.into_inner())
+    }
+}
+
+/// Get the texture of a mesh as an RGBA image.
+///
+/// # Errors
+///
+/// Returns an error if the texture data cannot be decoded.
+pub fn mesh_image(mesh: &Mesh) -> Result<RgbaImage> {
+    let (width, height) = (mesh.texture_width, mesh.texture_height);
+    let data = match mesh.texture_format {
+        TextureFormat::Rgba => mesh.texture_data.clone(),
+        TextureFormat::Rgb => mesh
+            .texture_data
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        TextureFormat::Dxt1 => {
+            let mut pixels = vec![0u32; width as usize * height as usize];
+            texture2ddecoder::decode_bc1(
+                &mesh.texture_data,
+                width as usize,
+                height as usize,
+                &mut pixels,
+            )
+            .map_err(|e| Error::Texture {
+                message: format!("failed to decode DXT1: {e}"),
+            })?;
+            // texture2ddecoder packs pixels as 0xAARRGGBB.
+            pixels
+                .into_iter()
+                .flat_map(|pixel| {
+                    let [b, g, r, a] = pixel.to_le_bytes();
+                    [r, g, b, a]
+                })
+                .collect()
+        }
+    };
+    RgbaImage::from_raw(width, height, data).ok_or_else(|| Error::Texture {
+        message: format!(
+            "{} bytes of texture data do not fill {width}x{height} pixels",
+            mesh.texture_data.len()
+        ),
+    })
+}
+
+/// Get the texel at `uv`, with `(0, 0)` at the top-left corner and
+/// `(1, 1)` at the bottom-right. Empty images read as opaque white.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)] // Texel indices are clamped to the image.
+pub(crate) fn sample_texel(image: &RgbaImage, uv: Vec2) -> [u8; 4] {
+    if image.width() == 0 || image.height() == 0 {
+        return [255; 4];
+    }
+    let x = (uv.x * image.width() as f32).clamp(0.0, (image.width() - 1) as f32) as u32;
+    let y = (uv.y * image.height() as f32).clamp(0.0, (image.height() - 1) as f32) as u32;
+    image.get_pixel(x, y).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_node;
+    use glam::DVec3;
+
+    #[test]
+    fn test_encode_round_trip() {
+        let node = quad_node("0", DVec3::ZERO);
+        let image = mesh_image(&node.meshes[0]).unwrap();
+        assert_eq!(image.get_pixel(1, 0).0, [0, 255, 0, 255]);
+
+        let png = TextureEncoding::Png.encode(&image).unwrap();
+        assert_eq!(image::load_from_memory(&png).unwrap().to_rgba8(), image);
+
+        let jpeg = TextureEncoding::default().encode(&image).unwrap();
+        let decoded = image::load_from_memory_with_format(&jpeg, ImageFormat::Jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (2, 2));
+    }
+
+    #[test]
+    fn test_mesh_image_size_mismatch() {
+        let mut node = quad_node("0", DVec3::ZERO);
+        node.meshes[0].texture_width = 3;
+        assert!(matches!(
+            mesh_image(&node.meshes[0]),
+            Err(Error::Texture { .. })
+        ));
+    }
+}
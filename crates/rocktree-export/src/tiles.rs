@@ -0,0 +1,391 @@
+//! OGC 3D Tiles export.
+//!
+//! Each rocktree node becomes a tile whose content is a GLB file, and the
+//! octree becomes the tile hierarchy: a node's tile is a child of the tile
+//! of its closest exported ancestor. Tiles use `REPLACE` refinement, as the
+//! children of a rocktree node together cover the same area at higher
+//! detail, and a node's meters per texel is used as its geometric error.
+//!
+//! `REPLACE` refinement hides a tile once all of its children are loaded,
+//! so octants whose child was not exported show up as holes. Export either
+//! none or all of a node's children; [`export_tileset`] exports every node
+//! intersecting a region, so only tiles along its edges can be incomplete.
+
+use glam::{DMat4, DVec3};
+use rocktree::{Cache, Client, GeoBounds, Node, NodeMetadata, NodeRequest, WalkStep, walk_region};
+use serde_json::{Value, json};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use crate::error::{Error, Result};
+use crate::frame::Frame;
+use crate::gltf::GltfBuilder;
+use crate::texture::TextureEncoding;
+
+/// Directory under the tileset holding tile content.
+const CONTENT_DIR: &str = "tiles";
+
+/// Writes a 3D Tiles tileset from rocktree nodes.
+///
+/// Tile content is written as tiles are added; the `tileset.json` that ties
+/// them together is written by [`TilesetWriter::finish`].
+#[derive(Debug)]
+pub struct TilesetWriter {
+    dir: PathBuf,
+    texture_encoding: TextureEncoding,
+    tiles: BTreeMap<String, Tile>,
+}
+
+/// A tile waiting to be placed in the hierarchy.
+#[derive(Debug)]
+struct Tile {
+    metadata: NodeMetadata,
+    content: Option<String>,
+}
+
+impl TilesetWriter {
+    /// Create a writer for a tileset in `dir`, creating the directory if
+    /// needed.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created.
+    pub fn create(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        let content_dir = dir.join(CONTENT_DIR);
+        std::fs::create_dir_all(&content_dir).map_err(|e| Error::io(&content_dir, e))?;
+        Ok(Self {
+            dir,
+            texture_encoding: TextureEncoding::default(),
+            tiles: BTreeMap::new(),
+        })
+    }
+
+    /// Set how textures are embedded in tile content.
+    #[must_use]
+    pub fn with_texture_encoding(mut self, encoding: TextureEncoding) -> Self {
+        self.texture_encoding = encoding;
+        self
+    }
+
+    /// Get the number of tiles added so far.
+    #[must_use]
+    pub fn tile_count(&self) -> usize {
+        self.tiles.len()
+    }
+
+    /// Add a tile for a node, with `node` as its content.
+    ///
+    /// Nodes without data can be added with no content to keep the
+    /// hierarchy connected.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the content cannot be encoded or written.
+    pub fn add_tile(&mut self, metadata: &NodeMetadata, node: Option<&Node>) -> Result<()> {
+        let content = match node {
+            Some(node) => {
+                let mut builder = GltfBuilder::new(Frame::ecef_offset(metadata.obb.center))
+                    .with_texture_encoding(self.texture_encoding);
+                builder.add_node(node, metadata.epoch)?;
+                let uri = format!("{CONTENT_DIR}/{}.glb", metadata.path);
+                builder.write(self.dir.join(&uri))?;
+                Some(uri)
+            }
+            None => None,
+        };
+        self.tiles.insert(
+            metadata.path.clone(),
+            Tile {
+                metadata: metadata.clone(),
+                content,
+            },
+        );
+        Ok(())
+    }
+
+    /// Write `tileset.json` and return its path.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Unsupported`] if no tiles were added, or an error if
+    /// writing `tileset.json` fails.
+    pub fn finish(self) -> Result<PathBuf> {
+        let mut children: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        let mut top = Vec::new();
+        for path in self.tiles.keys() {
+            // Attach each tile to its closest exported ancestor.
+            match (0..path.len())
+                .rev()
+                .map(|len| &path[..len])
+                .find(|ancestor| self.tiles.contains_key(*ancestor))
+            {
+                Some(parent) => children.entry(parent).or_default().push(path),
+                None => top.push(path.as_str()),
+            }
+        }
+
+        let root = match top.as_slice() {
+            [] => {
+                return Err(Error::Unsupported {
+                    format: "3d tiles",
+                    detail: "no tiles were added".to_string(),
+                });
+            }
+            [path] => self.tile_json(path, DVec3::ZERO, &children),
+            paths => {
+                // Group several top-level tiles under a root without content
+                // that encloses them all.
+                let (min, max) = paths
+                    .iter()
+                    .flat_map(|path| box_corners(&self.tiles[*path].metadata))
+                    .fold(
+                        (DVec3::INFINITY, DVec3::NEG_INFINITY),
+                        |(min, max), corner| (min.min(corner), max.max(corner)),
+                    );
+                let center = (min + max) / 2.0;
+                let half = (max - min) / 2.0;
+                let error = paths
+                    .iter()
+                    .map(|path| f64::from(self.tiles[*path].metadata.meters_per_texel))
+                    .fold(0.0, f64::max);
+                json!({
+                    "boundingVolume": {
+                        "box": [
+                            center.x, center.y, center.z,
+                            half.x, 0.0, 0.0,
+                            0.0, half.y, 0.0,
+                            0.0, 0.0, half.z,
+                        ],
+                    },
+                    "geometricError": error * 2.0,
+                    "refine": "REPLACE",
+                    "children": paths
+                        .iter()
+                        .map(|path| self.tile_json(path, DVec3::ZERO, &children))
+                        .collect::<Vec<_>>(),
+                })
+            }
+        };
+
+        let tileset = json!({
+            "asset": {
+                "version": "1.0",
+                "generator": concat!("rocktree-export ", env!("CARGO_PKG_VERSION")),
+            },
+            "geometricError": root["geometricError"],
+            "root": root,
+        });
+        let path = self.dir.join("tileset.json");
+        let text = serde_json::to_string_pretty(&tileset).expect("tileset is valid JSON");
+        std::fs::write(&path, text).map_err(|e| Error::io(&path, e))?;
+        Ok(path)
+    }
+
+    /// Build the JSON for a tile and its descendants.
+    ///
+    /// Tile transforms compose with their parent's, so each tile is
+    /// translated from its parent's center, `parent_center`, to its own.
+    fn tile_json(
+        &self,
+        path: &str,
+        parent_center: DVec3,
+        children: &BTreeMap<&str, Vec<&str>>,
+    ) -> Value {
+        let tile = &self.tiles[path];
+        let obb = &tile.metadata.obb;
+        let axes = [0, 1, 2].map(|i| obb.orientation.col(i) * obb.extents[i]);
+        let children: Vec<Value> = children
+            .get(path)
+            .into_iter()
+            .flatten()
+            .map(|child| self.tile_json(child, obb.center, children))
+            .collect();
+
+        let mut json = json!({
+            "transform": DMat4::from_translation(obb.center - parent_center).to_cols_array(),
+            "boundingVolume": {
+                "box": [
+                    0.0, 0.0, 0.0,
+                    axes[0].x, axes[0].y, axes[0].z,
+                    axes[1].x, axes[1].y, axes[1].z,
+                    axes[2].x, axes[2].y, axes[2].z,
+                ],
+            },
+            // Leaves have nothing to refine to.
+            "geometricError": if children.is_empty() {
+                0.0
+            } else {
+                f64::from(tile.metadata.meters_per_texel)
+            },
+            "refine": "REPLACE",
+            "extras": {
+                "path": path,
+                "epoch": tile.metadata.epoch,
+            },
+        });
+        if let Some(uri) = &tile.content {
+            json["content"] = json!({ "uri": uri });
+        }
+        if !children.is_empty() {
+            json["children"] = json!(children);
+        }
+        json
+    }
+}
+
+/// Get the corners of a node's bounding box in ECEF.
+fn box_corners(metadata: &NodeMetadata) -> impl Iterator<Item = DVec3> + '_ {
+    let obb = &metadata.obb;
+    (0..8).map(move |i| {
+        let sign = |bit: usize| if i & (1 << bit) == 0 { -1.0 } else { 1.0 };
+        obb.center
+            + obb.orientation
+                * DVec3::new(
+                    sign(0) * obb.extents.x,
+                    sign(1) * obb.extents.y,
+                    sign(2) * obb.extents.z,
+                )
+    })
+}
+
+/// Export everything within `bounds` up to `max_level` as tiles written by
+/// `writer`, then finish the tileset.
+///
+/// Like [`rocktree::export_region`], the octree is walked with
+/// [`walk_region`], descending into nodes above `max_level` and following
+/// the client's fetch policy. Every node reached becomes a tile, with
+/// content if it has data.
+///
+/// Returns the path of the written `tileset.json`.
+///
+/// # Errors
+///
+/// Returns an error if a fetch fails or a tile cannot be written.
+pub async fn export_tileset<C: Cache + 'static>(
+    client: &Client<C>,
+    bounds: GeoBounds,
+    max_level: usize,
+    mut writer: TilesetWriter,
+) -> Result<PathBuf> {
+    walk_region(
+        client,
+        &bounds,
+        |metadata| metadata.path.len() < max_level,
+        async |step| match step {
+            WalkStep::Node(metadata) if metadata.has_data => {
+                let node = client
+                    .fetch_node(&NodeRequest::new(
+                        metadata.path.clone(),
+                        metadata.epoch,
+                        metadata.texture_format,
+                        metadata.imagery_epoch,
+                    ))
+                    .await?;
+                writer.add_tile(metadata, Some(&node))
+            }
+            WalkStep::Node(metadata) => writer.add_tile(metadata, None),
+            _ => Ok(()),
+        },
+    )
+    .await?;
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::tests::quad_node;
+    use std::path::Path;
+
+    fn metadata(node: &Node, meters_per_texel: f32) -> NodeMetadata {
+        NodeMetadata {
+            path: node.path.clone(),
+            meters_per_texel,
+            obb: node.obb,
+            has_data: true,
+            flags: 0,
+            epoch: 3,
+            texture_format: 6,
+            imagery_epoch: None,
+        }
+    }
+
+    fn read_tileset(path: &Path) -> Value {
+        serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_tileset_hierarchy() {
+        let dir = tempfile::tempdir().unwrap();
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let root = quad_node("0", center);
+        let child = quad_node("01", center + DVec3::Y * 100.0);
+        // "012" has no data, so "0123" hangs below it without content.
+        let empty = NodeMetadata {
+            has_data: false,
+            ..metadata(&quad_node("012", center), 0.5)
+        };
+        let grandchild = quad_node("0123", center + DVec3::Y * 150.0);
+
+        let mut writer = TilesetWriter::create(dir.path())
+            .unwrap()
+            .with_texture_encoding(TextureEncoding::Png);
+        writer.add_tile(&metadata(&root, 4.0), Some(&root)).unwrap();
+        writer
+            .add_tile(&metadata(&child, 2.0), Some(&child))
+            .unwrap();
+        writer.add_tile(&empty, None).unwrap();
+        writer
+            .add_tile(&metadata(&grandchild, 0.25), Some(&grandchild))
+            .unwrap();
+        assert_eq!(writer.tile_count(), 4);
+        let path = writer.finish().unwrap();
+
+        let tileset = read_tileset(&path);
+        assert_eq!(tileset["asset"]["version"], "1.0");
+        assert_eq!(tileset["geometricError"], 4.0);
+        let root = &tileset["root"];
+        assert_eq!(root["refine"], "REPLACE");
+        assert_eq!(root["content"]["uri"], "tiles/0.glb");
+        assert_eq!(root["transform"][12], center.x);
+        assert_eq!(root["boundingVolume"]["box"][3], 1.0);
+
+        let child = &root["children"][0];
+        assert_eq!(child["extras"]["path"], "01");
+        assert_eq!(child["transform"][13], 100.0);
+        let empty = &child["children"][0];
+        assert!(empty.get("content").is_none());
+        assert_eq!(empty["geometricError"], 0.5);
+        assert_eq!(empty["transform"][13], -100.0);
+        let leaf = &empty["children"][0];
+        assert_eq!(leaf["geometricError"], 0.0);
+        assert!(leaf.get("children").is_none());
+
+        let glb = std::fs::read(dir.path().join("tiles/0123.glb")).unwrap();
+        let document = gltf::Gltf::from_slice(&glb).unwrap();
+        assert_eq!(document.nodes().next().unwrap().name(), Some("0123"));
+    }
+
+    #[test]
+    fn test_tileset_with_several_roots() {
+        let dir = tempfile::tempdir().unwrap();
+        let center = DVec3::new(6_378_137.0, 0.0, 0.0);
+        let mut writer = TilesetWriter::create(dir.path()).unwrap();
+        for (path, offset) in [("02", -10.0), ("03", 10.0)] {
+            let node = quad_node(path, center + DVec3::Y * offset);
+            writer.add_tile(&metadata(&node, 1.0), Some(&node)).unwrap();
+        }
+        let tileset = read_tileset(&writer.finish().unwrap());
+        let root = &tileset["root"];
+        assert!(root.get("content").is_none());
+        assert_eq!(root["geometricError"], 2.0);
+        assert_eq!(root["children"].as_array().unwrap().len(), 2);
+        let bounds = &root["boundingVolume"]["box"];
+        assert_eq!(bounds[0], center.x);
+        assert_eq!(bounds[7], 11.0);
+
+        let empty = TilesetWriter::create(dir.path().join("empty")).unwrap();
+        assert!(matches!(empty.finish(), Err(Error::Unsupported { .. })));
+    }
+}
@@ -0,0 +1,615 @@
+//! Web Mercator imagery tile pyramids.
+//!
+//! Textures are rendered top-down into square tiles of the XYZ scheme used
+//! by web maps (EPSG:3857, with row 0 at the north edge), and written either
+//! as a `{z}/{x}/{y}` directory tree or, with the `mbtiles` feature, as an
+//! MBTiles SQLite file.
+//!
+//! Tiles are rendered in square blocks, each from the nodes whose meters
+//! per texel are no coarser than the finest ground resolution among the
+//! block's tiles, so deeper zooms use deeper octree levels and tiles far
+//! from the equator do not fetch detail meant for tiles near it.
+
+use glam::DVec2;
+use image::RgbaImage;
+use rocktree::{Cache, Client, GeoBounds};
+use std::collections::BTreeMap;
+use std::f64::consts::PI;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+
+use crate::error::{Error, Result};
+use crate::ortho::Orthophoto;
+use crate::raster::visit_nodes;
+use crate::texture::TextureEncoding;
+
+/// Equatorial radius of the WGS84 ellipsoid, as used by Web Mercator.
+const EARTH_RADIUS: f64 = 6_378_137.0;
+
+/// Half the width of the Web Mercator plane in meters.
+pub const MERCATOR_EXTENT: f64 = PI * EARTH_RADIUS;
+
+/// Latitude of the north and south edges of the Web Mercator plane.
+pub const MAX_LATITUDE: f64 = 85.051_128_779_806_59;
+
+/// Deepest zoom level whose tile indices fit in a `u32`.
+pub const MAX_ZOOM: u32 = 31;
+
+/// Width and height in tiles of the blocks [`export_xyz`] renders at once.
+const BLOCK_SIZE: u32 = 8;
+
+/// Project a longitude and latitude in degrees to Web Mercator meters.
+///
+/// Latitudes are clamped to [`MAX_LATITUDE`].
+#[must_use]
+pub fn to_web_mercator(longitude: f64, latitude: f64) -> DVec2 {
+    let latitude = latitude.clamp(-MAX_LATITUDE, MAX_LATITUDE).to_radians();
+    DVec2::new(
+        EARTH_RADIUS * longitude.to_radians(),
+        EARTH_RADIUS * (PI / 4.0 + latitude / 2.0).tan().ln(),
+    )
+}
+
+/// Get the longitude and latitude in degrees of a Web Mercator position.
+#[must_use]
+pub fn from_web_mercator(position: DVec2) -> (f64, f64) {
+    (
+        (position.x / EARTH_RADIUS).to_degrees(),
+        (position.y / EARTH_RADIUS).sinh().atan().to_degrees(),
+    )
+}
+
+/// A tile in the XYZ Web Mercator scheme.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct XyzTile {
+    /// Zoom level; level 0 is a single tile covering the world.
+    ///
+    /// Levels deeper than [`MAX_ZOOM`] are not supported.
+    pub zoom: u32,
+    /// Column, counted from the antimeridian eastward.
+    pub x: u32,
+    /// Row, counted from the north edge southward.
+    pub y: u32,
+}
+
+impl XyzTile {
+    /// Create a tile ID.
+    #[must_use]
+    pub fn new(zoom: u32, x: u32, y: u32) -> Self {
+        Self { zoom, x, y }
+    }
+
+    /// Get the size of tiles at `zoom` in Web Mercator meters.
+    #[must_use]
+    pub fn size(zoom: u32) -> f64 {
+        2.0 * MERCATOR_EXTENT / f64::from(1u32 << zoom)
+    }
+
+    /// Get the ground size in meters of a pixel at `latitude`, for tiles at
+    /// `zoom` that are `tile_size` pixels across.
+    #[must_use]
+    pub fn meters_per_pixel(zoom: u32, latitude: f64, tile_size: u32) -> f64 {
+        Self::size(zoom) / f64::from(tile_size) * latitude.to_radians().cos()
+    }
+
+    /// Get the south-west and north-east corners of the tile in Web
+    /// Mercator meters.
+    #[must_use]
+    pub fn mercator_bounds(&self) -> (DVec2, DVec2) {
+        let size = Self::size(self.zoom);
+        let west = -MERCATOR_EXTENT + f64::from(self.x) * size;
+        let north = MERCATOR_EXTENT - f64::from(self.y) * size;
+        (
+            DVec2::new(west, north - size),
+            DVec2::new(west + size, north),
+        )
+    }
+
+    /// Get the area covered by the tile.
+    #[must_use]
+    pub fn bounds(&self) -> GeoBounds {
+        let (min, max) = self.mercator_bounds();
+        let (west, south) = from_web_mercator(min);
+        let (east, north) = from_web_mercator(max);
+        GeoBounds::new(south, west, north, east)
+    }
+
+    /// Get the row of the tile counted from the south edge, as in TMS and
+    /// MBTiles.
+    #[must_use]
+    pub fn tms_row(&self) -> u32 {
+        (1u32 << self.zoom) - 1 - self.y
+    }
+
+    /// Get the tiles at `zoom` that intersect `bounds`.
+    #[must_use]
+    pub fn covering(bounds: &GeoBounds, zoom: u32) -> Vec<XyzTile> {
+        // Split bounds crossing the antimeridian into two.
+        let spans = if bounds.west <= bounds.east {
+            vec![(bounds.west, bounds.east)]
+        } else {
+            vec![(bounds.west, 180.0), (-180.0, bounds.east)]
+        };
+        // Rows are counted from the north, so the north edge comes first.
+        let rows = tile_range(
+            MERCATOR_EXTENT - to_web_mercator(0.0, bounds.north).y,
+            MERCATOR_EXTENT - to_web_mercator(0.0, bounds.south).y,
+            zoom,
+        );
+        let mut tiles = Vec::new();
+        for (west, east) in spans {
+            let columns = tile_range(
+                to_web_mercator(west, 0.0).x + MERCATOR_EXTENT,
+                to_web_mercator(east, 0.0).x + MERCATOR_EXTENT,
+                zoom,
+            );
+            for x in columns {
+                tiles.extend(rows.clone().map(|y| XyzTile::new(zoom, x, y)));
+            }
+        }
+        tiles.sort();
+        tiles.dedup();
+        tiles
+    }
+}
+
+/// Get the indices of tiles at `zoom` overlapping `[start, end]` meters
+/// from the origin of the scheme.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)] // Clamped to the tile count.
+fn tile_range(start: f64, end: f64, zoom: u32) -> std::ops::Range<u32> {
+    let size = XyzTile::size(zoom);
+    let count = 1u32 << zoom;
+    let first = (start / size).floor().clamp(0.0, f64::from(count - 1)) as u32;
+    let last = (end / size)
+        .ceil()
+        .clamp(f64::from(first + 1), f64::from(count)) as u32;
+    first..last
+}
+
+/// Where tiles are written.
+#[derive(Debug)]
+enum Store {
+    /// A `{z}/{x}/{y}` directory tree.
+    Directory(PathBuf),
+    /// An MBTiles file, written in a single transaction.
+    #[cfg(feature = "mbtiles")]
+    MbTiles {
+        path: PathBuf,
+        connection: rusqlite::Connection,
+    },
+}
+
+/// Writes a Web Mercator tile pyramid.
+///
+/// ```ignore
+/// let mut writer = XyzWriter::create_dir("tiles")?;
+/// writer.write_tile(tile, ortho.image())?;
+/// writer.finish()?;
+/// ```
+#[derive(Debug)]
+pub struct XyzWriter {
+    store: Store,
+    encoding: TextureEncoding,
+    tile_size: u32,
+    /// Number of tiles written.
+    count: usize,
+    /// Smallest and largest zoom written.
+    zooms: Option<(u32, u32)>,
+    /// Union of the bounds of the tiles written.
+    bounds: Option<GeoBounds>,
+}
+
+impl XyzWriter {
+    fn new(store: Store) -> Self {
+        Self {
+            store,
+            encoding: TextureEncoding::Png,
+            tile_size: 256,
+            count: 0,
+            zooms: None,
+            bounds: None,
+        }
+    }
+
+    /// Create a writer for tiles in a `{z}/{x}/{y}` tree under `dir`, which
+    /// is created if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created.
+    pub fn create_dir(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+        Ok(Self::new(Store::Directory(dir)))
+    }
+
+    /// Create a writer for tiles in a new MBTiles file at `path`.
+    ///
+    /// An existing file at `path` is replaced.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if an existing file cannot be removed or the
+    /// database cannot be created.
+    #[cfg(feature = "mbtiles")]
+    pub fn create_mbtiles(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                return Err(Error::io(&path, e));
+            }
+            _ => {}
+        }
+        let connection = rusqlite::Connection::open(&path).map_err(mbtiles_error)?;
+        connection
+            .execute_batch(
+                "CREATE TABLE metadata (name TEXT, value TEXT);
+                 CREATE TABLE tiles (
+                     zoom_level INTEGER,
+                     tile_column INTEGER,
+                     tile_row INTEGER,
+                     tile_data BLOB
+                 );
+                 CREATE UNIQUE INDEX tile_index ON tiles (zoom_level, tile_column, tile_row);
+                 BEGIN;",
+            )
+            .map_err(mbtiles_error)?;
+        Ok(Self::new(Store::MbTiles { path, connection }))
+    }
+
+    /// Set how tile images are encoded.
+    ///
+    /// Defaults to PNG, which keeps pixels without imagery transparent.
+    #[must_use]
+    pub fn with_encoding(mut self, encoding: TextureEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Set the width and height of tiles in pixels.
+    ///
+    /// Defaults to 256. Values are clamped to between 1 and 4096.
+    #[must_use]
+    pub fn with_tile_size(mut self, size: u32) -> Self {
+        self.tile_size = size.clamp(1, 4096);
+        self
+    }
+
+    /// Get the width and height of tiles in pixels.
+    #[must_use]
+    pub fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+
+    /// Get the number of tiles written so far.
+    #[must_use]
+    pub fn tile_count(&self) -> usize {
+        self.count
+    }
+
+    /// Create an empty orthophoto of `tile` at this writer's tile size.
+    ///
+    /// # Errors
+    ///
+    /// Returns [`Error::Unsupported`] if the tile has too many pixels, which
+    /// the clamped tile size rules out in practice.
+    pub fn orthophoto(&self, tile: XyzTile) -> Result<Orthophoto> {
+        let (min, max) = tile.mercator_bounds();
+        Orthophoto::web_mercator(min, max, self.tile_size, self.tile_size)
+    }
+
+    /// Encode `image` and write it as `tile`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if encoding or writing the tile fails.
+    pub fn write_tile(&mut self, tile: XyzTile, image: &RgbaImage) -> Result<()> {
+        let data = self.encoding.encode(image)?;
+        match &self.store {
+            Store::Directory(root) => {
+                let dir = root.join(tile.zoom.to_string()).join(tile.x.to_string());
+                std::fs::create_dir_all(&dir).map_err(|e| Error::io(&dir, e))?;
+                let path = dir.join(format!("{}.{}", tile.y, self.encoding.extension()));
+                std::fs::write(&path, data).map_err(|e| Error::io(&path, e))?;
+            }
+            #[cfg(feature = "mbtiles")]
+            Store::MbTiles { connection, .. } => {
+                connection
+                    .execute(
+                        "INSERT OR REPLACE INTO tiles VALUES (?1, ?2, ?3, ?4)",
+                        rusqlite::params![tile.zoom, tile.x, tile.tms_row(), data],
+                    )
+                    .map_err(mbtiles_error)?;
+            }
+        }
+
+        self.count += 1;
+        self.zooms = Some(match self.zooms {
+            Some((min, max)) => (min.min(tile.zoom), max.max(tile.zoom)),
+            None => (tile.zoom, tile.zoom),
+        });
+        let tile_bounds = tile.bounds();
+        self.bounds = Some(match self.bounds {
+            Some(bounds) => GeoBounds::new(
+                bounds.south.min(tile_bounds.south),
+                bounds.west.min(tile_bounds.west),
+                bounds.north.max(tile_bounds.north),
+                bounds.east.max(tile_bounds.east),
+            ),
+            None => tile_bounds,
+        });
+        Ok(())
+    }
+
+    /// Finish the pyramid, writing the MBTiles metadata if needed.
+    ///
+    /// Returns the path of the directory or file written.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the MBTiles metadata cannot be written.
+    pub fn finish(self) -> Result<PathBuf> {
+        match self.store {
+            Store::Directory(dir) => Ok(dir),
+            #[cfg(feature = "mbtiles")]
+            Store::MbTiles { path, connection } => {
+                let name = path
+                    .file_stem()
+                    .map_or_else(|| "rocktree".into(), |stem| stem.to_string_lossy());
+                let mut metadata = vec![
+                    ("name", name.into_owned()),
+                    ("format", self.encoding.extension().replace("jpg", "jpeg")),
+                    ("type", "baselayer".to_string()),
+                ];
+                if let Some((min, max)) = self.zooms {
+                    metadata.push(("minzoom", min.to_string()));
+                    metadata.push(("maxzoom", max.to_string()));
+                }
+                if let Some(bounds) = self.bounds {
+                    let corners = [bounds.west, bounds.south, bounds.east, bounds.north];
+                    metadata.push(("bounds", corners.map(|v| v.to_string()).join(",")));
+                }
+                for (name, value) in metadata {
+                    connection
+                        .execute("INSERT INTO metadata VALUES (?1, ?2)", [name, &value])
+                        .map_err(mbtiles_error)?;
+                }
+                connection.execute_batch("COMMIT").map_err(mbtiles_error)?;
+                Ok(path)
+            }
+        }
+    }
+}
+
+#[cfg(feature = "mbtiles")]
+#[allow(clippy::needless_pass_by_value)] // Used with `map_err`.
+fn mbtiles_error(e: rusqlite::Error) -> Error {
+    Error::Unsupported {
+        format: "MBTiles",
+        detail: e.to_string(),
+    }
+}
+
+/// Render the imagery within `bounds` as tiles at each of `zooms` written
+/// by `writer`, then finish the pyramid.
+///
+/// The tiles of each zoom are rendered in square blocks of up to 64 tiles,
+/// so memory use does not grow with the size of `bounds`. For each block,
+/// the nodes covering it are fetched once, as by
+/// [`export_orthophoto`](crate::export_orthophoto), with the finest ground
+/// resolution among its tiles as the target, and each node is rendered into
+/// every tile of the block it overlaps. Coarse nodes shared by neighboring
+/// blocks are fetched again for each, so a client with a cache is
+/// recommended. Tiles no node covers are skipped.
+///
+/// Returns the path of the directory or file written.
+///
+/// # Errors
+///
+/// Returns [`Error::Unsupported`] if `zooms` goes deeper than
+/// [`MAX_ZOOM`], or an error if fetching, rendering, or writing a tile
+/// fails.
+pub async fn export_xyz<C: Cache + 'static>(
+    client: &Client<C>,
+    bounds: GeoBounds,
+    zooms: RangeInclusive<u32>,
+    mut writer: XyzWriter,
+) -> Result<PathBuf> {
+    check_zooms(&zooms)?;
+    for zoom in zooms {
+        for block in blocks(XyzTile::covering(&bounds, zoom)) {
+            let mut tiles: Vec<(XyzTile, GeoBounds, Orthophoto)> = block
+                .into_iter()
+                .map(|tile| Ok((tile, tile.bounds(), writer.orthophoto(tile)?)))
+                .collect::<Result<_>>()?;
+            let block_bounds = tiles
+                .iter()
+                .map(|(_, tile_bounds, _)| *tile_bounds)
+                .reduce(|a, b| {
+                    GeoBounds::new(
+                        a.south.min(b.south),
+                        a.west.min(b.west),
+                        a.north.max(b.north),
+                        a.east.max(b.east),
+                    )
+                })
+                .expect("blocks are not empty");
+            let target = tiles
+                .iter()
+                .map(|(_, _, ortho)| ortho.pixel_size_meters())
+                .fold(f64::INFINITY, f64::min);
+            visit_nodes(client, &block_bounds, target, |node| {
+                let node_bounds = GeoBounds::enclosing_obb(&node.obb);
+                for (_, tile_bounds, ortho) in &mut tiles {
+                    if tile_bounds.intersects(&node_bounds) {
+                        ortho.add_node(node)?;
+                    }
+                }
+                Ok(())
+            })
+            .await?;
+            for (tile, _, ortho) in &tiles {
+                if !ortho.is_empty() {
+                    writer.write_tile(*tile, ortho.image())?;
+                }
+            }
+        }
+    }
+    writer.finish()
+}
+
+/// Check that the tiles of every zoom in `zooms` can be indexed.
+fn check_zooms(zooms: &RangeInclusive<u32>) -> Result<()> {
+    if zooms.is_empty() || *zooms.end() <= MAX_ZOOM {
+        return Ok(());
+    }
+    Err(Error::Unsupported {
+        format: "XYZ tiles",
+        detail: format!("zoom {} is deeper than {MAX_ZOOM}", zooms.end()),
+    })
+}
+
+/// Group `tiles` into square blocks of up to [`BLOCK_SIZE`] tiles a side.
+///
+/// The tiles of a block are contiguous columns and rows, so a block never
+/// crosses the antimeridian.
+fn blocks(tiles: Vec<XyzTile>) -> Vec<Vec<XyzTile>> {
+    let mut blocks: BTreeMap<(u32, u32), Vec<XyzTile>> = BTreeMap::new();
+    for tile in tiles {
+        blocks
+            .entry((tile.x / BLOCK_SIZE, tile.y / BLOCK_SIZE))
+            .or_default()
+            .push(tile);
+    }
+    blocks.into_values().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tile_bounds() {
+        let (min, max) = XyzTile::new(0, 0, 0).mercator_bounds();
+        assert!((min + DVec2::splat(MERCATOR_EXTENT)).length() < 1e-6);
+        assert!((max - DVec2::splat(MERCATOR_EXTENT)).length() < 1e-6);
+
+        let bounds = XyzTile::new(1, 1, 0).bounds();
+        assert!(bounds.south.abs() < 1e-9 && (bounds.north - MAX_LATITUDE).abs() < 1e-9);
+        assert!(bounds.west.abs() < 1e-9 && (bounds.east - 180.0).abs() < 1e-9);
+        assert_eq!(XyzTile::new(1, 1, 0).tms_row(), 1);
+
+        let position = to_web_mercator(13.4, 52.5);
+        let (longitude, latitude) = from_web_mercator(position);
+        assert!((longitude - 13.4).abs() < 1e-9 && (latitude - 52.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_covering_and_resolution() {
+        // A small area north-east of the origin lies in the south-west
+        // quarter of the north-east quadrant.
+        let bounds = GeoBounds::new(1.0, 1.0, 2.0, 2.0);
+        assert_eq!(XyzTile::covering(&bounds, 2), [XyzTile::new(2, 2, 1)]);
+        // Crossing the antimeridian takes both edge columns.
+        let bounds = GeoBounds::new(-1.0, 179.0, 1.0, -179.0);
+        let columns: Vec<u32> = XyzTile::covering(&bounds, 1).iter().map(|t| t.x).collect();
+        assert_eq!(columns, [0, 0, 1, 1]);
+
+        let equator = XyzTile::meters_per_pixel(0, 0.0, 256);
+        assert!((equator - 156_543.034).abs() < 1e-3);
+        let north = XyzTile::meters_per_pixel(1, 60.0, 256);
+        assert!((north - equator / 4.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_blocks() {
+        let tiles = XyzTile::covering(&GeoBounds::new(-1.0, 179.0, 1.0, -179.0), 6);
+        let count = tiles.len();
+        let blocks = blocks(tiles);
+        assert_eq!(blocks.iter().map(Vec::len).sum::<usize>(), count);
+        for block in &blocks {
+            assert!(block.len() <= (BLOCK_SIZE * BLOCK_SIZE) as usize);
+            // No block spans the antimeridian.
+            let first = block[0].x / BLOCK_SIZE;
+            assert!(block.iter().all(|tile| tile.x / BLOCK_SIZE == first));
+        }
+        // Both edge columns, on both sides of the equator.
+        assert_eq!(blocks.len(), 4);
+    }
+
+    #[test]
+    fn test_check_zooms() {
+        assert!(check_zooms(&(0..=MAX_ZOOM)).is_ok());
+        // An empty range renders nothing, however deep.
+        assert!(check_zooms(&RangeInclusive::new(40, 35)).is_ok());
+        let err = check_zooms(&(30..=32)).unwrap_err();
+        assert!(matches!(err, Error::Unsupported { .. }), "{err}");
+        // The deepest supported zoom does not overflow.
+        assert_eq!(XyzTile::new(MAX_ZOOM, 0, 0).tms_row(), u32::MAX >> 1);
+        assert_eq!(
+            XyzTile::covering(&GeoBounds::new(1.0, 1.0, 1.0, 1.0), MAX_ZOOM).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_write_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = XyzWriter::create_dir(dir.path()).unwrap().with_tile_size(4);
+        let image = RgbaImage::from_pixel(4, 4, image::Rgba([0, 0, 255, 255]));
+        writer.write_tile(XyzTile::new(3, 4, 2), &image).unwrap();
+        assert_eq!(writer.tile_count(), 1);
+        assert_eq!(writer.finish().unwrap(), dir.path());
+
+        let png = image::open(dir.path().join("3/4/2.png")).unwrap();
+        assert_eq!(png.to_rgba8(), image);
+    }
+
+    #[cfg(feature = "mbtiles")]
+    #[test]
+    fn test_write_mbtiles() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("imagery.mbtiles");
+        let mut writer = XyzWriter::create_mbtiles(&path)
+            .unwrap()
+            .with_encoding(TextureEncoding::Jpeg { quality: 80 });
+        let image = RgbaImage::from_pixel(256, 256, image::Rgba([0, 0, 255, 255]));
+        writer.write_tile(XyzTile::new(2, 1, 0), &image).unwrap();
+        writer.write_tile(XyzTile::new(3, 2, 1), &image).unwrap();
+        assert_eq!(writer.finish().unwrap(), path);
+
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        let metadata = |name: &str| -> String {
+            connection
+                .query_row(
+                    "SELECT value FROM metadata WHERE name = ?1",
+                    [name],
+                    |row| row.get(0),
+                )
+                .unwrap()
+        };
+        assert_eq!(metadata("name"), "imagery");
+        assert_eq!(metadata("format"), "jpeg");
+        assert_eq!(
+            (metadata("minzoom"), metadata("maxzoom")),
+            ("2".into(), "3".into())
+        );
+        // Rows are flipped to count from the south.
+        let row: u32 = connection
+            .query_row(
+                "SELECT tile_row FROM tiles WHERE zoom_level = 2 AND tile_column = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(row, 3);
+        let data: Vec<u8> = connection
+            .query_row(
+                "SELECT tile_data FROM tiles WHERE zoom_level = 3",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(data[..2], [0xff, 0xd8]);
+    }
+}
@@ -43,10 +43,13 @@ fn main() -> io::Result<()> {
 
         println!("Successfully generated protobuf types!");
     } else {
-        eprintln!("Warning: Expected generated file not found: {generated_file:?}");
+        eprintln!(
+            "Warning: Expected generated file not found: {}",
+            generated_file.display()
+        );
         eprintln!("Available files in output directory:");
         for entry in fs::read_dir(&out_dir)? {
-            eprintln!("  {:?}", entry?.path());
+            eprintln!("  {}", entry?.path().display());
         }
     }
 
@@ -0,0 +1,131 @@
+//! Local caching proxy for the Google Earth rocktree API.
+//!
+//! Serves `PlanetoidMetadata`, `BulkMetadata`, and `NodeData` under the same
+//! URL scheme as the origin, so several viewers and batch jobs can share one
+//! cache. Point a client at it with `Client::with_base_url`:
+//!
+//! ```sh
+//! cargo run -p rocktree-proxy -- --cache-dir ~/.cache/rocktree
+//! ```
+//!
+//! and use `http://127.0.0.1:8080/` as the base URL.
+
+mod proxy;
+
+use clap::Parser;
+use proxy::Proxy;
+use rocktree::{Cache, Client, DiskCache, FetchPolicy, LayeredCache, MemoryCache};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+
+/// Local caching proxy for the Google Earth rocktree API.
+#[derive(Debug, Parser)]
+#[command(version)]
+struct Args {
+    /// Address to listen on.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+    /// Base URL of the upstream server. Defaults to Google Earth.
+    #[arg(long)]
+    upstream: Option<String>,
+    /// Directory for the persistent cache shared by all clients.
+    #[arg(long)]
+    cache_dir: Option<PathBuf>,
+    /// Memory budget for cached responses, in megabytes.
+    #[arg(long, default_value_t = 512)]
+    memory_cache_mb: usize,
+    /// Serve only cached responses, never contacting the upstream server.
+    #[arg(long)]
+    offline: bool,
+    /// Seconds between usage summaries in the log.
+    #[arg(long, default_value_t = 60)]
+    stats_interval: u64,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::fmt::layer())
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| tracing_subscriber::EnvFilter::new("info")),
+        )
+        .init();
+
+    let args = Args::parse();
+    match run(args).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(message) => {
+            tracing::error!("{message}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(args: Args) -> Result<(), String> {
+    let memory_budget = args
+        .memory_cache_mb
+        .checked_mul(1024 * 1024)
+        .ok_or_else(|| format!("--memory-cache-mb {} is too large", args.memory_cache_mb))?;
+    let memory = MemoryCache::with_max_size(memory_budget);
+    let cache: Box<dyn Cache> = match &args.cache_dir {
+        Some(dir) => {
+            let disk = DiskCache::new(dir).map_err(|e| format!("failed to open cache: {e}"))?;
+            tracing::info!("Caching responses in {}", dir.display());
+            Box::new(LayeredCache::new(memory, disk))
+        }
+        None => Box::new(memory),
+    };
+
+    let mut client = Client::with_cache(cache);
+    if let Some(upstream) = args.upstream {
+        // Request URLs are appended to the base URL directly.
+        let upstream = if upstream.ends_with('/') {
+            upstream
+        } else {
+            format!("{upstream}/")
+        };
+        client = client.with_base_url(upstream);
+    }
+    if args.offline {
+        client = client.with_fetch_policy(FetchPolicy::CacheOnly);
+    }
+    let upstream = client.base_url().to_string();
+    let proxy = Arc::new(Proxy::new(client));
+
+    let listener = tokio::net::TcpListener::bind(args.listen)
+        .await
+        .map_err(|e| format!("failed to listen on {}: {e}", args.listen))?;
+    tracing::info!(
+        "Proxying {upstream} on http://{}/{}",
+        args.listen,
+        if args.offline { " (offline)" } else { "" }
+    );
+
+    let stats = tokio::spawn({
+        let proxy = Arc::clone(&proxy);
+        let period = Duration::from_secs(args.stats_interval.max(1));
+        async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                proxy.log_usage();
+            }
+        }
+    });
+
+    let result = axum::serve(listener, proxy::router(Arc::clone(&proxy)))
+        .with_graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
+        .await
+        .map_err(|e| format!("failed to serve: {e}"));
+    stats.abort();
+    proxy.log_usage();
+    result
+}
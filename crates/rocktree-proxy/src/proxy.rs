@@ -0,0 +1,428 @@
+//! Request handling for the caching proxy.
+//!
+//! Requests are mapped to [`CacheKey`]s and fetched through a
+//! [`Client`], which serves hits from its cache and stores downloads in it.
+//! Concurrent requests for the same resource share one download: the first
+//! request holds a per-key lock while it fetches, and the others wait for
+//! it and then read the stored response from the cache.
+
+use axum::Router;
+use axum::extract::State;
+use axum::http::{Method, StatusCode, Uri, header};
+use axum::response::{IntoResponse, Response};
+use rocktree::{BulkRequest, Cache, CacheKey, Client, Error, NodeRequest};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// How a request was served.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Outcome {
+    /// Served from the cache.
+    Hit,
+    /// Downloaded from the upstream server.
+    Miss,
+    /// Served from the cache after waiting for another request's download.
+    Coalesced,
+}
+
+impl Outcome {
+    fn name(self) -> &'static str {
+        match self {
+            Outcome::Hit => "hit",
+            Outcome::Miss => "miss",
+            Outcome::Coalesced => "coalesced",
+        }
+    }
+}
+
+/// Counts of the requests served by the proxy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct Usage {
+    pub(crate) hits: u64,
+    pub(crate) misses: u64,
+    pub(crate) coalesced: u64,
+    pub(crate) errors: u64,
+    pub(crate) bytes_served: u64,
+}
+
+#[derive(Debug, Default)]
+struct UsageCounters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    coalesced: AtomicU64,
+    errors: AtomicU64,
+    bytes_served: AtomicU64,
+}
+
+impl UsageCounters {
+    fn record(&self, outcome: Outcome, bytes: usize) {
+        let counter = match outcome {
+            Outcome::Hit => &self.hits,
+            Outcome::Miss => &self.misses,
+            Outcome::Coalesced => &self.coalesced,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        self.bytes_served.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Usage {
+        Usage {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            bytes_served: self.bytes_served.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// A caching proxy in front of an upstream rocktree server.
+pub(crate) struct Proxy<C: Cache> {
+    client: Client<C>,
+    in_flight: Mutex<HashMap<CacheKey, Arc<tokio::sync::Mutex<()>>>>,
+    usage: UsageCounters,
+}
+
+impl<C: Cache + 'static> Proxy<C> {
+    /// Create a proxy that fetches through `client`.
+    ///
+    /// The client's base URL is the upstream server, and its cache and fetch
+    /// policy decide what is served locally.
+    pub(crate) fn new(client: Client<C>) -> Self {
+        Self {
+            client,
+            in_flight: Mutex::default(),
+            usage: UsageCounters::default(),
+        }
+    }
+
+    /// Get the counts of the requests served so far.
+    pub(crate) fn usage(&self) -> Usage {
+        self.usage.snapshot()
+    }
+
+    /// Log the usage so far, along with the upstream traffic.
+    pub(crate) fn log_usage(&self) {
+        let usage = self.usage();
+        let metrics = self.client.metrics().snapshot();
+        tracing::info!(
+            hits = usage.hits,
+            misses = usage.misses,
+            coalesced = usage.coalesced,
+            errors = usage.errors,
+            bytes_served = usage.bytes_served,
+            bytes_downloaded = metrics.total_bytes_downloaded(),
+            "proxy usage"
+        );
+    }
+
+    /// Fetch the response for `key`, sharing the download with concurrent
+    /// requests for the same key.
+    ///
+    /// Requests that arrive while a download is in flight wait for it and
+    /// then read the cached response. If that download failed, nothing was
+    /// cached, so each waiter in turn tries the upstream server itself rather
+    /// than sharing the first request's error.
+    pub(crate) async fn fetch(&self, key: &CacheKey) -> rocktree::Result<(Vec<u8>, Outcome)> {
+        let lock = Arc::clone(
+            self.in_flight
+                .lock()
+                .unwrap()
+                .entry(key.clone())
+                .or_default(),
+        );
+        let (guard, waited) = match lock.try_lock() {
+            Ok(guard) => (guard, false),
+            Err(_) => (lock.lock().await, true),
+        };
+        let result = self.fetch_exclusive(key, waited).await;
+        drop(guard);
+
+        // The map and this request hold the only references when no other
+        // request is waiting, and new references are only taken under the
+        // map's lock.
+        let mut in_flight = self.in_flight.lock().unwrap();
+        if Arc::strong_count(&lock) == 2 {
+            in_flight.remove(key);
+        }
+        result
+    }
+
+    async fn fetch_exclusive(
+        &self,
+        key: &CacheKey,
+        waited: bool,
+    ) -> rocktree::Result<(Vec<u8>, Outcome)> {
+        let cached = self.client.cache().contains(key).await?;
+        let data = self
+            .client
+            .fetch_bytes_from_url(&self.upstream_url(key))
+            .await?;
+        let outcome = match (cached, waited) {
+            (false, _) => Outcome::Miss,
+            (true, false) => Outcome::Hit,
+            (true, true) => Outcome::Coalesced,
+        };
+        Ok((data, outcome))
+    }
+
+    /// Build the upstream URL for `key`.
+    ///
+    /// URLs are rebuilt from the key rather than forwarded, so only
+    /// well-formed rocktree requests reach the upstream server.
+    fn upstream_url(&self, key: &CacheKey) -> String {
+        match key {
+            CacheKey::Planetoid => self.client.planetoid_url(),
+            CacheKey::Bulk { path, epoch } => self
+                .client
+                .bulk_url(&BulkRequest::new(path.clone(), *epoch)),
+            CacheKey::Node {
+                path,
+                epoch,
+                texture_format,
+                imagery_epoch,
+            } => self.client.node_url(&NodeRequest::new(
+                path.clone(),
+                *epoch,
+                *texture_format,
+                *imagery_epoch,
+            )),
+        }
+    }
+}
+
+/// Build the router serving the rocktree URL scheme from `proxy`.
+pub(crate) fn router<C: Cache + 'static>(proxy: Arc<Proxy<C>>) -> Router {
+    Router::new().fallback(handle::<C>).with_state(proxy)
+}
+
+async fn handle<C: Cache + 'static>(
+    State(proxy): State<Arc<Proxy<C>>>,
+    method: Method,
+    uri: Uri,
+) -> Response {
+    if method != Method::GET {
+        return StatusCode::METHOD_NOT_ALLOWED.into_response();
+    }
+    let Some(key) = CacheKey::from_url(uri.path()) else {
+        // A request in the rocktree scheme whose fields do not parse, such
+        // as one with a path that is not made of octants, is malformed.
+        if uri.path().contains("/pb=") {
+            tracing::debug!(%uri, "malformed rocktree request");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+        tracing::debug!(%uri, "not a rocktree request");
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let start = Instant::now();
+    match proxy.fetch(&key).await {
+        Ok((data, outcome)) => {
+            proxy.usage.record(outcome, data.len());
+            tracing::info!(
+                %key,
+                outcome = outcome.name(),
+                bytes = data.len(),
+                elapsed_ms = start.elapsed().as_secs_f64() * 1000.0,
+                "served"
+            );
+            ([(header::CONTENT_TYPE, "application/octet-stream")], data).into_response()
+        }
+        Err(e) => {
+            proxy.usage.errors.fetch_add(1, Ordering::Relaxed);
+            tracing::warn!(%key, error = %e, "failed to serve");
+            (error_status(&e), e.to_string()).into_response()
+        }
+    }
+}
+
+/// Choose the status code reported for a failed fetch.
+fn error_status(error: &Error) -> StatusCode {
+    match error {
+        Error::HttpStatus { status, .. } => {
+            StatusCode::from_u16(*status).unwrap_or(StatusCode::BAD_GATEWAY)
+        }
+        Error::NotCached { .. } => StatusCode::NOT_FOUND,
+        Error::Cache { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+        _ => StatusCode::BAD_GATEWAY,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocktree::{FetchPolicy, MemoryCache};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::AtomicUsize;
+    use std::time::Duration;
+
+    /// Serve `body` with `status` on a local port, after `delay`, counting
+    /// the requests.
+    fn upstream(status: u16, body: &'static [u8], delay: Duration) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                counter.fetch_add(1, Ordering::SeqCst);
+                let mut buf = [0u8; 4096];
+                let _ = stream.read(&mut buf).unwrap();
+                std::thread::sleep(delay);
+                let header = format!(
+                    "HTTP/1.1 {status} X\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    body.len()
+                );
+                stream.write_all(header.as_bytes()).unwrap();
+                stream.write_all(body).unwrap();
+            }
+        });
+        (format!("http://{addr}/"), requests)
+    }
+
+    /// Start a proxy in front of `upstream_url`, returning its base URL.
+    async fn start(upstream_url: String) -> (String, Arc<Proxy<MemoryCache>>) {
+        let client = Client::with_cache(MemoryCache::new()).with_base_url(upstream_url);
+        let proxy = Arc::new(Proxy::new(client));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(Arc::clone(&proxy));
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        (format!("http://{addr}/"), proxy)
+    }
+
+    fn bulk_key() -> CacheKey {
+        CacheKey::Bulk {
+            path: "0123".to_string(),
+            epoch: 5,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_serves_hits_from_cache() {
+        let (upstream_url, upstream_requests) = upstream(200, b"bulk", Duration::ZERO);
+        let (proxy_url, proxy) = start(upstream_url).await;
+
+        // A client without a cache goes to the proxy every time.
+        let client = Client::new().with_base_url(proxy_url);
+        let url = client.bulk_url(&BulkRequest::new("0123".to_string(), 5));
+        assert_eq!(client.fetch_bytes_from_url(&url).await.unwrap(), b"bulk");
+        assert_eq!(client.fetch_bytes_from_url(&url).await.unwrap(), b"bulk");
+
+        assert_eq!(upstream_requests.load(Ordering::SeqCst), 1);
+        let usage = proxy.usage();
+        assert_eq!((usage.hits, usage.misses), (1, 1));
+        assert_eq!(usage.bytes_served, 8);
+    }
+
+    #[tokio::test]
+    async fn test_coalesces_concurrent_misses() {
+        let (upstream_url, upstream_requests) = upstream(200, b"bulk", Duration::from_millis(100));
+        let (_, proxy) = start(upstream_url).await;
+
+        let tasks: Vec<_> = (0..5)
+            .map(|_| {
+                let proxy = Arc::clone(&proxy);
+                tokio::spawn(async move { proxy.fetch(&bulk_key()).await.unwrap() })
+            })
+            .collect();
+        let mut outcomes = Vec::new();
+        for task in tasks {
+            let (data, outcome) = task.await.unwrap();
+            assert_eq!(data, b"bulk");
+            outcomes.push(outcome);
+        }
+
+        assert_eq!(upstream_requests.load(Ordering::SeqCst), 1);
+        assert_eq!(outcomes.iter().filter(|o| **o == Outcome::Miss).count(), 1);
+        assert_eq!(
+            outcomes
+                .iter()
+                .filter(|o| **o == Outcome::Coalesced)
+                .count(),
+            4
+        );
+        assert!(proxy.in_flight.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_forwards_upstream_status() {
+        let (upstream_url, _) = upstream(404, b"", Duration::ZERO);
+        let (proxy_url, proxy) = start(upstream_url).await;
+
+        let client = Client::new().with_base_url(proxy_url);
+        let url = client.bulk_url(&BulkRequest::new("0123".to_string(), 5));
+        let err = client.fetch_bytes_from_url(&url).await.unwrap_err();
+        assert!(
+            matches!(err, Error::HttpStatus { status: 404, .. }),
+            "{err}"
+        );
+        assert_eq!(proxy.usage().errors, 1);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_unknown_paths() {
+        let (upstream_url, upstream_requests) = upstream(200, b"", Duration::ZERO);
+        let (proxy_url, _) = start(upstream_url).await;
+
+        let client = Client::new().with_base_url(proxy_url.clone());
+        let err = client
+            .fetch_bytes_from_url(&format!("{proxy_url}favicon.ico"))
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(err, Error::HttpStatus { status: 404, .. }),
+            "{err}"
+        );
+        assert_eq!(upstream_requests.load(Ordering::SeqCst), 0);
+    }
+
+    /// Send a GET for `path` to the proxy at `proxy_url` as written, without
+    /// the dot-segment normalization an HTTP client applies, returning the
+    /// status code.
+    async fn raw_get(proxy_url: &str, path: &str) -> u16 {
+        let addr = proxy_url
+            .trim_start_matches("http://")
+            .trim_end_matches('/')
+            .to_string();
+        let request = format!("GET {path} HTTP/1.1\r\nHost: {addr}\r\nConnection: close\r\n\r\n");
+        tokio::task::spawn_blocking(move || {
+            let mut stream = std::net::TcpStream::connect(addr).unwrap();
+            stream.write_all(request.as_bytes()).unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response.split(' ').nth(1).unwrap().parse().unwrap()
+        })
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_rejects_path_traversal() {
+        let (upstream_url, upstream_requests) = upstream(200, b"", Duration::ZERO);
+        let (proxy_url, proxy) = start(upstream_url).await;
+
+        for path in [
+            "/BulkMetadata/pb=!1m2!1s../../x!2u5",
+            "/BulkMetadata/pb=!1m2!1s..%2F..%2Fx!2u5",
+            "/NodeData/pb=!1m2!1s0x!2u5!2e1!4b0",
+        ] {
+            assert_eq!(raw_get(&proxy_url, path).await, 400, "{path}");
+        }
+        assert_eq!(upstream_requests.load(Ordering::SeqCst), 0);
+        assert_eq!(proxy.usage().misses, 0);
+    }
+
+    #[tokio::test]
+    async fn test_offline_miss_is_not_found() {
+        let client =
+            Client::with_cache(MemoryCache::new()).with_fetch_policy(FetchPolicy::CacheOnly);
+        let proxy = Proxy::new(client);
+        let err = proxy.fetch(&bulk_key()).await.unwrap_err();
+        assert_eq!(error_status(&err), StatusCode::NOT_FOUND);
+    }
+}
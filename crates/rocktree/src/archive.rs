@@ -0,0 +1,782 @@
+//! Offline archives of a geographic region.
+//!
+//! [`export_region`] walks the octree over a region, downloading every bulk
+//! and node that intersects it, and writes the raw responses into a single
+//! indexed file. [`ArchiveCache`] serves that file as a read-only [`Cache`],
+//! so a [`Client`] can browse the region without a network connection.
+//!
+//! # Format
+//!
+//! An archive starts with 8 magic bytes, followed by the entries back to
+//! back. After the entries come the index, the manifest, and a fixed-size
+//! footer holding the offsets of the index and manifest and the magic bytes
+//! again. All integers are little-endian.
+//!
+//! - Index: a `u32` entry count, then per entry a `u16` key length, the
+//!   [`CacheKey`] display form, and `u64` offset and length
+//! - Manifest: the [`ArchiveManifest`] as UTF-8 text, one field per line
+//!
+//! Entries are stored with the same checksum envelope as cached responses.
+
+use crate::cache::{
+    Cache, CacheEntry, CacheFuture, CacheKey, ContainsFuture, EntriesFuture, GetFuture, checksum,
+    temp_path,
+};
+use crate::client::Client;
+use crate::error::{Error, Result};
+use crate::geo::GeoBounds;
+use crate::types::NodeRequest;
+use crate::walk::{WalkStep, walk_region};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// Magic bytes at the start and end of every archive.
+const MAGIC: &[u8; 8] = b"RTARCHV1";
+
+/// Length of the footer: the index and manifest offsets and the magic bytes.
+const FOOTER_LEN: usize = 16 + MAGIC.len();
+
+/// First line of the manifest.
+const MANIFEST_HEADER: &str = "rocktree-archive 1";
+
+/// Summary of an archive's contents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveManifest {
+    /// The region the archive covers.
+    pub bounds: GeoBounds,
+    /// The deepest node level included.
+    pub max_level: usize,
+    /// Epochs of the archived bulks, by path.
+    pub bulk_epochs: BTreeMap<String, u32>,
+    /// Number of archived nodes at each level.
+    pub nodes_per_level: BTreeMap<usize, usize>,
+    /// Total size of the archived responses in bytes.
+    pub total_size: u64,
+}
+
+impl ArchiveManifest {
+    /// Create an empty manifest for a region.
+    #[must_use]
+    pub fn new(bounds: GeoBounds, max_level: usize) -> Self {
+        Self {
+            bounds,
+            max_level,
+            bulk_epochs: BTreeMap::new(),
+            nodes_per_level: BTreeMap::new(),
+            total_size: 0,
+        }
+    }
+
+    /// Get the epoch of the root bulk, if it is archived.
+    #[must_use]
+    pub fn root_epoch(&self) -> Option<u32> {
+        self.bulk_epochs.get("").copied()
+    }
+
+    /// Get the number of archived bulks.
+    #[must_use]
+    pub fn bulk_count(&self) -> usize {
+        self.bulk_epochs.len()
+    }
+
+    /// Get the number of archived nodes.
+    #[must_use]
+    pub fn node_count(&self) -> usize {
+        self.nodes_per_level.values().sum()
+    }
+
+    /// Account for an entry added to the archive.
+    fn record(&mut self, key: &CacheKey, size: usize) {
+        match key {
+            CacheKey::Planetoid => {}
+            CacheKey::Bulk { path, epoch } => {
+                self.bulk_epochs.insert(path.clone(), *epoch);
+            }
+            CacheKey::Node { path, .. } => {
+                *self.nodes_per_level.entry(path.len()).or_default() += 1;
+            }
+        }
+        self.total_size += size as u64;
+    }
+
+    fn encode(&self) -> String {
+        let GeoBounds {
+            south,
+            west,
+            north,
+            east,
+        } = self.bounds;
+        let mut text = format!(
+            "{MANIFEST_HEADER}\nbounds {south} {west} {north} {east}\nmax_level {}\ntotal_size {}\n",
+            self.max_level, self.total_size
+        );
+        text.extend(
+            self.nodes_per_level
+                .iter()
+                .map(|(level, count)| format!("nodes {level} {count}\n")),
+        );
+        // The path comes last because the root bulk's path is empty.
+        text.extend(
+            self.bulk_epochs
+                .iter()
+                .map(|(path, epoch)| format!("bulk {epoch} {path}\n")),
+        );
+        text
+    }
+
+    /// Parse an encoded manifest, returning `None` if it is malformed.
+    fn decode(text: &str) -> Option<Self> {
+        let mut lines = text.lines();
+        if lines.next()? != MANIFEST_HEADER {
+            return None;
+        }
+        let mut manifest = Self::new(GeoBounds::WORLD, 0);
+        for line in lines {
+            let (field, value) = line.split_once(' ')?;
+            match field {
+                "bounds" => {
+                    let edges: Vec<f64> = value
+                        .split(' ')
+                        .map(str::parse)
+                        .collect::<std::result::Result<_, _>>()
+                        .ok()?;
+                    let &[south, west, north, east] = edges.as_slice() else {
+                        return None;
+                    };
+                    manifest.bounds = GeoBounds::new(south, west, north, east);
+                }
+                "max_level" => manifest.max_level = value.parse().ok()?,
+                "total_size" => manifest.total_size = value.parse().ok()?,
+                "nodes" => {
+                    let (level, count) = value.split_once(' ')?;
+                    manifest
+                        .nodes_per_level
+                        .insert(level.parse().ok()?, count.parse().ok()?);
+                }
+                "bulk" => {
+                    let (epoch, path) = value.split_once(' ')?;
+                    manifest
+                        .bulk_epochs
+                        .insert(path.to_string(), epoch.parse().ok()?);
+                }
+                _ => return None,
+            }
+        }
+        Some(manifest)
+    }
+}
+
+/// Writes responses into a new archive.
+///
+/// The archive is written to a temporary file next to its destination and
+/// moved into place by [`ArchiveWriter::finish`], so an interrupted export
+/// never leaves a partial archive behind.
+#[derive(Debug)]
+pub struct ArchiveWriter {
+    path: PathBuf,
+    temp: PathBuf,
+    file: Option<BufWriter<File>>,
+    offset: u64,
+    index: Vec<(CacheKey, u64, u64)>,
+    keys: HashSet<CacheKey>,
+    manifest: ArchiveManifest,
+}
+
+impl ArchiveWriter {
+    /// Start writing an archive of `bounds` up to `max_level` to `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the temporary file cannot be created.
+    pub fn create(path: impl Into<PathBuf>, bounds: GeoBounds, max_level: usize) -> Result<Self> {
+        let path = path.into();
+        let temp = temp_path(&path);
+        let mut file = File::create(&temp)
+            .map(BufWriter::new)
+            .map_err(|e| io_error("create", &temp, &e))?;
+        file.write_all(MAGIC)
+            .map_err(|e| io_error("create", &temp, &e))?;
+        Ok(Self {
+            path,
+            temp,
+            file: Some(file),
+            offset: MAGIC.len() as u64,
+            index: Vec::new(),
+            keys: HashSet::new(),
+            manifest: ArchiveManifest::new(bounds, max_level),
+        })
+    }
+
+    /// Add the response `data` for `key`.
+    ///
+    /// Keys that are already in the archive are skipped.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing to the file fails.
+    pub fn add(&mut self, key: &CacheKey, data: &[u8]) -> Result<()> {
+        if !self.keys.insert(key.clone()) {
+            return Ok(());
+        }
+        let entry = checksum::seal(data);
+        self.write(&entry)?;
+        self.index
+            .push((key.clone(), self.offset, entry.len() as u64));
+        self.offset += entry.len() as u64;
+        self.manifest.record(key, data.len());
+        Ok(())
+    }
+
+    /// Get the manifest of the entries added so far.
+    #[must_use]
+    pub fn manifest(&self) -> &ArchiveManifest {
+        &self.manifest
+    }
+
+    /// Write the index and manifest and move the archive into place.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if writing or renaming the file fails.
+    // Key names are short, and archives hold far fewer than 2^32 entries.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn finish(mut self) -> Result<ArchiveManifest> {
+        let index_offset = self.offset;
+        let mut index = Vec::new();
+        index.extend_from_slice(&(self.index.len() as u32).to_le_bytes());
+        for (key, offset, len) in &self.index {
+            let name = key.to_string();
+            index.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            index.extend_from_slice(name.as_bytes());
+            index.extend_from_slice(&offset.to_le_bytes());
+            index.extend_from_slice(&len.to_le_bytes());
+        }
+        let manifest_offset = index_offset + index.len() as u64;
+        let mut tail = index;
+        tail.extend_from_slice(self.manifest.encode().as_bytes());
+        tail.extend_from_slice(&index_offset.to_le_bytes());
+        tail.extend_from_slice(&manifest_offset.to_le_bytes());
+        tail.extend_from_slice(MAGIC);
+        self.write(&tail)?;
+
+        let file = self
+            .file
+            .take()
+            .expect("archive file is open until finished");
+        file.into_inner()
+            .map_err(|e| io_error("write", &self.temp, e.error()))?
+            .sync_all()
+            .map_err(|e| io_error("write", &self.temp, &e))?;
+        std::fs::rename(&self.temp, &self.path).map_err(|e| io_error("write", &self.path, &e))?;
+        Ok(std::mem::replace(
+            &mut self.manifest,
+            ArchiveManifest::new(GeoBounds::WORLD, 0),
+        ))
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        let file = self
+            .file
+            .as_mut()
+            .expect("archive file is open until finished");
+        file.write_all(data)
+            .map_err(|e| io_error("write", &self.temp, &e))
+    }
+}
+
+impl Drop for ArchiveWriter {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = std::fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// A read-only cache serving the entries of an archive.
+///
+/// Writes are ignored, so the archive can serve as the back tier of a
+/// [`LayeredCache`](crate::LayeredCache), or back a client directly. Use
+/// [`FetchPolicy::CacheOnly`](crate::FetchPolicy::CacheOnly) to stay within
+/// the archived region.
+///
+/// This cache is only available on native targets.
+#[derive(Debug)]
+pub struct ArchiveCache {
+    path: PathBuf,
+    file: Mutex<File>,
+    index: HashMap<CacheKey, (u64, u64)>,
+    manifest: ArchiveManifest,
+}
+
+impl ArchiveCache {
+    /// Open the archive at `path`.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the file cannot be read or is not an archive.
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut file = File::open(&path).map_err(|e| io_error("open", &path, &e))?;
+        let len = file
+            .metadata()
+            .map_err(|e| io_error("open", &path, &e))?
+            .len();
+        if len < (MAGIC.len() + FOOTER_LEN) as u64 {
+            return Err(format_error(&path, "file is too short"));
+        }
+
+        let mut header = [0u8; MAGIC.len()];
+        read_at(&mut file, 0, &mut header).map_err(|e| io_error("open", &path, &e))?;
+        let mut footer = [0u8; FOOTER_LEN];
+        read_at(&mut file, len - FOOTER_LEN as u64, &mut footer)
+            .map_err(|e| io_error("open", &path, &e))?;
+        if &header != MAGIC || &footer[16..] != MAGIC {
+            return Err(format_error(&path, "not a rocktree archive"));
+        }
+        let index_offset = u64::from_le_bytes(footer[..8].try_into().unwrap());
+        let manifest_offset = u64::from_le_bytes(footer[8..16].try_into().unwrap());
+        if index_offset > manifest_offset || manifest_offset > len - FOOTER_LEN as u64 {
+            return Err(format_error(&path, "corrupt footer"));
+        }
+
+        let (Ok(tail_len), Ok(index_len)) = (
+            usize::try_from(len - FOOTER_LEN as u64 - index_offset),
+            usize::try_from(manifest_offset - index_offset),
+        ) else {
+            return Err(format_error(&path, "index is too large"));
+        };
+        let mut tail = vec![0u8; tail_len];
+        read_at(&mut file, index_offset, &mut tail).map_err(|e| io_error("open", &path, &e))?;
+        let (index, manifest) = tail.split_at(index_len);
+        let index = decode_index(index, index_offset)
+            .ok_or_else(|| format_error(&path, "corrupt index"))?;
+        let manifest = std::str::from_utf8(manifest)
+            .ok()
+            .and_then(ArchiveManifest::decode)
+            .ok_or_else(|| format_error(&path, "corrupt manifest"))?;
+
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+            index,
+            manifest,
+        })
+    }
+
+    /// Get the file holding the archive.
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Get the archive's manifest.
+    #[must_use]
+    pub fn manifest(&self) -> &ArchiveManifest {
+        &self.manifest
+    }
+
+    /// Get the number of archived entries.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Check if the archive has no entries.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    fn read(&self, key: &CacheKey) -> Result<Option<Vec<u8>>> {
+        let Some(&(offset, len)) = self.index.get(key) else {
+            return Ok(None);
+        };
+        let len = usize::try_from(len).map_err(|_| Error::Cache {
+            operation: "get",
+            message: format!("{}: {key} is too large", self.path.display()),
+        })?;
+        let mut data = vec![0u8; len];
+        let mut file = self.file.lock().unwrap();
+        read_at(&mut file, offset, &mut data).map_err(|e| io_error("get", &self.path, &e))?;
+        Ok(Some(data))
+    }
+}
+
+impl Cache for ArchiveCache {
+    fn get(&self, key: &CacheKey) -> GetFuture<'_> {
+        let result = self.read(key);
+        Box::pin(async move { result })
+    }
+
+    fn put(&self, _key: &CacheKey, _data: Vec<u8>) -> CacheFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn contains(&self, key: &CacheKey) -> ContainsFuture<'_> {
+        let exists = self.index.contains_key(key);
+        Box::pin(async move { Ok(exists) })
+    }
+
+    fn remove(&self, _key: &CacheKey) -> CacheFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn clear(&self) -> CacheFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn entries(&self) -> EntriesFuture<'_> {
+        let entries = self
+            .index
+            .iter()
+            .map(|(key, &(_, len))| CacheEntry {
+                key: key.clone(),
+                size: usize::try_from(len).unwrap_or(usize::MAX),
+            })
+            .collect();
+        Box::pin(async move { Ok(entries) })
+    }
+}
+
+/// Download everything within `bounds` up to `max_level` into an archive
+/// at `path`.
+///
+/// The octree is walked with [`walk_region`], descending into nodes above
+/// `max_level`, and the planetoid, every bulk fetched, and every node
+/// reached that has data are archived. Responses are fetched one at a time
+/// following the client's fetch policy, so entries already in the client's
+/// cache are reused; with
+/// [`FetchPolicy::CacheOnly`](crate::FetchPolicy::CacheOnly), a cache can be
+/// exported without touching the network.
+///
+/// Returns the manifest of the written archive.
+///
+/// # Errors
+///
+/// Returns an error if a fetch fails, a bulk cannot be decoded, or the
+/// archive cannot be written. No archive is left at `path` on failure.
+pub async fn export_region<C: Cache + 'static>(
+    client: &Client<C>,
+    bounds: GeoBounds,
+    max_level: usize,
+    path: impl Into<PathBuf>,
+) -> Result<ArchiveManifest> {
+    let mut writer = ArchiveWriter::create(path, bounds, max_level)?;
+    let policy = client.fetch_policy();
+
+    walk_region(
+        client,
+        &bounds,
+        |node| node.path.len() < max_level,
+        async |step| match step {
+            WalkStep::Planetoid { data } => writer.add(&CacheKey::Planetoid, data),
+            WalkStep::Bulk {
+                request,
+                bulk,
+                data,
+            } => {
+                tracing::debug!(path = %bulk.path, nodes = bulk.nodes.len(), "archiving bulk");
+                writer.add(&CacheKey::bulk(request), data)
+            }
+            WalkStep::Node(node) if node.has_data => {
+                let request = NodeRequest::new(
+                    node.path.clone(),
+                    node.epoch,
+                    node.texture_format,
+                    node.imagery_epoch,
+                );
+                let key = CacheKey::node(&request);
+                let (data, _) = client
+                    .fetch_bytes(Some(&key), &client.node_url(&request), policy)
+                    .await?;
+                writer.add(&key, &data)
+            }
+            WalkStep::Node(_) => Ok(()),
+        },
+    )
+    .await?;
+
+    let manifest = writer.finish()?;
+    tracing::info!(
+        bulks = manifest.bulk_count(),
+        nodes = manifest.node_count(),
+        bytes = manifest.total_size,
+        "exported region archive"
+    );
+    Ok(manifest)
+}
+
+/// Parse the index, rejecting entries that extend past its start.
+fn decode_index(mut data: &[u8], end: u64) -> Option<HashMap<CacheKey, (u64, u64)>> {
+    fn take<'a>(data: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+        let (head, rest) = data.split_at_checked(len)?;
+        *data = rest;
+        Some(head)
+    }
+
+    let count = u32::from_le_bytes(take(&mut data, 4)?.try_into().ok()?);
+    let mut index = HashMap::with_capacity(count as usize);
+    for _ in 0..count {
+        let name_len = u16::from_le_bytes(take(&mut data, 2)?.try_into().ok()?);
+        let key = std::str::from_utf8(take(&mut data, name_len.into())?)
+            .ok()?
+            .parse()
+            .ok()?;
+        let offset = u64::from_le_bytes(take(&mut data, 8)?.try_into().ok()?);
+        let len = u64::from_le_bytes(take(&mut data, 8)?.try_into().ok()?);
+        if offset.checked_add(len)? > end {
+            return None;
+        }
+        index.insert(key, (offset, len));
+    }
+    data.is_empty().then_some(index)
+}
+
+fn read_at(file: &mut File, offset: u64, buf: &mut [u8]) -> std::io::Result<()> {
+    file.seek(SeekFrom::Start(offset))?;
+    file.read_exact(buf)
+}
+
+fn io_error(operation: &'static str, path: &Path, e: &std::io::Error) -> Error {
+    Error::Cache {
+        operation,
+        message: format!("{}: {e}", path.display()),
+    }
+}
+
+fn format_error(path: &Path, message: &str) -> Error {
+    Error::Cache {
+        operation: "open",
+        message: format!("{}: {message}", path.display()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::cache::{MemoryCache, block_on};
+    use crate::geo::WGS84_A;
+    use crate::policy::FetchPolicy;
+    use crate::test_util::planetoid_body;
+    use crate::types::BulkRequest;
+    use prost::Message;
+    use rocktree_proto as proto;
+
+    /// Pack an octant path and flags as in bulk metadata.
+    fn path_and_flags(path: &str, flags: u32) -> u32 {
+        let digits = path.bytes().enumerate().fold(0, |acc, (i, digit)| {
+            acc | u32::from(digit - b'0') << (3 * i)
+        });
+        let level = u32::try_from(path.len()).unwrap();
+        (level - 1) | digits << 2 | flags << (2 + 3 * level)
+    }
+
+    /// Node metadata for a 2 km box at the equator, shifted east by
+    /// `offset` hundred meters.
+    fn node_proto(
+        path: &str,
+        flags: u32,
+        offset: i16,
+        bulk_epoch: Option<u32>,
+    ) -> proto::NodeMetadata {
+        let mut obb = vec![0, 0];
+        obb.extend_from_slice(&offset.to_le_bytes());
+        obb.extend_from_slice(&[0, 0, 10, 10, 10, 0, 0, 0, 0, 0, 0]);
+        proto::NodeMetadata {
+            path_and_flags: Some(path_and_flags(path, flags)),
+            epoch: Some(5),
+            bulk_metadata_epoch: bulk_epoch,
+            oriented_bounding_box: Some(obb),
+            meters_per_texel: Some(100.0),
+            ..Default::default()
+        }
+    }
+
+    fn bulk_body(nodes: Vec<proto::NodeMetadata>) -> Vec<u8> {
+        proto::BulkMetadata {
+            node_metadata: nodes,
+            head_node_center: vec![WGS84_A, 0.0, 0.0],
+            ..Default::default()
+        }
+        .encode_to_vec()
+    }
+
+    fn bulk_key(path: &str, epoch: u32) -> CacheKey {
+        CacheKey::Bulk {
+            path: path.to_string(),
+            epoch,
+        }
+    }
+
+    fn node_key(path: &str) -> CacheKey {
+        CacheKey::Node {
+            path: path.to_string(),
+            epoch: 5,
+            texture_format: 6,
+            imagery_epoch: None,
+        }
+    }
+
+    /// A cache holding a root bulk with nodes inside and outside the region
+    /// around (0, 0), and a child bulk below the inside branch.
+    pub(crate) fn populated_cache() -> MemoryCache {
+        const NO_DATA: u32 = 8;
+        const FAR: i16 = 20_000;
+        let cache = MemoryCache::new();
+        let entries = [
+            (CacheKey::Planetoid, planetoid_body()),
+            (
+                bulk_key("", 7),
+                bulk_body(vec![
+                    node_proto("0", 0, 0, None),
+                    node_proto("1", 0, FAR, None),
+                    node_proto("0000", NO_DATA, 0, Some(3)),
+                    node_proto("1111", NO_DATA, FAR, Some(4)),
+                ]),
+            ),
+            (
+                bulk_key("0000", 3),
+                bulk_body(vec![node_proto("1", 0, 0, None)]),
+            ),
+            (node_key("0"), b"node 0".to_vec()),
+            (node_key("00001"), b"node 00001".to_vec()),
+        ];
+        for (key, data) in entries {
+            block_on(cache.put(&key, data)).unwrap();
+        }
+        cache
+    }
+
+    pub(crate) fn region() -> GeoBounds {
+        GeoBounds::new(-1.0, -1.0, 1.0, 1.0)
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let mut manifest = ArchiveManifest::new(GeoBounds::new(-1.5, 170.25, 2.0, -170.0), 12);
+        manifest.record(&bulk_key("", 7), 100);
+        manifest.record(&bulk_key("0123", 3), 50);
+        manifest.record(&node_key("01"), 10);
+        manifest.record(&node_key("02"), 10);
+        manifest.record(&CacheKey::Planetoid, 5);
+
+        assert_eq!(manifest.root_epoch(), Some(7));
+        assert_eq!(manifest.bulk_count(), 2);
+        assert_eq!(manifest.node_count(), 2);
+        assert_eq!(manifest.total_size, 175);
+        assert_eq!(ArchiveManifest::decode(&manifest.encode()), Some(manifest));
+        assert_eq!(ArchiveManifest::decode("something else"), None);
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("region.rta");
+        let mut writer = ArchiveWriter::create(&path, region(), 4).unwrap();
+        writer.add(&bulk_key("", 7), b"root").unwrap();
+        writer.add(&node_key("0"), b"node").unwrap();
+        writer.add(&node_key("0"), b"duplicate").unwrap();
+        assert!(!path.exists());
+        writer.finish().unwrap();
+
+        let archive = ArchiveCache::open(&path).unwrap();
+        assert_eq!(archive.len(), 2);
+        assert_eq!(archive.manifest().total_size, 8);
+        let entry = block_on(archive.get(&node_key("0"))).unwrap().unwrap();
+        assert_eq!(checksum::unseal(entry), Some(b"node".to_vec()));
+        assert_eq!(block_on(archive.get(&node_key("1"))).unwrap(), None);
+
+        // Writes are ignored.
+        block_on(archive.put(&node_key("1"), vec![1])).unwrap();
+        block_on(archive.clear()).unwrap();
+        assert!(!block_on(archive.contains(&node_key("1"))).unwrap());
+        assert!(block_on(archive.contains(&node_key("0"))).unwrap());
+        assert_eq!(block_on(archive.entries()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_writers_to_one_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("region.rta");
+        let mut first = ArchiveWriter::create(&path, region(), 4).unwrap();
+        let mut second = ArchiveWriter::create(&path, region(), 4).unwrap();
+        first.add(&node_key("0"), b"first").unwrap();
+        second.add(&node_key("1"), b"second").unwrap();
+        first.finish().unwrap();
+        second.finish().unwrap();
+
+        // The last writer to finish wins, intact.
+        let archive = ArchiveCache::open(&path).unwrap();
+        assert_eq!(archive.len(), 1);
+        assert!(block_on(archive.contains(&node_key("1"))).unwrap());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_abandoned_writer_leaves_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut writer = ArchiveWriter::create(dir.path().join("a.rta"), region(), 4).unwrap();
+        writer.add(&node_key("0"), b"node").unwrap();
+        drop(writer);
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_open_rejects_other_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("not-an-archive");
+        std::fs::write(&path, vec![0u8; 64]).unwrap();
+        let err = ArchiveCache::open(&path).unwrap_err();
+        assert!(err.to_string().contains("not a rocktree archive"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn test_export_region() {
+        // Only the entries inside the region are cached, so fetching any
+        // other entry fails the export.
+        let client =
+            Client::with_cache(populated_cache()).with_fetch_policy(FetchPolicy::CacheOnly);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("region.rta");
+        let manifest = export_region(&client, region(), 5, &path).await.unwrap();
+
+        assert_eq!(manifest.root_epoch(), Some(7));
+        assert_eq!(
+            manifest.bulk_epochs,
+            BTreeMap::from([(String::new(), 7), ("0000".to_string(), 3)])
+        );
+        assert_eq!(manifest.nodes_per_level, BTreeMap::from([(1, 1), (5, 1)]));
+
+        // The archive backs a client on its own.
+        let archive = ArchiveCache::open(&path).unwrap();
+        assert_eq!(archive.manifest(), &manifest);
+        let offline = Client::with_cache(archive).with_fetch_policy(FetchPolicy::CacheOnly);
+        let planetoid = offline.fetch_planetoid().await.unwrap();
+        let root = offline
+            .fetch_bulk(&BulkRequest::root(planetoid.root_epoch))
+            .await
+            .unwrap();
+        assert!(root.child_bulk_paths.contains_key("0000"));
+        let request = NodeRequest::new("00001".to_string(), 5, 6, None);
+        let url = offline.node_url(&request);
+        assert_eq!(
+            offline.fetch_bytes_from_url(&url).await.unwrap(),
+            b"node 00001"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_respects_max_level() {
+        let client =
+            Client::with_cache(populated_cache()).with_fetch_policy(FetchPolicy::CacheOnly);
+        let dir = tempfile::tempdir().unwrap();
+        let manifest = export_region(&client, region(), 4, dir.path().join("a.rta"))
+            .await
+            .unwrap();
+        assert_eq!(manifest.bulk_count(), 1);
+        assert_eq!(manifest.node_count(), 1);
+    }
+}
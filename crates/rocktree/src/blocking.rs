@@ -0,0 +1,511 @@
+//! Blocking HTTP client for synchronous consumers.
+//!
+//! This module provides [`BlockingClient`], a synchronous counterpart to
+//! [`Client`](crate::Client) for batch scripts, command-line tools, and FFI
+//! layers that do not run an async executor. It exposes the same fetch surface,
+//! fetches and decodes responses with the same code as the async client, and
+//! accepts the same [`Cache`] implementations.
+//!
+//! This module is only available with the `blocking` feature on native
+//! targets.
+
+use crate::cache::{Cache, DecodedNodeCache, NoCache, block_on};
+use crate::cancel::CancellationToken;
+use crate::error::Result;
+use crate::metrics::ClientMetrics;
+use crate::policy::{BackgroundTask, FetchPolicy, Spawner};
+use crate::protocol::{Fetcher, HttpFuture, Transport, http_error};
+use crate::types::{BulkMetadata, BulkRequest, Node, NodeRequest, Planetoid};
+use std::sync::Arc;
+
+/// Blocking HTTP client for fetching Google Earth mesh data.
+///
+/// Every method blocks the calling thread until the request completes. The
+/// underlying `reqwest` blocking client manages its own background runtime, so
+/// callers do not need to start one.
+///
+/// This client must not be created, used, or dropped from within an async
+/// runtime. Use [`Client`](crate::Client) there instead.
+///
+/// # Example
+///
+/// ```ignore
+/// use rocktree::{BlockingClient, BulkRequest, MemoryCache};
+///
+/// let client = BlockingClient::with_cache(MemoryCache::new());
+/// let planetoid = client.fetch_planetoid()?;
+/// let bulk = client.fetch_bulk(&BulkRequest::root(planetoid.root_epoch))?;
+/// ```
+pub struct BlockingClient<C: Cache = NoCache> {
+    inner: Fetcher<C, reqwest::blocking::Client>,
+}
+
+impl BlockingClient<NoCache> {
+    /// Create a new blocking client with default settings and no caching.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::with_cache(NoCache)
+    }
+}
+
+impl Default for BlockingClient<NoCache> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: Cache + 'static> BlockingClient<C> {
+    /// Create a new blocking client with a custom cache.
+    #[must_use]
+    pub fn with_cache(cache: C) -> Self {
+        Self::with_http_and_cache(reqwest::blocking::Client::new(), cache)
+    }
+
+    /// Create a new blocking client with a custom HTTP client and cache.
+    #[must_use]
+    pub fn with_http_and_cache(http: reqwest::blocking::Client, cache: C) -> Self {
+        let mut inner = Fetcher::new(http, cache);
+        inner.spawner = Some(thread_spawner());
+        Self { inner }
+    }
+
+    /// Set a custom base URL for testing.
+    #[must_use]
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.inner.base_url = base_url;
+        self
+    }
+
+    /// Record metrics into a shared [`ClientMetrics`] instead of the client's
+    /// own.
+    #[must_use]
+    pub fn with_metrics(mut self, metrics: Arc<ClientMetrics>) -> Self {
+        self.inner.metrics = metrics;
+        self
+    }
+
+    /// Emit a `rocktree_fetch` tracing span for every fetch.
+    ///
+    /// See [`Client::with_request_spans`](crate::Client::with_request_spans)
+    /// for the recorded fields.
+    #[must_use]
+    pub fn with_request_spans(mut self, enabled: bool) -> Self {
+        self.inner.request_spans = enabled;
+        self
+    }
+
+    /// Keep decoded nodes in `cache` so that repeated requests for the same
+    /// node skip downloading and decoding.
+    ///
+    /// Only the `fetch_node_shared` methods use this cache. Hits are counted
+    /// as node cache hits in the client's metrics.
+    #[must_use]
+    pub fn with_node_cache(mut self, cache: DecodedNodeCache) -> Self {
+        self.inner.node_cache = Some(cache);
+        self
+    }
+
+    /// Set the default [`FetchPolicy`] for requests that do not specify one.
+    ///
+    /// The refreshes made by [`FetchPolicy::StaleWhileRevalidate`] run on a
+    /// new thread, with at most one in flight per cache entry.
+    #[must_use]
+    pub fn with_fetch_policy(mut self, policy: FetchPolicy) -> Self {
+        self.inner.fetch_policy = policy;
+        self
+    }
+
+    /// Run background work, such as the refreshes made by
+    /// [`FetchPolicy::StaleWhileRevalidate`], with `spawner` instead of on a
+    /// new thread each.
+    ///
+    /// Tasks finish on their first poll unless the cache is waiting on
+    /// something, so a thread pool can run them with any simple `block_on`.
+    /// Each cache entry has at most one refresh in flight.
+    #[must_use]
+    pub fn with_spawner(
+        mut self,
+        spawner: impl Fn(BackgroundTask) + Send + Sync + 'static,
+    ) -> Self {
+        self.inner.spawner = Some(Arc::new(spawner));
+        self
+    }
+
+    /// Get the default fetch policy.
+    #[must_use]
+    pub fn fetch_policy(&self) -> FetchPolicy {
+        self.inner.fetch_policy
+    }
+
+    /// Get the metrics recorded by this client.
+    #[must_use]
+    pub fn metrics(&self) -> &Arc<ClientMetrics> {
+        &self.inner.metrics
+    }
+
+    /// Get the cache of raw responses.
+    #[must_use]
+    pub fn cache(&self) -> &C {
+        &self.inner.cache
+    }
+
+    /// Get the base URL that requests are made against.
+    #[must_use]
+    pub fn base_url(&self) -> &str {
+        &self.inner.base_url
+    }
+
+    /// Fetch the root planetoid metadata.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP request fails or the response cannot be decoded.
+    pub fn fetch_planetoid(&self) -> Result<Planetoid> {
+        self.fetch_planetoid_with_policy(self.inner.fetch_policy)
+    }
+
+    /// Fetch the root planetoid metadata with `policy` instead of the
+    /// client's default.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP request fails, the response cannot be
+    /// decoded, or the policy requires a cached entry that does not exist.
+    pub fn fetch_planetoid_with_policy(&self, policy: FetchPolicy) -> Result<Planetoid> {
+        block_on(self.inner.fetch_planetoid(policy))
+    }
+
+    /// Fetch bulk metadata for a given path and epoch.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP request fails or the response cannot be decoded.
+    pub fn fetch_bulk(&self, request: &BulkRequest) -> Result<BulkMetadata> {
+        block_on(self.inner.fetch_bulk(request))
+    }
+
+    /// Fetch node data for a given request.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP request fails or the response cannot be decoded.
+    pub fn fetch_node(&self, request: &NodeRequest) -> Result<Node> {
+        block_on(self.inner.fetch_node(request))
+    }
+
+    /// Fetch node data as a shared node, using the decoded node cache.
+    ///
+    /// If the client has a [`DecodedNodeCache`] and it holds the node, the
+    /// cached node is returned without touching the byte cache or decoding,
+    /// unless the request's fetch policy is [`FetchPolicy::NetworkOnly`].
+    /// Otherwise the node is fetched like [`BlockingClient::fetch_node`] and stored in
+    /// the decoded node cache.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP request fails or the response cannot be decoded.
+    pub fn fetch_node_shared(&self, request: &NodeRequest) -> Result<Arc<Node>> {
+        block_on(self.inner.fetch_node_shared(request))
+    }
+
+    /// Fetch bulk metadata, giving up when `token` is cancelled.
+    ///
+    /// The token is checked before the fetch starts and whenever it waits on
+    /// the cache. A blocking HTTP request cannot be interrupted, so a token
+    /// cancelled from another thread while the request is in flight takes
+    /// effect only for later fetches. Cancelled fetches return
+    /// [`Error::Cancelled`](crate::Error::Cancelled) and are counted under
+    /// [`ErrorKind::Cancelled`](crate::ErrorKind::Cancelled) in the client's
+    /// metrics.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the fetch is cancelled, the HTTP request fails, or
+    /// the response cannot be decoded.
+    pub fn fetch_bulk_cancellable(
+        &self,
+        request: &BulkRequest,
+        token: &CancellationToken,
+    ) -> Result<BulkMetadata> {
+        block_on(self.inner.fetch_bulk_cancellable(request, token))
+    }
+
+    /// Fetch node data, giving up when `token` is cancelled.
+    ///
+    /// See [`BlockingClient::fetch_bulk_cancellable`] for the cancellation
+    /// semantics.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the fetch is cancelled, the HTTP request fails, or
+    /// the response cannot be decoded.
+    pub fn fetch_node_cancellable(
+        &self,
+        request: &NodeRequest,
+        token: &CancellationToken,
+    ) -> Result<Node> {
+        block_on(self.inner.fetch_node_cancellable(request, token))
+    }
+
+    /// Fetch node data as a shared node, giving up when `token` is cancelled.
+    ///
+    /// This combines [`BlockingClient::fetch_node_shared`] with the
+    /// cancellation semantics of [`BlockingClient::fetch_bulk_cancellable`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the fetch is cancelled, the HTTP request fails, or
+    /// the response cannot be decoded.
+    pub fn fetch_node_shared_cancellable(
+        &self,
+        request: &NodeRequest,
+        token: &CancellationToken,
+    ) -> Result<Arc<Node>> {
+        block_on(self.inner.fetch_node_shared_cancellable(request, token))
+    }
+
+    /// Fetch raw bytes from a URL, using cache if available.
+    ///
+    /// The response is cached and metrics are recorded if the URL is a
+    /// rocktree request recognized by [`CacheKey::from_url`](crate::CacheKey::from_url).
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP request fails or the policy requires a
+    /// cached entry that does not exist.
+    pub fn fetch_bytes_from_url(&self, url: &str) -> Result<Vec<u8>> {
+        block_on(self.inner.fetch_bytes_from_url(url))
+    }
+
+    /// Build the URL for fetching bulk metadata.
+    #[must_use]
+    pub fn bulk_url(&self, request: &BulkRequest) -> String {
+        self.inner.bulk_url(request)
+    }
+
+    /// Build the URL for fetching node data.
+    #[must_use]
+    pub fn node_url(&self, request: &NodeRequest) -> String {
+        self.inner.node_url(request)
+    }
+
+    /// Build the URL for fetching planetoid metadata.
+    #[must_use]
+    pub fn planetoid_url(&self) -> String {
+        self.inner.planetoid_url()
+    }
+}
+
+impl Transport for reqwest::blocking::Client {
+    /// Send the request on the calling thread; the returned future completes
+    /// on its first poll.
+    fn get<'a>(&'a self, url: &'a str) -> HttpFuture<'a> {
+        Box::pin(async move {
+            let response = reqwest::blocking::Client::get(self, url)
+                .send()
+                .map_err(|e| http_error(url, &e))?;
+            let status = response.status();
+            if !status.is_success() {
+                return Ok((status.as_u16(), Vec::new()));
+            }
+            let data = response.bytes().map_err(|e| http_error(url, &e))?;
+            Ok((status.as_u16(), data.to_vec()))
+        })
+    }
+}
+
+/// A spawner that runs each background task to completion on a new thread.
+fn thread_spawner() -> Spawner {
+    Arc::new(|task| {
+        std::thread::spawn(move || block_on(task));
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{CacheKey, MemoryCache, checksum};
+    use crate::error::Error;
+    use crate::test_util::{planetoid_body, serve};
+    use prost::Message;
+    use rocktree_proto as proto;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_blocking_client_default() {
+        let client = BlockingClient::new();
+        assert!(client.planetoid_url().starts_with("https://"));
+    }
+
+    #[test]
+    fn test_blocking_fetch_planetoid() {
+        let base_url = serve(planetoid_body(), 1);
+        let client = BlockingClient::new().with_base_url(base_url);
+
+        let planetoid = client.fetch_planetoid().unwrap();
+        assert_eq!(planetoid.root_epoch, 7);
+    }
+
+    #[test]
+    fn test_blocking_fetch_uses_cache() {
+        // The server only answers once, so the second fetch must be served
+        // from the cache.
+        let base_url = serve(planetoid_body(), 1);
+        let cache = MemoryCache::new();
+        let client = BlockingClient::with_cache(cache.clone()).with_base_url(base_url);
+
+        assert_eq!(client.fetch_planetoid().unwrap().root_epoch, 7);
+        assert_eq!(cache.len(), 1);
+        assert_eq!(client.fetch_planetoid().unwrap().root_epoch, 7);
+
+        let metrics = client.metrics().snapshot();
+        assert_eq!(metrics.planetoid.requests, 2);
+        assert_eq!(metrics.planetoid.cache_hits, 1);
+        assert_eq!(metrics.planetoid.cache_misses, 1);
+        assert_eq!(
+            metrics.planetoid.bytes_downloaded,
+            planetoid_body().len() as u64
+        );
+        assert_eq!(metrics.planetoid.latency.count(), 1);
+    }
+
+    #[test]
+    fn test_blocking_fetch_node_shared() {
+        // The server only answers once, so the second fetch must come from
+        // the decoded node cache.
+        let base_url = serve(proto::NodeData::default().encode_to_vec(), 1);
+        let nodes = DecodedNodeCache::new(1 << 20);
+        let client = BlockingClient::new()
+            .with_base_url(base_url)
+            .with_node_cache(nodes.clone());
+        let request = NodeRequest::new("0123".to_string(), 1, 1, None);
+
+        let first = client.fetch_node_shared(&request).unwrap();
+        let second = client.fetch_node_shared(&request).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(nodes.len(), 1);
+
+        let metrics = client.metrics().snapshot();
+        assert_eq!(metrics.node.requests, 2);
+        assert_eq!(metrics.node.cache_hits, 1);
+    }
+
+    #[test]
+    fn test_blocking_cache_shared_across_base_urls() {
+        // Entries are keyed by resource, so a client pointed at a mirror
+        // reuses what the origin client cached.
+        let origin = serve(planetoid_body(), 1);
+        let cache = MemoryCache::new();
+        let client = BlockingClient::with_cache(cache.clone()).with_base_url(origin);
+        assert_eq!(client.fetch_planetoid().unwrap().root_epoch, 7);
+
+        let mirror = BlockingClient::with_cache(cache)
+            .with_base_url("http://127.0.0.1:9/mirror/".to_string());
+        assert_eq!(mirror.fetch_planetoid().unwrap().root_epoch, 7);
+        assert_eq!(mirror.metrics().snapshot().planetoid.cache_hits, 1);
+    }
+
+    #[test]
+    fn test_blocking_http_status_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf).unwrap();
+            stream
+                .write_all(
+                    b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+                .unwrap();
+        });
+
+        let client = BlockingClient::new().with_base_url(format!("http://{addr}/"));
+        let err = client.fetch_planetoid().unwrap_err();
+        assert!(matches!(err, Error::HttpStatus { status: 404, .. }));
+
+        let metrics = client.metrics().snapshot();
+        assert_eq!(metrics.planetoid.errors, 1);
+        assert_eq!(metrics.errors.get(crate::ErrorKind::HttpStatus), 1);
+    }
+
+    #[test]
+    fn test_blocking_corrupt_entry_refetched() {
+        let base_url = serve(planetoid_body(), 1);
+        let cache = MemoryCache::new();
+        let mut entry = checksum::seal(&planetoid_body());
+        entry.truncate(entry.len() - 1);
+        block_on(cache.put(&CacheKey::Planetoid, entry)).unwrap();
+
+        let client = BlockingClient::with_cache(cache.clone()).with_base_url(base_url);
+        assert_eq!(client.fetch_planetoid().unwrap().root_epoch, 7);
+        assert_eq!(client.metrics().snapshot().planetoid.cache_misses, 1);
+
+        let repaired = block_on(cache.get(&CacheKey::Planetoid)).unwrap().unwrap();
+        assert_eq!(checksum::unseal(repaired), Some(planetoid_body()));
+    }
+
+    #[test]
+    fn test_blocking_cancelled_fetch() {
+        // The server never answers, so only a fetch that is not sent can
+        // finish.
+        let client = BlockingClient::new().with_base_url("http://127.0.0.1:9/".to_string());
+        assert_eq!(client.base_url(), "http://127.0.0.1:9/");
+        let token = CancellationToken::new();
+        token.cancel();
+
+        let err = client
+            .fetch_bulk_cancellable(&BulkRequest::root(1), &token)
+            .unwrap_err();
+        assert!(matches!(err, Error::Cancelled), "{err:?}");
+        let request = NodeRequest::new("0".to_string(), 1, 1, None);
+        let err = client
+            .fetch_node_shared_cancellable(&request, &token)
+            .unwrap_err();
+        assert!(matches!(err, Error::Cancelled), "{err:?}");
+
+        let metrics = client.metrics().snapshot();
+        assert_eq!(metrics.errors.get(crate::ErrorKind::Cancelled), 2);
+        assert_eq!(metrics.total_requests(), 0);
+    }
+
+    #[test]
+    fn test_blocking_custom_spawner() {
+        let cache = MemoryCache::new();
+        let spawned = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&spawned);
+        let client = BlockingClient::with_cache(cache.clone())
+            .with_base_url(serve(planetoid_body(), 2))
+            .with_fetch_policy(FetchPolicy::StaleWhileRevalidate)
+            .with_spawner(move |task| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                block_on(task);
+            });
+
+        assert_eq!(client.fetch_planetoid().unwrap().root_epoch, 7);
+        assert_eq!(spawned.load(Ordering::SeqCst), 0);
+        // A hit serves the cached entry and refreshes it with the spawner.
+        assert_eq!(client.fetch_planetoid().unwrap().root_epoch, 7);
+        assert_eq!(spawned.load(Ordering::SeqCst), 1);
+        assert_eq!(client.cache().len(), 1);
+    }
+
+    #[test]
+    fn test_blocking_cache_only() {
+        let cache = MemoryCache::new();
+        let client = BlockingClient::with_cache(cache.clone())
+            .with_base_url(serve(planetoid_body(), 1))
+            .with_fetch_policy(FetchPolicy::CacheOnly);
+
+        let err = client.fetch_planetoid().unwrap_err();
+        assert!(matches!(err, Error::NotCached { .. }), "{err:?}");
+
+        let fetched = client
+            .fetch_planetoid_with_policy(FetchPolicy::CacheFirst)
+            .unwrap();
+        assert_eq!(fetched.root_epoch, 7);
+        assert_eq!(client.fetch_planetoid().unwrap().root_epoch, 7);
+    }
+}
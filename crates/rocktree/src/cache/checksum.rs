@@ -0,0 +1,69 @@
+//! Integrity envelope for cached responses.
+//!
+//! The clients store every response behind a short header carrying a CRC-32
+//! of the payload, and verify it when reading the entry back. This catches
+//! truncated and corrupted entries before they reach the decoder.
+
+/// Magic bytes that start every sealed entry.
+const MAGIC: &[u8; 4] = b"RTCK";
+
+/// Length of the header: the magic bytes and the little-endian CRC-32.
+const HEADER_LEN: usize = MAGIC.len() + 4;
+
+/// Prefix `data` with a checksum header.
+pub(crate) fn seal(data: &[u8]) -> Vec<u8> {
+    let mut entry = Vec::with_capacity(HEADER_LEN + data.len());
+    entry.extend_from_slice(MAGIC);
+    entry.extend_from_slice(&crc32fast::hash(data).to_le_bytes());
+    entry.extend_from_slice(data);
+    entry
+}
+
+/// Verify and strip the checksum header.
+///
+/// Returns `None` if the checksum does not match. Entries without a header,
+/// such as those stored before checksums were introduced or written directly
+/// through [`Cache::put`](super::Cache::put), are returned unchanged.
+pub(crate) fn unseal(mut entry: Vec<u8>) -> Option<Vec<u8>> {
+    if !entry.starts_with(MAGIC) {
+        return Some(entry);
+    }
+    let checksum = u32::from_le_bytes(entry.get(MAGIC.len()..HEADER_LEN)?.try_into().ok()?);
+    if crc32fast::hash(&entry[HEADER_LEN..]) != checksum {
+        return None;
+    }
+    entry.drain(..HEADER_LEN);
+    Some(entry)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let data = b"bulk metadata".to_vec();
+        assert_eq!(unseal(seal(&data)), Some(data));
+        assert_eq!(unseal(seal(&[])), Some(Vec::new()));
+    }
+
+    #[test]
+    fn test_detects_truncation() {
+        let mut entry = seal(b"bulk metadata");
+        entry.truncate(entry.len() - 3);
+        assert_eq!(unseal(entry), None);
+        assert_eq!(unseal(MAGIC[..].to_vec()), None);
+    }
+
+    #[test]
+    fn test_detects_corruption() {
+        let mut entry = seal(b"bulk metadata");
+        *entry.last_mut().unwrap() ^= 0x01;
+        assert_eq!(unseal(entry), None);
+    }
+
+    #[test]
+    fn test_unsealed_entries_pass_through() {
+        assert_eq!(unseal(vec![1, 2, 3]), Some(vec![1, 2, 3]));
+    }
+}
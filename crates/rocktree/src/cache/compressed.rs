@@ -0,0 +1,439 @@
+//! Transparent compression for cache entries.
+
+use super::{Cache, CacheFuture, CacheKey, ContainsFuture, EntriesFuture, GetFuture};
+use crate::error::{Error, Result};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The codec used to compress new cache entries.
+///
+/// Every entry records the codec it was written with, so changing this
+/// setting does not invalidate existing entries. Entries written by a codec
+/// whose cargo feature is disabled in the current build are treated as
+/// missing and evicted when read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    /// Store entries uncompressed, with only the entry header.
+    None,
+    /// Deflate (zlib) compression at the given level, from 0 to 9.
+    #[cfg(feature = "deflate")]
+    Deflate {
+        /// Compression level, from 0 (fastest) to 9 (smallest).
+        level: u32,
+    },
+    /// Zstandard compression at the given level, from 1 to 22.
+    #[cfg(all(feature = "zstd", not(target_family = "wasm")))]
+    Zstd {
+        /// Compression level, from 1 (fastest) to 22 (smallest).
+        level: i32,
+    },
+}
+
+/// Statistics about the entries written through a [`CompressedCache`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompressionStats {
+    /// Number of entries written.
+    pub entries_written: u64,
+    /// Total size of the written entries before compression.
+    pub uncompressed_bytes: u64,
+    /// Total size of the written entries as stored, including headers.
+    pub stored_bytes: u64,
+    /// Number of entries read back and decompressed.
+    pub entries_read: u64,
+    /// Number of entries that could not be decompressed and were evicted.
+    pub unreadable: u64,
+}
+
+impl CompressionStats {
+    /// Ratio of stored size to uncompressed size, or `None` if nothing was
+    /// written.
+    ///
+    /// Lower is better; 0.25 means entries take a quarter of their original
+    /// space.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn compression_ratio(&self) -> Option<f64> {
+        (self.uncompressed_bytes > 0)
+            .then(|| self.stored_bytes as f64 / self.uncompressed_bytes as f64)
+    }
+}
+
+/// A cache wrapper that compresses entries before storing them in an inner
+/// cache.
+///
+/// Each stored entry starts with a small header naming the codec it was
+/// compressed with, so entries stay readable when the configured
+/// [`Compression`] changes. Entries without the header are passed through
+/// unchanged, which lets the wrapper be added in front of an existing cache.
+///
+/// If compressing an entry does not make it smaller, it is stored
+/// uncompressed.
+///
+/// [`Cache::contains`] only asks the inner cache and does not decompress the
+/// entry, so it reports entries that cannot be decoded. Reading such an entry
+/// with [`Cache::get`] returns `None` and evicts it, after which both agree.
+#[derive(Debug)]
+pub struct CompressedCache<C: Cache> {
+    inner: C,
+    compression: Compression,
+    counters: Counters,
+}
+
+impl<C: Cache> CompressedCache<C> {
+    /// Wrap `inner`, compressing new entries with `compression`.
+    #[must_use]
+    pub fn new(inner: C, compression: Compression) -> Self {
+        Self {
+            inner,
+            compression,
+            counters: Counters::default(),
+        }
+    }
+
+    /// Get the wrapped cache.
+    #[must_use]
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Get the compression statistics.
+    #[must_use]
+    pub fn stats(&self) -> CompressionStats {
+        CompressionStats {
+            entries_written: self.counters.entries_written.load(Ordering::Relaxed),
+            uncompressed_bytes: self.counters.uncompressed_bytes.load(Ordering::Relaxed),
+            stored_bytes: self.counters.stored_bytes.load(Ordering::Relaxed),
+            entries_read: self.counters.entries_read.load(Ordering::Relaxed),
+            unreadable: self.counters.unreadable.load(Ordering::Relaxed),
+        }
+    }
+
+    async fn get_decompressed(&self, key: &CacheKey) -> Result<Option<Vec<u8>>> {
+        let Some(stored) = self.inner.get(key).await? else {
+            return Ok(None);
+        };
+        match decode_entry(stored) {
+            Ok(data) => {
+                self.counters.entries_read.fetch_add(1, Ordering::Relaxed);
+                Ok(Some(data))
+            }
+            Err(e) => {
+                tracing::warn!(%key, error = %e, "unreadable compressed cache entry");
+                self.counters.unreadable.fetch_add(1, Ordering::Relaxed);
+                self.inner.remove(key).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn put_compressed(&self, key: &CacheKey, data: Vec<u8>) -> Result<()> {
+        let uncompressed = data.len() as u64;
+        let stored = encode_entry(self.compression, data)?;
+        self.counters
+            .entries_written
+            .fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .uncompressed_bytes
+            .fetch_add(uncompressed, Ordering::Relaxed);
+        self.counters
+            .stored_bytes
+            .fetch_add(stored.len() as u64, Ordering::Relaxed);
+        self.inner.put(key, stored).await
+    }
+}
+
+impl<C: Cache> Cache for CompressedCache<C> {
+    fn get(&self, key: &CacheKey) -> GetFuture<'_> {
+        let key = key.clone();
+        Box::pin(async move { self.get_decompressed(&key).await })
+    }
+
+    fn put(&self, key: &CacheKey, data: Vec<u8>) -> CacheFuture<'_> {
+        let key = key.clone();
+        Box::pin(async move { self.put_compressed(&key, data).await })
+    }
+
+    fn contains(&self, key: &CacheKey) -> ContainsFuture<'_> {
+        self.inner.contains(key)
+    }
+
+    fn remove(&self, key: &CacheKey) -> CacheFuture<'_> {
+        self.inner.remove(key)
+    }
+
+    fn clear(&self) -> CacheFuture<'_> {
+        self.inner.clear()
+    }
+
+    fn entries(&self) -> EntriesFuture<'_> {
+        self.inner.entries()
+    }
+}
+
+/// Magic bytes that start every entry written by [`CompressedCache`].
+const MAGIC: &[u8; 3] = b"RTZ";
+
+/// Codec identifiers stored after [`MAGIC`].
+const CODEC_NONE: u8 = 0;
+const CODEC_DEFLATE: u8 = 1;
+const CODEC_ZSTD: u8 = 2;
+
+/// Length of the entry header: the magic bytes and the codec identifier.
+const HEADER_LEN: usize = MAGIC.len() + 1;
+
+#[derive(Debug, Default)]
+struct Counters {
+    entries_written: AtomicU64,
+    uncompressed_bytes: AtomicU64,
+    stored_bytes: AtomicU64,
+    entries_read: AtomicU64,
+    unreadable: AtomicU64,
+}
+
+/// Compress `data` and prepend the entry header.
+#[cfg_attr(
+    not(any(feature = "deflate", feature = "zstd")),
+    allow(clippy::unnecessary_wraps)
+)]
+fn encode_entry(compression: Compression, data: Vec<u8>) -> Result<Vec<u8>> {
+    let compressed: Option<(u8, Vec<u8>)> = match compression {
+        Compression::None => None,
+        #[cfg(feature = "deflate")]
+        Compression::Deflate { level } => Some((CODEC_DEFLATE, deflate::compress(&data, level)?)),
+        #[cfg(all(feature = "zstd", not(target_family = "wasm")))]
+        Compression::Zstd { level } => Some((CODEC_ZSTD, zstd_codec::compress(&data, level)?)),
+    };
+
+    // Fall back to storing the data as is when compression does not help.
+    let (codec, payload) = match compressed {
+        Some((codec, payload)) if payload.len() < data.len() => (codec, payload),
+        _ => (CODEC_NONE, data),
+    };
+
+    let mut entry = Vec::with_capacity(HEADER_LEN + payload.len());
+    entry.extend_from_slice(MAGIC);
+    entry.push(codec);
+    entry.extend_from_slice(&payload);
+    Ok(entry)
+}
+
+/// Strip the entry header and decompress the payload.
+///
+/// Data without a header predates the wrapper and is returned unchanged.
+fn decode_entry(mut stored: Vec<u8>) -> Result<Vec<u8>> {
+    if stored.len() < HEADER_LEN || &stored[..MAGIC.len()] != MAGIC {
+        return Ok(stored);
+    }
+    let codec = stored[MAGIC.len()];
+    match codec {
+        CODEC_NONE => {
+            stored.drain(..HEADER_LEN);
+            Ok(stored)
+        }
+        #[cfg(feature = "deflate")]
+        CODEC_DEFLATE => deflate::decompress(&stored[HEADER_LEN..]),
+        #[cfg(all(feature = "zstd", not(target_family = "wasm")))]
+        CODEC_ZSTD => zstd_codec::decompress(&stored[HEADER_LEN..]),
+        _ => Err(Error::Cache {
+            operation: "decompress",
+            message: format!("codec {} ({codec}) is not enabled", codec_name(codec)),
+        }),
+    }
+}
+
+fn codec_name(codec: u8) -> &'static str {
+    match codec {
+        CODEC_NONE => "none",
+        CODEC_DEFLATE => "deflate",
+        CODEC_ZSTD => "zstd",
+        _ => "unknown",
+    }
+}
+
+#[cfg(feature = "deflate")]
+mod deflate {
+    use crate::error::{Error, Result};
+    use flate2::Compression;
+    use flate2::read::ZlibDecoder;
+    use flate2::write::ZlibEncoder;
+    use std::io::{Read, Write};
+
+    pub(super) fn compress(data: &[u8], level: u32) -> Result<Vec<u8>> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level.min(9)));
+        let compress_error = |e: std::io::Error| Error::Cache {
+            operation: "compress",
+            message: format!("deflate: {e}"),
+        };
+        encoder.write_all(data).map_err(compress_error)?;
+        encoder.finish().map_err(compress_error)
+    }
+
+    pub(super) fn decompress(payload: &[u8]) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        ZlibDecoder::new(payload)
+            .read_to_end(&mut data)
+            .map_err(|e| Error::Cache {
+                operation: "decompress",
+                message: format!("deflate: {e}"),
+            })?;
+        Ok(data)
+    }
+}
+
+#[cfg(all(feature = "zstd", not(target_family = "wasm")))]
+mod zstd_codec {
+    use crate::error::{Error, Result};
+
+    pub(super) fn compress(data: &[u8], level: i32) -> Result<Vec<u8>> {
+        zstd::bulk::compress(data, level).map_err(|e| Error::Cache {
+            operation: "compress",
+            message: format!("zstd: {e}"),
+        })
+    }
+
+    pub(super) fn decompress(payload: &[u8]) -> Result<Vec<u8>> {
+        zstd::stream::decode_all(payload).map_err(|e| Error::Cache {
+            operation: "decompress",
+            message: format!("zstd: {e}"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{MemoryCache, block_on};
+
+    fn key(path: &str) -> CacheKey {
+        CacheKey::Bulk {
+            path: path.to_string(),
+            epoch: 1,
+        }
+    }
+
+    /// Data that compresses well.
+    fn repetitive() -> Vec<u8> {
+        b"rocktree ".repeat(200)
+    }
+
+    #[test]
+    fn test_uncompressed_round_trip() {
+        let cache = CompressedCache::new(MemoryCache::new(), Compression::None);
+        block_on(cache.put(&key("0"), repetitive())).unwrap();
+
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), Some(repetitive()));
+        let stored = block_on(cache.inner().get(&key("0"))).unwrap().unwrap();
+        assert_eq!(stored.len(), repetitive().len() + HEADER_LEN);
+    }
+
+    #[test]
+    fn test_legacy_entries_pass_through() {
+        let cache = CompressedCache::new(MemoryCache::new(), Compression::None);
+        block_on(cache.inner().put(&key("0"), vec![1, 2, 3])).unwrap();
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), Some(vec![1, 2, 3]));
+    }
+
+    #[test]
+    fn test_unknown_codec_is_a_miss() {
+        let cache = CompressedCache::new(MemoryCache::new(), Compression::None);
+        let mut stored = MAGIC.to_vec();
+        stored.extend_from_slice(&[0xEE, 1, 2, 3]);
+        block_on(cache.inner().put(&key("0"), stored)).unwrap();
+
+        assert!(block_on(cache.contains(&key("0"))).unwrap());
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), None);
+        assert_eq!(cache.stats().unreadable, 1);
+        assert!(!block_on(cache.contains(&key("0"))).unwrap());
+    }
+
+    #[test]
+    fn test_remove_and_clear_reach_inner() {
+        let cache = CompressedCache::new(MemoryCache::new(), Compression::None);
+        block_on(cache.put(&key("0"), vec![1])).unwrap();
+        block_on(cache.put(&key("1"), vec![2])).unwrap();
+        assert!(block_on(cache.contains(&key("0"))).unwrap());
+
+        block_on(cache.remove(&key("0"))).unwrap();
+        assert_eq!(cache.inner().len(), 1);
+        block_on(cache.clear()).unwrap();
+        assert!(cache.inner().is_empty());
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn test_deflate_round_trip_and_ratio() {
+        let cache = CompressedCache::new(MemoryCache::new(), Compression::Deflate { level: 6 });
+        block_on(cache.put(&key("0"), repetitive())).unwrap();
+
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), Some(repetitive()));
+        let stored = block_on(cache.inner().get(&key("0"))).unwrap().unwrap();
+        assert_eq!(stored[MAGIC.len()], CODEC_DEFLATE);
+
+        let stats = cache.stats();
+        assert_eq!(stats.entries_written, 1);
+        assert_eq!(stats.entries_read, 1);
+        assert_eq!(stats.uncompressed_bytes, repetitive().len() as u64);
+        assert_eq!(stats.stored_bytes, stored.len() as u64);
+        assert!(stats.compression_ratio().unwrap() < 0.2);
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn test_incompressible_data_stored_raw() {
+        let cache = CompressedCache::new(MemoryCache::new(), Compression::Deflate { level: 9 });
+        block_on(cache.put(&key("0"), vec![7])).unwrap();
+
+        let stored = block_on(cache.inner().get(&key("0"))).unwrap().unwrap();
+        assert_eq!(stored[MAGIC.len()], CODEC_NONE);
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), Some(vec![7]));
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn test_readable_after_codec_change() {
+        let inner = MemoryCache::new();
+        let deflated = CompressedCache::new(inner.clone(), Compression::Deflate { level: 6 });
+        block_on(deflated.put(&key("0"), repetitive())).unwrap();
+
+        let plain = CompressedCache::new(inner, Compression::None);
+        assert_eq!(block_on(plain.get(&key("0"))).unwrap(), Some(repetitive()));
+    }
+
+    #[cfg(feature = "deflate")]
+    #[test]
+    fn test_corrupt_payload_is_a_miss() {
+        let cache = CompressedCache::new(MemoryCache::new(), Compression::Deflate { level: 6 });
+        let mut stored = MAGIC.to_vec();
+        stored.extend_from_slice(&[CODEC_DEFLATE, 0xFF, 0xFF, 0xFF]);
+        block_on(cache.inner().put(&key("0"), stored)).unwrap();
+
+        assert!(block_on(cache.contains(&key("0"))).unwrap());
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), None);
+        assert_eq!(cache.stats().unreadable, 1);
+        assert!(!block_on(cache.contains(&key("0"))).unwrap());
+    }
+
+    #[cfg(all(feature = "zstd", not(target_family = "wasm")))]
+    #[test]
+    fn test_zstd_round_trip() {
+        let cache = CompressedCache::new(MemoryCache::new(), Compression::Zstd { level: 3 });
+        block_on(cache.put(&key("0"), repetitive())).unwrap();
+
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), Some(repetitive()));
+        let stored = block_on(cache.inner().get(&key("0"))).unwrap().unwrap();
+        assert_eq!(stored[MAGIC.len()], CODEC_ZSTD);
+        assert!(cache.stats().compression_ratio().unwrap() < 0.2);
+    }
+
+    #[cfg(all(feature = "zstd", feature = "deflate", not(target_family = "wasm")))]
+    #[test]
+    fn test_mixed_codecs_readable() {
+        let inner = MemoryCache::new();
+        let deflated = CompressedCache::new(inner.clone(), Compression::Deflate { level: 6 });
+        block_on(deflated.put(&key("0"), repetitive())).unwrap();
+
+        let zstd = CompressedCache::new(inner, Compression::Zstd { level: 3 });
+        block_on(zstd.put(&key("1"), repetitive())).unwrap();
+        assert_eq!(block_on(zstd.get(&key("0"))).unwrap(), Some(repetitive()));
+        assert_eq!(block_on(zstd.get(&key("1"))).unwrap(), Some(repetitive()));
+    }
+}
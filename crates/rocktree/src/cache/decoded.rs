@@ -0,0 +1,184 @@
+//! In-memory cache of decoded nodes.
+
+use super::lru::{Limits, Lru};
+use super::{CacheKey, CacheStats};
+use crate::types::{Node, NodeRequest};
+use std::sync::{Arc, Mutex};
+
+/// A memory-bounded LRU cache of decoded nodes.
+///
+/// The byte caches store raw protobuf responses, so every hit still pays for
+/// decoding, including texture decompression. This cache sits after decoding
+/// and hands out shared [`Arc<Node>`]s, so revisiting a node costs a lookup.
+/// Attach one to a client with
+/// [`Client::with_node_cache`](crate::Client::with_node_cache).
+///
+/// Entries are keyed by node request and weighed by
+/// [`Node::memory_size`]. Clones share the same storage and statistics.
+#[derive(Debug, Clone)]
+pub struct DecodedNodeCache {
+    data: Arc<Mutex<Lru<Arc<Node>>>>,
+    limits: Limits,
+}
+
+impl DecodedNodeCache {
+    /// Create a cache holding at most `max_size` bytes of decoded nodes.
+    #[must_use]
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            data: Arc::new(Mutex::new(Lru::default())),
+            limits: Limits {
+                max_size: Some(max_size),
+                ..Limits::default()
+            },
+        }
+    }
+
+    /// Limit the number of nodes the cache holds.
+    #[must_use]
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.limits.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Get the decoded node for a request, marking it as most recently used.
+    #[must_use]
+    pub fn get(&self, request: &NodeRequest) -> Option<Arc<Node>> {
+        self.data
+            .lock()
+            .unwrap()
+            .get(&CacheKey::node(request), self.limits)
+            .cloned()
+    }
+
+    /// Store the decoded node for a request, evicting least recently used
+    /// nodes as needed.
+    ///
+    /// Nodes larger than the whole cache are not stored.
+    pub fn insert(&self, request: &NodeRequest, node: Arc<Node>) {
+        let size = node.memory_size();
+        self.data
+            .lock()
+            .unwrap()
+            .insert(CacheKey::node(request), node, size, self.limits);
+    }
+
+    /// Remove the decoded node for a request, returning whether it was cached.
+    #[allow(clippy::must_use_candidate)]
+    pub fn remove(&self, request: &NodeRequest) -> bool {
+        self.data.lock().unwrap().remove(&CacheKey::node(request))
+    }
+
+    /// Remove all nodes, keeping the statistics.
+    pub fn clear(&self) {
+        self.data.lock().unwrap().clear();
+    }
+
+    /// Get the estimated memory held by cached nodes in bytes.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.data.lock().unwrap().size()
+    }
+
+    /// Get the number of cached nodes.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.data.lock().unwrap().len()
+    }
+
+    /// Check if the cache is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get hit, miss, and eviction statistics along with the current usage.
+    #[must_use]
+    pub fn stats(&self) -> CacheStats {
+        self.data.lock().unwrap().stats()
+    }
+
+    /// Reset the hit, miss, and eviction counters.
+    pub fn reset_stats(&self) {
+        self.data.lock().unwrap().reset_stats();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::{DMat3, DMat4, DVec3};
+    use rocktree_decode::OrientedBoundingBox;
+
+    fn request(path: &str) -> NodeRequest {
+        NodeRequest::new(path.to_string(), 1, 1, None)
+    }
+
+    fn node(path: &str) -> Arc<Node> {
+        Arc::new(Node {
+            path: path.to_string(),
+            matrix_globe_from_mesh: DMat4::IDENTITY,
+            meters_per_texel: 1.0,
+            obb: OrientedBoundingBox {
+                center: DVec3::ZERO,
+                extents: DVec3::ONE,
+                orientation: DMat3::IDENTITY,
+            },
+            meshes: Vec::new(),
+        })
+    }
+
+    #[test]
+    fn test_shares_nodes() {
+        let cache = DecodedNodeCache::new(1 << 20);
+        let original = node("0");
+        cache.insert(&request("0"), Arc::clone(&original));
+
+        let cached = cache.get(&request("0")).unwrap();
+        assert!(Arc::ptr_eq(&cached, &original));
+        assert!(cache.get(&request("1")).is_none());
+
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!(stats.size, original.memory_size());
+    }
+
+    #[test]
+    fn test_keyed_by_full_request() {
+        let cache = DecodedNodeCache::new(1 << 20);
+        cache.insert(&request("0"), node("0"));
+        let other_epoch = NodeRequest::new("0".to_string(), 2, 1, None);
+        assert!(cache.get(&other_epoch).is_none());
+    }
+
+    #[test]
+    fn test_memory_bound_evicts_lru() {
+        let size = node("0").memory_size();
+        let cache = DecodedNodeCache::new(size * 2);
+        cache.insert(&request("0"), node("0"));
+        cache.insert(&request("1"), node("1"));
+        assert!(cache.get(&request("0")).is_some());
+        cache.insert(&request("2"), node("2"));
+
+        assert!(cache.get(&request("0")).is_some());
+        assert!(cache.get(&request("1")).is_none());
+        assert!(cache.get(&request("2")).is_some());
+        assert_eq!(cache.stats().evictions, 1);
+        assert!(cache.size() <= size * 2);
+    }
+
+    #[test]
+    fn test_remove_and_clear() {
+        let cache = DecodedNodeCache::new(1 << 20).with_max_entries(8);
+        cache.insert(&request("0"), node("0"));
+        cache.insert(&request("1"), node("1"));
+
+        assert!(cache.remove(&request("0")));
+        assert!(!cache.remove(&request("0")));
+        assert_eq!(cache.len(), 1);
+
+        cache.clear();
+        assert!(cache.is_empty());
+        assert_eq!(cache.size(), 0);
+    }
+}
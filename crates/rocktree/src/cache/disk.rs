@@ -0,0 +1,336 @@
+//! Persistent cache storing one file per entry.
+
+use super::key::is_octant_path;
+use super::{Cache, CacheEntry, CacheFuture, CacheKey, ContainsFuture, EntriesFuture, GetFuture};
+use crate::error::{Error, Result};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// A cache that stores each entry as a file in a directory.
+///
+/// Entries live at paths derived from the [`CacheKey`] display form, for
+/// example `bulk/42/0123.bin`, so the cache survives restarts and can be
+/// inspected with ordinary tools. Writes go to a temporary file that is
+/// renamed into place, so readers never see partial entries. Clearing the
+/// cache deletes only its entry files, not other files in the directory.
+///
+/// File operations run on the calling thread. Entries are small, but callers
+/// on latency-sensitive executors may prefer to put a [`MemoryCache`] in
+/// front with a [`LayeredCache`].
+///
+/// This cache is only available on native targets.
+///
+/// [`MemoryCache`]: super::MemoryCache
+/// [`LayeredCache`]: super::LayeredCache
+#[derive(Debug, Clone)]
+pub struct DiskCache {
+    root: PathBuf,
+}
+
+impl DiskCache {
+    /// Open the cache in `root`, creating the directory if it does not exist.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the directory cannot be created.
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root).map_err(|e| io_error("open", &root, &e))?;
+        Ok(Self { root })
+    }
+
+    /// Get the directory holding the cache.
+    #[must_use]
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Get the file that stores the entry for `key`.
+    ///
+    /// Keys whose path is not made of octants are rejected, so no key can
+    /// name a file outside the root.
+    fn entry_path(&self, operation: &'static str, key: &CacheKey) -> Result<PathBuf> {
+        if !is_octant_path(key.path()) {
+            return Err(Error::Cache {
+                operation,
+                message: format!("invalid key path {:?}", key.path()),
+            });
+        }
+        let name = key.to_string();
+        let mut path = self.root.clone();
+        let mut components = name.split('/').peekable();
+        while let Some(component) = components.next() {
+            if components.peek().is_some() {
+                path.push(component);
+            } else {
+                path.push(format!("{component}.bin"));
+            }
+        }
+        Ok(path)
+    }
+
+    fn read(&self, key: &CacheKey) -> Result<Option<Vec<u8>>> {
+        let path = self.entry_path("get", key)?;
+        match std::fs::read(&path) {
+            Ok(data) => Ok(Some(data)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(io_error("get", &path, &e)),
+        }
+    }
+
+    fn write(&self, key: &CacheKey, data: &[u8]) -> Result<()> {
+        let path = self.entry_path("put", key)?;
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| io_error("put", parent, &e))?;
+        }
+        let temp = temp_path(&path);
+        std::fs::write(&temp, data).map_err(|e| io_error("put", &temp, &e))?;
+        std::fs::rename(&temp, &path).map_err(|e| {
+            let _ = std::fs::remove_file(&temp);
+            io_error("put", &path, &e)
+        })
+    }
+
+    fn delete(&self, key: &CacheKey) -> Result<()> {
+        let path = self.entry_path("remove", key)?;
+        match std::fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(io_error("remove", &path, &e)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Walk the directory tree, collecting every file that maps back to a key.
+    ///
+    /// Temporary files and files that do not belong to the cache are skipped.
+    fn list(&self) -> Result<Vec<CacheEntry>> {
+        let mut entries = Vec::new();
+        let mut dirs = vec![(self.root.clone(), String::new())];
+        while let Some((dir, prefix)) = dirs.pop() {
+            let read_dir = std::fs::read_dir(&dir).map_err(|e| io_error("list", &dir, &e))?;
+            for item in read_dir {
+                let item = item.map_err(|e| io_error("list", &dir, &e))?;
+                let Ok(name) = item.file_name().into_string() else {
+                    continue;
+                };
+                let file_type = item.file_type().map_err(|e| io_error("list", &dir, &e))?;
+                if file_type.is_dir() {
+                    dirs.push((item.path(), format!("{prefix}{name}/")));
+                    continue;
+                }
+                let Some(stem) = name.strip_suffix(".bin") else {
+                    continue;
+                };
+                let Ok(key) = format!("{prefix}{stem}").parse::<CacheKey>() else {
+                    continue;
+                };
+                let metadata = item.metadata().map_err(|e| io_error("list", &dir, &e))?;
+                entries.push(CacheEntry {
+                    key,
+                    size: usize::try_from(metadata.len()).unwrap_or(usize::MAX),
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Delete every entry, then the directories it leaves empty.
+    ///
+    /// Only files that map back to a key are deleted, so anything else in
+    /// the directory, including the directory itself, is left alone.
+    fn delete_all(&self) -> Result<()> {
+        for entry in self.list()? {
+            self.delete(&entry.key)?;
+            self.prune_empty_dirs(&self.entry_path("clear", &entry.key)?);
+        }
+        Ok(())
+    }
+
+    /// Remove the directories between `path` and the root, stopping at the
+    /// first one that is not empty.
+    fn prune_empty_dirs(&self, path: &Path) {
+        let mut dir = path.parent();
+        while let Some(current) = dir.filter(|d| *d != self.root && d.starts_with(&self.root)) {
+            if std::fs::remove_dir(current).is_err() {
+                break;
+            }
+            dir = current.parent();
+        }
+    }
+}
+
+impl Cache for DiskCache {
+    fn get(&self, key: &CacheKey) -> GetFuture<'_> {
+        let result = self.read(key);
+        Box::pin(async move { result })
+    }
+
+    fn put(&self, key: &CacheKey, data: Vec<u8>) -> CacheFuture<'_> {
+        let result = self.write(key, &data);
+        Box::pin(async move { result })
+    }
+
+    fn contains(&self, key: &CacheKey) -> ContainsFuture<'_> {
+        let result = self.entry_path("contains", key).map(|path| path.is_file());
+        Box::pin(async move { result })
+    }
+
+    fn remove(&self, key: &CacheKey) -> CacheFuture<'_> {
+        let result = self.delete(key);
+        Box::pin(async move { result })
+    }
+
+    fn clear(&self) -> CacheFuture<'_> {
+        let result = self.delete_all();
+        Box::pin(async move { result })
+    }
+
+    fn entries(&self) -> EntriesFuture<'_> {
+        let result = self.list();
+        Box::pin(async move { result })
+    }
+}
+
+/// Get a temporary path next to `path` that no other write, in this or
+/// another process, uses at the same time.
+pub(crate) fn temp_path(path: &Path) -> PathBuf {
+    static NEXT: AtomicU64 = AtomicU64::new(0);
+    let mut temp = path.as_os_str().to_os_string();
+    temp.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    PathBuf::from(temp)
+}
+
+fn io_error(operation: &'static str, path: &Path, e: &std::io::Error) -> Error {
+    Error::Cache {
+        operation,
+        message: format!("{}: {e}", path.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::block_on;
+
+    fn bulk(path: &str) -> CacheKey {
+        CacheKey::Bulk {
+            path: path.to_string(),
+            epoch: 1,
+        }
+    }
+
+    #[test]
+    fn test_round_trip_and_persistence() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path()).unwrap();
+        block_on(cache.put(&bulk(""), vec![1])).unwrap();
+        block_on(cache.put(&bulk("01"), vec![2])).unwrap();
+        assert!(dir.path().join("bulk/1/.bin").is_file());
+
+        // A new instance over the same directory sees the entries.
+        let reopened = DiskCache::new(dir.path()).unwrap();
+        assert_eq!(block_on(reopened.get(&bulk(""))).unwrap(), Some(vec![1]));
+        assert_eq!(block_on(reopened.get(&bulk("01"))).unwrap(), Some(vec![2]));
+        assert_eq!(block_on(reopened.get(&bulk("02"))).unwrap(), None);
+        assert!(block_on(reopened.contains(&bulk("01"))).unwrap());
+    }
+
+    #[test]
+    fn test_overwrite_remove_and_clear() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path()).unwrap();
+        block_on(cache.put(&bulk("0"), vec![1])).unwrap();
+        block_on(cache.put(&bulk("0"), vec![2])).unwrap();
+        assert_eq!(block_on(cache.get(&bulk("0"))).unwrap(), Some(vec![2]));
+
+        block_on(cache.remove(&bulk("0"))).unwrap();
+        block_on(cache.remove(&bulk("0"))).unwrap();
+        assert!(!block_on(cache.contains(&bulk("0"))).unwrap());
+
+        block_on(cache.put(&CacheKey::Planetoid, vec![3])).unwrap();
+        block_on(cache.clear()).unwrap();
+        assert_eq!(block_on(cache.get(&CacheKey::Planetoid)).unwrap(), None);
+        assert!(dir.path().is_dir());
+    }
+
+    #[test]
+    fn test_clear_keeps_foreign_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path()).unwrap();
+        block_on(cache.put(&CacheKey::Planetoid, vec![1])).unwrap();
+        block_on(cache.put(&bulk("0"), vec![2])).unwrap();
+        block_on(cache.put(&bulk("1"), vec![3])).unwrap();
+        std::fs::write(dir.path().join("notes.txt"), b"keep").unwrap();
+        std::fs::write(dir.path().join("bulk/README"), b"keep").unwrap();
+
+        block_on(cache.clear()).unwrap();
+        assert!(block_on(cache.entries()).unwrap().is_empty());
+        assert_eq!(
+            std::fs::read(dir.path().join("notes.txt")).unwrap(),
+            b"keep"
+        );
+        assert_eq!(
+            std::fs::read(dir.path().join("bulk/README")).unwrap(),
+            b"keep"
+        );
+        // Directories emptied by the clear are removed.
+        assert!(!dir.path().join("bulk/1").exists());
+    }
+
+    #[test]
+    fn test_rejects_path_traversal() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("cache");
+        let cache = DiskCache::new(&root).unwrap();
+        let key = bulk("../../../escaped");
+        assert!(cache.entry_path("put", &key).is_err());
+        assert!(block_on(cache.put(&key, vec![1])).is_err());
+        assert!(block_on(cache.get(&key)).is_err());
+        assert!(!dir.path().join("escaped.bin").exists());
+        assert_eq!(std::fs::read_dir(&root).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_concurrent_puts_of_one_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path()).unwrap();
+        std::thread::scope(|scope| {
+            for value in 0..8u8 {
+                let cache = &cache;
+                scope.spawn(move || {
+                    for _ in 0..50 {
+                        block_on(cache.put(&bulk("0"), vec![value; 1024])).unwrap();
+                    }
+                });
+            }
+        });
+        let data = block_on(cache.get(&bulk("0"))).unwrap().unwrap();
+        assert_eq!(data.len(), 1024);
+        assert!(data.iter().all(|&byte| byte == data[0]));
+        // No temporary files are left behind.
+        assert_eq!(
+            std::fs::read_dir(dir.path().join("bulk/1"))
+                .unwrap()
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = DiskCache::new(dir.path()).unwrap();
+        block_on(cache.put(&CacheKey::Planetoid, vec![1, 2])).unwrap();
+        block_on(cache.put(&bulk("0"), vec![3])).unwrap();
+        std::fs::write(dir.path().join("bulk/README"), b"not an entry").unwrap();
+
+        let mut entries = block_on(cache.entries()).unwrap();
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        let listed: Vec<(CacheKey, usize)> = entries.into_iter().map(|e| (e.key, e.size)).collect();
+        assert_eq!(listed, vec![(CacheKey::Planetoid, 2), (bulk("0"), 1)]);
+    }
+}
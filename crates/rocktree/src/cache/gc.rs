@@ -0,0 +1,348 @@
+//! Garbage collection of entries for superseded epochs.
+
+use super::{Cache, CacheEntry, CacheKey};
+use crate::error::Result;
+use crate::types::BulkMetadata;
+use std::collections::HashMap;
+
+/// The epochs currently published, used to recognize superseded entries.
+///
+/// Start from the planetoid's root epoch and add the bulk metadata at hand;
+/// the more bulks are known, the more superseded entries can be identified.
+/// Independently of what is known here, an entry is also superseded when the
+/// cache holds a newer epoch of the same resource.
+#[derive(Debug, Clone, Default)]
+pub struct CurrentEpochs {
+    bulks: HashMap<String, u32>,
+    nodes: HashMap<String, (u32, Option<u32>)>,
+}
+
+impl CurrentEpochs {
+    /// Create the epochs for a planetoid whose root bulk has `root_epoch`.
+    #[must_use]
+    pub fn new(root_epoch: u32) -> Self {
+        let mut epochs = Self::default();
+        epochs.insert_bulk(String::new(), root_epoch);
+        epochs
+    }
+
+    /// Record the current epoch of the bulk at `path`.
+    pub fn insert_bulk(&mut self, path: String, epoch: u32) {
+        self.bulks.insert(path, epoch);
+    }
+
+    /// Record the epochs that `bulk` publishes for its child bulks and nodes.
+    pub fn add_bulk_metadata(&mut self, bulk: &BulkMetadata) {
+        for (relative, &epoch) in &bulk.child_bulk_paths {
+            self.insert_bulk(format!("{}{relative}", bulk.path), epoch);
+        }
+        for node in bulk.nodes.iter().filter(|node| node.has_data) {
+            self.nodes
+                .insert(node.path.clone(), (node.epoch, node.imagery_epoch));
+        }
+    }
+
+    /// Get the current epoch of the bulk at `path`, if known.
+    #[must_use]
+    pub fn bulk_epoch(&self, path: &str) -> Option<u32> {
+        self.bulks.get(path).copied()
+    }
+
+    /// Check whether `key` refers to an older epoch than the known current
+    /// one.
+    ///
+    /// Keys for resources whose current epoch is unknown are not superseded.
+    #[must_use]
+    pub fn is_superseded(&self, key: &CacheKey) -> bool {
+        match key {
+            CacheKey::Planetoid => false,
+            CacheKey::Bulk { path, epoch } => self.bulk_epoch(path).is_some_and(|c| *epoch < c),
+            CacheKey::Node {
+                path,
+                epoch,
+                imagery_epoch,
+                ..
+            } => self.nodes.get(path).is_some_and(|&(current, current_imagery)| {
+                *epoch < current
+                    || (*epoch == current
+                        && matches!((imagery_epoch, current_imagery), (Some(a), Some(b)) if *a < b))
+            }),
+        }
+    }
+}
+
+/// Cache entries identified as superseded.
+#[derive(Debug, Clone, Default)]
+pub struct GcReport {
+    entries: Vec<CacheEntry>,
+}
+
+impl GcReport {
+    /// Get the superseded entries, ordered by key.
+    #[must_use]
+    pub fn entries(&self) -> &[CacheEntry] {
+        &self.entries
+    }
+
+    /// Get the number of superseded entries.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Check if no entries are superseded.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Get the total size of the superseded entries in bytes.
+    #[must_use]
+    pub fn bytes(&self) -> u64 {
+        self.entries.iter().map(|entry| entry.size as u64).sum()
+    }
+}
+
+/// Find the entries in `cache` whose epoch has been superseded, without
+/// removing them.
+///
+/// This is the dry run of [`remove_superseded`]: the report's
+/// [`bytes`](GcReport::bytes) is the space that removing them would reclaim.
+///
+/// # Errors
+///
+/// Returns an error if `cache` cannot list its entries.
+pub async fn find_superseded<C: Cache + ?Sized>(
+    cache: &C,
+    current: &CurrentEpochs,
+) -> Result<GcReport> {
+    let entries = cache.entries().await?;
+
+    // The newest epoch of each resource present in the cache.
+    let mut newest: HashMap<Resource<'_>, (u32, Option<u32>)> = HashMap::new();
+    for entry in &entries {
+        if let Some((resource, version)) = Resource::of(&entry.key) {
+            newest
+                .entry(resource)
+                .and_modify(|v| *v = (*v).max(version))
+                .or_insert(version);
+        }
+    }
+
+    let mut superseded: Vec<CacheEntry> = entries
+        .iter()
+        .filter(|entry| {
+            current.is_superseded(&entry.key)
+                || Resource::of(&entry.key)
+                    .is_some_and(|(resource, version)| version < newest[&resource])
+        })
+        .cloned()
+        .collect();
+    superseded.sort_by(|a, b| a.key.cmp(&b.key));
+    Ok(GcReport {
+        entries: superseded,
+    })
+}
+
+/// Remove the entries in `cache` whose epoch has been superseded.
+///
+/// Returns a report of the removed entries. Use [`find_superseded`] to see
+/// what would be removed first.
+///
+/// # Errors
+///
+/// Returns an error if `cache` cannot list its entries or fails to remove
+/// one.
+pub async fn remove_superseded<C: Cache + ?Sized>(
+    cache: &C,
+    current: &CurrentEpochs,
+) -> Result<GcReport> {
+    let report = find_superseded(cache, current).await?;
+    for entry in &report.entries {
+        cache.remove(&entry.key).await?;
+    }
+    tracing::info!(
+        entries = report.len(),
+        bytes = report.bytes(),
+        "removed superseded cache entries"
+    );
+    Ok(report)
+}
+
+/// A versioned resource: the key without its epochs.
+#[derive(Debug, PartialEq, Eq, Hash)]
+enum Resource<'a> {
+    Bulk(&'a str),
+    Node(&'a str, i32),
+}
+
+impl<'a> Resource<'a> {
+    /// Split a key into its resource and its `(epoch, imagery epoch)`
+    /// version, or `None` for unversioned keys.
+    fn of(key: &'a CacheKey) -> Option<(Self, (u32, Option<u32>))> {
+        match key {
+            CacheKey::Planetoid => None,
+            CacheKey::Bulk { path, epoch } => Some((Resource::Bulk(path), (*epoch, None))),
+            CacheKey::Node {
+                path,
+                epoch,
+                texture_format,
+                imagery_epoch,
+            } => Some((
+                Resource::Node(path, *texture_format),
+                (*epoch, *imagery_epoch),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{CacheFuture, ContainsFuture, GetFuture, MemoryCache, block_on};
+    use crate::types::NodeMetadata;
+    use glam::{DMat3, DVec3, Vec3};
+    use rocktree_decode::OrientedBoundingBox;
+
+    fn bulk(path: &str, epoch: u32) -> CacheKey {
+        CacheKey::Bulk {
+            path: path.to_string(),
+            epoch,
+        }
+    }
+
+    fn node(path: &str, epoch: u32, imagery_epoch: Option<u32>) -> CacheKey {
+        CacheKey::Node {
+            path: path.to_string(),
+            epoch,
+            texture_format: 1,
+            imagery_epoch,
+        }
+    }
+
+    fn node_metadata(path: &str, epoch: u32, imagery_epoch: Option<u32>) -> NodeMetadata {
+        NodeMetadata {
+            path: path.to_string(),
+            meters_per_texel: 1.0,
+            obb: OrientedBoundingBox {
+                center: DVec3::ZERO,
+                extents: DVec3::ONE,
+                orientation: DMat3::IDENTITY,
+            },
+            has_data: true,
+            flags: 0,
+            epoch,
+            texture_format: 1,
+            imagery_epoch,
+        }
+    }
+
+    fn root_bulk() -> BulkMetadata {
+        BulkMetadata {
+            path: String::new(),
+            head_node_center: Vec3::ZERO,
+            meters_per_texel: Vec::new(),
+            nodes: vec![node_metadata("0", 5, Some(2))],
+            child_bulk_paths: HashMap::from([("0123".to_string(), 8)]),
+            epoch: 10,
+        }
+    }
+
+    fn populate(cache: &MemoryCache, keys: &[CacheKey]) {
+        for key in keys {
+            block_on(cache.put(key, vec![0; 10])).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_current_epochs() {
+        let mut current = CurrentEpochs::new(10);
+        current.add_bulk_metadata(&root_bulk());
+
+        assert!(current.is_superseded(&bulk("", 9)));
+        assert!(!current.is_superseded(&bulk("", 10)));
+        assert!(!current.is_superseded(&bulk("", 11)));
+        assert!(current.is_superseded(&bulk("0123", 7)));
+        assert!(!current.is_superseded(&bulk("4567", 1)));
+        assert!(current.is_superseded(&node("0", 4, Some(9))));
+        assert!(current.is_superseded(&node("0", 5, Some(1))));
+        assert!(!current.is_superseded(&node("0", 5, Some(2))));
+        assert!(!current.is_superseded(&node("0", 5, None)));
+        assert!(!current.is_superseded(&CacheKey::Planetoid));
+    }
+
+    #[test]
+    fn test_dry_run_reports_without_removing() {
+        let cache = MemoryCache::new();
+        populate(
+            &cache,
+            &[
+                CacheKey::Planetoid,
+                bulk("", 9),
+                bulk("", 10),
+                bulk("0123", 7),
+            ],
+        );
+        let mut current = CurrentEpochs::new(10);
+        current.add_bulk_metadata(&root_bulk());
+
+        let report = block_on(find_superseded(&cache, &current)).unwrap();
+        let keys: Vec<&CacheKey> = report.entries().iter().map(|e| &e.key).collect();
+        assert_eq!(keys, vec![&bulk("", 9), &bulk("0123", 7)]);
+        assert_eq!(report.bytes(), 20);
+        assert_eq!(cache.len(), 4);
+    }
+
+    #[test]
+    fn test_newer_entry_supersedes_without_known_epoch() {
+        let cache = MemoryCache::new();
+        populate(
+            &cache,
+            &[
+                bulk("4567", 1),
+                bulk("4567", 2),
+                node("01", 3, Some(1)),
+                node("01", 3, Some(4)),
+                node("02", 3, None),
+            ],
+        );
+
+        let report = block_on(remove_superseded(&cache, &CurrentEpochs::default())).unwrap();
+        let keys: Vec<&CacheKey> = report.entries().iter().map(|e| &e.key).collect();
+        assert_eq!(keys, vec![&bulk("4567", 1), &node("01", 3, Some(1))]);
+        assert_eq!(cache.len(), 3);
+        assert!(!block_on(cache.contains(&bulk("4567", 1))).unwrap());
+        assert!(block_on(cache.contains(&bulk("4567", 2))).unwrap());
+    }
+
+    #[test]
+    fn test_unlistable_cache_fails() {
+        struct Opaque;
+        impl Cache for Opaque {
+            fn get(&self, _key: &CacheKey) -> GetFuture<'_> {
+                Box::pin(async { Ok(None) })
+            }
+            fn put(&self, _key: &CacheKey, _data: Vec<u8>) -> CacheFuture<'_> {
+                Box::pin(async { Ok(()) })
+            }
+            fn contains(&self, _key: &CacheKey) -> ContainsFuture<'_> {
+                Box::pin(async { Ok(false) })
+            }
+            fn remove(&self, _key: &CacheKey) -> CacheFuture<'_> {
+                Box::pin(async { Ok(()) })
+            }
+            fn clear(&self) -> CacheFuture<'_> {
+                Box::pin(async { Ok(()) })
+            }
+        }
+
+        let err = block_on(find_superseded(&Opaque, &CurrentEpochs::new(1))).unwrap_err();
+        assert!(matches!(
+            err,
+            crate::Error::Cache {
+                operation: "list",
+                ..
+            }
+        ));
+    }
+}
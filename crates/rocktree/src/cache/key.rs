@@ -0,0 +1,371 @@
+//! Structured cache keys.
+
+use super::Cache;
+use crate::error::{Error, Result};
+use crate::metrics::Endpoint;
+use crate::types::{BulkRequest, NodeRequest};
+use std::fmt;
+use std::str::FromStr;
+
+/// Identifies a cached resource independently of where it was fetched from.
+///
+/// Keys describe what a response contains rather than the URL it came from,
+/// so mirrors, proxies, and the origin server can share one cache, and
+/// changes to the URL encoding do not invalidate it.
+///
+/// The [`Display`](fmt::Display) form is a stable string suitable for use as
+/// a storage key, and parses back with [`FromStr`]:
+///
+/// - `planetoid`
+/// - `bulk/<epoch>/<path>`
+/// - `node/<epoch>/<texture format>/<imagery epoch or ->/<path>`
+///
+/// The path comes last because the root bulk's path is empty.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum CacheKey {
+    /// The planetoid metadata.
+    Planetoid,
+    /// Bulk metadata.
+    Bulk {
+        /// The full octant path.
+        path: String,
+        /// The bulk epoch.
+        epoch: u32,
+    },
+    /// Node data.
+    Node {
+        /// The full octant path.
+        path: String,
+        /// The node epoch.
+        epoch: u32,
+        /// The requested texture format.
+        texture_format: i32,
+        /// The imagery epoch, if the node has one.
+        imagery_epoch: Option<u32>,
+    },
+}
+
+impl CacheKey {
+    /// Create the key for a bulk request.
+    #[must_use]
+    pub fn bulk(request: &BulkRequest) -> Self {
+        CacheKey::Bulk {
+            path: request.path.clone(),
+            epoch: request.epoch,
+        }
+    }
+
+    /// Create the key for a node request.
+    #[must_use]
+    pub fn node(request: &NodeRequest) -> Self {
+        CacheKey::Node {
+            path: request.path.clone(),
+            epoch: request.epoch,
+            texture_format: request.texture_format,
+            imagery_epoch: request.imagery_epoch,
+        }
+    }
+
+    /// Recover the key from a rocktree request URL, ignoring its base URL.
+    ///
+    /// Returns `None` if the URL is not a planetoid, bulk, or node request,
+    /// or if its path contains anything other than octants `0` to `7`.
+    /// This is the migration path for caches that stored entries under their
+    /// request URLs; see [`migrate_url_entries`].
+    #[must_use]
+    pub fn from_url(url: &str) -> Option<Self> {
+        if url.ends_with("/PlanetoidMetadata") || url == "PlanetoidMetadata" {
+            return Some(CacheKey::Planetoid);
+        }
+
+        let (kind, params) = url.rsplit_once("/pb=")?;
+        let mut path = None;
+        let mut epoch = None;
+        let mut texture_format = None;
+        let mut imagery_epoch = None;
+        for field in params.split('!').skip(1) {
+            let (tag, value) = field.split_at_checked(2)?;
+            match tag {
+                // Message header and the trailing flag carry no key fields.
+                "1m" | "4b" => {}
+                "1s" => path = Some(value),
+                "2u" => epoch = Some(value.parse().ok()?),
+                "2e" => texture_format = Some(value.parse().ok()?),
+                "3u" => imagery_epoch = Some(value.parse().ok()?),
+                _ => return None,
+            }
+        }
+        let path = path.filter(|path| is_octant_path(path))?.to_string();
+        let epoch = epoch?;
+
+        if kind.ends_with("BulkMetadata") {
+            Some(CacheKey::Bulk { path, epoch })
+        } else if kind.ends_with("NodeData") {
+            Some(CacheKey::Node {
+                path,
+                epoch,
+                texture_format: texture_format?,
+                imagery_epoch,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Get the endpoint that serves this resource.
+    #[must_use]
+    pub fn endpoint(&self) -> Endpoint {
+        match self {
+            CacheKey::Planetoid => Endpoint::Planetoid,
+            CacheKey::Bulk { .. } => Endpoint::Bulk,
+            CacheKey::Node { .. } => Endpoint::Node,
+        }
+    }
+
+    /// Get the octant path, which is empty for the planetoid.
+    #[must_use]
+    pub fn path(&self) -> &str {
+        match self {
+            CacheKey::Planetoid => "",
+            CacheKey::Bulk { path, .. } | CacheKey::Node { path, .. } => path,
+        }
+    }
+
+    /// Get the epoch, if the resource has one.
+    #[must_use]
+    pub fn epoch(&self) -> Option<u32> {
+        match self {
+            CacheKey::Planetoid => None,
+            CacheKey::Bulk { epoch, .. } | CacheKey::Node { epoch, .. } => Some(*epoch),
+        }
+    }
+}
+
+impl fmt::Display for CacheKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheKey::Planetoid => write!(f, "planetoid"),
+            CacheKey::Bulk { path, epoch } => write!(f, "bulk/{epoch}/{path}"),
+            CacheKey::Node {
+                path,
+                epoch,
+                texture_format,
+                imagery_epoch: Some(imagery_epoch),
+            } => write!(f, "node/{epoch}/{texture_format}/{imagery_epoch}/{path}"),
+            CacheKey::Node {
+                path,
+                epoch,
+                texture_format,
+                imagery_epoch: None,
+            } => write!(f, "node/{epoch}/{texture_format}/-/{path}"),
+        }
+    }
+}
+
+impl FromStr for CacheKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || Error::InvalidData {
+            context: "cache key",
+            detail: format!("malformed cache key {s:?}"),
+        };
+
+        let mut parts = s.split('/');
+        let key = match parts.next() {
+            Some("planetoid") => CacheKey::Planetoid,
+            Some("bulk") => {
+                let epoch = parts
+                    .next()
+                    .and_then(|e| e.parse().ok())
+                    .ok_or_else(invalid)?;
+                let path = parts.next().ok_or_else(invalid)?;
+                CacheKey::Bulk {
+                    path: path.to_string(),
+                    epoch,
+                }
+            }
+            Some("node") => {
+                let epoch = parts
+                    .next()
+                    .and_then(|e| e.parse().ok())
+                    .ok_or_else(invalid)?;
+                let texture_format = parts
+                    .next()
+                    .and_then(|t| t.parse().ok())
+                    .ok_or_else(invalid)?;
+                let imagery_epoch = match parts.next().ok_or_else(invalid)? {
+                    "-" => None,
+                    e => Some(e.parse().map_err(|_| invalid())?),
+                };
+                let path = parts.next().ok_or_else(invalid)?;
+                CacheKey::Node {
+                    path: path.to_string(),
+                    epoch,
+                    texture_format,
+                    imagery_epoch,
+                }
+            }
+            _ => return Err(invalid()),
+        };
+
+        if parts.next().is_some() || !is_octant_path(key.path()) {
+            return Err(invalid());
+        }
+        Ok(key)
+    }
+}
+
+/// Check that `path` is a sequence of octants `0` to `7`.
+pub(crate) fn is_octant_path(path: &str) -> bool {
+    path.bytes().all(|b| (b'0'..=b'7').contains(&b))
+}
+
+/// Re-key cache entries that were stored under their request URLs.
+///
+/// Each entry whose URL is recognized by [`CacheKey::from_url`] is stored in
+/// `cache` under its structured key. Returns the number of entries migrated;
+/// unrecognized URLs are skipped.
+///
+/// # Errors
+///
+/// Returns the first error reported by `cache`.
+pub async fn migrate_url_entries<C, I>(cache: &C, entries: I) -> Result<usize>
+where
+    C: Cache + ?Sized,
+    I: IntoIterator<Item = (String, Vec<u8>)>,
+{
+    let mut migrated = 0;
+    for (url, data) in entries {
+        if let Some(key) = CacheKey::from_url(&url) {
+            cache.put(&key, data).await?;
+            migrated += 1;
+        }
+    }
+    Ok(migrated)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{MemoryCache, block_on};
+    use crate::protocol;
+
+    fn node_key(imagery_epoch: Option<u32>) -> CacheKey {
+        CacheKey::Node {
+            path: "0123".to_string(),
+            epoch: 5,
+            texture_format: 6,
+            imagery_epoch,
+        }
+    }
+
+    #[test]
+    fn test_from_url_ignores_base() {
+        let request = NodeRequest::new("0123".to_string(), 5, 6, Some(9));
+        for base in ["https://kh.google.com/rt/earth/", "http://mirror:8080/x/"] {
+            let url = protocol::node_url(base, &request);
+            assert_eq!(CacheKey::from_url(&url), Some(CacheKey::node(&request)));
+        }
+    }
+
+    #[test]
+    fn test_from_url_round_trips_all_kinds() {
+        let base = "https://kh.google.com/rt/earth/";
+        let bulk = BulkRequest::new(String::new(), 42);
+        let node = NodeRequest::new("0123".to_string(), 5, 6, None);
+
+        assert_eq!(
+            CacheKey::from_url(&protocol::planetoid_url(base)),
+            Some(CacheKey::Planetoid)
+        );
+        assert_eq!(
+            CacheKey::from_url(&protocol::bulk_url(base, &bulk)),
+            Some(CacheKey::bulk(&bulk))
+        );
+        assert_eq!(
+            CacheKey::from_url(&protocol::node_url(base, &node)),
+            Some(node_key(None))
+        );
+    }
+
+    #[test]
+    fn test_from_url_rejects_unknown() {
+        assert_eq!(CacheKey::from_url("https://example.com/index.html"), None);
+        assert_eq!(
+            CacheKey::from_url("https://x/BulkMetadata/pb=!1m2!1s01"),
+            None
+        );
+        assert_eq!(CacheKey::from_url("https://x/Other/pb=!1m2!1s01!2u1"), None);
+    }
+
+    #[test]
+    fn test_from_url_rejects_path_traversal() {
+        for url in [
+            "https://x/BulkMetadata/pb=!1m2!1s../../x!2u5",
+            "https://x/NodeData/pb=!1m2!1s0/../1!2u5!2e1!4b0",
+            "https://x/BulkMetadata/pb=!1m2!1s08!2u5",
+        ] {
+            assert_eq!(CacheKey::from_url(url), None, "{url}");
+        }
+    }
+
+    #[test]
+    fn test_display_round_trip() {
+        let keys = [
+            CacheKey::Planetoid,
+            CacheKey::Bulk {
+                path: String::new(),
+                epoch: 42,
+            },
+            node_key(None),
+            node_key(Some(9)),
+        ];
+        for key in keys {
+            assert_eq!(key.to_string().parse::<CacheKey>().unwrap(), key);
+        }
+        assert_eq!(node_key(None).to_string(), "node/5/6/-/0123");
+    }
+
+    #[test]
+    fn test_parse_rejects_malformed() {
+        for s in [
+            "",
+            "bulk",
+            "bulk/x/01",
+            "node/1/2/01",
+            "bulk/1/01/extra",
+            "bulk/1/09",
+        ] {
+            assert!(s.parse::<CacheKey>().is_err(), "{s:?} should not parse");
+        }
+    }
+
+    #[test]
+    fn test_migrate_url_entries() {
+        let cache = MemoryCache::new();
+        let entries = vec![
+            (
+                "https://kh.google.com/rt/earth/PlanetoidMetadata".to_string(),
+                vec![1],
+            ),
+            (
+                "https://kh.google.com/rt/earth/BulkMetadata/pb=!1m2!1s01!2u3".to_string(),
+                vec![2],
+            ),
+            ("https://example.com/unrelated".to_string(), vec![3]),
+        ];
+
+        let migrated = block_on(migrate_url_entries(&cache, entries)).unwrap();
+        assert_eq!(migrated, 2);
+        let bulk = CacheKey::Bulk {
+            path: "01".to_string(),
+            epoch: 3,
+        };
+        assert_eq!(block_on(cache.get(&bulk)).unwrap(), Some(vec![2]));
+        assert_eq!(
+            block_on(cache.get(&CacheKey::Planetoid)).unwrap(),
+            Some(vec![1])
+        );
+    }
+}
@@ -0,0 +1,622 @@
+//! Two-tier cache combining a fast front cache with a larger back cache.
+
+use super::{Cache, CacheEntry, CacheFuture, CacheKey, ContainsFuture, EntriesFuture, GetFuture};
+use crate::error::Result;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// When writes to a [`LayeredCache`] reach the back tier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WritePolicy {
+    /// Every `put` is written to both tiers before it completes.
+    WriteThrough,
+    /// A `put` is written to the front tier and buffered; buffered writes
+    /// reach the back tier on [`LayeredCache::flush`], or automatically once
+    /// more than `max_pending_bytes` are buffered.
+    WriteBack {
+        /// Buffered size in bytes above which a `put` flushes the buffer.
+        max_pending_bytes: usize,
+    },
+}
+
+/// A cache made of two tiers, such as a small [`MemoryCache`] in front of a
+/// large persistent cache.
+///
+/// Reads check the front tier first. On a front miss, the back tier is
+/// consulted and a hit is promoted into the front tier. Writes follow the
+/// configured [`WritePolicy`]. `remove` and `clear` apply to both tiers and
+/// to any buffered writes, so a removed entry cannot reappear from either
+/// tier.
+///
+/// With [`WritePolicy::WriteBack`], buffered writes stay readable even if the
+/// front tier evicts them, but they are lost if the cache is dropped without
+/// calling [`LayeredCache::flush`].
+///
+/// [`MemoryCache`]: super::MemoryCache
+#[derive(Debug)]
+pub struct LayeredCache<Front: Cache, Back: Cache> {
+    front: Front,
+    back: Back,
+    policy: WritePolicy,
+    pending: Mutex<PendingWrites>,
+}
+
+impl<Front: Cache, Back: Cache> LayeredCache<Front, Back> {
+    /// Create a layered cache with the [`WritePolicy::WriteThrough`] policy.
+    #[must_use]
+    pub fn new(front: Front, back: Back) -> Self {
+        Self {
+            front,
+            back,
+            policy: WritePolicy::WriteThrough,
+            pending: Mutex::new(PendingWrites::default()),
+        }
+    }
+
+    /// Set the write policy.
+    #[must_use]
+    pub fn with_write_policy(mut self, policy: WritePolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Get the front tier.
+    #[must_use]
+    pub fn front(&self) -> &Front {
+        &self.front
+    }
+
+    /// Get the back tier.
+    #[must_use]
+    pub fn back(&self) -> &Back {
+        &self.back
+    }
+
+    /// Get the number of buffered writes not yet written to the back tier.
+    #[must_use]
+    pub fn pending_writes(&self) -> usize {
+        self.pending.lock().unwrap().entries.len()
+    }
+
+    /// Write all buffered writes to the back tier.
+    ///
+    /// Writes that fail remain buffered, so a later flush retries them.
+    ///
+    /// # Errors
+    ///
+    /// Returns the first error reported by the back tier.
+    pub async fn flush(&self) -> Result<()> {
+        let snapshot: Vec<(CacheKey, u64, Vec<u8>)> = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.tracking += 1;
+            pending
+                .entries
+                .iter()
+                .map(|(key, (generation, data))| (key.clone(), *generation, data.clone()))
+                .collect()
+        };
+        let _guard = TrackingGuard(&self.pending);
+
+        for (key, generation, data) in snapshot {
+            self.back.put(&key, data).await?;
+            let removed = {
+                let mut pending = self.pending.lock().unwrap();
+                // Only drop the buffered write if it was not replaced meanwhile.
+                if pending
+                    .entries
+                    .get(&key)
+                    .is_some_and(|(g, _)| *g == generation)
+                {
+                    pending.remove(&key);
+                }
+                pending.removed_since(&key, generation)
+            };
+            // A removal during the write must win, so undo the write.
+            if removed {
+                self.back.remove(&key).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_layered(&self, key: &CacheKey) -> Result<Option<Vec<u8>>> {
+        if let Some(data) = self.front.get(key).await? {
+            return Ok(Some(data));
+        }
+
+        let (buffered, generation) = {
+            let mut pending = self.pending.lock().unwrap();
+            pending.tracking += 1;
+            let generation = pending.next_generation;
+            pending.next_generation += 1;
+            let buffered = pending.entries.get(key).map(|(_, data)| data.clone());
+            (buffered, generation)
+        };
+        let _guard = TrackingGuard(&self.pending);
+        let data = match buffered {
+            Some(data) => data,
+            None => match self.back.get(key).await? {
+                Some(data) => data,
+                None => return Ok(None),
+            },
+        };
+
+        // Promote into the front tier, unless the entry was removed since it
+        // was read. A removal during the promotion must win, so undo it.
+        if self.removed_since(key, generation) {
+            return Ok(Some(data));
+        }
+        self.front.put(key, data.clone()).await?;
+        if self.removed_since(key, generation) {
+            self.front.remove(key).await?;
+        }
+        Ok(Some(data))
+    }
+
+    fn removed_since(&self, key: &CacheKey, generation: u64) -> bool {
+        self.pending.lock().unwrap().removed_since(key, generation)
+    }
+
+    async fn put_layered(&self, key: &CacheKey, data: Vec<u8>) -> Result<()> {
+        match self.policy {
+            WritePolicy::WriteThrough => {
+                self.front.put(key, data.clone()).await?;
+                self.back.put(key, data).await
+            }
+            WritePolicy::WriteBack { max_pending_bytes } => {
+                self.front.put(key, data.clone()).await?;
+                let over_limit = {
+                    let mut pending = self.pending.lock().unwrap();
+                    pending.insert(key.clone(), data);
+                    pending.size > max_pending_bytes
+                };
+                if over_limit {
+                    self.flush().await?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    async fn contains_layered(&self, key: &CacheKey) -> Result<bool> {
+        if self.front.contains(key).await? || self.pending.lock().unwrap().entries.contains_key(key)
+        {
+            return Ok(true);
+        }
+        self.back.contains(key).await
+    }
+
+    async fn remove_layered(&self, key: &CacheKey) -> Result<()> {
+        self.pending.lock().unwrap().remove_and_forget(key);
+        self.front.remove(key).await?;
+        self.back.remove(key).await
+    }
+
+    /// List the entries of both tiers and the write buffer, counting each
+    /// key once with its total size across them.
+    async fn entries_layered(&self) -> Result<Vec<CacheEntry>> {
+        let mut sizes: HashMap<CacheKey, usize> = HashMap::new();
+        let buffered: Vec<CacheEntry> = self
+            .pending
+            .lock()
+            .unwrap()
+            .entries
+            .iter()
+            .map(|(key, (_, data))| CacheEntry {
+                key: key.clone(),
+                size: data.len(),
+            })
+            .collect();
+        let front = self.front.entries().await?;
+        let back = self.back.entries().await?;
+        for entry in buffered.into_iter().chain(front).chain(back) {
+            *sizes.entry(entry.key).or_default() += entry.size;
+        }
+        Ok(sizes
+            .into_iter()
+            .map(|(key, size)| CacheEntry { key, size })
+            .collect())
+    }
+
+    async fn clear_layered(&self) -> Result<()> {
+        self.pending.lock().unwrap().clear();
+        self.front.clear().await?;
+        self.back.clear().await
+    }
+}
+
+impl<Front: Cache, Back: Cache> Cache for LayeredCache<Front, Back> {
+    fn get(&self, key: &CacheKey) -> GetFuture<'_> {
+        let key = key.clone();
+        Box::pin(async move { self.get_layered(&key).await })
+    }
+
+    fn put(&self, key: &CacheKey, data: Vec<u8>) -> CacheFuture<'_> {
+        let key = key.clone();
+        Box::pin(async move { self.put_layered(&key, data).await })
+    }
+
+    fn contains(&self, key: &CacheKey) -> ContainsFuture<'_> {
+        let key = key.clone();
+        Box::pin(async move { self.contains_layered(&key).await })
+    }
+
+    fn remove(&self, key: &CacheKey) -> CacheFuture<'_> {
+        let key = key.clone();
+        Box::pin(async move { self.remove_layered(&key).await })
+    }
+
+    fn clear(&self) -> CacheFuture<'_> {
+        Box::pin(async move { self.clear_layered().await })
+    }
+
+    fn entries(&self) -> EntriesFuture<'_> {
+        Box::pin(async move { self.entries_layered().await })
+    }
+}
+
+/// Writes buffered under [`WritePolicy::WriteBack`].
+///
+/// Each entry carries a generation number so that a flush can tell whether
+/// the entry it wrote has since been replaced. While flushes or reads are
+/// running, removals are recorded with the generation they happened at, so
+/// a flush or a read can tell whether the entry it wrote or promoted has
+/// since been removed.
+#[derive(Debug, Default)]
+struct PendingWrites {
+    entries: HashMap<CacheKey, (u64, Vec<u8>)>,
+    size: usize,
+    next_generation: u64,
+    /// Number of flushes and reads in progress.
+    tracking: usize,
+    /// Generation at which each key was last removed during a flush or read.
+    removals: HashMap<CacheKey, u64>,
+    /// Generation at which the buffer was last cleared during a flush or
+    /// read.
+    cleared: Option<u64>,
+}
+
+impl PendingWrites {
+    fn insert(&mut self, key: CacheKey, data: Vec<u8>) {
+        self.size += data.len();
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        if let Some((_, old)) = self.entries.insert(key, (generation, data)) {
+            self.size -= old.len();
+        }
+    }
+
+    fn remove(&mut self, key: &CacheKey) {
+        if let Some((_, old)) = self.entries.remove(key) {
+            self.size -= old.len();
+        }
+    }
+
+    /// Drop the buffered write of `key` and, if a flush or read may be
+    /// writing it, record the removal.
+    fn remove_and_forget(&mut self, key: &CacheKey) {
+        self.remove(key);
+        if self.tracking > 0 {
+            self.removals.insert(key.clone(), self.next_generation);
+            self.next_generation += 1;
+        }
+    }
+
+    /// Drop all buffered writes. Generations keep counting up, so an
+    /// in-progress flush cannot mistake a new write for one it snapshotted.
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.size = 0;
+        if self.tracking > 0 {
+            self.cleared = Some(self.next_generation);
+            self.next_generation += 1;
+        }
+    }
+
+    /// Whether `key` was removed or the buffer cleared after the write of
+    /// `generation` was buffered.
+    fn removed_since(&self, key: &CacheKey, generation: u64) -> bool {
+        self.cleared.is_some_and(|g| g > generation)
+            || self.removals.get(key).is_some_and(|&g| g > generation)
+    }
+}
+
+/// Marks a flush or read as finished when dropped, even if it failed or was
+/// cancelled, and forgets recorded removals once none is running.
+struct TrackingGuard<'a>(&'a Mutex<PendingWrites>);
+
+impl Drop for TrackingGuard<'_> {
+    fn drop(&mut self) {
+        let mut pending = self
+            .0
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        pending.tracking -= 1;
+        if pending.tracking == 0 {
+            pending.removals.clear();
+            pending.cleared = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{MemoryCache, NoCache, block_on};
+    use std::task::{Context, Poll, Waker};
+
+    fn key(path: &str) -> CacheKey {
+        CacheKey::Bulk {
+            path: path.to_string(),
+            epoch: 1,
+        }
+    }
+
+    fn write_back(max_pending_bytes: usize) -> WritePolicy {
+        WritePolicy::WriteBack { max_pending_bytes }
+    }
+
+    #[test]
+    fn test_read_through_promotion() {
+        let cache = LayeredCache::new(MemoryCache::new(), MemoryCache::new());
+        block_on(cache.back().put(&key("0"), vec![1, 2, 3])).unwrap();
+        assert!(!block_on(cache.front().contains(&key("0"))).unwrap());
+
+        let data = block_on(cache.get(&key("0"))).unwrap();
+        assert_eq!(data, Some(vec![1, 2, 3]));
+        assert!(block_on(cache.front().contains(&key("0"))).unwrap());
+
+        // The second read is served by the front tier.
+        block_on(cache.get(&key("0"))).unwrap();
+        assert_eq!(cache.back().stats().hits, 1);
+        assert_eq!(cache.front().stats().hits, 1);
+    }
+
+    #[test]
+    fn test_miss_in_both_tiers() {
+        let cache = LayeredCache::new(MemoryCache::new(), MemoryCache::new());
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), None);
+        assert!(!block_on(cache.contains(&key("0"))).unwrap());
+        assert!(cache.front().is_empty());
+    }
+
+    #[test]
+    fn test_write_through() {
+        let cache = LayeredCache::new(MemoryCache::new(), MemoryCache::new());
+        block_on(cache.put(&key("0"), vec![1])).unwrap();
+        assert!(block_on(cache.front().contains(&key("0"))).unwrap());
+        assert!(block_on(cache.back().contains(&key("0"))).unwrap());
+        assert_eq!(cache.pending_writes(), 0);
+    }
+
+    #[test]
+    fn test_write_back_buffers_until_flush() {
+        let cache = LayeredCache::new(MemoryCache::new().with_max_entries(1), MemoryCache::new())
+            .with_write_policy(write_back(1_000));
+        block_on(cache.put(&key("0"), vec![1])).unwrap();
+        block_on(cache.put(&key("1"), vec![2])).unwrap();
+        assert!(cache.back().is_empty());
+        assert_eq!(cache.pending_writes(), 2);
+
+        // "0" was evicted from the front tier but is still readable.
+        assert!(!block_on(cache.front().contains(&key("0"))).unwrap());
+        assert!(block_on(cache.contains(&key("0"))).unwrap());
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), Some(vec![1]));
+
+        block_on(cache.flush()).unwrap();
+        assert_eq!(cache.pending_writes(), 0);
+        assert_eq!(cache.back().len(), 2);
+    }
+
+    #[test]
+    fn test_write_back_flushes_over_limit() {
+        let cache = LayeredCache::new(MemoryCache::new(), MemoryCache::new())
+            .with_write_policy(write_back(4));
+        block_on(cache.put(&key("0"), vec![0; 3])).unwrap();
+        assert_eq!(cache.pending_writes(), 1);
+        block_on(cache.put(&key("1"), vec![0; 3])).unwrap();
+        assert_eq!(cache.pending_writes(), 0);
+        assert_eq!(cache.back().len(), 2);
+    }
+
+    #[test]
+    fn test_write_back_replacement() {
+        let cache = LayeredCache::new(MemoryCache::new(), MemoryCache::new())
+            .with_write_policy(write_back(1_000));
+        block_on(cache.put(&key("0"), vec![1])).unwrap();
+        block_on(cache.put(&key("0"), vec![2, 3])).unwrap();
+        assert_eq!(cache.pending_writes(), 1);
+        assert_eq!(cache.pending.lock().unwrap().size, 2);
+
+        block_on(cache.flush()).unwrap();
+        assert_eq!(
+            block_on(cache.back().get(&key("0"))).unwrap(),
+            Some(vec![2, 3])
+        );
+    }
+
+    #[test]
+    fn test_remove_applies_to_all_tiers() {
+        let cache = LayeredCache::new(MemoryCache::new(), MemoryCache::new())
+            .with_write_policy(write_back(1_000));
+        block_on(cache.back().put(&key("0"), vec![1])).unwrap();
+        block_on(cache.put(&key("0"), vec![2])).unwrap();
+
+        block_on(cache.remove(&key("0"))).unwrap();
+        assert_eq!(cache.pending_writes(), 0);
+        assert!(!block_on(cache.contains(&key("0"))).unwrap());
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), None);
+
+        // Flushing must not resurrect the removed entry.
+        block_on(cache.flush()).unwrap();
+        assert!(cache.back().is_empty());
+    }
+
+    /// A cache whose reads and writes wait for one extra poll before
+    /// running, so a test can act while they are in flight.
+    #[derive(Debug, Default)]
+    struct Slow(MemoryCache);
+
+    /// Return `Pending` once, waking the task so it is polled again.
+    async fn yield_once() {
+        let mut yielded = false;
+        std::future::poll_fn(|cx| {
+            if yielded {
+                Poll::Ready(())
+            } else {
+                yielded = true;
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+        })
+        .await;
+    }
+
+    impl Cache for Slow {
+        fn get(&self, key: &CacheKey) -> GetFuture<'_> {
+            let key = key.clone();
+            Box::pin(async move {
+                yield_once().await;
+                self.0.get(&key).await
+            })
+        }
+
+        fn put(&self, key: &CacheKey, data: Vec<u8>) -> CacheFuture<'_> {
+            let key = key.clone();
+            Box::pin(async move {
+                yield_once().await;
+                self.0.put(&key, data).await
+            })
+        }
+
+        fn contains(&self, key: &CacheKey) -> ContainsFuture<'_> {
+            self.0.contains(key)
+        }
+
+        fn remove(&self, key: &CacheKey) -> CacheFuture<'_> {
+            self.0.remove(key)
+        }
+
+        fn clear(&self) -> CacheFuture<'_> {
+            self.0.clear()
+        }
+
+        fn entries(&self) -> EntriesFuture<'_> {
+            self.0.entries()
+        }
+    }
+
+    #[test]
+    fn test_remove_during_flush() {
+        let cache = LayeredCache::new(MemoryCache::new(), Slow::default())
+            .with_write_policy(write_back(1_000));
+        block_on(cache.put(&key("0"), vec![1])).unwrap();
+
+        let mut cx = Context::from_waker(Waker::noop());
+        let mut flush = std::pin::pin!(cache.flush());
+        // The flush is now writing "0" to the back tier.
+        assert!(flush.as_mut().poll(&mut cx).is_pending());
+        block_on(cache.remove(&key("0"))).unwrap();
+        assert!(matches!(flush.as_mut().poll(&mut cx), Poll::Ready(Ok(()))));
+
+        assert!(block_on(cache.back().entries()).unwrap().is_empty());
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), None);
+        // Removals are only tracked while a flush runs.
+        assert!(cache.pending.lock().unwrap().removals.is_empty());
+    }
+
+    #[test]
+    fn test_remove_during_get() {
+        let mut cx = Context::from_waker(Waker::noop());
+
+        // Removed while the back tier is being read: no promotion.
+        let cache = LayeredCache::new(MemoryCache::new(), Slow::default());
+        block_on(cache.back().put(&key("0"), vec![1])).unwrap();
+        let mut get = std::pin::pin!(cache.get(&key("0")));
+        assert!(get.as_mut().poll(&mut cx).is_pending());
+        block_on(cache.remove(&key("0"))).unwrap();
+        assert!(matches!(get.as_mut().poll(&mut cx), Poll::Ready(Ok(_))));
+        assert!(cache.front().is_empty());
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), None);
+
+        // Removed while being promoted: the promotion is undone.
+        let cache = LayeredCache::new(Slow::default(), MemoryCache::new());
+        block_on(cache.back().put(&key("0"), vec![1])).unwrap();
+        let mut get = std::pin::pin!(cache.get(&key("0")));
+        // The first poll reads the front tier, the second promotes.
+        assert!(get.as_mut().poll(&mut cx).is_pending());
+        assert!(get.as_mut().poll(&mut cx).is_pending());
+        block_on(cache.remove(&key("0"))).unwrap();
+        assert!(matches!(get.as_mut().poll(&mut cx), Poll::Ready(Ok(_))));
+        assert!(block_on(cache.front().0.entries()).unwrap().is_empty());
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), None);
+        assert!(cache.pending.lock().unwrap().removals.is_empty());
+    }
+
+    #[test]
+    fn test_clear_applies_to_all_tiers() {
+        let cache = LayeredCache::new(MemoryCache::new(), MemoryCache::new())
+            .with_write_policy(write_back(1_000));
+        block_on(cache.back().put(&key("0"), vec![1])).unwrap();
+        block_on(cache.put(&key("1"), vec![2])).unwrap();
+
+        block_on(cache.clear()).unwrap();
+        assert!(cache.front().is_empty());
+        assert!(cache.back().is_empty());
+        assert_eq!(cache.pending_writes(), 0);
+    }
+
+    #[test]
+    fn test_no_cache_front() {
+        let cache = LayeredCache::new(NoCache, MemoryCache::new());
+        block_on(cache.put(&key("0"), vec![1])).unwrap();
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), Some(vec![1]));
+        assert!(block_on(cache.contains(&key("0"))).unwrap());
+
+        block_on(cache.remove(&key("0"))).unwrap();
+        assert!(cache.back().is_empty());
+    }
+
+    #[test]
+    fn test_no_cache_back() {
+        let cache = LayeredCache::new(MemoryCache::new(), NoCache);
+        block_on(cache.put(&key("0"), vec![1])).unwrap();
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), Some(vec![1]));
+
+        block_on(cache.clear()).unwrap();
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), None);
+    }
+
+    #[test]
+    fn test_no_cache_both_tiers() {
+        let cache = LayeredCache::new(NoCache, NoCache).with_write_policy(write_back(0));
+        block_on(cache.put(&key("0"), vec![1])).unwrap();
+        assert_eq!(cache.pending_writes(), 0);
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), None);
+    }
+
+    #[test]
+    fn test_nested_layers() {
+        let inner = LayeredCache::new(MemoryCache::new(), MemoryCache::new());
+        let cache = LayeredCache::new(MemoryCache::new(), inner);
+        block_on(cache.back().back().put(&key("0"), vec![1])).unwrap();
+
+        assert_eq!(block_on(cache.get(&key("0"))).unwrap(), Some(vec![1]));
+        assert!(block_on(cache.front().contains(&key("0"))).unwrap());
+        assert!(block_on(cache.back().front().contains(&key("0"))).unwrap());
+    }
+
+    #[test]
+    fn test_entries_across_tiers() {
+        let cache = LayeredCache::new(MemoryCache::new(), MemoryCache::new())
+            .with_write_policy(write_back(1024));
+        block_on(cache.put(&key("0"), vec![1, 2])).unwrap();
+        block_on(cache.back().put(&key("1"), vec![3])).unwrap();
+
+        let mut entries = block_on(cache.entries()).unwrap();
+        entries.sort_by(|a, b| a.key.cmp(&b.key));
+        let sizes: Vec<(CacheKey, usize)> = entries.into_iter().map(|e| (e.key, e.size)).collect();
+        // The pending write and its front copy both count.
+        assert_eq!(sizes, vec![(key("0"), 4), (key("1"), 1)]);
+    }
+}
@@ -0,0 +1,302 @@
+//! Constant-time LRU storage shared by the in-memory caches.
+
+use super::{CacheEntry, CacheKey, CacheStats};
+use std::collections::HashMap;
+use std::time::Duration;
+use web_time::Instant;
+
+/// Bounds applied by an [`Lru`].
+#[derive(Debug, Clone, Copy, Default)]
+pub(super) struct Limits {
+    pub(super) max_size: Option<usize>,
+    pub(super) max_entries: Option<usize>,
+    pub(super) ttl: Option<Duration>,
+}
+
+/// Slab-backed LRU list indexed by [`CacheKey`].
+///
+/// Entries live in a slab threaded onto a doubly linked recency list, so
+/// lookup, insertion, removal, and eviction all run in constant time. The
+/// head of the list is the most recently used entry. Each entry carries the
+/// size it counts for against [`Limits::max_size`].
+#[derive(Debug)]
+pub(super) struct Lru<V> {
+    index: HashMap<CacheKey, usize>,
+    slots: Vec<Option<Entry<V>>>,
+    free: Vec<usize>,
+    head: usize,
+    tail: usize,
+    size: usize,
+    stats: CacheStats,
+}
+
+impl<V> Lru<V> {
+    /// Get the number of entries.
+    pub(super) fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Get the total size of all entries.
+    pub(super) fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Get the counters along with the current usage.
+    pub(super) fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.index.len(),
+            size: self.size,
+            ..self.stats
+        }
+    }
+
+    /// Reset the hit, miss, eviction, and expiration counters.
+    pub(super) fn reset_stats(&mut self) {
+        self.stats = CacheStats::default();
+    }
+
+    /// Look up an entry and mark it as most recently used, dropping it
+    /// instead if it has expired.
+    pub(super) fn get(&mut self, key: &CacheKey, limits: Limits) -> Option<&V> {
+        let Some(&slot) = self.index.get(key) else {
+            self.stats.misses += 1;
+            return None;
+        };
+        if let Some(ttl) = limits.ttl
+            && self.entry(slot).is_expired(Instant::now(), ttl)
+        {
+            self.unlink_and_free(slot);
+            self.stats.expirations += 1;
+            self.stats.misses += 1;
+            return None;
+        }
+        self.stats.hits += 1;
+        self.move_to_front(slot);
+        Some(&self.entry(slot).value)
+    }
+
+    /// Check for a live entry without affecting recency or statistics.
+    pub(super) fn contains(&self, key: &CacheKey, limits: Limits) -> bool {
+        self.index.get(key).is_some_and(|&slot| {
+            limits
+                .ttl
+                .is_none_or(|ttl| !self.entry(slot).is_expired(Instant::now(), ttl))
+        })
+    }
+
+    /// List the live entries without affecting recency or statistics.
+    pub(super) fn entries(&self, limits: Limits) -> Vec<CacheEntry> {
+        let now = Instant::now();
+        self.index
+            .values()
+            .map(|&slot| self.entry(slot))
+            .filter(|entry| limits.ttl.is_none_or(|ttl| !entry.is_expired(now, ttl)))
+            .map(|entry| CacheEntry {
+                key: entry.key.clone(),
+                size: entry.size,
+            })
+            .collect()
+    }
+
+    /// Store an entry as the most recently used one, evicting least recently
+    /// used entries until it fits.
+    ///
+    /// An entry larger than the size limit is not stored, and any previous
+    /// entry under the same key is removed.
+    pub(super) fn insert(&mut self, key: CacheKey, value: V, size: usize, limits: Limits) {
+        self.remove(&key);
+
+        if limits.max_size.is_some_and(|max| size > max) || limits.max_entries == Some(0) {
+            return;
+        }
+
+        while limits.max_size.is_some_and(|max| self.size + size > max)
+            || limits
+                .max_entries
+                .is_some_and(|max| self.index.len() >= max)
+        {
+            self.unlink_and_free(self.tail);
+            self.stats.evictions += 1;
+        }
+
+        self.insert_front(key, value, size);
+    }
+
+    /// Remove an entry, returning whether it existed.
+    pub(super) fn remove(&mut self, key: &CacheKey) -> bool {
+        match self.index.get(key) {
+            Some(&slot) => {
+                self.unlink_and_free(slot);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drop all entries, keeping the statistics.
+    pub(super) fn clear(&mut self) {
+        *self = Self {
+            stats: self.stats,
+            ..Self::default()
+        };
+    }
+
+    /// Drop all expired entries, returning how many were removed.
+    pub(super) fn purge_expired(&mut self, limits: Limits) -> usize {
+        let Some(ttl) = limits.ttl else {
+            return 0;
+        };
+        let now = Instant::now();
+        let expired: Vec<usize> = self
+            .index
+            .values()
+            .copied()
+            .filter(|&slot| self.entry(slot).is_expired(now, ttl))
+            .collect();
+        for &slot in &expired {
+            self.unlink_and_free(slot);
+        }
+        self.stats.expirations += expired.len() as u64;
+        expired.len()
+    }
+
+    /// Get the keys from most to least recently used, checking that the list,
+    /// the index, and the tracked size agree.
+    #[cfg(test)]
+    pub(super) fn recency_order(&self) -> Vec<CacheKey> {
+        let mut keys = Vec::new();
+        let mut size = 0;
+        let mut prev = NIL;
+        let mut slot = self.head;
+        while slot != NIL {
+            let entry = self.entry(slot);
+            assert_eq!(entry.prev, prev);
+            assert_eq!(self.index[&entry.key], slot);
+            size += entry.size;
+            keys.push(entry.key.clone());
+            prev = slot;
+            slot = entry.next;
+        }
+        assert_eq!(self.tail, prev);
+        assert_eq!(keys.len(), self.index.len());
+        assert_eq!(size, self.size);
+        keys
+    }
+
+    /// Get the number of allocated slots, including free ones.
+    #[cfg(test)]
+    pub(super) fn capacity(&self) -> usize {
+        self.slots.len()
+    }
+
+    fn entry(&self, slot: usize) -> &Entry<V> {
+        self.slots[slot].as_ref().unwrap()
+    }
+
+    fn entry_mut(&mut self, slot: usize) -> &mut Entry<V> {
+        self.slots[slot].as_mut().unwrap()
+    }
+
+    /// Store a new entry at the front of the list. The key must be absent.
+    fn insert_front(&mut self, key: CacheKey, value: V, size: usize) {
+        self.size += size;
+        let entry = Entry {
+            key: key.clone(),
+            value,
+            size,
+            stored_at: Instant::now(),
+            prev: NIL,
+            next: NIL,
+        };
+        let slot = if let Some(slot) = self.free.pop() {
+            self.slots[slot] = Some(entry);
+            slot
+        } else {
+            self.slots.push(Some(entry));
+            self.slots.len() - 1
+        };
+        self.index.insert(key, slot);
+        self.link_front(slot);
+    }
+
+    fn move_to_front(&mut self, slot: usize) {
+        if self.head != slot {
+            self.unlink(slot);
+            self.link_front(slot);
+        }
+    }
+
+    /// Remove an entry from the list, the index, and the slab.
+    fn unlink_and_free(&mut self, slot: usize) {
+        self.unlink(slot);
+        let entry = self.slots[slot].take().unwrap();
+        self.index.remove(&entry.key);
+        self.size -= entry.size;
+        self.free.push(slot);
+    }
+
+    fn link_front(&mut self, slot: usize) {
+        let old_head = self.head;
+        {
+            let entry = self.entry_mut(slot);
+            entry.prev = NIL;
+            entry.next = old_head;
+        }
+        if old_head == NIL {
+            self.tail = slot;
+        } else {
+            self.entry_mut(old_head).prev = slot;
+        }
+        self.head = slot;
+    }
+
+    fn unlink(&mut self, slot: usize) {
+        let (prev, next) = {
+            let entry = self.entry(slot);
+            (entry.prev, entry.next)
+        };
+        if prev == NIL {
+            self.head = next;
+        } else {
+            self.entry_mut(prev).next = next;
+        }
+        if next == NIL {
+            self.tail = prev;
+        } else {
+            self.entry_mut(next).prev = prev;
+        }
+    }
+}
+
+impl<V> Default for Lru<V> {
+    fn default() -> Self {
+        Self {
+            index: HashMap::new(),
+            slots: Vec::new(),
+            free: Vec::new(),
+            head: NIL,
+            tail: NIL,
+            size: 0,
+            stats: CacheStats::default(),
+        }
+    }
+}
+
+/// Sentinel slot index marking the end of the recency list.
+const NIL: usize = usize::MAX;
+
+#[derive(Debug)]
+struct Entry<V> {
+    key: CacheKey,
+    value: V,
+    size: usize,
+    stored_at: Instant,
+    prev: usize,
+    next: usize,
+}
+
+impl<V> Entry<V> {
+    fn is_expired(&self, now: Instant, ttl: Duration) -> bool {
+        now.duration_since(self.stored_at) >= ttl
+    }
+}
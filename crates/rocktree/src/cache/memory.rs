@@ -0,0 +1,450 @@
+//! In-memory LRU cache.
+
+use super::lru::{Limits, Lru};
+use super::{Cache, CacheFuture, CacheKey, ContainsFuture, EntriesFuture, GetFuture};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// An in-memory least-recently-used cache.
+///
+/// Entries live in a slab threaded onto a doubly linked recency list, indexed
+/// by a `HashMap`, so `get`, `put`, `remove`, and eviction all run in constant
+/// time. Both `get` and `put` mark an entry as most recently used.
+///
+/// The cache can be bounded by total size in bytes, by number of entries, or
+/// both. When a limit would be exceeded, the least recently used entries are
+/// evicted. Entries can also be given a time to live, after which they are
+/// treated as missing and dropped on next access.
+///
+/// Clones share the same storage and statistics.
+#[derive(Debug)]
+pub struct MemoryCache {
+    data: Arc<Mutex<Lru<Vec<u8>>>>,
+    limits: Limits,
+}
+
+/// Statistics about a [`MemoryCache`] or
+/// [`DecodedNodeCache`](super::DecodedNodeCache).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of lookups that found a live entry.
+    pub hits: u64,
+    /// Number of lookups that found no entry or an expired one.
+    pub misses: u64,
+    /// Number of entries evicted to stay within the size or entry limits.
+    pub evictions: u64,
+    /// Number of entries dropped because their time to live had passed.
+    pub expirations: u64,
+    /// Number of entries currently stored.
+    pub entries: usize,
+    /// Total size of the stored data in bytes.
+    pub size: usize,
+}
+
+impl CacheStats {
+    /// Fraction of lookups that were hits, or `None` if there were none.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn hit_ratio(&self) -> Option<f64> {
+        let lookups = self.hits + self.misses;
+        (lookups > 0).then(|| self.hits as f64 / lookups as f64)
+    }
+}
+
+impl MemoryCache {
+    /// Create a new memory cache with no limits.
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            data: Arc::new(Mutex::new(Lru::default())),
+            limits: Limits::default(),
+        }
+    }
+
+    /// Create a new memory cache with a maximum size in bytes.
+    #[must_use]
+    pub fn with_max_size(max_size: usize) -> Self {
+        let mut cache = Self::new();
+        cache.limits.max_size = Some(max_size);
+        cache
+    }
+
+    /// Limit the number of entries the cache holds.
+    #[must_use]
+    pub fn with_max_entries(mut self, max_entries: usize) -> Self {
+        self.limits.max_entries = Some(max_entries);
+        self
+    }
+
+    /// Expire entries after they have been stored for `ttl`.
+    ///
+    /// Storing an entry again resets its age.
+    #[must_use]
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.limits.ttl = Some(ttl);
+        self
+    }
+
+    /// Get the current size of cached data in bytes.
+    #[must_use]
+    pub fn size(&self) -> usize {
+        self.data.lock().unwrap().size()
+    }
+
+    /// Get the number of cached entries.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.data.lock().unwrap().len()
+    }
+
+    /// Check if the cache is empty.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get hit, miss, and eviction statistics along with the current usage.
+    #[must_use]
+    pub fn stats(&self) -> CacheStats {
+        self.data.lock().unwrap().stats()
+    }
+
+    /// Reset the hit, miss, eviction, and expiration counters.
+    pub fn reset_stats(&self) {
+        self.data.lock().unwrap().reset_stats();
+    }
+
+    /// Drop all expired entries, returning how many were removed.
+    ///
+    /// Expired entries are otherwise only dropped when they are accessed or
+    /// evicted. This takes time proportional to the number of entries.
+    #[allow(clippy::must_use_candidate)]
+    pub fn purge_expired(&self) -> usize {
+        self.data.lock().unwrap().purge_expired(self.limits)
+    }
+}
+
+impl Default for MemoryCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clone for MemoryCache {
+    fn clone(&self) -> Self {
+        Self {
+            data: Arc::clone(&self.data),
+            limits: self.limits,
+        }
+    }
+}
+
+impl Cache for MemoryCache {
+    fn get(&self, key: &CacheKey) -> GetFuture<'_> {
+        let result = self.data.lock().unwrap().get(key, self.limits).cloned();
+        Box::pin(async move { Ok(result) })
+    }
+
+    fn put(&self, key: &CacheKey, data: Vec<u8>) -> CacheFuture<'_> {
+        let size = data.len();
+        self.data
+            .lock()
+            .unwrap()
+            .insert(key.clone(), data, size, self.limits);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn contains(&self, key: &CacheKey) -> ContainsFuture<'_> {
+        let result = self.data.lock().unwrap().contains(key, self.limits);
+        Box::pin(async move { Ok(result) })
+    }
+
+    fn remove(&self, key: &CacheKey) -> CacheFuture<'_> {
+        self.data.lock().unwrap().remove(key);
+        Box::pin(async { Ok(()) })
+    }
+
+    fn clear(&self) -> CacheFuture<'_> {
+        self.data.lock().unwrap().clear();
+        Box::pin(async { Ok(()) })
+    }
+
+    fn entries(&self) -> EntriesFuture<'_> {
+        let entries = self.data.lock().unwrap().entries(self.limits);
+        Box::pin(async move { Ok(entries) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::block_on;
+
+    fn key(path: &str) -> CacheKey {
+        CacheKey::Bulk {
+            path: path.to_string(),
+            epoch: 1,
+        }
+    }
+
+    /// Get the key paths from most to least recently used.
+    fn recency_order(cache: &MemoryCache) -> Vec<String> {
+        let lru = cache.data.lock().unwrap();
+        lru.recency_order()
+            .iter()
+            .map(|key| key.path().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_memory_cache_basic() {
+        let cache = MemoryCache::new();
+
+        // Initially empty.
+        assert!(cache.is_empty());
+        assert_eq!(cache.size(), 0);
+
+        // Put data.
+        block_on(cache.put(&key("0"), vec![1, 2, 3])).unwrap();
+        assert!(!cache.is_empty());
+        assert_eq!(cache.len(), 1);
+        assert_eq!(cache.size(), 3);
+
+        // Get data.
+        let result = block_on(cache.get(&key("0"))).unwrap();
+        assert_eq!(result, Some(vec![1, 2, 3]));
+
+        // Contains.
+        assert!(block_on(cache.contains(&key("0"))).unwrap());
+        assert!(!block_on(cache.contains(&key("1"))).unwrap());
+
+        // Remove.
+        block_on(cache.remove(&key("0"))).unwrap();
+        assert!(cache.is_empty());
+        assert_eq!(cache.size(), 0);
+    }
+
+    #[test]
+    fn test_memory_cache_eviction() {
+        // Cache with 10-byte limit.
+        let cache = MemoryCache::with_max_size(10);
+
+        // Add 5 bytes.
+        block_on(cache.put(&key("0"), vec![1, 2, 3, 4, 5])).unwrap();
+        assert_eq!(cache.size(), 5);
+        assert!(block_on(cache.contains(&key("0"))).unwrap());
+
+        // Add 5 more bytes.
+        block_on(cache.put(&key("1"), vec![6, 7, 8, 9, 10])).unwrap();
+        assert_eq!(cache.size(), 10);
+
+        // Add 3 more bytes, which should evict "0".
+        block_on(cache.put(&key("2"), vec![11, 12, 13])).unwrap();
+        assert_eq!(cache.size(), 8); // 5 + 3.
+        assert!(!block_on(cache.contains(&key("0"))).unwrap());
+        assert!(block_on(cache.contains(&key("1"))).unwrap());
+        assert!(block_on(cache.contains(&key("2"))).unwrap());
+        assert_eq!(cache.stats().evictions, 1);
+    }
+
+    #[test]
+    fn test_memory_cache_get_updates_recency() {
+        let cache = MemoryCache::with_max_size(10);
+        block_on(cache.put(&key("0"), vec![0; 5])).unwrap();
+        block_on(cache.put(&key("1"), vec![0; 5])).unwrap();
+
+        // Reading "0" makes "1" the least recently used entry.
+        block_on(cache.get(&key("0"))).unwrap();
+        assert_eq!(recency_order(&cache), ["0", "1"]);
+
+        block_on(cache.put(&key("2"), vec![0; 5])).unwrap();
+        assert!(block_on(cache.contains(&key("0"))).unwrap());
+        assert!(!block_on(cache.contains(&key("1"))).unwrap());
+        assert_eq!(recency_order(&cache), ["2", "0"]);
+    }
+
+    #[test]
+    fn test_memory_cache_max_entries() {
+        let cache = MemoryCache::new().with_max_entries(2);
+        block_on(cache.put(&key("0"), vec![1])).unwrap();
+        block_on(cache.put(&key("1"), vec![2])).unwrap();
+        block_on(cache.put(&key("2"), vec![3])).unwrap();
+
+        assert_eq!(cache.len(), 2);
+        assert_eq!(recency_order(&cache), ["2", "1"]);
+
+        // Replacing an existing entry does not evict anything.
+        block_on(cache.put(&key("1"), vec![4])).unwrap();
+        assert_eq!(recency_order(&cache), ["1", "2"]);
+        assert_eq!(cache.stats().evictions, 1);
+    }
+
+    #[test]
+    fn test_memory_cache_oversized_entry() {
+        let cache = MemoryCache::with_max_size(4);
+        block_on(cache.put(&key("0"), vec![1, 2])).unwrap();
+        block_on(cache.put(&key("1"), vec![0; 5])).unwrap();
+
+        // The oversized entry is dropped without flushing the cache.
+        assert!(!block_on(cache.contains(&key("1"))).unwrap());
+        assert!(block_on(cache.contains(&key("0"))).unwrap());
+        assert_eq!(cache.stats().evictions, 0);
+    }
+
+    #[test]
+    fn test_memory_cache_ttl() {
+        let cache = MemoryCache::new().with_ttl(Duration::from_millis(20));
+        block_on(cache.put(&key("0"), vec![1, 2, 3])).unwrap();
+        assert!(block_on(cache.contains(&key("0"))).unwrap());
+        assert!(block_on(cache.get(&key("0"))).unwrap().is_some());
+
+        std::thread::sleep(Duration::from_millis(40));
+        assert!(!block_on(cache.contains(&key("0"))).unwrap());
+        assert!(block_on(cache.get(&key("0"))).unwrap().is_none());
+        assert!(cache.is_empty());
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.expirations, 1);
+    }
+
+    #[test]
+    fn test_memory_cache_purge_expired() {
+        let cache = MemoryCache::new().with_ttl(Duration::from_millis(20));
+        block_on(cache.put(&key("0"), vec![1])).unwrap();
+        block_on(cache.put(&key("1"), vec![2])).unwrap();
+        std::thread::sleep(Duration::from_millis(40));
+        block_on(cache.put(&key("2"), vec![3])).unwrap();
+
+        assert_eq!(cache.purge_expired(), 2);
+        assert_eq!(recency_order(&cache), ["2"]);
+        assert_eq!(cache.stats().expirations, 2);
+    }
+
+    #[test]
+    fn test_memory_cache_stats() {
+        let cache = MemoryCache::new();
+        assert_eq!(cache.stats().hit_ratio(), None);
+
+        block_on(cache.put(&key("0"), vec![1, 2, 3])).unwrap();
+        block_on(cache.get(&key("0"))).unwrap();
+        block_on(cache.get(&key("0"))).unwrap();
+        block_on(cache.get(&key("1"))).unwrap();
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 2);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.size, 3);
+        assert!((stats.hit_ratio().unwrap() - 2.0 / 3.0).abs() < 1e-9);
+
+        cache.reset_stats();
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 0);
+        assert_eq!(stats.entries, 1);
+    }
+
+    #[test]
+    fn test_memory_cache_clear() {
+        let cache = MemoryCache::new();
+
+        block_on(cache.put(&key("0"), vec![1, 2, 3])).unwrap();
+        block_on(cache.put(&key("1"), vec![4, 5, 6])).unwrap();
+        assert_eq!(cache.len(), 2);
+
+        block_on(cache.clear()).unwrap();
+        assert!(cache.is_empty());
+        assert_eq!(cache.size(), 0);
+        assert!(recency_order(&cache).is_empty());
+
+        // The cache is usable after clearing.
+        block_on(cache.put(&key("2"), vec![7])).unwrap();
+        assert_eq!(recency_order(&cache), ["2"]);
+    }
+
+    #[test]
+    fn test_memory_cache_update() {
+        let cache = MemoryCache::new();
+
+        // Add initial data.
+        block_on(cache.put(&key("0"), vec![1, 2, 3])).unwrap();
+        assert_eq!(cache.size(), 3);
+
+        // Update with larger data.
+        block_on(cache.put(&key("0"), vec![1, 2, 3, 4, 5])).unwrap();
+        assert_eq!(cache.size(), 5);
+        assert_eq!(cache.len(), 1);
+
+        let result = block_on(cache.get(&key("0"))).unwrap();
+        assert_eq!(result, Some(vec![1, 2, 3, 4, 5]));
+    }
+
+    #[test]
+    fn test_memory_cache_reuses_slots() {
+        let cache = MemoryCache::new().with_max_entries(4);
+        for i in 0..100 {
+            block_on(cache.put(&key(&i.to_string()), vec![0; 8])).unwrap();
+        }
+        assert_eq!(cache.data.lock().unwrap().capacity(), 4);
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.stats().evictions, 96);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_memory_cache_concurrent_stress() {
+        const TASKS: u64 = 32;
+        const OPS: u64 = 2_000;
+        const KEYS: u64 = 200;
+
+        let cache = MemoryCache::with_max_size(4_096).with_max_entries(64);
+        let tasks: Vec<_> = (0..TASKS)
+            .map(|t| {
+                let cache = cache.clone();
+                tokio::spawn(async move {
+                    let mut gets = 0;
+                    for i in 0..OPS {
+                        let path = ((t * 7 + i * 13) % KEYS).to_string();
+                        let key = key(&path);
+                        match i % 4 {
+                            0 | 1 => {
+                                // Values encode their key so reads can be checked.
+                                let value = path.as_bytes().repeat(1 + (i % 5) as usize);
+                                cache.put(&key, value).await.unwrap();
+                            }
+                            2 => {
+                                gets += 1;
+                                if let Some(value) = cache.get(&key).await.unwrap() {
+                                    assert!(value.chunks(path.len()).all(|c| c == path.as_bytes()));
+                                }
+                            }
+                            _ => {
+                                if i % 40 == 3 {
+                                    cache.remove(&key).await.unwrap();
+                                } else {
+                                    cache.contains(&key).await.unwrap();
+                                }
+                            }
+                        }
+                        if i % 64 == 0 {
+                            tokio::task::yield_now().await;
+                        }
+                    }
+                    gets
+                })
+            })
+            .collect();
+
+        let mut gets = 0;
+        for task in tasks {
+            gets += task.await.unwrap();
+        }
+
+        let stats = cache.stats();
+        assert_eq!(stats.hits + stats.misses, gets);
+        assert!(stats.size <= 4_096);
+        assert!(stats.entries <= 64);
+        assert!(stats.evictions > 0);
+        assert_eq!(recency_order(&cache).len(), stats.entries);
+    }
+}
@@ -0,0 +1,256 @@
+//! Cache abstractions for storing fetched data.
+//!
+//! This module provides a `Cache` trait and implementations for caching
+//! downloaded data to reduce network requests.
+//!
+//! # Implementations
+//!
+//! - [`MemoryCache`]: In-memory LRU cache with optional size, entry-count,
+//!   and TTL limits
+//! - `DiskCache`: One file per entry in a directory, persisting across runs
+//!   (native only)
+//! - [`LayeredCache`]: Two tiers, such as memory in front of disk, with
+//!   read-through promotion and write-through or write-back policies
+//! - [`CompressedCache`]: Wrapper that compresses entries before storing
+//!   them in another cache
+//! - [`NoCache`]: Passthrough implementation that caches nothing
+//!
+//! Region archives written by `export_region` are served by the read-only
+//! `ArchiveCache` (native only); see the `archive` module.
+//!
+//! These caches hold raw responses. [`DecodedNodeCache`] additionally holds
+//! decoded nodes so that revisits skip decoding.
+//!
+//! # Keys
+//!
+//! Entries are keyed by [`CacheKey`], which describes the resource rather
+//! than the URL it was fetched from. Caches that persisted entries under
+//! request URLs can be carried over with [`migrate_url_entries`].
+//!
+//! # Maintenance
+//!
+//! Entries for superseded epochs are never requested again once Google
+//! republishes the data. [`find_superseded`] reports them along with the
+//! bytes they occupy, and [`remove_superseded`] deletes them.
+//!
+//! # Integrity
+//!
+//! The clients store responses with a CRC-32 checksum and verify it on read.
+//! Entries that fail the check, or that fail to decode, are evicted and
+//! fetched again from the network.
+
+pub(crate) mod checksum;
+mod compressed;
+mod decoded;
+#[cfg(not(target_family = "wasm"))]
+mod disk;
+mod gc;
+mod key;
+mod layered;
+mod lru;
+mod memory;
+
+pub use compressed::{CompressedCache, Compression, CompressionStats};
+pub use decoded::DecodedNodeCache;
+#[cfg(not(target_family = "wasm"))]
+pub use disk::DiskCache;
+#[cfg(not(target_family = "wasm"))]
+pub(crate) use disk::temp_path;
+pub use gc::{CurrentEpochs, GcReport, find_superseded, remove_superseded};
+pub use key::{CacheKey, migrate_url_entries};
+pub use layered::{LayeredCache, WritePolicy};
+pub use memory::{CacheStats, MemoryCache};
+
+use crate::error::{Error, Result};
+use std::future::Future;
+use std::pin::Pin;
+#[cfg(any(test, all(feature = "blocking", not(target_family = "wasm"))))]
+use std::{
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::Thread,
+};
+
+/// Future type for cache get operations.
+pub type GetFuture<'a> = Pin<Box<dyn Future<Output = Result<Option<Vec<u8>>>> + Send + 'a>>;
+
+/// Future type for cache put/remove operations.
+pub type CacheFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+/// Future type for cache contains operations.
+pub type ContainsFuture<'a> = Pin<Box<dyn Future<Output = Result<bool>> + Send + 'a>>;
+
+/// Future type for cache entry listings.
+pub type EntriesFuture<'a> = Pin<Box<dyn Future<Output = Result<Vec<CacheEntry>>> + Send + 'a>>;
+
+/// A stored entry, as reported by [`Cache::entries`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CacheEntry {
+    /// The entry's key.
+    pub key: CacheKey,
+    /// The number of bytes the entry occupies in the cache.
+    pub size: usize,
+}
+
+/// A cache for storing fetched data.
+///
+/// The cache is keyed by [`CacheKey`] and stores raw response bytes.
+/// Implementations may choose to store data in memory, on disk, or in any
+/// other persistent storage.
+pub trait Cache: Send + Sync {
+    /// Get data from the cache.
+    ///
+    /// Returns `Ok(Some(data))` if the data is cached, `Ok(None)` if not cached,
+    /// or an error if the cache operation failed.
+    fn get(&self, key: &CacheKey) -> GetFuture<'_>;
+
+    /// Store data in the cache.
+    ///
+    /// The data is associated with the given key for later retrieval.
+    fn put(&self, key: &CacheKey, data: Vec<u8>) -> CacheFuture<'_>;
+
+    /// Check if data exists in the cache without retrieving it.
+    fn contains(&self, key: &CacheKey) -> ContainsFuture<'_>;
+
+    /// Remove data from the cache.
+    fn remove(&self, key: &CacheKey) -> CacheFuture<'_>;
+
+    /// Clear all cached data.
+    fn clear(&self) -> CacheFuture<'_>;
+
+    /// List the key and stored size of every entry, in no particular order.
+    ///
+    /// This is used for maintenance such as [`remove_superseded`]. The
+    /// default implementation fails, for caches that cannot enumerate their
+    /// contents.
+    fn entries(&self) -> EntriesFuture<'_> {
+        Box::pin(async {
+            Err(Error::Cache {
+                operation: "list",
+                message: "this cache cannot list its entries".to_string(),
+            })
+        })
+    }
+}
+
+impl<T: Cache + ?Sized> Cache for Box<T> {
+    fn get(&self, key: &CacheKey) -> GetFuture<'_> {
+        (**self).get(key)
+    }
+
+    fn put(&self, key: &CacheKey, data: Vec<u8>) -> CacheFuture<'_> {
+        (**self).put(key, data)
+    }
+
+    fn contains(&self, key: &CacheKey) -> ContainsFuture<'_> {
+        (**self).contains(key)
+    }
+
+    fn remove(&self, key: &CacheKey) -> CacheFuture<'_> {
+        (**self).remove(key)
+    }
+
+    fn clear(&self) -> CacheFuture<'_> {
+        (**self).clear()
+    }
+
+    fn entries(&self) -> EntriesFuture<'_> {
+        (**self).entries()
+    }
+}
+
+/// A cache that stores nothing (passthrough).
+///
+/// This is useful when caching is not desired or for testing.
+#[derive(Debug, Clone, Default)]
+pub struct NoCache;
+
+impl NoCache {
+    /// Create a new no-op cache.
+    #[must_use]
+    pub fn new() -> Self {
+        Self
+    }
+}
+
+impl Cache for NoCache {
+    fn get(&self, _key: &CacheKey) -> GetFuture<'_> {
+        Box::pin(async { Ok(None) })
+    }
+
+    fn put(&self, _key: &CacheKey, _data: Vec<u8>) -> CacheFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn contains(&self, _key: &CacheKey) -> ContainsFuture<'_> {
+        Box::pin(async { Ok(false) })
+    }
+
+    fn remove(&self, _key: &CacheKey) -> CacheFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn clear(&self) -> CacheFuture<'_> {
+        Box::pin(async { Ok(()) })
+    }
+
+    fn entries(&self) -> EntriesFuture<'_> {
+        Box::pin(async { Ok(Vec::new()) })
+    }
+}
+
+/// Drive a future to completion on the current thread.
+///
+/// Cache operations are exposed as futures so that implementations can do
+/// async I/O. The built-in caches complete immediately, but custom caches may
+/// return `Pending`, so the thread parks until the future's waker fires.
+#[cfg(any(test, all(feature = "blocking", not(target_family = "wasm"))))]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let mut future = std::pin::pin!(future);
+
+    loop {
+        match future.as_mut().poll(&mut cx) {
+            Poll::Ready(output) => return output,
+            Poll::Pending => std::thread::park(),
+        }
+    }
+}
+
+/// Waker that unparks the thread blocked in [`block_on`].
+#[cfg(any(test, all(feature = "blocking", not(target_family = "wasm"))))]
+struct ThreadWaker(Thread);
+
+#[cfg(any(test, all(feature = "blocking", not(target_family = "wasm"))))]
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_no_cache() {
+        let cache = NoCache::new();
+        let key = CacheKey::Planetoid;
+
+        // Put should succeed but not store anything.
+        block_on(cache.put(&key, vec![1, 2, 3])).unwrap();
+
+        // Get should return None.
+        let result = block_on(cache.get(&key)).unwrap();
+        assert!(result.is_none());
+
+        // Contains should return false.
+        let contains = block_on(cache.contains(&key)).unwrap();
+        assert!(!contains);
+    }
+}
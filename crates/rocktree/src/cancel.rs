@@ -0,0 +1,204 @@
+//! Cooperative cancellation for in-flight fetches.
+//!
+//! A [`CancellationToken`] is a cheap, cloneable handle shared between the code
+//! that starts a fetch and the code that may later decide the result is no
+//! longer needed. Wrapping a fetch future with [`CancellationToken::run`]
+//! makes it resolve to [`Error::Cancelled`] as soon as the token is cancelled.
+//! The wrapped future is dropped at that point, which aborts the underlying
+//! HTTP request and frees its bandwidth.
+
+use crate::error::{Error, Result};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+/// A handle for cancelling one or more fetches.
+///
+/// Clones share the same state: cancelling any clone cancels every future run
+/// with any of them. Cancellation is permanent.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<TokenInner>,
+}
+
+impl CancellationToken {
+    /// Create a new, uncancelled token.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Cancel the token, waking every future that is waiting on it.
+    pub fn cancel(&self) {
+        if self.inner.cancelled.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let wakers = std::mem::take(&mut *self.inner.wakers.lock().unwrap());
+        for waker in wakers.into_values() {
+            waker.wake();
+        }
+    }
+
+    /// Check whether the token has been cancelled.
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Run a fallible future until it completes or the token is cancelled.
+    ///
+    /// If the token is cancelled first, the future is dropped and the returned
+    /// future resolves to [`Error::Cancelled`]. A token that is already
+    /// cancelled never polls the future at all.
+    pub fn run<F, T>(&self, future: F) -> Cancellable<F>
+    where
+        F: Future<Output = Result<T>>,
+    {
+        Cancellable {
+            future: Some(Box::pin(future)),
+            token: self.clone(),
+            id: self.inner.next_id.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+}
+
+impl fmt::Debug for CancellationToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CancellationToken")
+            .field("cancelled", &self.is_cancelled())
+            .finish()
+    }
+}
+
+/// A future that resolves to [`Error::Cancelled`] once its token is cancelled.
+///
+/// Created by [`CancellationToken::run`].
+#[must_use = "futures do nothing unless polled"]
+pub struct Cancellable<F> {
+    future: Option<Pin<Box<F>>>,
+    token: CancellationToken,
+    /// Key of this future's waker in the token's waker table.
+    id: u64,
+}
+
+impl<F, T> Future for Cancellable<F>
+where
+    F: Future<Output = Result<T>>,
+{
+    type Output = Result<T>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if self.token.is_cancelled() {
+            self.future = None;
+            return Poll::Ready(Err(Error::Cancelled));
+        }
+
+        let Some(future) = self.future.as_mut() else {
+            panic!("`Cancellable` polled after completion");
+        };
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            self.future = None;
+            self.token.inner.wakers.lock().unwrap().remove(&self.id);
+            return Poll::Ready(output);
+        }
+
+        // Register the waker before re-checking the flag so that a concurrent
+        // `cancel` either sees this waker or is observed by the check below.
+        self.token
+            .inner
+            .wakers
+            .lock()
+            .unwrap()
+            .insert(self.id, cx.waker().clone());
+        if self.token.is_cancelled() {
+            self.future = None;
+            return Poll::Ready(Err(Error::Cancelled));
+        }
+        Poll::Pending
+    }
+}
+
+impl<F> Drop for Cancellable<F> {
+    fn drop(&mut self) {
+        if let Ok(mut wakers) = self.token.inner.wakers.lock() {
+            wakers.remove(&self.id);
+        }
+    }
+}
+
+#[derive(Default)]
+struct TokenInner {
+    cancelled: AtomicBool,
+    next_id: AtomicU64,
+    wakers: Mutex<HashMap<u64, Waker>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::task::Waker;
+
+    fn poll_once<F: Future>(future: Pin<&mut F>) -> Poll<F::Output> {
+        future.poll(&mut Context::from_waker(Waker::noop()))
+    }
+
+    #[test]
+    fn test_cancellable_completes() {
+        let token = CancellationToken::new();
+        let mut future = std::pin::pin!(token.run(async { Ok(5) }));
+        assert!(matches!(poll_once(future.as_mut()), Poll::Ready(Ok(5))));
+    }
+
+    #[test]
+    fn test_cancel_before_poll() {
+        let token = CancellationToken::new();
+        token.cancel();
+        assert!(token.is_cancelled());
+
+        let mut future = std::pin::pin!(token.run(async { Ok(5) }));
+        assert!(matches!(
+            poll_once(future.as_mut()),
+            Poll::Ready(Err(Error::Cancelled))
+        ));
+    }
+
+    #[test]
+    fn test_cancel_pending_future() {
+        let token = CancellationToken::new();
+        let mut future = std::pin::pin!(token.run(std::future::pending::<Result<()>>()));
+        assert!(poll_once(future.as_mut()).is_pending());
+        assert_eq!(token.inner.wakers.lock().unwrap().len(), 1);
+
+        token.clone().cancel();
+        assert!(matches!(
+            poll_once(future.as_mut()),
+            Poll::Ready(Err(Error::Cancelled))
+        ));
+        assert!(token.inner.wakers.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_dropped_future_unregisters_waker() {
+        let token = CancellationToken::new();
+        {
+            let mut future = std::pin::pin!(token.run(std::future::pending::<Result<()>>()));
+            assert!(poll_once(future.as_mut()).is_pending());
+        }
+        assert!(token.inner.wakers.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancel_wakes_waiting_task() {
+        let token = CancellationToken::new();
+        let task = tokio::spawn(token.run(std::future::pending::<Result<()>>()));
+        tokio::task::yield_now().await;
+
+        token.cancel();
+        let result = task.await.unwrap();
+        assert!(matches!(result, Err(Error::Cancelled)));
+    }
+}
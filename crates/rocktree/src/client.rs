@@ -3,25 +3,27 @@
 //! This module provides the main `Client` type for downloading planetoid metadata,
 //! bulk metadata, and node data from Google Earth's servers.
 
-use crate::cache::{Cache, NoCache};
-use crate::error::{Error, Result};
-use crate::types::{
-    BulkMetadata, BulkRequest, Mesh, Node, NodeMetadata, NodeRequest, Planetoid, TextureFormat,
-};
-use glam::{DMat4, Vec3};
-use prost::Message;
-use rocktree_decode::OrientedBoundingBox;
-use rocktree_proto as proto;
+use crate::cache::{Cache, CacheKey, DecodedNodeCache, NoCache};
+use crate::cancel::CancellationToken;
+use crate::error::Result;
+use crate::metrics::ClientMetrics;
+use crate::policy::{BackgroundTask, FetchPolicy};
+use crate::protocol::{Fetcher, Source};
+use crate::types::{BulkMetadata, BulkRequest, Node, NodeRequest, Planetoid};
 use std::sync::Arc;
 
-/// Base URL for Google Earth's rocktree API.
-const BASE_URL: &str = "https://kh.google.com/rt/earth/";
-
 /// HTTP client for fetching Google Earth mesh data.
 ///
 /// The client handles HTTP requests, caching, and protobuf decoding. It is
 /// designed to be runtime-agnostic and works with any async executor.
 ///
+/// Request counts, cache behavior, and latency are recorded in the client's
+/// [`ClientMetrics`], available from [`Client::metrics`].
+///
+/// How each fetch uses the cache and the network is controlled by a
+/// [`FetchPolicy`]. With [`FetchPolicy::CacheOnly`], missing entries fail with
+/// [`Error::NotCached`](crate::Error::NotCached) instead of being downloaded.
+///
 /// # Example
 ///
 /// ```ignore
@@ -29,20 +31,14 @@ const BASE_URL: &str = "https://kh.google.com/rt/earth/";
 /// let planetoid = client.fetch_planetoid().await?;
 /// ```
 pub struct Client<C: Cache = NoCache> {
-    http: reqwest::Client,
-    cache: Arc<C>,
-    base_url: String,
+    inner: Fetcher<C, reqwest::Client>,
 }
 
 impl Client<NoCache> {
     /// Create a new client with default settings and no caching.
     #[must_use]
     pub fn new() -> Self {
-        Self {
-            http: reqwest::Client::new(),
-            cache: Arc::new(NoCache),
-            base_url: BASE_URL.to_string(),
-        }
+        Self::with_cache(NoCache)
     }
 }
 
@@ -52,34 +48,108 @@ impl Default for Client<NoCache> {
     }
 }
 
-impl<C: Cache> Client<C> {
+impl<C: Cache + 'static> Client<C> {
     /// Create a new client with a custom cache.
     #[must_use]
     pub fn with_cache(cache: C) -> Self {
-        Self {
-            http: reqwest::Client::new(),
-            cache: Arc::new(cache),
-            base_url: BASE_URL.to_string(),
-        }
+        Self::with_http_and_cache(reqwest::Client::new(), cache)
     }
 
     /// Create a new client with a custom HTTP client and cache.
     #[must_use]
     pub fn with_http_and_cache(http: reqwest::Client, cache: C) -> Self {
         Self {
-            http,
-            cache: Arc::new(cache),
-            base_url: BASE_URL.to_string(),
+            inner: Fetcher::new(http, cache),
         }
     }
 
     /// Set a custom base URL for testing.
     #[must_use]
     pub fn with_base_url(mut self, base_url: String) -> Self {
-        self.base_url = base_url;
+        self.inner.base_url = base_url;
+        self
+    }
+
+    /// Record metrics into a shared [`ClientMetrics`] instead of the client's
+    /// own.
+    ///
+    /// This is useful for aggregating the metrics of several clients.
+    #[must_use]
+    pub fn with_metrics(mut self, metrics: Arc<ClientMetrics>) -> Self {
+        self.inner.metrics = metrics;
+        self
+    }
+
+    /// Emit a `rocktree_fetch` tracing span for every fetch.
+    ///
+    /// The span is created at the debug level and carries the `endpoint` and
+    /// `path` of the request. Once known, the `cache_hit`, `bytes`, `status`,
+    /// and `elapsed_ms` fields are recorded on it.
+    #[must_use]
+    pub fn with_request_spans(mut self, enabled: bool) -> Self {
+        self.inner.request_spans = enabled;
+        self
+    }
+
+    /// Keep decoded nodes in `cache` so that repeated requests for the same
+    /// node skip downloading and decoding.
+    ///
+    /// Only the `fetch_node_shared` methods use this cache. Hits are counted
+    /// as node cache hits in the client's metrics.
+    #[must_use]
+    pub fn with_node_cache(mut self, cache: DecodedNodeCache) -> Self {
+        self.inner.node_cache = Some(cache);
+        self
+    }
+
+    /// Set the default [`FetchPolicy`] for requests that do not specify one.
+    #[must_use]
+    pub fn with_fetch_policy(mut self, policy: FetchPolicy) -> Self {
+        self.inner.fetch_policy = policy;
+        self
+    }
+
+    /// Run background work, such as the refreshes made by
+    /// [`FetchPolicy::StaleWhileRevalidate`], with `spawner`.
+    ///
+    /// The client is runtime-agnostic, so it cannot start tasks on its own.
+    /// The spawner typically hands the task to the application's executor,
+    /// for example with `tokio::spawn`. Each cache entry has at most one
+    /// refresh in flight; hits on an entry that is being refreshed do not
+    /// spawn another.
+    #[must_use]
+    pub fn with_spawner(
+        mut self,
+        spawner: impl Fn(BackgroundTask) + Send + Sync + 'static,
+    ) -> Self {
+        self.inner.spawner = Some(Arc::new(spawner));
         self
     }
 
+    /// Get the default fetch policy.
+    #[must_use]
+    pub fn fetch_policy(&self) -> FetchPolicy {
+        self.inner.fetch_policy
+    }
+
+    /// Get the metrics recorded by this client.
+    #[must_use]
+    pub fn metrics(&self) -> &Arc<ClientMetrics> {
+        &self.inner.metrics
+    }
+
+    /// Get the cache of raw responses.
+    #[must_use]
+    pub fn cache(&self) -> &C {
+        &self.inner.cache
+    }
+
+    /// Get the base URL that requests are made against.
+    #[must_use]
+    pub fn base_url(&self) -> &str {
+        &self.inner.base_url
+    }
+
     /// Fetch the root planetoid metadata.
     ///
     /// This returns information about the planet including radius and the
@@ -89,24 +159,19 @@ impl<C: Cache> Client<C> {
     ///
     /// Returns an error if the HTTP request fails or the response cannot be decoded.
     pub async fn fetch_planetoid(&self) -> Result<Planetoid> {
-        let url = format!("{}PlanetoidMetadata", self.base_url);
-        let data = self.fetch_bytes(&url).await?;
-
-        let proto =
-            proto::PlanetoidMetadata::decode(data.as_slice()).map_err(|e| Error::Protobuf {
-                context: "planetoid metadata",
-                message: e.to_string(),
-            })?;
-
-        let root_epoch = proto
-            .root_node_metadata
-            .as_ref()
-            .map_or(0, |r| r.epoch.unwrap_or(0));
+        self.fetch_planetoid_with_policy(self.inner.fetch_policy)
+            .await
+    }
 
-        Ok(Planetoid {
-            radius: f64::from(proto.radius.unwrap_or(0.0)),
-            root_epoch,
-        })
+    /// Fetch the root planetoid metadata with `policy` instead of the
+    /// client's default.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP request fails, the response cannot be
+    /// decoded, or the policy requires a cached entry that does not exist.
+    pub async fn fetch_planetoid_with_policy(&self, policy: FetchPolicy) -> Result<Planetoid> {
+        self.inner.fetch_planetoid(policy).await
     }
 
     /// Fetch bulk metadata for a given path and epoch.
@@ -118,18 +183,7 @@ impl<C: Cache> Client<C> {
     ///
     /// Returns an error if the HTTP request fails or the response cannot be decoded.
     pub async fn fetch_bulk(&self, request: &BulkRequest) -> Result<BulkMetadata> {
-        let url = format!(
-            "{}BulkMetadata/pb=!1m2!1s{}!2u{}",
-            self.base_url, request.path, request.epoch
-        );
-        let data = self.fetch_bytes(&url).await?;
-
-        let proto = proto::BulkMetadata::decode(data.as_slice()).map_err(|e| Error::Protobuf {
-            context: "bulk metadata",
-            message: e.to_string(),
-        })?;
-
-        Self::decode_bulk_metadata(&request.path, &proto)
+        self.inner.fetch_bulk(request).await
     }
 
     /// Fetch node data for a given request.
@@ -140,403 +194,331 @@ impl<C: Cache> Client<C> {
     ///
     /// Returns an error if the HTTP request fails or the response cannot be decoded.
     pub async fn fetch_node(&self, request: &NodeRequest) -> Result<Node> {
-        let url = if let Some(imagery_epoch) = request.imagery_epoch {
-            format!(
-                "{}NodeData/pb=!1m2!1s{}!2u{}!2e{}!3u{}!4b0",
-                self.base_url, request.path, request.epoch, request.texture_format, imagery_epoch
-            )
-        } else {
-            format!(
-                "{}NodeData/pb=!1m2!1s{}!2u{}!2e{}!4b0",
-                self.base_url, request.path, request.epoch, request.texture_format
-            )
-        };
-        let data = self.fetch_bytes(&url).await?;
-
-        let proto = proto::NodeData::decode(data.as_slice()).map_err(|e| Error::Protobuf {
-            context: "node data",
-            message: e.to_string(),
-        })?;
-
-        Self::decode_node_data(&request.path, &proto)
+        self.inner.fetch_node(request).await
+    }
+
+    /// Fetch node data as a shared node, using the decoded node cache.
+    ///
+    /// If the client has a [`DecodedNodeCache`] and it holds the node, the
+    /// cached node is returned without touching the byte cache or decoding,
+    /// unless the request's fetch policy is [`FetchPolicy::NetworkOnly`].
+    /// Otherwise the node is fetched like [`Client::fetch_node`] and stored in
+    /// the decoded node cache.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the HTTP request fails or the response cannot be decoded.
+    pub async fn fetch_node_shared(&self, request: &NodeRequest) -> Result<Arc<Node>> {
+        self.inner.fetch_node_shared(request).await
+    }
+
+    /// Fetch node data as a shared node, giving up when `token` is cancelled.
+    ///
+    /// This combines [`Client::fetch_node_shared`] with the cancellation
+    /// semantics of [`Client::fetch_bulk_cancellable`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the fetch is cancelled, the HTTP request fails, or
+    /// the response cannot be decoded.
+    pub async fn fetch_node_shared_cancellable(
+        &self,
+        request: &NodeRequest,
+        token: &CancellationToken,
+    ) -> Result<Arc<Node>> {
+        self.inner
+            .fetch_node_shared_cancellable(request, token)
+            .await
+    }
+
+    /// Fetch bulk metadata, giving up when `token` is cancelled.
+    ///
+    /// Cancelling the token aborts the HTTP request if it is in flight and
+    /// makes this method return
+    /// [`Error::Cancelled`](crate::Error::Cancelled). Cancelled fetches are
+    /// counted under [`ErrorKind::Cancelled`](crate::ErrorKind::Cancelled) in
+    /// the client's metrics.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the fetch is cancelled, the HTTP request fails, or
+    /// the response cannot be decoded.
+    pub async fn fetch_bulk_cancellable(
+        &self,
+        request: &BulkRequest,
+        token: &CancellationToken,
+    ) -> Result<BulkMetadata> {
+        self.inner.fetch_bulk_cancellable(request, token).await
+    }
+
+    /// Fetch node data, giving up when `token` is cancelled.
+    ///
+    /// See [`Client::fetch_bulk_cancellable`] for the cancellation semantics.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the fetch is cancelled, the HTTP request fails, or
+    /// the response cannot be decoded.
+    pub async fn fetch_node_cancellable(
+        &self,
+        request: &NodeRequest,
+        token: &CancellationToken,
+    ) -> Result<Node> {
+        self.inner.fetch_node_cancellable(request, token).await
     }
 
     /// Fetch raw bytes from a URL, using cache if available.
     ///
     /// This is exposed for test vector generation - it allows saving raw
-    /// protobuf responses to disk.
+    /// protobuf responses to disk. The response is cached and metrics are
+    /// recorded if the URL is a rocktree request recognized by
+    /// [`CacheKey::from_url`].
     pub async fn fetch_bytes_from_url(&self, url: &str) -> Result<Vec<u8>> {
-        self.fetch_bytes(url).await
+        self.inner.fetch_bytes_from_url(url).await
     }
 
     /// Build the URL for fetching bulk metadata.
     #[must_use]
     pub fn bulk_url(&self, request: &BulkRequest) -> String {
-        format!(
-            "{}BulkMetadata/pb=!1m2!1s{}!2u{}",
-            self.base_url, request.path, request.epoch
-        )
+        self.inner.bulk_url(request)
     }
 
     /// Build the URL for fetching node data.
     #[must_use]
     pub fn node_url(&self, request: &NodeRequest) -> String {
-        if let Some(imagery_epoch) = request.imagery_epoch {
-            format!(
-                "{}NodeData/pb=!1m2!1s{}!2u{}!2e{}!3u{}!4b0",
-                self.base_url, request.path, request.epoch, request.texture_format, imagery_epoch
-            )
-        } else {
-            format!(
-                "{}NodeData/pb=!1m2!1s{}!2u{}!2e{}!4b0",
-                self.base_url, request.path, request.epoch, request.texture_format
-            )
-        }
+        self.inner.node_url(request)
     }
 
     /// Build the URL for fetching planetoid metadata.
     #[must_use]
     pub fn planetoid_url(&self) -> String {
-        format!("{}PlanetoidMetadata", self.base_url)
+        self.inner.planetoid_url()
     }
 
-    /// Fetch raw bytes from a URL, using cache if available.
-    async fn fetch_bytes(&self, url: &str) -> Result<Vec<u8>> {
-        // Check cache first.
-        if let Some(data) = self.cache.get(url).await? {
-            tracing::debug!(url, "cache hit");
-            return Ok(data);
-        }
+    /// Fetch and decode a resource; see [`Fetcher::fetch_decoded`].
+    pub(crate) async fn fetch_decoded<T>(
+        &self,
+        key: &CacheKey,
+        url: &str,
+        policy: FetchPolicy,
+        decode: impl Fn(&[u8]) -> Result<T>,
+    ) -> Result<T> {
+        self.inner.fetch_decoded(key, url, policy, decode).await
+    }
 
-        tracing::debug!(url, "fetching");
+    /// Fetch raw bytes from a URL following `policy`; see
+    /// [`Fetcher::fetch_bytes`].
+    pub(crate) async fn fetch_bytes(
+        &self,
+        key: Option<&CacheKey>,
+        url: &str,
+        policy: FetchPolicy,
+    ) -> Result<(Vec<u8>, Source)> {
+        self.inner.fetch_bytes(key, url, policy).await
+    }
+}
 
-        // Fetch from network.
-        let response = self.http.get(url).send().await.map_err(|e| Error::Http {
-            url: url.to_string(),
-            message: e.to_string(),
-        })?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{CacheFuture, ContainsFuture, GetFuture, MemoryCache, checksum};
+    use crate::error::{Error, ErrorKind};
+    use crate::test_util::{planetoid_body, planetoid_body_with_epoch, serve};
 
-        let status = response.status();
-        if !status.is_success() {
-            return Err(Error::HttpStatus {
-                url: url.to_string(),
-                status: status.as_u16(),
-            });
-        }
+    #[test]
+    fn test_client_default() {
+        let client = Client::new();
+        assert!(client.base_url().starts_with("https://"));
+    }
 
-        let data = response.bytes().await.map_err(|e| Error::Http {
-            url: url.to_string(),
-            message: e.to_string(),
-        })?;
-        let data = data.to_vec();
-
-        // Store in cache.
-        self.cache.put(url, data.clone()).await?;
-
-        Ok(data)
-    }
-
-    /// Decode bulk metadata from protobuf.
-    fn decode_bulk_metadata(base_path: &str, proto: &proto::BulkMetadata) -> Result<BulkMetadata> {
-        // Flags from the proto definition.
-        const NODATA: u32 = 8;
-        const LEAF: u32 = 4;
-        const USE_IMAGERY_EPOCH: u32 = 16;
-
-        // head_node_center is Vec<f64>, convert to Vec3.
-        let head_node_center = if proto.head_node_center.len() >= 3 {
-            #[allow(clippy::cast_possible_truncation)]
-            Vec3::new(
-                proto.head_node_center[0] as f32,
-                proto.head_node_center[1] as f32,
-                proto.head_node_center[2] as f32,
-            )
-        } else {
-            Vec3::ZERO
-        };
-
-        let head_epoch = proto
-            .head_node_key
-            .as_ref()
-            .and_then(|k| k.epoch)
-            .unwrap_or(0);
-
-        let meters_per_texel: Vec<f32> = proto.meters_per_texel.clone();
-        #[allow(clippy::cast_possible_wrap)]
-        let default_texture_format = proto.default_available_texture_formats.unwrap_or(0) as i32;
-        let default_imagery_epoch = proto.default_imagery_epoch;
-
-        let mut nodes = Vec::new();
-        let mut child_bulk_paths = std::collections::HashMap::new();
-
-        for node_meta in &proto.node_metadata {
-            let path_and_flags = node_meta.path_and_flags.unwrap_or(0);
-            let pf = rocktree_decode::unpack_path_and_flags(path_and_flags);
-
-            let full_path = format!("{base_path}{}", pf.path);
-
-            let has_data = (pf.flags & NODATA) == 0;
-            let is_leaf = (pf.flags & LEAF) != 0;
-            let use_imagery_epoch = (pf.flags & USE_IMAGERY_EPOCH) != 0;
-
-            // Check for child bulk (4-char paths that aren't leaves).
-            if pf.path.len() == 4 && !is_leaf {
-                let epoch = node_meta.bulk_metadata_epoch.unwrap_or(head_epoch);
-                child_bulk_paths.insert(pf.path.clone(), epoch);
+    #[tokio::test]
+    async fn test_fetch_node_cancellable() {
+        // Accept the connection but never respond, so the fetch only finishes
+        // through cancellation.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let _connection = listener.accept();
+            std::thread::park();
+        });
+
+        let client = Arc::new(Client::new().with_base_url(format!("http://{addr}/")));
+        let token = CancellationToken::new();
+        let task = tokio::spawn({
+            let client = Arc::clone(&client);
+            let token = token.clone();
+            async move {
+                let request = NodeRequest::new("0".to_string(), 1, 1, None);
+                client.fetch_node_cancellable(&request, &token).await
             }
+        });
 
-            // Skip nodes without OBB if they have data or aren't leaves.
-            let has_obb = node_meta.oriented_bounding_box.is_some();
-            if (has_data || !is_leaf) && !has_obb {
-                continue;
-            }
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        token.cancel();
+        let result = task.await.unwrap();
+        assert!(matches!(result, Err(Error::Cancelled)));
 
-            if (has_data || !is_leaf) && has_obb {
-                let meters_per_texel_value = node_meta.meters_per_texel.unwrap_or_else(|| {
-                    if pf.level > 0 && (pf.level - 1) < meters_per_texel.len() {
-                        meters_per_texel[pf.level - 1]
-                    } else {
-                        1.0
-                    }
-                });
-
-                let obb_data = node_meta.oriented_bounding_box.as_ref().unwrap();
-                let obb = rocktree_decode::unpack_obb(
-                    obb_data,
-                    head_node_center,
-                    meters_per_texel_value,
-                )?;
-
-                let epoch = node_meta.epoch.unwrap_or(head_epoch);
-
-                #[allow(clippy::cast_possible_wrap)]
-                let texture_format = node_meta
-                    .available_texture_formats
-                    .map_or(default_texture_format, |f| f as i32);
-
-                let imagery_epoch = if use_imagery_epoch {
-                    node_meta.imagery_epoch.or(default_imagery_epoch)
-                } else {
-                    None
-                };
-
-                nodes.push(NodeMetadata {
-                    path: full_path,
-                    meters_per_texel: meters_per_texel_value,
-                    obb,
-                    has_data,
-                    epoch,
-                    texture_format: select_texture_format(texture_format),
-                    imagery_epoch,
-                });
-            }
-        }
+        let metrics = client.metrics().snapshot();
+        assert_eq!(metrics.node.errors, 1);
+        assert_eq!(metrics.errors.get(crate::ErrorKind::Cancelled), 1);
+    }
 
-        Ok(BulkMetadata {
-            path: base_path.to_string(),
-            head_node_center,
-            meters_per_texel,
-            nodes,
-            child_bulk_paths,
-            epoch: head_epoch,
-        })
-    }
-
-    /// Decode node data from protobuf.
-    fn decode_node_data(path: &str, proto: &proto::NodeData) -> Result<Node> {
-        let matrix_data: &[f64] = &proto.matrix_globe_from_mesh;
-        let matrix_globe_from_mesh = if matrix_data.len() == 16 {
-            DMat4::from_cols_array(matrix_data.try_into().unwrap_or(&[0.0; 16]))
-        } else {
-            DMat4::IDENTITY
-        };
-
-        let mut meshes = Vec::new();
-
-        for mesh_proto in &proto.meshes {
-            let mesh = Self::decode_mesh(mesh_proto)?;
-            meshes.push(mesh);
-        }
+    #[tokio::test]
+    async fn test_bad_checksum_is_a_miss() {
+        let base_url = serve(planetoid_body(), 1);
+        let cache = MemoryCache::new();
+        let mut entry = checksum::seal(&planetoid_body());
+        *entry.last_mut().unwrap() ^= 0x01;
+        cache.put(&CacheKey::Planetoid, entry).await.unwrap();
+
+        let client = Client::with_cache(cache.clone()).with_base_url(base_url);
+        assert_eq!(client.fetch_planetoid().await.unwrap().root_epoch, 7);
+
+        let metrics = client.metrics().snapshot();
+        assert_eq!(metrics.planetoid.cache_hits, 0);
+        assert_eq!(metrics.planetoid.cache_misses, 1);
+        let repaired = cache.get(&CacheKey::Planetoid).await.unwrap().unwrap();
+        assert_eq!(checksum::unseal(repaired), Some(planetoid_body()));
+    }
+
+    #[tokio::test]
+    async fn test_undecodable_entry_refetched() {
+        // Entries without a checksum are trusted until they fail to decode.
+        let base_url = serve(planetoid_body(), 1);
+        let cache = MemoryCache::new();
+        cache
+            .put(&CacheKey::Planetoid, vec![0xFF; 16])
+            .await
+            .unwrap();
+
+        let client = Client::with_cache(cache.clone()).with_base_url(base_url);
+        assert_eq!(client.fetch_planetoid().await.unwrap().root_epoch, 7);
+
+        let metrics = client.metrics().snapshot();
+        assert_eq!(metrics.planetoid.cache_hits, 1);
+        assert_eq!(metrics.planetoid.cache_misses, 1);
+        assert_eq!(metrics.planetoid.errors, 0);
+        assert_eq!(
+            metrics.planetoid.bytes_downloaded,
+            planetoid_body().len() as u64
+        );
+        assert_eq!(client.fetch_planetoid().await.unwrap().root_epoch, 7);
+    }
 
-        // Get OBB from first mesh if available (or create a default).
-        let obb = OrientedBoundingBox {
-            center: glam::DVec3::ZERO,
-            extents: glam::DVec3::ONE,
-            orientation: glam::DMat3::IDENTITY,
-        };
-
-        Ok(Node {
-            path: path.to_string(),
-            matrix_globe_from_mesh,
-            meters_per_texel: 1.0, // Will be set from metadata.
-            obb,
-            meshes,
-        })
-    }
-
-    /// Decode a mesh from protobuf.
-    fn decode_mesh(proto: &proto::Mesh) -> Result<Mesh> {
-        // Unpack vertices.
-        let vertices_data = proto.vertices.as_deref().unwrap_or(&[]);
-        let mut vertices = rocktree_decode::unpack_vertices(vertices_data)?;
-
-        // Unpack indices.
-        let indices_data = proto.indices.as_deref().unwrap_or(&[]);
-        let indices = rocktree_decode::unpack_indices(indices_data)?;
-
-        // Unpack texture coordinates.
-        let texcoords_data = proto.texture_coordinates.as_deref().unwrap_or(&[]);
-        let uv_transform = if !texcoords_data.is_empty() && !vertices.is_empty() {
-            rocktree_decode::unpack_tex_coords(texcoords_data, &mut vertices)?
-        } else {
-            rocktree_decode::UvTransform::default()
-        };
-
-        // Apply explicit UV offset/scale if provided.
-        let uv_transform = if proto.uv_offset_and_scale.len() == 4 {
-            rocktree_decode::UvTransform {
-                offset: glam::Vec2::new(proto.uv_offset_and_scale[0], proto.uv_offset_and_scale[1]),
-                scale: glam::Vec2::new(proto.uv_offset_and_scale[2], proto.uv_offset_and_scale[3]),
+    #[tokio::test]
+    async fn test_cache_read_error_downloads() {
+        struct Unreadable;
+        impl Cache for Unreadable {
+            fn get(&self, _key: &CacheKey) -> GetFuture<'_> {
+                Box::pin(async {
+                    Err(Error::Cache {
+                        operation: "get",
+                        message: "unreadable".to_string(),
+                    })
+                })
             }
-        } else {
-            // Flip V coordinate.
-            rocktree_decode::UvTransform {
-                offset: glam::Vec2::new(
-                    uv_transform.offset.x,
-                    uv_transform.offset.y - 1.0 / uv_transform.scale.y,
-                ),
-                scale: glam::Vec2::new(uv_transform.scale.x, -uv_transform.scale.y),
+            fn put(&self, _key: &CacheKey, _data: Vec<u8>) -> CacheFuture<'_> {
+                Box::pin(async { Ok(()) })
             }
-        };
-
-        // Unpack octant masks and get layer bounds.
-        let octant_data = proto.layer_and_octant_counts.as_deref().unwrap_or(&[]);
-        let has_octant_data =
-            !octant_data.is_empty() && !indices.is_empty() && !vertices.is_empty();
-        let layer_bounds = if has_octant_data {
-            rocktree_decode::unpack_octant_mask_and_layer_bounds(
-                octant_data,
-                &indices,
-                &mut vertices,
-            )?
-        } else {
-            [indices.len(); 10]
-        };
-
-        // Truncate indices to layer 3 bound (visible geometry).
-        let visible_index_count = layer_bounds[3].min(indices.len());
-        let indices: Vec<u16> = indices.into_iter().take(visible_index_count).collect();
-
-        // Decode texture.
-        let (texture_data, texture_format, texture_width, texture_height) =
-            Self::decode_texture(proto)?;
-
-        Ok(Mesh {
-            vertices,
-            indices,
-            uv_transform,
-            texture_data,
-            texture_format,
-            texture_width,
-            texture_height,
-            has_octant_data,
-        })
-    }
-
-    /// Decode texture data from a mesh.
-    fn decode_texture(mesh: &proto::Mesh) -> Result<(Vec<u8>, TextureFormat, u32, u32)> {
-        let textures = &mesh.texture;
-        if textures.is_empty() {
-            return Err(Error::InvalidData {
-                context: "mesh texture",
-                detail: "no textures found".to_string(),
-            });
-        }
-
-        let texture = &textures[0];
-        if texture.data.is_empty() {
-            return Err(Error::InvalidData {
-                context: "mesh texture",
-                detail: "no texture data found".to_string(),
-            });
-        }
-
-        let tex_data = &texture.data[0];
-        let format = texture.format.unwrap_or(proto::texture::Format::Jpg as i32);
-        match format {
-            f if f == proto::texture::Format::Jpg as i32 => {
-                let decoded = rocktree_decode::texture::decode_jpeg_to_rgba(tex_data)?;
-                // Return as RGBA since we fully decode JPEG.
-                Ok((
-                    decoded.data,
-                    TextureFormat::Rgba,
-                    decoded.width,
-                    decoded.height,
-                ))
+            fn contains(&self, _key: &CacheKey) -> ContainsFuture<'_> {
+                Box::pin(async { Ok(true) })
+            }
+            fn remove(&self, _key: &CacheKey) -> CacheFuture<'_> {
+                Box::pin(async { Ok(()) })
             }
-            f if f == proto::texture::Format::CrnDxt1 as i32 => {
-                let decoded = rocktree_decode::texture::decode_crn_to_rgba(tex_data)?;
-                // Return as RGBA since we fully decode CRN.
-                Ok((
-                    decoded.data,
-                    TextureFormat::Rgba,
-                    decoded.width,
-                    decoded.height,
-                ))
+            fn clear(&self) -> CacheFuture<'_> {
+                Box::pin(async { Ok(()) })
             }
-            other => Err(Error::InvalidData {
-                context: "texture format",
-                detail: format!("unsupported format: {other}"),
-            }),
         }
-    }
-}
 
-/// Select the best texture format from available formats bitmask.
-fn select_texture_format(available: i32) -> i32 {
-    // Preference order: CRN_DXT1 (6), JPG (1).
-    const CRN_DXT1: i32 = 6;
-    const JPG: i32 = 1;
+        let base_url = serve(planetoid_body(), 1);
+        let client = Client::with_cache(Unreadable).with_base_url(base_url);
+        assert_eq!(client.fetch_planetoid().await.unwrap().root_epoch, 7);
 
-    let supported = [CRN_DXT1, JPG];
-
-    for format in supported {
-        // Format availability is encoded as (1 << (format - 1)).
-        if available & (1 << (format - 1)) != 0 {
-            return format;
-        }
+        let metrics = client.metrics().snapshot();
+        assert_eq!(metrics.planetoid.cache_misses, 1);
+        assert_eq!(metrics.planetoid.errors, 0);
+        assert_eq!(metrics.errors.get(ErrorKind::Cache), 1);
     }
 
-    // Default to first supported.
-    supported[0]
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_select_texture_format_prefers_crn() {
-        // Both available.
-        let both = (1 << (6 - 1)) | (1 << (1 - 1));
-        assert_eq!(select_texture_format(both), 6);
+    #[tokio::test]
+    async fn test_refetches_only_once() {
+        // The server answers once with garbage. A second refetch would fail
+        // with a connection error instead of the decode error.
+        let base_url = serve(vec![0xFF; 16], 1);
+        let cache = MemoryCache::new();
+        cache
+            .put(&CacheKey::Planetoid, vec![0xFF; 16])
+            .await
+            .unwrap();
+
+        let client = Client::with_cache(cache.clone()).with_base_url(base_url);
+        let err = client.fetch_planetoid().await.unwrap_err();
+        assert!(matches!(err, Error::Protobuf { .. }), "{err:?}");
+        assert_eq!(client.metrics().snapshot().planetoid.errors, 1);
     }
 
-    #[test]
-    fn test_select_texture_format_jpg_only() {
-        let jpg_only = 1 << (1 - 1);
-        assert_eq!(select_texture_format(jpg_only), 1);
+    #[tokio::test]
+    async fn test_cache_only_miss() {
+        let client = Client::with_cache(MemoryCache::new())
+            .with_base_url("http://127.0.0.1:9/".to_string())
+            .with_fetch_policy(FetchPolicy::CacheOnly);
+
+        let err = client.fetch_planetoid().await.unwrap_err();
+        assert!(matches!(err, Error::NotCached { .. }), "{err:?}");
+        let metrics = client.metrics().snapshot();
+        assert_eq!(metrics.planetoid.cache_misses, 1);
+        assert_eq!(metrics.errors.get(crate::ErrorKind::NotCached), 1);
     }
 
-    #[test]
-    fn test_select_texture_format_none_available() {
-        // Returns default (CRN_DXT1).
-        assert_eq!(select_texture_format(0), 6);
+    #[tokio::test]
+    async fn test_per_request_policy() {
+        let cache = MemoryCache::new();
+        let stale = checksum::seal(&planetoid_body_with_epoch(3));
+        cache.put(&CacheKey::Planetoid, stale).await.unwrap();
+        let client = Client::with_cache(cache).with_base_url(serve(planetoid_body(), 1));
+
+        let refreshed = client
+            .fetch_planetoid_with_policy(FetchPolicy::NetworkOnly)
+            .await
+            .unwrap();
+        assert_eq!(refreshed.root_epoch, 7);
+        let cached = client
+            .fetch_planetoid_with_policy(FetchPolicy::CacheOnly)
+            .await
+            .unwrap();
+        assert_eq!(cached.root_epoch, 7);
+
+        let request = BulkRequest::root(7).with_fetch_policy(FetchPolicy::CacheOnly);
+        let err = client.fetch_bulk(&request).await.unwrap_err();
+        assert!(matches!(err, Error::NotCached { .. }), "{err:?}");
     }
 
-    #[test]
-    fn test_client_default() {
-        let client = Client::new();
-        assert!(client.base_url.starts_with("https://"));
+    #[tokio::test]
+    async fn test_stale_while_revalidate() {
+        let cache = MemoryCache::new();
+        let stale = checksum::seal(&planetoid_body_with_epoch(3));
+        cache.put(&CacheKey::Planetoid, stale).await.unwrap();
+        let client = Client::with_cache(cache.clone())
+            .with_base_url(serve(planetoid_body(), 1))
+            .with_fetch_policy(FetchPolicy::StaleWhileRevalidate)
+            .with_spawner(|task| {
+                tokio::spawn(task);
+            });
+
+        // The stale entry is served while the refresh runs in the background.
+        assert_eq!(client.fetch_planetoid().await.unwrap().root_epoch, 3);
+        for _ in 0..100 {
+            let entry = cache.get(&CacheKey::Planetoid).await.unwrap().unwrap();
+            if checksum::unseal(entry) == Some(planetoid_body()) {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+        let refreshed = client
+            .fetch_planetoid_with_policy(FetchPolicy::CacheOnly)
+            .await
+            .unwrap();
+        assert_eq!(refreshed.root_epoch, 7);
     }
 }
@@ -7,6 +7,7 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 /// Errors that can occur in rocktree operations.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// HTTP request failed.
     Http {
@@ -45,6 +46,90 @@ pub enum Error {
         /// Description of what was invalid.
         detail: String,
     },
+    /// The operation was cancelled through a
+    /// [`CancellationToken`](crate::CancellationToken).
+    Cancelled,
+    /// The resource is not in the cache and the
+    /// [`FetchPolicy`](crate::FetchPolicy) does not allow downloading it.
+    NotCached {
+        /// The URL of the missing resource.
+        url: String,
+    },
+}
+
+/// The category of an [`Error`], without its context.
+///
+/// This is useful for grouping errors, for example when counting failures in
+/// [`ClientMetrics`](crate::ClientMetrics).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// HTTP request failed.
+    Http,
+    /// HTTP response had a non-success status code.
+    HttpStatus,
+    /// Protobuf decoding failed.
+    Protobuf,
+    /// Mesh decoding failed.
+    Decode,
+    /// Cache operation failed.
+    Cache,
+    /// Invalid data in response.
+    InvalidData,
+    /// The operation was cancelled.
+    Cancelled,
+    /// The resource is not in the cache.
+    NotCached,
+}
+
+impl ErrorKind {
+    /// All error kinds, in declaration order.
+    pub const ALL: [ErrorKind; 8] = [
+        ErrorKind::Http,
+        ErrorKind::HttpStatus,
+        ErrorKind::Protobuf,
+        ErrorKind::Decode,
+        ErrorKind::Cache,
+        ErrorKind::InvalidData,
+        ErrorKind::Cancelled,
+        ErrorKind::NotCached,
+    ];
+
+    /// A short, lowercase name for this kind.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Http => "http",
+            ErrorKind::HttpStatus => "http status",
+            ErrorKind::Protobuf => "protobuf",
+            ErrorKind::Decode => "decode",
+            ErrorKind::Cache => "cache",
+            ErrorKind::InvalidData => "invalid data",
+            ErrorKind::Cancelled => "cancelled",
+            ErrorKind::NotCached => "not cached",
+        }
+    }
+
+    pub(crate) fn index(self) -> usize {
+        self as usize
+    }
+}
+
+impl Error {
+    /// Get the category of this error.
+    #[must_use]
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::Http { .. } => ErrorKind::Http,
+            Error::HttpStatus { .. } => ErrorKind::HttpStatus,
+            Error::Protobuf { .. } => ErrorKind::Protobuf,
+            Error::Decode(_) => ErrorKind::Decode,
+            Error::Cache { .. } => ErrorKind::Cache,
+            Error::InvalidData { .. } => ErrorKind::InvalidData,
+            Error::Cancelled => ErrorKind::Cancelled,
+            Error::NotCached { .. } => ErrorKind::NotCached,
+        }
+    }
 }
 
 impl fmt::Display for Error {
@@ -66,6 +151,8 @@ impl fmt::Display for Error {
             Error::InvalidData { context, detail } => {
                 write!(f, "invalid {context}: {detail}")
             }
+            Error::Cancelled => write!(f, "operation was cancelled"),
+            Error::NotCached { url } => write!(f, "{url} is not in the cache"),
         }
     }
 }
@@ -0,0 +1,285 @@
+//! Geographic coordinates on the WGS84 ellipsoid.
+//!
+//! Rocktree data is positioned in Earth-centered, Earth-fixed (ECEF)
+//! coordinates. This module converts between ECEF and geodetic latitude,
+//! longitude and height, and describes geographic regions for selecting
+//! nodes.
+
+use glam::DVec3;
+use rocktree_decode::OrientedBoundingBox;
+
+/// Semi-major axis of the WGS84 ellipsoid in meters.
+pub const WGS84_A: f64 = 6_378_137.0;
+
+/// Flattening of the WGS84 ellipsoid.
+pub const WGS84_F: f64 = 1.0 / 298.257_223_563;
+
+/// First eccentricity squared of the WGS84 ellipsoid.
+const WGS84_E2: f64 = WGS84_F * (2.0 - WGS84_F);
+
+/// Semi-minor axis of the WGS84 ellipsoid in meters.
+const WGS84_B: f64 = WGS84_A * (1.0 - WGS84_F);
+
+/// A geodetic position on the WGS84 ellipsoid.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Geodetic {
+    /// Latitude in degrees, positive north.
+    pub latitude: f64,
+    /// Longitude in degrees, positive east.
+    pub longitude: f64,
+    /// Height above the ellipsoid in meters.
+    pub height: f64,
+}
+
+impl Geodetic {
+    /// Create a geodetic position.
+    #[must_use]
+    pub fn new(latitude: f64, longitude: f64, height: f64) -> Self {
+        Self {
+            latitude,
+            longitude,
+            height,
+        }
+    }
+
+    /// Convert an ECEF position to geodetic coordinates.
+    ///
+    /// Uses Bowring's method, which is accurate to well under a millimeter
+    /// for positions near the surface.
+    #[must_use]
+    pub fn from_ecef(position: DVec3) -> Self {
+        let p = position.x.hypot(position.y);
+        let longitude = position.y.atan2(position.x);
+        let ep2 = (WGS84_A * WGS84_A - WGS84_B * WGS84_B) / (WGS84_B * WGS84_B);
+        let theta = (position.z * WGS84_A).atan2(p * WGS84_B);
+        let (sin_theta, cos_theta) = theta.sin_cos();
+        let latitude = (position.z + ep2 * WGS84_B * sin_theta.powi(3))
+            .atan2(p - WGS84_E2 * WGS84_A * cos_theta.powi(3));
+        let (sin_lat, cos_lat) = latitude.sin_cos();
+        let n = WGS84_A / (1.0 - WGS84_E2 * sin_lat * sin_lat).sqrt();
+        // Near the poles the cosine vanishes, so measure along the axis.
+        let height = if cos_lat.abs() > 1e-9 {
+            p / cos_lat - n
+        } else {
+            position.z.abs() - WGS84_B
+        };
+        Self {
+            latitude: latitude.to_degrees(),
+            longitude: longitude.to_degrees(),
+            height,
+        }
+    }
+
+    /// Convert to an ECEF position.
+    #[must_use]
+    pub fn to_ecef(self) -> DVec3 {
+        let (sin_lat, cos_lat) = self.latitude.to_radians().sin_cos();
+        let (sin_lon, cos_lon) = self.longitude.to_radians().sin_cos();
+        let n = WGS84_A / (1.0 - WGS84_E2 * sin_lat * sin_lat).sqrt();
+        DVec3::new(
+            (n + self.height) * cos_lat * cos_lon,
+            (n + self.height) * cos_lat * sin_lon,
+            (n * (1.0 - WGS84_E2) + self.height) * sin_lat,
+        )
+    }
+}
+
+/// A latitude/longitude rectangle in degrees.
+///
+/// A rectangle whose `west` edge is greater than its `east` edge crosses the
+/// antimeridian.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoBounds {
+    /// Southern edge in degrees.
+    pub south: f64,
+    /// Western edge in degrees.
+    pub west: f64,
+    /// Northern edge in degrees.
+    pub north: f64,
+    /// Eastern edge in degrees.
+    pub east: f64,
+}
+
+impl GeoBounds {
+    /// The whole globe.
+    pub const WORLD: Self = Self {
+        south: -90.0,
+        west: -180.0,
+        north: 90.0,
+        east: 180.0,
+    };
+
+    /// Create bounds from their edges in degrees.
+    #[must_use]
+    pub fn new(south: f64, west: f64, north: f64, east: f64) -> Self {
+        Self {
+            south,
+            west,
+            north,
+            east,
+        }
+    }
+
+    /// Get bounds that enclose everything within an oriented bounding box.
+    ///
+    /// The bounds are conservative: they enclose the box's bounding sphere,
+    /// so they may extend somewhat beyond the box itself.
+    #[must_use]
+    pub fn enclosing_obb(obb: &OrientedBoundingBox) -> Self {
+        let radius = obb.extents.length();
+        let distance = obb.center.length();
+        if distance <= radius {
+            return Self::WORLD;
+        }
+
+        // The sphere subtends a cap of this angular radius, seen from the
+        // Earth's center. The cap is bounded in geocentric latitude, then
+        // shifted to geodetic latitude, which stretches it by under 1%.
+        let angle = (radius / distance).asin();
+        let geocentric = (obb.center.z / distance).asin();
+        let center = Geodetic::from_ecef(obb.center);
+        let shift = center.latitude - geocentric.to_degrees();
+        let margin = angle.to_degrees() * 0.01;
+        let south = (geocentric - angle).to_degrees() + shift - margin;
+        let north = (geocentric + angle).to_degrees() + shift + margin;
+        if south <= -90.0 || north >= 90.0 {
+            return Self::new(south.max(-90.0), -180.0, north.min(90.0), 180.0);
+        }
+
+        let half_width = (angle.sin() / geocentric.cos())
+            .min(1.0)
+            .asin()
+            .to_degrees();
+        if half_width >= 90.0 {
+            return Self::new(south, -180.0, north, 180.0);
+        }
+        Self::new(
+            south,
+            wrap_longitude(center.longitude - half_width),
+            north,
+            wrap_longitude(center.longitude + half_width),
+        )
+    }
+
+    /// Check whether a position lies within the bounds.
+    #[must_use]
+    pub fn contains(&self, latitude: f64, longitude: f64) -> bool {
+        (self.south..=self.north).contains(&latitude)
+            && self
+                .longitude_ranges()
+                .any(|(west, east)| (west..=east).contains(&longitude))
+    }
+
+    /// Check whether two bounds overlap.
+    #[must_use]
+    pub fn intersects(&self, other: &GeoBounds) -> bool {
+        self.south <= other.north
+            && other.south <= self.north
+            && self.longitude_ranges().any(|(west, east)| {
+                other
+                    .longitude_ranges()
+                    .any(|(other_west, other_east)| west <= other_east && other_west <= east)
+            })
+    }
+
+    /// Check whether the bounds may overlap an oriented bounding box.
+    ///
+    /// See [`GeoBounds::enclosing_obb`] for how the box is approximated.
+    #[must_use]
+    pub fn intersects_obb(&self, obb: &OrientedBoundingBox) -> bool {
+        self.intersects(&Self::enclosing_obb(obb))
+    }
+
+    /// Split the longitude span at the antimeridian.
+    fn longitude_ranges(&self) -> impl Iterator<Item = (f64, f64)> {
+        let ranges = if self.west <= self.east {
+            [Some((self.west, self.east)), None]
+        } else {
+            [Some((self.west, 180.0)), Some((-180.0, self.east))]
+        };
+        ranges.into_iter().flatten()
+    }
+}
+
+/// Wrap a longitude into `[-180, 180]`.
+fn wrap_longitude(longitude: f64) -> f64 {
+    if (-180.0..=180.0).contains(&longitude) {
+        longitude
+    } else {
+        (longitude + 180.0).rem_euclid(360.0) - 180.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use glam::DMat3;
+
+    fn obb_at(position: Geodetic, half_size: f64) -> OrientedBoundingBox {
+        OrientedBoundingBox {
+            center: position.to_ecef(),
+            extents: DVec3::splat(half_size),
+            orientation: DMat3::IDENTITY,
+        }
+    }
+
+    #[test]
+    fn test_ecef_round_trip() {
+        for &(latitude, longitude, height) in &[
+            (0.0, 0.0, 0.0),
+            (40.7, -74.0, 120.0),
+            (-33.9, 151.2, -30.0),
+            (89.999, 10.0, 500.0),
+            (-90.0, 0.0, 0.0),
+        ] {
+            let position = Geodetic::new(latitude, longitude, height);
+            let back = Geodetic::from_ecef(position.to_ecef());
+            assert!((back.latitude - latitude).abs() < 1e-9, "{back:?}");
+            assert!((back.height - height).abs() < 1e-3, "{back:?}");
+            if latitude.abs() < 90.0 {
+                assert!((back.longitude - longitude).abs() < 1e-9, "{back:?}");
+            }
+        }
+        let equator = Geodetic::new(0.0, 0.0, 0.0).to_ecef();
+        assert!((equator - DVec3::new(WGS84_A, 0.0, 0.0)).length() < 1e-6);
+    }
+
+    #[test]
+    fn test_intersects_across_antimeridian() {
+        let pacific = GeoBounds::new(-10.0, 170.0, 10.0, -170.0);
+        assert!(pacific.contains(0.0, 175.0));
+        assert!(pacific.contains(0.0, -175.0));
+        assert!(!pacific.contains(0.0, 0.0));
+        assert!(pacific.intersects(&GeoBounds::new(-1.0, -179.0, 1.0, -178.0)));
+        assert!(!pacific.intersects(&GeoBounds::new(-1.0, 0.0, 1.0, 1.0)));
+        assert!(!pacific.intersects(&GeoBounds::new(20.0, 175.0, 30.0, 176.0)));
+        assert!(GeoBounds::WORLD.intersects(&pacific));
+    }
+
+    #[test]
+    fn test_enclosing_obb() {
+        let small = obb_at(Geodetic::new(40.0, -74.0, 0.0), 100.0);
+        let bounds = GeoBounds::enclosing_obb(&small);
+        assert!(bounds.contains(40.0, -74.0));
+        assert!(bounds.north - bounds.south < 0.01);
+        assert!(bounds.east - bounds.west < 0.01);
+        assert!(GeoBounds::new(39.0, -75.0, 41.0, -73.0).intersects_obb(&small));
+        assert!(!GeoBounds::new(41.0, -75.0, 42.0, -73.0).intersects_obb(&small));
+
+        // Boxes around the Earth's center cover everything.
+        let whole = OrientedBoundingBox {
+            center: DVec3::ZERO,
+            extents: DVec3::splat(WGS84_A),
+            orientation: DMat3::IDENTITY,
+        };
+        assert_eq!(GeoBounds::enclosing_obb(&whole), GeoBounds::WORLD);
+
+        // Boxes around a pole cover every longitude.
+        let pole = obb_at(Geodetic::new(89.99, 0.0, 0.0), 10_000.0);
+        let bounds = GeoBounds::enclosing_obb(&pole);
+        assert_eq!(
+            (bounds.west, bounds.east, bounds.north),
+            (-180.0, 180.0, 90.0)
+        );
+    }
+}
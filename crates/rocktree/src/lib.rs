@@ -10,6 +10,13 @@
 //! - **Runtime-agnostic**: Returns `impl Future`, works with any executor
 //! - **Sync decoding**: Decode functions are synchronous; client parallelizes
 //!
+//! # Feature flags
+//!
+//! - `blocking`: Enables [`BlockingClient`], a synchronous client for code
+//!   that does not run an async executor (native only)
+//! - `deflate`: Enables `Compression::Deflate` for [`CompressedCache`]
+//! - `zstd`: Enables `Compression::Zstd` for [`CompressedCache`] (native only)
+//!
 //! # Example
 //!
 //! ```ignore
@@ -25,18 +32,47 @@
 //! let bulk = client.fetch_bulk(BulkRequest::root(planetoid.root_epoch)).await?;
 //! ```
 
+#[cfg(not(target_family = "wasm"))]
+pub mod archive;
+#[cfg(all(feature = "blocking", not(target_family = "wasm")))]
+mod blocking;
 pub mod cache;
+mod cancel;
 mod client;
 mod error;
+pub mod geo;
+pub mod metrics;
+mod policy;
+mod protocol;
+pub mod scheduler;
+#[cfg(test)]
+mod test_util;
 pub mod types;
+mod walk;
 
-pub use cache::{Cache, MemoryCache, NoCache};
+#[cfg(not(target_family = "wasm"))]
+pub use archive::{ArchiveCache, ArchiveManifest, ArchiveWriter, export_region};
+#[cfg(all(feature = "blocking", not(target_family = "wasm")))]
+pub use blocking::BlockingClient;
+
+#[cfg(not(target_family = "wasm"))]
+pub use cache::DiskCache;
+pub use cache::{
+    Cache, CacheKey, CacheStats, CompressedCache, Compression, CompressionStats, DecodedNodeCache,
+    LayeredCache, MemoryCache, NoCache, WritePolicy,
+};
+pub use cancel::{Cancellable, CancellationToken};
 pub use client::Client;
-pub use error::{Error, Result};
+pub use error::{Error, ErrorKind, Result};
+pub use geo::{GeoBounds, Geodetic};
+pub use metrics::{ClientMetrics, Endpoint, MetricsSnapshot};
+pub use policy::{BackgroundTask, FetchPolicy};
+pub use scheduler::{FetchRequest, Priority, Scheduler};
 pub use types::{
-    BulkMetadata, BulkRequest, Frustum, LodMetrics, Mesh, Node, NodeMetadata, NodeRequest,
-    Planetoid, TextureFormat,
+    BulkMetadata, BulkRequest, Frustum, LodMetrics, Mesh, MeshLayer, Node, NodeMetadata,
+    NodeRequest, Planetoid, TextureFormat,
 };
+pub use walk::{WalkStep, walk_region};
 
 // Re-export decode types for convenience.
 pub use rocktree_decode::{OrientedBoundingBox, UvTransform, Vertex};
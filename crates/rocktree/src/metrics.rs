@@ -0,0 +1,418 @@
+//! Request metrics for the rocktree clients.
+//!
+//! Every client owns a [`ClientMetrics`] that counts requests, cache hits and
+//! misses, downloaded bytes, errors by kind, and network latency per
+//! [`Endpoint`]. Counters are lock-free atomics, so recording is cheap enough to
+//! stay enabled at all times. Call [`ClientMetrics::snapshot`] to read a
+//! consistent-enough copy for display or export.
+
+use crate::error::{Error, ErrorKind};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// A rocktree API endpoint.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Endpoint {
+    /// `PlanetoidMetadata` requests.
+    Planetoid,
+    /// `BulkMetadata` requests.
+    Bulk,
+    /// `NodeData` requests.
+    Node,
+}
+
+impl Endpoint {
+    /// All endpoints, in declaration order.
+    pub const ALL: [Endpoint; 3] = [Endpoint::Planetoid, Endpoint::Bulk, Endpoint::Node];
+
+    /// A short, lowercase name for this endpoint.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Endpoint::Planetoid => "planetoid",
+            Endpoint::Bulk => "bulk",
+            Endpoint::Node => "node",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Upper bounds (inclusive, in milliseconds) of the latency histogram buckets.
+///
+/// Latencies above the last bound are counted in an overflow bucket.
+pub const LATENCY_BUCKETS_MS: [u64; 10] = [10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000];
+
+/// Live request counters for a client.
+///
+/// Clients create one automatically; use `with_metrics` on the client to share
+/// a single instance between several clients.
+#[derive(Debug, Default)]
+pub struct ClientMetrics {
+    endpoints: [EndpointCounters; 3],
+    errors: [AtomicU64; ErrorKind::ALL.len()],
+}
+
+impl ClientMetrics {
+    /// Create a new set of zeroed counters.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take a snapshot of the current counter values.
+    ///
+    /// Counters are read individually, so a snapshot taken while requests are
+    /// in flight may be off by the requests that completed during the read.
+    #[must_use]
+    pub fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            planetoid: self.endpoints[Endpoint::Planetoid.index()].snapshot(),
+            bulk: self.endpoints[Endpoint::Bulk.index()].snapshot(),
+            node: self.endpoints[Endpoint::Node.index()].snapshot(),
+            errors: ErrorCounts(std::array::from_fn(|i| {
+                self.errors[i].load(Ordering::Relaxed)
+            })),
+        }
+    }
+
+    /// Reset all counters to zero.
+    pub fn reset(&self) {
+        for counters in &self.endpoints {
+            counters.reset();
+        }
+        for count in &self.errors {
+            count.store(0, Ordering::Relaxed);
+        }
+    }
+
+    /// Record that a fetch was requested from an endpoint.
+    pub(crate) fn record_request(&self, endpoint: Endpoint) {
+        self.endpoints[endpoint.index()]
+            .requests
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a fetch was served from the cache.
+    pub(crate) fn record_cache_hit(&self, endpoint: Endpoint) {
+        self.endpoints[endpoint.index()]
+            .cache_hits
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a fetch missed the cache and went to the network.
+    pub(crate) fn record_cache_miss(&self, endpoint: Endpoint) {
+        self.endpoints[endpoint.index()]
+            .cache_misses
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a completed network download.
+    pub(crate) fn record_download(&self, endpoint: Endpoint, bytes: usize, elapsed: Duration) {
+        let counters = &self.endpoints[endpoint.index()];
+        counters
+            .bytes_downloaded
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        counters.record_latency(elapsed);
+    }
+
+    /// Record a failed fetch.
+    pub(crate) fn record_error(&self, endpoint: Endpoint, error: &Error) {
+        self.endpoints[endpoint.index()]
+            .errors
+            .fetch_add(1, Ordering::Relaxed);
+        self.errors[error.kind().index()].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record a cache error that the fetch recovered from by downloading.
+    pub(crate) fn record_cache_error(&self, error: &Error) {
+        self.errors[error.kind().index()].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A point-in-time copy of a client's [`ClientMetrics`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MetricsSnapshot {
+    /// Metrics for `PlanetoidMetadata` requests.
+    pub planetoid: EndpointSnapshot,
+    /// Metrics for `BulkMetadata` requests.
+    pub bulk: EndpointSnapshot,
+    /// Metrics for `NodeData` requests.
+    pub node: EndpointSnapshot,
+    /// Errors across all endpoints, by kind, including cache read errors
+    /// that a fetch recovered from.
+    pub errors: ErrorCounts,
+}
+
+impl MetricsSnapshot {
+    /// Get the metrics for a single endpoint.
+    #[must_use]
+    pub fn endpoint(&self, endpoint: Endpoint) -> &EndpointSnapshot {
+        match endpoint {
+            Endpoint::Planetoid => &self.planetoid,
+            Endpoint::Bulk => &self.bulk,
+            Endpoint::Node => &self.node,
+        }
+    }
+
+    /// Total fetches requested across all endpoints.
+    #[must_use]
+    pub fn total_requests(&self) -> u64 {
+        Endpoint::ALL
+            .iter()
+            .map(|e| self.endpoint(*e).requests)
+            .sum()
+    }
+
+    /// Total bytes downloaded across all endpoints.
+    #[must_use]
+    pub fn total_bytes_downloaded(&self) -> u64 {
+        Endpoint::ALL
+            .iter()
+            .map(|e| self.endpoint(*e).bytes_downloaded)
+            .sum()
+    }
+}
+
+/// Metrics for a single endpoint.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EndpointSnapshot {
+    /// Fetches requested, whether served from the cache or the network.
+    pub requests: u64,
+    /// Fetches served from the cache.
+    pub cache_hits: u64,
+    /// Fetches that missed the cache and went to the network.
+    pub cache_misses: u64,
+    /// Response body bytes downloaded from the network.
+    pub bytes_downloaded: u64,
+    /// Fetches that failed, including decoding failures.
+    pub errors: u64,
+    /// Latency of successful network downloads.
+    pub latency: LatencyHistogram,
+}
+
+impl EndpointSnapshot {
+    /// The fraction of fetches served from the cache, or `None` if there were
+    /// no cache lookups.
+    #[must_use]
+    pub fn cache_hit_ratio(&self) -> Option<f64> {
+        let lookups = self.cache_hits + self.cache_misses;
+        #[allow(clippy::cast_precision_loss)]
+        (lookups > 0).then(|| self.cache_hits as f64 / lookups as f64)
+    }
+}
+
+/// A latency histogram with the fixed buckets from [`LATENCY_BUCKETS_MS`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    /// Sample counts per bucket. The final element is the overflow bucket.
+    pub counts: [u64; LATENCY_BUCKETS_MS.len() + 1],
+    /// Sum of all samples in microseconds.
+    pub sum_us: u64,
+}
+
+impl LatencyHistogram {
+    /// The number of recorded samples.
+    #[must_use]
+    pub fn count(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    /// The mean latency, or `None` if there are no samples.
+    #[must_use]
+    pub fn mean(&self) -> Option<Duration> {
+        let count = self.count();
+        (count > 0).then(|| Duration::from_micros(self.sum_us / count))
+    }
+
+    /// An upper bound for the latency at quantile `q` (between 0 and 1).
+    ///
+    /// Returns the upper bound of the bucket that contains the quantile, or
+    /// `None` if there are no samples or the quantile is in the overflow
+    /// bucket.
+    #[must_use]
+    pub fn quantile_upper_bound(&self, q: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let target = ((q.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (bound, bucket) in LATENCY_BUCKETS_MS.iter().zip(&self.counts) {
+            seen += bucket;
+            if seen >= target {
+                return Some(Duration::from_millis(*bound));
+            }
+        }
+        None
+    }
+}
+
+/// Error counts by [`ErrorKind`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorCounts([u64; ErrorKind::ALL.len()]);
+
+impl ErrorCounts {
+    /// The number of errors of the given kind.
+    #[must_use]
+    pub fn get(&self, kind: ErrorKind) -> u64 {
+        self.0[kind.index()]
+    }
+
+    /// The total number of errors.
+    #[must_use]
+    pub fn total(&self) -> u64 {
+        self.0.iter().sum()
+    }
+
+    /// Iterate over all kinds with their counts, including zero counts.
+    pub fn iter(&self) -> impl Iterator<Item = (ErrorKind, u64)> + '_ {
+        ErrorKind::ALL.iter().copied().zip(self.0.iter().copied())
+    }
+}
+
+/// Create the span emitted for a fetch when request spans are enabled.
+pub(crate) fn request_span(endpoint: Endpoint, path: &str) -> tracing::Span {
+    tracing::debug_span!(
+        "rocktree_fetch",
+        endpoint = endpoint.name(),
+        path,
+        cache_hit = tracing::field::Empty,
+        bytes = tracing::field::Empty,
+        status = tracing::field::Empty,
+        elapsed_ms = tracing::field::Empty,
+    )
+}
+
+#[derive(Debug, Default)]
+struct EndpointCounters {
+    requests: AtomicU64,
+    cache_hits: AtomicU64,
+    cache_misses: AtomicU64,
+    bytes_downloaded: AtomicU64,
+    errors: AtomicU64,
+    latency_counts: [AtomicU64; LATENCY_BUCKETS_MS.len() + 1],
+    latency_sum_us: AtomicU64,
+}
+
+impl EndpointCounters {
+    fn record_latency(&self, elapsed: Duration) {
+        let ms = u64::try_from(elapsed.as_millis()).unwrap_or(u64::MAX);
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|bound| ms <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.latency_counts[bucket].fetch_add(1, Ordering::Relaxed);
+        let us = u64::try_from(elapsed.as_micros()).unwrap_or(u64::MAX);
+        self.latency_sum_us.fetch_add(us, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> EndpointSnapshot {
+        EndpointSnapshot {
+            requests: self.requests.load(Ordering::Relaxed),
+            cache_hits: self.cache_hits.load(Ordering::Relaxed),
+            cache_misses: self.cache_misses.load(Ordering::Relaxed),
+            bytes_downloaded: self.bytes_downloaded.load(Ordering::Relaxed),
+            errors: self.errors.load(Ordering::Relaxed),
+            latency: LatencyHistogram {
+                counts: std::array::from_fn(|i| self.latency_counts[i].load(Ordering::Relaxed)),
+                sum_us: self.latency_sum_us.load(Ordering::Relaxed),
+            },
+        }
+    }
+
+    fn reset(&self) {
+        for counter in [
+            &self.requests,
+            &self.cache_hits,
+            &self.cache_misses,
+            &self.bytes_downloaded,
+            &self.errors,
+            &self.latency_sum_us,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        for counter in &self.latency_counts {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metrics_counters() {
+        let metrics = ClientMetrics::new();
+        metrics.record_request(Endpoint::Node);
+        metrics.record_cache_miss(Endpoint::Node);
+        metrics.record_download(Endpoint::Node, 1_000, Duration::from_millis(40));
+        metrics.record_request(Endpoint::Node);
+        metrics.record_cache_hit(Endpoint::Node);
+        metrics.record_request(Endpoint::Bulk);
+        metrics.record_error(
+            Endpoint::Bulk,
+            &Error::HttpStatus {
+                url: String::new(),
+                status: 404,
+            },
+        );
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.node.requests, 2);
+        assert_eq!(snapshot.node.cache_hits, 1);
+        assert_eq!(snapshot.node.cache_misses, 1);
+        assert_eq!(snapshot.node.bytes_downloaded, 1_000);
+        assert_eq!(snapshot.node.cache_hit_ratio(), Some(0.5));
+        assert_eq!(snapshot.bulk.errors, 1);
+        assert_eq!(snapshot.errors.get(ErrorKind::HttpStatus), 1);
+        assert_eq!(snapshot.errors.total(), 1);
+        assert_eq!(snapshot.total_requests(), 3);
+        assert_eq!(snapshot.planetoid, EndpointSnapshot::default());
+
+        metrics.reset();
+        assert_eq!(metrics.snapshot(), MetricsSnapshot::default());
+    }
+
+    #[test]
+    fn test_counter_indices() {
+        // Counters are indexed by discriminant, so `ALL` must list every
+        // variant in declaration order.
+        for (i, kind) in ErrorKind::ALL.iter().enumerate() {
+            assert_eq!(kind.index(), i, "{kind:?}");
+        }
+        for (i, endpoint) in Endpoint::ALL.iter().enumerate() {
+            assert_eq!(endpoint.index(), i, "{endpoint:?}");
+        }
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let metrics = ClientMetrics::new();
+        for ms in [5, 20, 20, 80, 20_000] {
+            metrics.record_download(Endpoint::Planetoid, 0, Duration::from_millis(ms));
+        }
+
+        let latency = metrics.snapshot().planetoid.latency;
+        assert_eq!(latency.count(), 5);
+        assert_eq!(latency.counts[0], 1);
+        assert_eq!(latency.counts[1], 2);
+        assert_eq!(latency.counts[3], 1);
+        assert_eq!(latency.counts[LATENCY_BUCKETS_MS.len()], 1);
+        assert_eq!(latency.mean(), Some(Duration::from_millis(4_025)));
+        assert_eq!(
+            latency.quantile_upper_bound(0.5),
+            Some(Duration::from_millis(25))
+        );
+        assert_eq!(latency.quantile_upper_bound(1.0), None);
+        assert_eq!(LatencyHistogram::default().mean(), None);
+    }
+}
@@ -0,0 +1,63 @@
+//! Policies for choosing between the cache and the network.
+
+use std::future::Future;
+use std::pin::Pin;
+
+/// How a fetch uses the cache and the network.
+///
+/// A client has a default policy, set with
+/// [`Client::with_fetch_policy`](crate::Client::with_fetch_policy), which
+/// individual requests can override with `with_fetch_policy` on
+/// [`BulkRequest`](crate::BulkRequest) and
+/// [`NodeRequest`](crate::NodeRequest).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum FetchPolicy {
+    /// Serve from the cache if possible, otherwise download the resource and
+    /// store it.
+    #[default]
+    CacheFirst,
+    /// Serve only from the cache, failing with
+    /// [`Error::NotCached`](crate::Error::NotCached) on a miss.
+    ///
+    /// The network is never used, which makes this the policy for offline
+    /// runs.
+    CacheOnly,
+    /// Always download the resource and store it, ignoring cached entries.
+    ///
+    /// Use this to refresh entries that may be out of date.
+    NetworkOnly,
+    /// Serve from the cache if possible and refresh the entry in the
+    /// background, otherwise download the resource like
+    /// [`FetchPolicy::CacheFirst`].
+    ///
+    /// Background refreshes need a spawner; see
+    /// [`Client::with_spawner`](crate::Client::with_spawner). Without one,
+    /// this behaves like [`FetchPolicy::CacheFirst`].
+    StaleWhileRevalidate,
+}
+
+impl FetchPolicy {
+    /// Whether this policy reads from the cache.
+    #[must_use]
+    pub fn reads_cache(self) -> bool {
+        self != FetchPolicy::NetworkOnly
+    }
+
+    /// Whether this policy may download resources.
+    #[must_use]
+    pub fn uses_network(self) -> bool {
+        self != FetchPolicy::CacheOnly
+    }
+}
+
+/// A background task handed to a client's spawner.
+#[cfg(not(target_family = "wasm"))]
+pub type BackgroundTask = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A background task handed to a client's spawner.
+// Browser fetch futures are not `Send`.
+#[cfg(target_family = "wasm")]
+pub type BackgroundTask = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Runs [`BackgroundTask`]s on the caller's executor.
+pub(crate) type Spawner = std::sync::Arc<dyn Fn(BackgroundTask) + Send + Sync>;
@@ -0,0 +1,956 @@
+//! Wire protocol and fetch logic shared by the async and blocking clients.
+//!
+//! This module builds request URLs for the rocktree API, decodes the
+//! protobuf responses into the high-level types from [`crate::types`], and
+//! implements fetching in [`Fetcher`]: cache lookups following a
+//! [`FetchPolicy`], downloads, checksums, metrics, and request spans. Only
+//! the HTTP [`Transport`] differs between client flavors, so every flavor
+//! produces identical results.
+
+use crate::cache::{Cache, CacheKey, DecodedNodeCache, checksum};
+use crate::cancel::CancellationToken;
+use crate::error::{Error, Result};
+use crate::metrics::{ClientMetrics, Endpoint, request_span};
+use crate::policy::{FetchPolicy, Spawner};
+use crate::types::{
+    BulkMetadata, BulkRequest, Mesh, Node, NodeMetadata, NodeRequest, Planetoid, TextureFormat,
+};
+use glam::{DMat4, Vec3};
+use prost::Message;
+use rocktree_decode::OrientedBoundingBox;
+use rocktree_proto as proto;
+use std::collections::HashSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tracing::Instrument;
+use web_time::Instant;
+
+/// Base URL for Google Earth's rocktree API.
+pub(crate) const BASE_URL: &str = "https://kh.google.com/rt/earth/";
+
+/// Build the URL for fetching planetoid metadata.
+pub(crate) fn planetoid_url(base_url: &str) -> String {
+    format!("{base_url}PlanetoidMetadata")
+}
+
+/// Build the URL for fetching bulk metadata.
+pub(crate) fn bulk_url(base_url: &str, request: &BulkRequest) -> String {
+    format!(
+        "{base_url}BulkMetadata/pb=!1m2!1s{}!2u{}",
+        request.path, request.epoch
+    )
+}
+
+/// Build the URL for fetching node data.
+pub(crate) fn node_url(base_url: &str, request: &NodeRequest) -> String {
+    if let Some(imagery_epoch) = request.imagery_epoch {
+        format!(
+            "{base_url}NodeData/pb=!1m2!1s{}!2u{}!2e{}!3u{}!4b0",
+            request.path, request.epoch, request.texture_format, imagery_epoch
+        )
+    } else {
+        format!(
+            "{base_url}NodeData/pb=!1m2!1s{}!2u{}!2e{}!4b0",
+            request.path, request.epoch, request.texture_format
+        )
+    }
+}
+
+/// Decode a `PlanetoidMetadata` response.
+pub(crate) fn decode_planetoid(data: &[u8]) -> Result<Planetoid> {
+    let proto = proto::PlanetoidMetadata::decode(data).map_err(|e| Error::Protobuf {
+        context: "planetoid metadata",
+        message: e.to_string(),
+    })?;
+
+    let root_epoch = proto
+        .root_node_metadata
+        .as_ref()
+        .map_or(0, |r| r.epoch.unwrap_or(0));
+
+    Ok(Planetoid {
+        radius: f64::from(proto.radius.unwrap_or(0.0)),
+        root_epoch,
+    })
+}
+
+/// Decode a `BulkMetadata` response for the bulk at `path`.
+pub(crate) fn decode_bulk(path: &str, data: &[u8]) -> Result<BulkMetadata> {
+    let proto = proto::BulkMetadata::decode(data).map_err(|e| Error::Protobuf {
+        context: "bulk metadata",
+        message: e.to_string(),
+    })?;
+
+    decode_bulk_metadata(path, &proto)
+}
+
+/// Decode a `NodeData` response for the node at `path`.
+pub(crate) fn decode_node(path: &str, data: &[u8]) -> Result<Node> {
+    let proto = proto::NodeData::decode(data).map_err(|e| Error::Protobuf {
+        context: "node data",
+        message: e.to_string(),
+    })?;
+
+    decode_node_data(path, &proto)
+}
+
+/// A GET request in flight, resolving to the status code and, for
+/// successful responses, the body.
+#[cfg(not(target_family = "wasm"))]
+pub(crate) type HttpFuture<'a> = Pin<Box<dyn Future<Output = Result<(u16, Vec<u8>)>> + Send + 'a>>;
+
+/// A GET request in flight, resolving to the status code and, for
+/// successful responses, the body.
+// Browser fetch futures are not `Send`.
+#[cfg(target_family = "wasm")]
+pub(crate) type HttpFuture<'a> = Pin<Box<dyn Future<Output = Result<(u16, Vec<u8>)>> + 'a>>;
+
+/// The HTTP client a [`Fetcher`] downloads with.
+pub(crate) trait Transport: Clone + Send + Sync + 'static {
+    /// Send a GET request for `url`.
+    fn get<'a>(&'a self, url: &'a str) -> HttpFuture<'a>;
+}
+
+impl Transport for reqwest::Client {
+    fn get<'a>(&'a self, url: &'a str) -> HttpFuture<'a> {
+        Box::pin(async move {
+            let response = reqwest::Client::get(self, url)
+                .send()
+                .await
+                .map_err(|e| http_error(url, &e))?;
+            let status = response.status();
+            if !status.is_success() {
+                return Ok((status.as_u16(), Vec::new()));
+            }
+            let data = response.bytes().await.map_err(|e| http_error(url, &e))?;
+            Ok((status.as_u16(), data.to_vec()))
+        })
+    }
+}
+
+/// Build the error for a request to `url` that failed in the transport.
+pub(crate) fn http_error(url: &str, error: &impl std::fmt::Display) -> Error {
+    Error::Http {
+        url: url.to_string(),
+        message: error.to_string(),
+    }
+}
+
+/// Where the bytes returned by a fetch came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Source {
+    Cache,
+    Network,
+}
+
+/// Fetch logic shared by the async and blocking clients.
+///
+/// The fetch methods are async. The blocking client drives them with
+/// `block_on`, which works because its transport completes without
+/// yielding.
+pub(crate) struct Fetcher<C: Cache, T: Transport> {
+    pub(crate) http: T,
+    pub(crate) cache: Arc<C>,
+    pub(crate) base_url: String,
+    pub(crate) metrics: Arc<ClientMetrics>,
+    pub(crate) request_spans: bool,
+    pub(crate) node_cache: Option<DecodedNodeCache>,
+    pub(crate) fetch_policy: FetchPolicy,
+    pub(crate) spawner: Option<Spawner>,
+    /// Keys whose background refresh is in flight, shared with the detached
+    /// fetchers that run the refreshes.
+    revalidating: Arc<Mutex<HashSet<CacheKey>>>,
+}
+
+impl<C: Cache + 'static, T: Transport> Fetcher<C, T> {
+    /// Create a fetcher with default settings.
+    pub(crate) fn new(http: T, cache: C) -> Self {
+        Self {
+            http,
+            cache: Arc::new(cache),
+            base_url: BASE_URL.to_string(),
+            metrics: Arc::default(),
+            request_spans: false,
+            node_cache: None,
+            fetch_policy: FetchPolicy::default(),
+            spawner: None,
+            revalidating: Arc::default(),
+        }
+    }
+
+    /// Build the URL for fetching planetoid metadata.
+    pub(crate) fn planetoid_url(&self) -> String {
+        planetoid_url(&self.base_url)
+    }
+
+    /// Build the URL for fetching bulk metadata.
+    pub(crate) fn bulk_url(&self, request: &BulkRequest) -> String {
+        bulk_url(&self.base_url, request)
+    }
+
+    /// Build the URL for fetching node data.
+    pub(crate) fn node_url(&self, request: &NodeRequest) -> String {
+        node_url(&self.base_url, request)
+    }
+
+    /// Fetch the root planetoid metadata with `policy`.
+    pub(crate) async fn fetch_planetoid(&self, policy: FetchPolicy) -> Result<Planetoid> {
+        self.fetch_decoded(
+            &CacheKey::Planetoid,
+            &self.planetoid_url(),
+            policy,
+            decode_planetoid,
+        )
+        .await
+    }
+
+    /// Fetch bulk metadata with the request's policy or the default one.
+    pub(crate) async fn fetch_bulk(&self, request: &BulkRequest) -> Result<BulkMetadata> {
+        self.fetch_decoded(
+            &CacheKey::bulk(request),
+            &self.bulk_url(request),
+            request.fetch_policy.unwrap_or(self.fetch_policy),
+            |data| decode_bulk(&request.path, data),
+        )
+        .await
+    }
+
+    /// Fetch node data with the request's policy or the default one.
+    pub(crate) async fn fetch_node(&self, request: &NodeRequest) -> Result<Node> {
+        self.fetch_decoded(
+            &CacheKey::node(request),
+            &self.node_url(request),
+            request.fetch_policy.unwrap_or(self.fetch_policy),
+            |data| decode_node(&request.path, data),
+        )
+        .await
+    }
+
+    /// Fetch node data as a shared node, using the decoded node cache.
+    pub(crate) async fn fetch_node_shared(&self, request: &NodeRequest) -> Result<Arc<Node>> {
+        if let Some(node) = self.cached_node(request) {
+            return Ok(node);
+        }
+        let node = Arc::new(self.fetch_node(request).await?);
+        self.store_node(request, &node);
+        Ok(node)
+    }
+
+    /// Fetch bulk metadata, giving up when `token` is cancelled.
+    pub(crate) async fn fetch_bulk_cancellable(
+        &self,
+        request: &BulkRequest,
+        token: &CancellationToken,
+    ) -> Result<BulkMetadata> {
+        let result = token.run(self.fetch_bulk(request)).await;
+        if let Err(e @ Error::Cancelled) = &result {
+            self.metrics.record_error(Endpoint::Bulk, e);
+        }
+        result
+    }
+
+    /// Fetch node data, giving up when `token` is cancelled.
+    pub(crate) async fn fetch_node_cancellable(
+        &self,
+        request: &NodeRequest,
+        token: &CancellationToken,
+    ) -> Result<Node> {
+        let result = token.run(self.fetch_node(request)).await;
+        if let Err(e @ Error::Cancelled) = &result {
+            self.metrics.record_error(Endpoint::Node, e);
+        }
+        result
+    }
+
+    /// Fetch node data as a shared node, giving up when `token` is
+    /// cancelled.
+    pub(crate) async fn fetch_node_shared_cancellable(
+        &self,
+        request: &NodeRequest,
+        token: &CancellationToken,
+    ) -> Result<Arc<Node>> {
+        if let Some(node) = self.cached_node(request) {
+            return Ok(node);
+        }
+        let node = Arc::new(self.fetch_node_cancellable(request, token).await?);
+        self.store_node(request, &node);
+        Ok(node)
+    }
+
+    /// Fetch raw bytes from a URL with the default policy, recording an
+    /// error if the URL is a rocktree request.
+    pub(crate) async fn fetch_bytes_from_url(&self, url: &str) -> Result<Vec<u8>> {
+        let key = CacheKey::from_url(url);
+        let result = self
+            .fetch_bytes(key.as_ref(), url, self.fetch_policy)
+            .await
+            .map(|(data, _)| data);
+        if let (Some(key), Err(e)) = (&key, &result) {
+            self.metrics.record_error(key.endpoint(), e);
+        }
+        result
+    }
+
+    /// Look up a node in the decoded node cache, recording a hit.
+    pub(crate) fn cached_node(&self, request: &NodeRequest) -> Option<Arc<Node>> {
+        let policy = request.fetch_policy.unwrap_or(self.fetch_policy);
+        if !policy.reads_cache() {
+            return None;
+        }
+        let node = self.node_cache.as_ref()?.get(request)?;
+        tracing::debug!(path = %request.path, "decoded node cache hit");
+        self.metrics.record_request(Endpoint::Node);
+        self.metrics.record_cache_hit(Endpoint::Node);
+        Some(node)
+    }
+
+    /// Store a node in the decoded node cache, if there is one.
+    pub(crate) fn store_node(&self, request: &NodeRequest, node: &Arc<Node>) {
+        if let Some(cache) = &self.node_cache {
+            cache.insert(request, Arc::clone(node));
+        }
+    }
+
+    /// Fetch and decode a resource, recording metrics and the request span.
+    ///
+    /// If a cached entry fails to decode, it is evicted and, if the policy
+    /// allows, the resource is fetched from the network once more before the
+    /// error is returned.
+    pub(crate) async fn fetch_decoded<V>(
+        &self,
+        key: &CacheKey,
+        url: &str,
+        policy: FetchPolicy,
+        decode: impl Fn(&[u8]) -> Result<V>,
+    ) -> Result<V> {
+        let endpoint = key.endpoint();
+        let span = if self.request_spans {
+            request_span(endpoint, key.path())
+        } else {
+            tracing::Span::none()
+        };
+
+        let result = async {
+            let (data, source) = self.fetch_bytes(Some(key), url, policy).await?;
+            match decode(&data) {
+                Err(e) if source == Source::Cache => {
+                    tracing::warn!(%key, error = %e, "evicting undecodable cache entry");
+                    self.cache.remove(key).await?;
+                    if !policy.uses_network() {
+                        return Err(e);
+                    }
+                    self.metrics.record_cache_miss(endpoint);
+                    let data = self.download(Some(key), url).await?;
+                    decode(&data)
+                }
+                result => result,
+            }
+        }
+        .instrument(span)
+        .await;
+
+        if let Err(e) = &result {
+            self.metrics.record_error(endpoint, e);
+        }
+        result
+    }
+
+    /// Fetch raw bytes from a URL following `policy`, using the cache entry
+    /// for `key` if given.
+    pub(crate) async fn fetch_bytes(
+        &self,
+        key: Option<&CacheKey>,
+        url: &str,
+        policy: FetchPolicy,
+    ) -> Result<(Vec<u8>, Source)> {
+        let span = tracing::Span::current();
+        let endpoint = key.map(CacheKey::endpoint);
+        if let Some(endpoint) = endpoint {
+            self.metrics.record_request(endpoint);
+        }
+
+        // Check cache first.
+        let cached = match key {
+            Some(key) if policy.reads_cache() => self.cached_bytes(key).await?,
+            _ => None,
+        };
+        if let Some(data) = cached {
+            tracing::debug!(url, "cache hit");
+            if let Some(endpoint) = endpoint {
+                self.metrics.record_cache_hit(endpoint);
+            }
+            span.record("cache_hit", true);
+            if let (FetchPolicy::StaleWhileRevalidate, Some(key)) = (policy, key) {
+                self.revalidate(key, url);
+            }
+            return Ok((data, Source::Cache));
+        }
+
+        if let Some(endpoint) = endpoint {
+            self.metrics.record_cache_miss(endpoint);
+        }
+        span.record("cache_hit", false);
+        if !policy.uses_network() {
+            return Err(Error::NotCached {
+                url: url.to_string(),
+            });
+        }
+
+        tracing::debug!(url, "fetching");
+        let data = self.download(key, url).await?;
+        Ok((data, Source::Network))
+    }
+
+    /// Read and verify a cache entry, evicting it if its checksum is wrong.
+    /// Read errors are logged and treated as a miss.
+    async fn cached_bytes(&self, key: &CacheKey) -> Result<Option<Vec<u8>>> {
+        let entry = match self.cache.get(key).await {
+            Ok(Some(entry)) => entry,
+            Ok(None) => return Ok(None),
+            Err(e) => {
+                tracing::warn!(%key, error = %e, "cache read failed, treating as a miss");
+                self.metrics.record_cache_error(&e);
+                return Ok(None);
+            }
+        };
+        if let Some(data) = checksum::unseal(entry) {
+            return Ok(Some(data));
+        }
+        tracing::warn!(%key, "evicting cache entry with bad checksum");
+        self.cache.remove(key).await?;
+        Ok(None)
+    }
+
+    /// Refresh the cache entry for `key` in the background, if there is a
+    /// spawner and no refresh of the entry is already in flight.
+    fn revalidate(&self, key: &CacheKey, url: &str) {
+        let Some(spawner) = &self.spawner else {
+            return;
+        };
+        if !self.revalidating.lock().unwrap().insert(key.clone()) {
+            tracing::debug!(url, "revalidation already in flight");
+            return;
+        }
+        let guard = RevalidationGuard {
+            revalidating: Arc::clone(&self.revalidating),
+            key: key.clone(),
+        };
+        let fetcher = self.detached();
+        let url = url.to_string();
+        spawner(Box::pin(async move {
+            tracing::debug!(url, "revalidating");
+            if let Err(e) = fetcher.download(Some(&guard.key), &url).await {
+                tracing::warn!(key = %guard.key, error = %e, "failed to revalidate cache entry");
+            }
+        }));
+    }
+
+    /// Create a fetcher sharing this fetcher's HTTP connections, cache, and
+    /// metrics, for use by background tasks.
+    fn detached(&self) -> Self {
+        Self {
+            http: self.http.clone(),
+            cache: Arc::clone(&self.cache),
+            base_url: self.base_url.clone(),
+            metrics: Arc::clone(&self.metrics),
+            request_spans: self.request_spans,
+            node_cache: self.node_cache.clone(),
+            fetch_policy: self.fetch_policy,
+            spawner: None,
+            revalidating: Arc::clone(&self.revalidating),
+        }
+    }
+
+    /// Download a URL, storing the response under `key` if given.
+    async fn download(&self, key: Option<&CacheKey>, url: &str) -> Result<Vec<u8>> {
+        let span = tracing::Span::current();
+        let endpoint = key.map(CacheKey::endpoint);
+        let start = Instant::now();
+
+        let (status, data) = self.http.get(url).await?;
+        span.record("status", status);
+        if !(200..300).contains(&status) {
+            return Err(Error::HttpStatus {
+                url: url.to_string(),
+                status,
+            });
+        }
+
+        let elapsed = start.elapsed();
+        if let Some(endpoint) = endpoint {
+            self.metrics.record_download(endpoint, data.len(), elapsed);
+        }
+        span.record("bytes", data.len());
+        span.record("elapsed_ms", elapsed.as_secs_f64() * 1000.0);
+
+        // Store in cache.
+        if let Some(key) = key {
+            self.cache.put(key, checksum::seal(&data)).await?;
+        }
+
+        Ok(data)
+    }
+}
+
+/// Marks a key as no longer being revalidated when its refresh finishes,
+/// or when the spawner drops the task without running it.
+struct RevalidationGuard {
+    revalidating: Arc<Mutex<HashSet<CacheKey>>>,
+    key: CacheKey,
+}
+
+impl Drop for RevalidationGuard {
+    fn drop(&mut self) {
+        // Panicking here while unwinding would abort.
+        if let Ok(mut revalidating) = self.revalidating.lock() {
+            revalidating.remove(&self.key);
+        }
+    }
+}
+
+/// Decode bulk metadata from protobuf.
+fn decode_bulk_metadata(base_path: &str, proto: &proto::BulkMetadata) -> Result<BulkMetadata> {
+    // head_node_center is Vec<f64>, convert to Vec3.
+    let head_node_center = if proto.head_node_center.len() >= 3 {
+        #[allow(clippy::cast_possible_truncation)]
+        Vec3::new(
+            proto.head_node_center[0] as f32,
+            proto.head_node_center[1] as f32,
+            proto.head_node_center[2] as f32,
+        )
+    } else {
+        Vec3::ZERO
+    };
+
+    let head_epoch = proto
+        .head_node_key
+        .as_ref()
+        .and_then(|k| k.epoch)
+        .unwrap_or(0);
+
+    let meters_per_texel: Vec<f32> = proto.meters_per_texel.clone();
+    #[allow(clippy::cast_possible_wrap)]
+    let default_texture_format = proto.default_available_texture_formats.unwrap_or(0) as i32;
+    let default_imagery_epoch = proto.default_imagery_epoch;
+
+    let mut nodes = Vec::new();
+    let mut child_bulk_paths = std::collections::HashMap::new();
+
+    for node_meta in &proto.node_metadata {
+        let path_and_flags = node_meta.path_and_flags.unwrap_or(0);
+        let pf = rocktree_decode::unpack_path_and_flags(path_and_flags);
+
+        let full_path = format!("{base_path}{}", pf.path);
+
+        let has_data = (pf.flags & NodeMetadata::FLAG_NO_DATA) == 0;
+        let is_leaf = (pf.flags & NodeMetadata::FLAG_LEAF) != 0;
+        let use_imagery_epoch = (pf.flags & NodeMetadata::FLAG_USE_IMAGERY_EPOCH) != 0;
+
+        // Check for child bulk (4-char paths that aren't leaves).
+        if pf.path.len() == 4 && !is_leaf {
+            let epoch = node_meta.bulk_metadata_epoch.unwrap_or(head_epoch);
+            child_bulk_paths.insert(pf.path.clone(), epoch);
+        }
+
+        // Skip nodes without OBB if they have data or aren't leaves.
+        let has_obb = node_meta.oriented_bounding_box.is_some();
+        if (has_data || !is_leaf) && !has_obb {
+            continue;
+        }
+
+        if (has_data || !is_leaf) && has_obb {
+            let meters_per_texel_value = node_meta.meters_per_texel.unwrap_or_else(|| {
+                if pf.level > 0 && (pf.level - 1) < meters_per_texel.len() {
+                    meters_per_texel[pf.level - 1]
+                } else {
+                    1.0
+                }
+            });
+
+            let obb_data = node_meta.oriented_bounding_box.as_ref().unwrap();
+            let obb =
+                rocktree_decode::unpack_obb(obb_data, head_node_center, meters_per_texel_value)?;
+
+            let epoch = node_meta.epoch.unwrap_or(head_epoch);
+
+            #[allow(clippy::cast_possible_wrap)]
+            let texture_format = node_meta
+                .available_texture_formats
+                .map_or(default_texture_format, |f| f as i32);
+
+            let imagery_epoch = if use_imagery_epoch {
+                node_meta.imagery_epoch.or(default_imagery_epoch)
+            } else {
+                None
+            };
+
+            nodes.push(NodeMetadata {
+                path: full_path,
+                meters_per_texel: meters_per_texel_value,
+                obb,
+                has_data,
+                flags: pf.flags,
+                epoch,
+                texture_format: select_texture_format(texture_format),
+                imagery_epoch,
+            });
+        }
+    }
+
+    Ok(BulkMetadata {
+        path: base_path.to_string(),
+        head_node_center,
+        meters_per_texel,
+        nodes,
+        child_bulk_paths,
+        epoch: head_epoch,
+    })
+}
+
+/// Decode node data from protobuf.
+fn decode_node_data(path: &str, proto: &proto::NodeData) -> Result<Node> {
+    let matrix_data: &[f64] = &proto.matrix_globe_from_mesh;
+    let matrix_globe_from_mesh = if matrix_data.len() == 16 {
+        DMat4::from_cols_array(matrix_data.try_into().unwrap_or(&[0.0; 16]))
+    } else {
+        DMat4::IDENTITY
+    };
+
+    let mut meshes = Vec::new();
+
+    for mesh_proto in &proto.meshes {
+        let mesh = decode_mesh(mesh_proto)?;
+        meshes.push(mesh);
+    }
+
+    // Get OBB from first mesh if available (or create a default).
+    let obb = OrientedBoundingBox {
+        center: glam::DVec3::ZERO,
+        extents: glam::DVec3::ONE,
+        orientation: glam::DMat3::IDENTITY,
+    };
+
+    Ok(Node {
+        path: path.to_string(),
+        matrix_globe_from_mesh,
+        meters_per_texel: 1.0, // Will be set from metadata.
+        obb,
+        meshes,
+    })
+}
+
+/// Decode a mesh from protobuf.
+fn decode_mesh(proto: &proto::Mesh) -> Result<Mesh> {
+    // Unpack vertices.
+    let vertices_data = proto.vertices.as_deref().unwrap_or(&[]);
+    let mut vertices = rocktree_decode::unpack_vertices(vertices_data)?;
+
+    // Unpack indices.
+    let indices_data = proto.indices.as_deref().unwrap_or(&[]);
+    let indices = rocktree_decode::unpack_indices(indices_data)?;
+
+    // Unpack texture coordinates.
+    let texcoords_data = proto.texture_coordinates.as_deref().unwrap_or(&[]);
+    let uv_transform = if !texcoords_data.is_empty() && !vertices.is_empty() {
+        rocktree_decode::unpack_tex_coords(texcoords_data, &mut vertices)?
+    } else {
+        rocktree_decode::UvTransform::default()
+    };
+
+    // Apply explicit UV offset/scale if provided.
+    let uv_transform = if proto.uv_offset_and_scale.len() == 4 {
+        rocktree_decode::UvTransform {
+            offset: glam::Vec2::new(proto.uv_offset_and_scale[0], proto.uv_offset_and_scale[1]),
+            scale: glam::Vec2::new(proto.uv_offset_and_scale[2], proto.uv_offset_and_scale[3]),
+        }
+    } else {
+        // Flip V coordinate.
+        rocktree_decode::UvTransform {
+            offset: glam::Vec2::new(
+                uv_transform.offset.x,
+                uv_transform.offset.y - 1.0 / uv_transform.scale.y,
+            ),
+            scale: glam::Vec2::new(uv_transform.scale.x, -uv_transform.scale.y),
+        }
+    };
+
+    // Unpack octant masks and get layer bounds.
+    let octant_data = proto.layer_and_octant_counts.as_deref().unwrap_or(&[]);
+    let has_octant_data = !octant_data.is_empty() && !indices.is_empty() && !vertices.is_empty();
+    let layer_bounds = if has_octant_data {
+        rocktree_decode::unpack_octant_mask_and_layer_bounds(octant_data, &indices, &mut vertices)?
+    } else {
+        [indices.len(); 10]
+    };
+
+    // Truncate indices to layer 3 bound (visible geometry).
+    let visible_index_count = layer_bounds[3].min(indices.len());
+    let indices: Vec<u16> = indices.into_iter().take(visible_index_count).collect();
+
+    // Without octant data there are no layer bounds, so treat all geometry
+    // as overground.
+    let layer_bounds = if has_octant_data {
+        [
+            layer_bounds[0].min(visible_index_count),
+            layer_bounds[1].min(visible_index_count),
+            layer_bounds[2].min(visible_index_count),
+            visible_index_count,
+        ]
+    } else {
+        [
+            0,
+            visible_index_count,
+            visible_index_count,
+            visible_index_count,
+        ]
+    };
+
+    // Decode texture.
+    let (texture_data, texture_format, texture_width, texture_height) = decode_texture(proto)?;
+
+    Ok(Mesh {
+        vertices,
+        indices,
+        uv_transform,
+        texture_data,
+        texture_format,
+        texture_width,
+        texture_height,
+        has_octant_data,
+        layer_bounds,
+    })
+}
+
+/// Decode texture data from a mesh.
+fn decode_texture(mesh: &proto::Mesh) -> Result<(Vec<u8>, TextureFormat, u32, u32)> {
+    let textures = &mesh.texture;
+    if textures.is_empty() {
+        return Err(Error::InvalidData {
+            context: "mesh texture",
+            detail: "no textures found".to_string(),
+        });
+    }
+
+    let texture = &textures[0];
+    if texture.data.is_empty() {
+        return Err(Error::InvalidData {
+            context: "mesh texture",
+            detail: "no texture data found".to_string(),
+        });
+    }
+
+    let tex_data = &texture.data[0];
+    let format = texture.format.unwrap_or(proto::texture::Format::Jpg as i32);
+    match format {
+        f if f == proto::texture::Format::Jpg as i32 => {
+            let decoded = rocktree_decode::texture::decode_jpeg_to_rgba(tex_data)?;
+            // Return as RGBA since we fully decode JPEG.
+            Ok((
+                decoded.data,
+                TextureFormat::Rgba,
+                decoded.width,
+                decoded.height,
+            ))
+        }
+        f if f == proto::texture::Format::CrnDxt1 as i32 => {
+            let decoded = rocktree_decode::texture::decode_crn_to_rgba(tex_data)?;
+            // Return as RGBA since we fully decode CRN.
+            Ok((
+                decoded.data,
+                TextureFormat::Rgba,
+                decoded.width,
+                decoded.height,
+            ))
+        }
+        other => Err(Error::InvalidData {
+            context: "texture format",
+            detail: format!("unsupported format: {other}"),
+        }),
+    }
+}
+
+/// Select the best texture format from available formats bitmask.
+fn select_texture_format(available: i32) -> i32 {
+    // Preference order: CRN_DXT1 (6), JPG (1).
+    const CRN_DXT1: i32 = 6;
+    const JPG: i32 = 1;
+
+    let supported = [CRN_DXT1, JPG];
+
+    for format in supported {
+        // Format availability is encoded as (1 << (format - 1)).
+        if available & (1 << (format - 1)) != 0 {
+            return format;
+        }
+    }
+
+    // Default to first supported.
+    supported[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cache::{MemoryCache, block_on};
+    use crate::policy::BackgroundTask;
+    use crate::test_util::planetoid_body;
+    use crate::types::MeshLayer;
+
+    /// A transport that answers every request with the same body.
+    #[derive(Clone)]
+    struct StaticTransport(Vec<u8>);
+
+    impl Transport for StaticTransport {
+        fn get<'a>(&'a self, _url: &'a str) -> HttpFuture<'a> {
+            let body = self.0.clone();
+            Box::pin(async move { Ok((200, body)) })
+        }
+    }
+
+    #[test]
+    fn test_select_texture_format_prefers_crn() {
+        // Both available.
+        let both = (1 << (6 - 1)) | (1 << (1 - 1));
+        assert_eq!(select_texture_format(both), 6);
+    }
+
+    #[test]
+    fn test_select_texture_format_jpg_only() {
+        let jpg_only = 1 << (1 - 1);
+        assert_eq!(select_texture_format(jpg_only), 1);
+    }
+
+    #[test]
+    fn test_select_texture_format_none_available() {
+        // Returns default (CRN_DXT1).
+        assert_eq!(select_texture_format(0), 6);
+    }
+
+    #[test]
+    fn test_node_url_with_imagery_epoch() {
+        let request = NodeRequest::new("0231".to_string(), 12, 6, Some(34));
+        assert_eq!(
+            node_url(BASE_URL, &request),
+            "https://kh.google.com/rt/earth/NodeData/pb=!1m2!1s0231!2u12!2e6!3u34!4b0"
+        );
+    }
+
+    #[test]
+    fn test_node_url_without_imagery_epoch() {
+        let request = NodeRequest::new("0231".to_string(), 12, 1, None);
+        assert_eq!(
+            node_url(BASE_URL, &request),
+            "https://kh.google.com/rt/earth/NodeData/pb=!1m2!1s0231!2u12!2e1!4b0"
+        );
+    }
+
+    /// A triangle strip over three vertices, `strip_len` indices long.
+    fn packed_strip(strip_len: u8) -> Vec<u8> {
+        // Each index is encoded as the number of distinct indices seen so
+        // far minus the index, so the first three are new.
+        let mut packed = vec![strip_len, 0, 0, 0];
+        packed.extend((3..strip_len).map(|i| 3 - i % 3));
+        packed
+    }
+
+    /// A mesh with a strip of `strip_len` indices over three vertices, a
+    /// one-pixel JPEG texture, and the given layer and octant counts.
+    fn mesh_proto(strip_len: u8, layer_and_octant_counts: Option<Vec<u8>>) -> proto::Mesh {
+        let mut jpeg = Vec::new();
+        image::RgbImage::new(1, 1)
+            .write_to(
+                &mut std::io::Cursor::new(&mut jpeg),
+                image::ImageFormat::Jpeg,
+            )
+            .unwrap();
+        proto::Mesh {
+            vertices: Some(vec![0, 1, 1, 0, 0, 1, 0, 0, 0]),
+            indices: Some(packed_strip(strip_len)),
+            layer_and_octant_counts,
+            texture: vec![proto::Texture {
+                data: vec![jpeg],
+                format: Some(proto::texture::Format::Jpg as i32),
+                width: Some(1),
+                height: Some(1),
+                ..Default::default()
+            }],
+            uv_offset_and_scale: vec![0.0, 0.0, 1.0, 1.0],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_decode_mesh_layer_ranges() {
+        // Eight octant counts per layer: three indices in the first octant
+        // of each visible layer, and three more in the hidden fourth layer.
+        let mut counts = vec![25];
+        for _ in 0..3 {
+            counts.push(3);
+            counts.extend([0; 7]);
+        }
+        counts.push(3);
+        let mesh = decode_mesh(&mesh_proto(12, Some(counts))).unwrap();
+
+        assert!(mesh.has_octant_data);
+        assert_eq!(mesh.indices.len(), 9);
+        assert_eq!(mesh.layer_bounds, [0, 3, 6, 9]);
+        assert_eq!(mesh.layer_range(MeshLayer::Overground), 0..3);
+        assert_eq!(mesh.layer_range(MeshLayer::TerrainBelowWater), 3..6);
+        assert_eq!(mesh.layer_range(MeshLayer::TerrainAboveWater), 6..9);
+    }
+
+    #[test]
+    fn test_decode_mesh_without_octant_data_is_overground() {
+        let mesh = decode_mesh(&mesh_proto(6, None)).unwrap();
+
+        assert!(!mesh.has_octant_data);
+        assert_eq!(mesh.indices.len(), 6);
+        assert_eq!(mesh.layer_range(MeshLayer::Overground), 0..6);
+        assert!(mesh.layer_range(MeshLayer::TerrainBelowWater).is_empty());
+        assert!(mesh.layer_range(MeshLayer::TerrainAboveWater).is_empty());
+    }
+
+    #[test]
+    fn test_decode_planetoid_round_trip() {
+        let proto = proto::PlanetoidMetadata {
+            root_node_metadata: Some(proto::NodeMetadata {
+                epoch: Some(42),
+                ..Default::default()
+            }),
+            radius: Some(6_371_010.0),
+            ..Default::default()
+        };
+        let planetoid = decode_planetoid(&proto.encode_to_vec()).unwrap();
+        assert_eq!(planetoid.root_epoch, 42);
+        assert!((planetoid.radius - 6_371_010.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_revalidations_coalesced_per_key() {
+        let cache = MemoryCache::new();
+        block_on(cache.put(&CacheKey::Planetoid, checksum::seal(&planetoid_body()))).unwrap();
+        let tasks: Arc<Mutex<Vec<BackgroundTask>>> = Arc::default();
+        let mut fetcher = Fetcher::new(StaticTransport(planetoid_body()), cache);
+        fetcher.spawner = Some(Arc::new({
+            let tasks = Arc::clone(&tasks);
+            move |task| tasks.lock().unwrap().push(task)
+        }));
+        let fetch = || block_on(fetcher.fetch_planetoid(FetchPolicy::StaleWhileRevalidate));
+
+        // Hits while a refresh is pending do not spawn another.
+        fetch().unwrap();
+        fetch().unwrap();
+        assert_eq!(tasks.lock().unwrap().len(), 1);
+
+        let task = tasks.lock().unwrap().pop().unwrap();
+        block_on(task);
+        fetch().unwrap();
+        assert_eq!(tasks.lock().unwrap().len(), 1);
+
+        // A dropped task does not block later refreshes either.
+        tasks.lock().unwrap().clear();
+        fetch().unwrap();
+        assert_eq!(tasks.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,646 @@
+//! Priority-ordered scheduling of bulk and node fetches.
+//!
+//! A [`Scheduler`] holds a queue of pending [`FetchRequest`]s, each with a
+//! [`Priority`], and releases the most important ones as the concurrency limit
+//! allows. It does not spawn anything itself: [`Scheduler::dispatch`] returns
+//! [`Dispatch`] futures that the caller runs on its executor of choice, and the
+//! resulting [`Completion`]s are handed back through [`Scheduler::complete`].
+//! A request whose `Dispatch` or `Completion` is dropped instead gives up its
+//! slot, and can be submitted again.
+//!
+//! The intended use is frame-based. Each frame, the caller computes the set of
+//! requests it currently wants along with their priorities, passes them to
+//! [`Scheduler::sync`], and dispatches. Requests that are no longer wanted are
+//! dropped from the queue, and their in-flight fetches are cancelled.
+
+use crate::cache::Cache;
+use crate::cancel::CancellationToken;
+use crate::client::Client;
+use crate::error::Result;
+use crate::types::{BulkMetadata, BulkRequest, LodMetrics, Node, NodeRequest};
+use glam::DVec3;
+use std::cmp::{Ordering, Reverse};
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Weak};
+use std::task::{Context, Poll};
+
+/// The importance of a scheduled request.
+///
+/// Requests are ordered by descending screen-space error, then by ascending
+/// distance to the camera, then by ascending octree level. Higher priorities
+/// are dispatched first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Priority {
+    /// Screen-space error of the node in pixels.
+    pub screen_space_error: f64,
+    /// Distance from the camera to the node center in meters.
+    pub distance: f64,
+    /// Octree level of the node (the length of its path).
+    pub level: usize,
+}
+
+impl Priority {
+    /// Compute the priority of a node from the current LOD metrics.
+    #[must_use]
+    pub fn from_lod(
+        metrics: &LodMetrics,
+        node_center: DVec3,
+        meters_per_texel: f32,
+        level: usize,
+    ) -> Self {
+        Self {
+            screen_space_error: metrics.screen_space_error(node_center, meters_per_texel),
+            distance: metrics.camera_position.distance(node_center),
+            level,
+        }
+    }
+}
+
+impl Eq for Priority {}
+
+impl PartialOrd for Priority {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Priority {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.screen_space_error
+            .total_cmp(&other.screen_space_error)
+            .then_with(|| other.distance.total_cmp(&self.distance))
+            .then_with(|| other.level.cmp(&self.level))
+    }
+}
+
+/// The kind of a scheduled request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RequestKind {
+    /// A bulk metadata request.
+    Bulk,
+    /// A node data request.
+    Node,
+}
+
+/// Identifies a scheduled request: its kind and octant path.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct RequestKey {
+    /// The kind of request.
+    pub kind: RequestKind,
+    /// The full octant path.
+    pub path: String,
+}
+
+impl RequestKey {
+    /// Create a key for a bulk request.
+    #[must_use]
+    pub fn bulk(path: impl Into<String>) -> Self {
+        Self {
+            kind: RequestKind::Bulk,
+            path: path.into(),
+        }
+    }
+
+    /// Create a key for a node request.
+    #[must_use]
+    pub fn node(path: impl Into<String>) -> Self {
+        Self {
+            kind: RequestKind::Node,
+            path: path.into(),
+        }
+    }
+}
+
+/// A request accepted by the [`Scheduler`].
+#[derive(Debug, Clone)]
+pub enum FetchRequest {
+    /// Fetch bulk metadata.
+    Bulk(BulkRequest),
+    /// Fetch node data.
+    Node(NodeRequest),
+}
+
+impl FetchRequest {
+    /// Get the key that identifies this request.
+    #[must_use]
+    pub fn key(&self) -> RequestKey {
+        match self {
+            FetchRequest::Bulk(request) => RequestKey::bulk(request.path.clone()),
+            FetchRequest::Node(request) => RequestKey::node(request.path.clone()),
+        }
+    }
+}
+
+/// The result of a dispatched fetch.
+#[derive(Debug)]
+pub enum FetchOutcome {
+    /// The result of a bulk metadata fetch.
+    Bulk(Result<BulkMetadata>),
+    /// The result of a node data fetch.
+    ///
+    /// Nodes are shared so that they can come from the client's
+    /// [`DecodedNodeCache`](crate::DecodedNodeCache).
+    Node(Result<Arc<Node>>),
+}
+
+/// A finished fetch, to be passed back to [`Scheduler::complete`].
+#[derive(Debug)]
+pub struct Completion {
+    key: RequestKey,
+    id: u64,
+    outcome: FetchOutcome,
+    // Keeps the request in flight until the completion is accepted or dropped.
+    _lease: Arc<()>,
+}
+
+impl Completion {
+    /// Get the key of the request that finished.
+    #[must_use]
+    pub fn key(&self) -> &RequestKey {
+        &self.key
+    }
+}
+
+/// A fetch released by the scheduler, ready to be spawned.
+///
+/// The future resolves to a [`Completion`] when the fetch finishes or is
+/// cancelled. It is `Send` on native targets so that it can be spawned on a
+/// multi-threaded executor. Dropping it, or the [`Completion`], abandons the
+/// request and frees its slot.
+#[must_use = "futures do nothing unless polled"]
+pub struct Dispatch {
+    key: RequestKey,
+    future: DispatchFuture,
+}
+
+impl Dispatch {
+    /// Get the key of the dispatched request.
+    #[must_use]
+    pub fn key(&self) -> &RequestKey {
+        &self.key
+    }
+}
+
+impl Future for Dispatch {
+    type Output = Completion;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Completion> {
+        self.future.as_mut().poll(cx)
+    }
+}
+
+/// A priority queue of fetches with a concurrency limit per request kind.
+///
+/// Each request key is either queued, in flight, or unknown to the scheduler.
+/// Submitting a key that is already in flight has no effect, so a request is
+/// never fetched twice at once.
+#[derive(Debug)]
+pub struct Scheduler {
+    queued: HashMap<RequestKey, (FetchRequest, Priority)>,
+    in_flight: HashMap<RequestKey, InFlight>,
+    max_bulks: usize,
+    max_nodes: usize,
+    next_id: u64,
+}
+
+impl Scheduler {
+    /// Create a scheduler with at most `max_bulks` bulk fetches and
+    /// `max_nodes` node fetches in flight at once.
+    #[must_use]
+    pub fn new(max_bulks: usize, max_nodes: usize) -> Self {
+        Self {
+            queued: HashMap::new(),
+            in_flight: HashMap::new(),
+            max_bulks,
+            max_nodes,
+            next_id: 0,
+        }
+    }
+
+    /// Queue a request, or update its priority if it is already queued.
+    ///
+    /// Requests that are already in flight are ignored.
+    pub fn submit(&mut self, request: FetchRequest, priority: Priority) {
+        self.expire_abandoned();
+        let key = request.key();
+        if !self.in_flight.contains_key(&key) {
+            self.queued.insert(key, (request, priority));
+        }
+    }
+
+    /// Change the priority of a queued request.
+    ///
+    /// Returns `false` if the request is not queued.
+    pub fn reprioritize(&mut self, key: &RequestKey, priority: Priority) -> bool {
+        match self.queued.get_mut(key) {
+            Some((_, p)) => {
+                *p = priority;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Remove a request from the queue, or cancel it if it is in flight.
+    ///
+    /// Returns `false` if the scheduler did not know the request.
+    pub fn cancel(&mut self, key: &RequestKey) -> bool {
+        if self.queued.remove(key).is_some() {
+            return true;
+        }
+        match self.in_flight.remove(key) {
+            Some(in_flight) => {
+                in_flight.token.cancel();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Keep only the requests for which `keep` returns `true`.
+    ///
+    /// Dropped queued requests are forgotten, and dropped in-flight requests
+    /// are cancelled.
+    pub fn retain(&mut self, mut keep: impl FnMut(&RequestKey) -> bool) {
+        self.expire_abandoned();
+        self.queued.retain(|key, _| keep(key));
+        self.in_flight.retain(|key, in_flight| {
+            let kept = keep(key);
+            if !kept {
+                in_flight.token.cancel();
+            }
+            kept
+        });
+    }
+
+    /// Replace the wanted set of requests.
+    ///
+    /// Every request in `wanted` is submitted with its new priority. Queued and
+    /// in-flight requests that are not in `wanted` are dropped and cancelled,
+    /// respectively.
+    pub fn sync(&mut self, wanted: impl IntoIterator<Item = (FetchRequest, Priority)>) {
+        let wanted: HashMap<RequestKey, (FetchRequest, Priority)> = wanted
+            .into_iter()
+            .map(|(request, priority)| (request.key(), (request, priority)))
+            .collect();
+        self.retain(|key| wanted.contains_key(key));
+        for (key, entry) in wanted {
+            if !self.in_flight.contains_key(&key) {
+                self.queued.insert(key, entry);
+            }
+        }
+    }
+
+    /// Release the highest-priority queued requests that fit within the
+    /// concurrency limits.
+    ///
+    /// The returned futures perform the fetches with `client`. A slot is freed
+    /// when the [`Completion`] a future resolves to is passed to
+    /// [`Scheduler::complete`], or when the future or its completion is
+    /// dropped.
+    pub fn dispatch<C: Cache + 'static>(&mut self, client: &Arc<Client<C>>) -> Vec<Dispatch> {
+        self.expire_abandoned();
+        let mut bulk_slots = self
+            .max_bulks
+            .saturating_sub(self.in_flight_count(RequestKind::Bulk));
+        let mut node_slots = self
+            .max_nodes
+            .saturating_sub(self.in_flight_count(RequestKind::Node));
+        if bulk_slots == 0 && node_slots == 0 {
+            return Vec::new();
+        }
+
+        let mut candidates: Vec<(&RequestKey, Priority)> = self
+            .queued
+            .iter()
+            .map(|(key, (_, priority))| (key, *priority))
+            .collect();
+        candidates.sort_unstable_by_key(|&(_, priority)| Reverse(priority));
+
+        let mut selected = Vec::new();
+        for (key, _) in candidates {
+            let slots = match key.kind {
+                RequestKind::Bulk => &mut bulk_slots,
+                RequestKind::Node => &mut node_slots,
+            };
+            if *slots > 0 {
+                *slots -= 1;
+                selected.push(key.clone());
+            }
+            if bulk_slots == 0 && node_slots == 0 {
+                break;
+            }
+        }
+
+        selected
+            .into_iter()
+            .map(|key| {
+                let (request, _) = self.queued.remove(&key).unwrap();
+                let id = self.next_id;
+                self.next_id += 1;
+                let token = CancellationToken::new();
+                let lease = Arc::new(());
+                self.in_flight.insert(
+                    key.clone(),
+                    InFlight {
+                        id,
+                        token: token.clone(),
+                        lease: Arc::downgrade(&lease),
+                    },
+                );
+                Dispatch {
+                    key: key.clone(),
+                    future: fetch(Arc::clone(client), key, id, request, token, lease),
+                }
+            })
+            .collect()
+    }
+
+    /// Accept a finished fetch, freeing its concurrency slot.
+    ///
+    /// Returns the outcome if the fetch is still wanted, or `None` if it was
+    /// cancelled or superseded in the meantime.
+    pub fn complete(&mut self, completion: Completion) -> Option<(RequestKey, FetchOutcome)> {
+        match self.in_flight.get(&completion.key) {
+            Some(in_flight) if in_flight.id == completion.id => {
+                self.in_flight.remove(&completion.key);
+                Some((completion.key, completion.outcome))
+            }
+            _ => None,
+        }
+    }
+
+    /// Check whether a request is queued or in flight.
+    #[must_use]
+    pub fn contains(&self, key: &RequestKey) -> bool {
+        self.queued.contains_key(key) || self.is_in_flight(key)
+    }
+
+    /// Check whether a request is in flight.
+    #[must_use]
+    pub fn is_in_flight(&self, key: &RequestKey) -> bool {
+        self.in_flight.get(key).is_some_and(InFlight::is_live)
+    }
+
+    /// The number of queued requests of a kind.
+    #[must_use]
+    pub fn queued_count(&self, kind: RequestKind) -> usize {
+        self.queued.keys().filter(|k| k.kind == kind).count()
+    }
+
+    /// The number of in-flight requests of a kind.
+    #[must_use]
+    pub fn in_flight_count(&self, kind: RequestKind) -> usize {
+        self.in_flight
+            .iter()
+            .filter(|(k, in_flight)| k.kind == kind && in_flight.is_live())
+            .count()
+    }
+
+    /// Forget in-flight requests whose dispatch or completion was dropped.
+    fn expire_abandoned(&mut self) {
+        self.in_flight.retain(|_, in_flight| in_flight.is_live());
+    }
+}
+
+#[cfg(not(target_family = "wasm"))]
+type DispatchFuture = Pin<Box<dyn Future<Output = Completion> + Send>>;
+
+// Browser fetch futures are not `Send`.
+#[cfg(target_family = "wasm")]
+type DispatchFuture = Pin<Box<dyn Future<Output = Completion>>>;
+
+#[derive(Debug)]
+struct InFlight {
+    id: u64,
+    token: CancellationToken,
+    lease: Weak<()>,
+}
+
+impl InFlight {
+    fn is_live(&self) -> bool {
+        self.lease.strong_count() > 0
+    }
+}
+
+fn fetch<C: Cache + 'static>(
+    client: Arc<Client<C>>,
+    key: RequestKey,
+    id: u64,
+    request: FetchRequest,
+    token: CancellationToken,
+    lease: Arc<()>,
+) -> DispatchFuture {
+    Box::pin(async move {
+        let outcome = match request {
+            FetchRequest::Bulk(request) => {
+                FetchOutcome::Bulk(client.fetch_bulk_cancellable(&request, &token).await)
+            }
+            FetchRequest::Node(request) => {
+                FetchOutcome::Node(client.fetch_node_shared_cancellable(&request, &token).await)
+            }
+        };
+        Completion {
+            key,
+            id,
+            outcome,
+            _lease: lease,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    fn priority(screen_space_error: f64) -> Priority {
+        Priority {
+            screen_space_error,
+            distance: 100.0,
+            level: 5,
+        }
+    }
+
+    fn node(path: &str) -> FetchRequest {
+        FetchRequest::Node(NodeRequest::new(path.to_string(), 1, 1, None))
+    }
+
+    fn bulk(path: &str) -> FetchRequest {
+        FetchRequest::Bulk(BulkRequest::new(path.to_string(), 1))
+    }
+
+    fn client() -> Arc<Client> {
+        // Nothing listens here; dispatched futures are never polled in these
+        // tests.
+        Arc::new(Client::new().with_base_url("http://127.0.0.1:9/".to_string()))
+    }
+
+    fn keys(dispatches: &[Dispatch]) -> Vec<String> {
+        dispatches.iter().map(|d| d.key().path.clone()).collect()
+    }
+
+    #[test]
+    fn test_priority_ordering() {
+        let base = priority(1.0);
+        assert!(priority(2.0) > base);
+        assert!(
+            Priority {
+                distance: 50.0,
+                ..base
+            } > base
+        );
+        assert!(Priority { level: 4, ..base } > base);
+        // Screen-space error dominates distance.
+        assert!(
+            Priority {
+                screen_space_error: 1.5,
+                distance: 1_000.0,
+                level: 9,
+            } > base
+        );
+    }
+
+    #[test]
+    fn test_dispatch_in_priority_order_with_limit() {
+        let client = client();
+        let mut scheduler = Scheduler::new(1, 2);
+        scheduler.submit(node("01"), priority(1.0));
+        scheduler.submit(node("02"), priority(3.0));
+        scheduler.submit(node("03"), priority(2.0));
+
+        let dispatched = scheduler.dispatch(&client);
+        assert_eq!(keys(&dispatched), ["02", "03"]);
+        assert_eq!(scheduler.in_flight_count(RequestKind::Node), 2);
+        assert_eq!(scheduler.queued_count(RequestKind::Node), 1);
+
+        // No free slots until something completes.
+        assert!(scheduler.dispatch(&client).is_empty());
+    }
+
+    #[test]
+    fn test_limits_are_per_kind() {
+        let client = client();
+        let mut scheduler = Scheduler::new(1, 1);
+        scheduler.submit(node("01"), priority(5.0));
+        scheduler.submit(node("02"), priority(4.0));
+        scheduler.submit(bulk("0123"), priority(1.0));
+
+        let dispatched = scheduler.dispatch(&client);
+        assert_eq!(keys(&dispatched), ["01", "0123"]);
+    }
+
+    #[test]
+    fn test_reprioritize() {
+        let client = client();
+        let mut scheduler = Scheduler::new(0, 1);
+        scheduler.submit(node("01"), priority(1.0));
+        scheduler.submit(node("02"), priority(2.0));
+        assert!(scheduler.reprioritize(&RequestKey::node("01"), priority(3.0)));
+        assert!(!scheduler.reprioritize(&RequestKey::node("03"), priority(3.0)));
+
+        assert_eq!(keys(&scheduler.dispatch(&client)), ["01"]);
+    }
+
+    #[test]
+    fn test_sync_drops_and_cancels_unwanted() {
+        let client = client();
+        let mut scheduler = Scheduler::new(0, 1);
+        scheduler.submit(node("01"), priority(2.0));
+        scheduler.submit(node("02"), priority(1.0));
+        let dispatched = scheduler.dispatch(&client);
+        assert_eq!(keys(&dispatched), ["01"]);
+        let token = scheduler.in_flight[&RequestKey::node("01")].token.clone();
+
+        scheduler.sync([(node("03"), priority(1.0))]);
+        assert!(token.is_cancelled());
+        assert!(!scheduler.contains(&RequestKey::node("01")));
+        assert!(!scheduler.contains(&RequestKey::node("02")));
+        assert!(scheduler.contains(&RequestKey::node("03")));
+    }
+
+    #[test]
+    fn test_complete_frees_slot_and_ignores_stale() {
+        let client = client();
+        let mut scheduler = Scheduler::new(0, 1);
+        scheduler.submit(node("01"), priority(1.0));
+        let dispatched = scheduler.dispatch(&client);
+        assert_eq!(dispatched.len(), 1);
+
+        // Cancel and resubmit, so the first dispatch becomes stale.
+        assert!(scheduler.cancel(&RequestKey::node("01")));
+        scheduler.submit(node("01"), priority(1.0));
+        let redispatched = scheduler.dispatch(&client);
+        assert_eq!(redispatched.len(), 1);
+
+        let stale = Completion {
+            key: RequestKey::node("01"),
+            id: 0,
+            outcome: FetchOutcome::Node(Err(Error::Cancelled)),
+            _lease: Arc::new(()),
+        };
+        assert!(scheduler.complete(stale).is_none());
+        assert!(scheduler.is_in_flight(&RequestKey::node("01")));
+
+        let current = Completion {
+            key: RequestKey::node("01"),
+            id: 1,
+            outcome: FetchOutcome::Node(Err(Error::Cancelled)),
+            _lease: Arc::new(()),
+        };
+        let (key, _) = scheduler.complete(current).unwrap();
+        assert_eq!(key, RequestKey::node("01"));
+        assert_eq!(scheduler.in_flight_count(RequestKind::Node), 0);
+    }
+
+    #[test]
+    fn test_submit_ignores_in_flight() {
+        let client = client();
+        let mut scheduler = Scheduler::new(0, 1);
+        scheduler.submit(node("01"), priority(1.0));
+        let _dispatched = scheduler.dispatch(&client);
+
+        scheduler.submit(node("01"), priority(9.0));
+        assert_eq!(scheduler.queued_count(RequestKind::Node), 0);
+    }
+
+    #[test]
+    fn test_dropped_dispatch_frees_slot() {
+        let client = client();
+        let mut scheduler = Scheduler::new(0, 1);
+        scheduler.submit(node("01"), priority(2.0));
+        scheduler.submit(node("02"), priority(1.0));
+        let dispatched = scheduler.dispatch(&client);
+        assert_eq!(keys(&dispatched), ["01"]);
+        assert!(scheduler.dispatch(&client).is_empty());
+
+        drop(dispatched);
+        assert!(!scheduler.is_in_flight(&RequestKey::node("01")));
+        assert_eq!(scheduler.in_flight_count(RequestKind::Node), 0);
+        assert_eq!(keys(&scheduler.dispatch(&client)), ["02"]);
+
+        // The abandoned request can be submitted again.
+        scheduler.submit(node("01"), priority(1.0));
+        assert_eq!(scheduler.queued_count(RequestKind::Node), 1);
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_future_reports_cancellation() {
+        let client = client();
+        let mut scheduler = Scheduler::new(0, 1);
+        scheduler.submit(node("01"), priority(1.0));
+        let dispatch = scheduler.dispatch(&client).pop().unwrap();
+        let token = scheduler.in_flight[&RequestKey::node("01")].token.clone();
+        token.cancel();
+
+        let completion = dispatch.await;
+        assert!(matches!(
+            completion.outcome,
+            FetchOutcome::Node(Err(Error::Cancelled))
+        ));
+        let (_, outcome) = scheduler.complete(completion).unwrap();
+        assert!(matches!(outcome, FetchOutcome::Node(Err(Error::Cancelled))));
+    }
+}
@@ -0,0 +1,47 @@
+//! Helpers shared by the client tests.
+
+use prost::Message;
+use rocktree_proto as proto;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+
+/// Serve `body` for `requests` consecutive HTTP requests on a local port.
+///
+/// Returns the base URL of the server. Connections after the last request
+/// are refused.
+pub(crate) fn serve(body: Vec<u8>, requests: usize) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().take(requests) {
+            let mut stream = stream.unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf).unwrap();
+            let header = format!(
+                "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                body.len()
+            );
+            stream.write_all(header.as_bytes()).unwrap();
+            stream.write_all(&body).unwrap();
+        }
+    });
+    format!("http://{addr}/")
+}
+
+/// Encoded planetoid metadata with a root epoch of 7.
+pub(crate) fn planetoid_body() -> Vec<u8> {
+    planetoid_body_with_epoch(7)
+}
+
+/// Encoded planetoid metadata with the given root epoch.
+pub(crate) fn planetoid_body_with_epoch(epoch: u32) -> Vec<u8> {
+    proto::PlanetoidMetadata {
+        root_node_metadata: Some(proto::NodeMetadata {
+            epoch: Some(epoch),
+            ..Default::default()
+        }),
+        radius: Some(6_371_010.0),
+        ..Default::default()
+    }
+    .encode_to_vec()
+}
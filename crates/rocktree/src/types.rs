@@ -8,6 +8,8 @@ use std::collections::HashMap;
 use glam::{DMat4, DVec3, Vec3};
 use rocktree_decode::{OrientedBoundingBox, UvTransform, Vertex};
 
+use crate::policy::FetchPolicy;
+
 /// Texture format for mesh textures.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TextureFormat {
@@ -19,6 +21,45 @@ pub enum TextureFormat {
     Dxt1,
 }
 
+/// A layer of mesh geometry.
+///
+/// Meshes order their triangles by layer. Only the visible layers are kept
+/// when decoding.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MeshLayer {
+    /// Buildings, vegetation, and other geometry above the terrain.
+    Overground,
+    /// Terrain below the water surface.
+    TerrainBelowWater,
+    /// Terrain above the water surface.
+    TerrainAboveWater,
+}
+
+impl MeshLayer {
+    /// All layers, in the order their triangles appear in a mesh.
+    pub const ALL: [MeshLayer; 3] = [
+        MeshLayer::Overground,
+        MeshLayer::TerrainBelowWater,
+        MeshLayer::TerrainAboveWater,
+    ];
+
+    /// Get the name of the layer as used in the protobuf definition.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            MeshLayer::Overground => "OVERGROUND",
+            MeshLayer::TerrainBelowWater => "TERRAIN_BELOW_WATER",
+            MeshLayer::TerrainAboveWater => "TERRAIN_ABOVE_WATER",
+        }
+    }
+
+    /// Whether the layer is terrain rather than overground geometry.
+    #[must_use]
+    pub fn is_terrain(self) -> bool {
+        self != MeshLayer::Overground
+    }
+}
+
 /// A decoded mesh ready for rendering.
 #[derive(Debug, Clone)]
 pub struct Mesh {
@@ -40,6 +81,30 @@ pub struct Mesh {
     /// When false, all vertices have `w = 0` and per-vertex octant masking should
     /// not be applied (it would incorrectly collapse all vertices).
     pub has_octant_data: bool,
+    /// Offsets in `indices` where each [`MeshLayer`] starts, followed by the
+    /// end of the last layer.
+    ///
+    /// Meshes without octant data carry no layer information, and all of
+    /// their geometry is reported as [`MeshLayer::Overground`].
+    pub layer_bounds: [usize; 4],
+}
+
+impl Mesh {
+    /// Estimate the memory used by the mesh in bytes.
+    #[must_use]
+    pub fn memory_size(&self) -> usize {
+        size_of::<Self>()
+            + self.vertices.len() * size_of::<Vertex>()
+            + self.indices.len() * size_of::<u16>()
+            + self.texture_data.len()
+    }
+
+    /// Get the range of `indices` holding the triangle strip for `layer`.
+    #[must_use]
+    pub fn layer_range(&self, layer: MeshLayer) -> std::ops::Range<usize> {
+        let i = layer as usize;
+        self.layer_bounds[i]..self.layer_bounds[i + 1]
+    }
 }
 
 /// A decoded node containing one or more meshes.
@@ -57,6 +122,16 @@ pub struct Node {
     pub meshes: Vec<Mesh>,
 }
 
+impl Node {
+    /// Estimate the memory used by the node and its meshes in bytes.
+    #[must_use]
+    pub fn memory_size(&self) -> usize {
+        size_of::<Self>()
+            + self.path.len()
+            + self.meshes.iter().map(Mesh::memory_size).sum::<usize>()
+    }
+}
+
 /// Metadata for a node before downloading its mesh data.
 #[derive(Debug, Clone)]
 pub struct NodeMetadata {
@@ -68,6 +143,8 @@ pub struct NodeMetadata {
     pub obb: OrientedBoundingBox,
     /// Whether this node has mesh data to download.
     pub has_data: bool,
+    /// Raw flags from the bulk metadata; see the `FLAG_*` constants.
+    pub flags: u32,
     /// Epoch for this node's data.
     pub epoch: u32,
     /// Texture format for the mesh data.
@@ -76,6 +153,21 @@ pub struct NodeMetadata {
     pub imagery_epoch: Option<u32>,
 }
 
+impl NodeMetadata {
+    /// Flag set on nodes without children.
+    pub const FLAG_LEAF: u32 = 4;
+    /// Flag set on nodes without mesh data.
+    pub const FLAG_NO_DATA: u32 = 8;
+    /// Flag set on nodes whose requests carry an imagery epoch.
+    pub const FLAG_USE_IMAGERY_EPOCH: u32 = 16;
+
+    /// Whether the node has no children.
+    #[must_use]
+    pub fn is_leaf(&self) -> bool {
+        self.flags & Self::FLAG_LEAF != 0
+    }
+}
+
 /// Metadata for a bulk of nodes.
 #[derive(Debug, Clone)]
 pub struct BulkMetadata {
@@ -109,22 +201,32 @@ pub struct BulkRequest {
     pub path: String,
     /// The epoch for this bulk.
     pub epoch: u32,
+    /// Fetch policy for this request, overriding the client's default.
+    pub fetch_policy: Option<FetchPolicy>,
 }
 
 impl BulkRequest {
     /// Create a new bulk request.
     #[must_use]
     pub fn new(path: String, epoch: u32) -> Self {
-        Self { path, epoch }
+        Self {
+            path,
+            epoch,
+            fetch_policy: None,
+        }
     }
 
     /// Create a request for the root bulk.
     #[must_use]
     pub fn root(epoch: u32) -> Self {
-        Self {
-            path: String::new(),
-            epoch,
-        }
+        Self::new(String::new(), epoch)
+    }
+
+    /// Fetch this bulk with `policy` instead of the client's default.
+    #[must_use]
+    pub fn with_fetch_policy(mut self, policy: FetchPolicy) -> Self {
+        self.fetch_policy = Some(policy);
+        self
     }
 }
 
@@ -139,6 +241,8 @@ pub struct NodeRequest {
     pub texture_format: i32,
     /// Imagery epoch (optional).
     pub imagery_epoch: Option<u32>,
+    /// Fetch policy for this request, overriding the client's default.
+    pub fetch_policy: Option<FetchPolicy>,
 }
 
 impl NodeRequest {
@@ -150,8 +254,16 @@ impl NodeRequest {
             epoch,
             texture_format,
             imagery_epoch,
+            fetch_policy: None,
         }
     }
+
+    /// Fetch this node with `policy` instead of the client's default.
+    #[must_use]
+    pub fn with_fetch_policy(mut self, policy: FetchPolicy) -> Self {
+        self.fetch_policy = Some(policy);
+        self
+    }
 }
 
 /// A frustum for culling nodes based on their OBBs.
@@ -271,14 +383,19 @@ impl LodMetrics {
     /// Returns true if the node's screen-space error exceeds the threshold.
     #[must_use]
     pub fn should_refine(&self, node_center: DVec3, meters_per_texel: f32) -> bool {
+        self.screen_space_error(node_center, meters_per_texel) > self.error_threshold
+    }
+
+    /// Compute a node's screen-space error in pixels.
+    ///
+    /// Returns infinity when the camera is at the node center.
+    #[must_use]
+    pub fn screen_space_error(&self, node_center: DVec3, meters_per_texel: f32) -> f64 {
         let distance = self.camera_position.distance(node_center);
         if distance <= 0.0 {
-            return true;
+            return f64::INFINITY;
         }
-
-        // Screen-space error in pixels.
-        let error = f64::from(meters_per_texel) * self.pixels_per_meter / distance;
-        error > self.error_threshold
+        f64::from(meters_per_texel) * self.pixels_per_meter / distance
     }
 }
 
@@ -323,6 +440,6 @@ mod tests {
         assert!(metrics.should_refine(DVec3::new(100.0, 0.0, 0.0), 10.0));
 
         // Far node with small texels should not refine.
-        assert!(!metrics.should_refine(DVec3::new(100000.0, 0.0, 0.0), 0.1));
+        assert!(!metrics.should_refine(DVec3::new(100_000.0, 0.0, 0.0), 0.1));
     }
 }
@@ -0,0 +1,172 @@
+//! Breadth-first walks of the octree over a geographic region.
+//!
+//! [`walk_region`] fetches the bulks covering a region, level by level, and
+//! reports every node within it. Callers decide how deep to go with a
+//! `descend` predicate and fetch whatever they need per node, which lets
+//! region archives, tilesets, and rasters share one traversal.
+
+use crate::cache::{Cache, CacheKey};
+use crate::client::Client;
+use crate::error::Error;
+use crate::geo::GeoBounds;
+use crate::protocol;
+use crate::types::{BulkMetadata, BulkRequest, NodeMetadata};
+use std::collections::{HashSet, VecDeque};
+
+/// A step of [`walk_region`].
+#[derive(Debug, Clone, Copy)]
+pub enum WalkStep<'a> {
+    /// The planetoid was fetched.
+    Planetoid {
+        /// The undecoded response.
+        data: &'a [u8],
+    },
+    /// A bulk was fetched.
+    Bulk {
+        /// The request the bulk was fetched with.
+        request: &'a BulkRequest,
+        /// The decoded bulk.
+        bulk: &'a BulkMetadata,
+        /// The undecoded response.
+        data: &'a [u8],
+    },
+    /// A node within the region was reached.
+    Node(&'a NodeMetadata),
+}
+
+/// Walk the octree over `bounds`, passing every fetched response and every
+/// node reached to `visit`.
+///
+/// `visit` may fail with any error that fetch errors convert into, so
+/// callers can return their own.
+///
+/// Starting from the root bulk, bulks are fetched breadth first following
+/// the client's fetch policy. A node is reached if its bounding box
+/// intersects `bounds` and no ancestor was found outside `bounds` or
+/// reached without `descend` returning `true`, so nodes on the first level
+/// are always reached. Child bulks are fetched in path order, so walks are
+/// reproducible.
+///
+/// # Errors
+///
+/// Returns an error if a fetch fails, a response cannot be decoded, or
+/// `visit` fails.
+pub async fn walk_region<C: Cache + 'static, E: From<Error>>(
+    client: &Client<C>,
+    bounds: &GeoBounds,
+    mut descend: impl FnMut(&NodeMetadata) -> bool,
+    mut visit: impl AsyncFnMut(WalkStep<'_>) -> Result<(), E>,
+) -> Result<(), E> {
+    let policy = client.fetch_policy();
+    let (planetoid, data) = client
+        .fetch_decoded(
+            &CacheKey::Planetoid,
+            &client.planetoid_url(),
+            policy,
+            |data| Ok((protocol::decode_planetoid(data)?, data.to_vec())),
+        )
+        .await?;
+    visit(WalkStep::Planetoid { data: &data }).await?;
+
+    let mut bulks = VecDeque::from([BulkRequest::root(planetoid.root_epoch)]);
+    // Nodes that were reached without being descended into, or that lie
+    // outside the region; nothing below them is reached.
+    let mut pruned = HashSet::new();
+    let below_pruned = |pruned: &HashSet<String>, path: &str| {
+        (1..path.len()).any(|len| pruned.contains(&path[..len]))
+    };
+    while let Some(request) = bulks.pop_front() {
+        let (bulk, data) = client
+            .fetch_decoded(
+                &CacheKey::bulk(&request),
+                &client.bulk_url(&request),
+                request.fetch_policy.unwrap_or(policy),
+                |data| Ok((protocol::decode_bulk(&request.path, data)?, data.to_vec())),
+            )
+            .await?;
+        visit(WalkStep::Bulk {
+            request: &request,
+            bulk: &bulk,
+            data: &data,
+        })
+        .await?;
+
+        // Nodes are listed parents first, so ancestors within the bulk are
+        // decided before their descendants.
+        for metadata in &bulk.nodes {
+            if below_pruned(&pruned, &metadata.path) {
+                continue;
+            }
+            if !bounds.intersects_obb(&metadata.obb) {
+                pruned.insert(metadata.path.clone());
+                continue;
+            }
+            if !descend(metadata) {
+                pruned.insert(metadata.path.clone());
+            }
+            visit(WalkStep::Node(metadata)).await?;
+        }
+
+        let mut children: Vec<(String, u32)> = bulk
+            .child_bulk_paths
+            .iter()
+            .map(|(relative, &epoch)| (format!("{}{relative}", bulk.path), epoch))
+            .filter(|(path, _)| !pruned.contains(path) && !below_pruned(&pruned, path))
+            .collect();
+        children.sort();
+        bulks.extend(
+            children
+                .into_iter()
+                .map(|(path, epoch)| BulkRequest::new(path, epoch)),
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::tests::{populated_cache, region};
+    use crate::policy::FetchPolicy;
+
+    async fn walk(descend: impl FnMut(&NodeMetadata) -> bool) -> Vec<String> {
+        let client =
+            Client::with_cache(populated_cache()).with_fetch_policy(FetchPolicy::CacheOnly);
+        let mut steps = Vec::new();
+        walk_region(&client, &region(), descend, async |step| {
+            steps.push(match step {
+                WalkStep::Planetoid { .. } => "planetoid".to_string(),
+                WalkStep::Bulk { bulk, .. } => format!("bulk {}", bulk.path),
+                WalkStep::Node(node) => format!("node {}", node.path),
+            });
+            Ok::<_, Error>(())
+        })
+        .await
+        .unwrap();
+        steps
+    }
+
+    #[tokio::test]
+    async fn test_walk_skips_nodes_outside_bounds() {
+        assert_eq!(
+            walk(|_| true).await,
+            [
+                "planetoid",
+                "bulk ",
+                "node 0",
+                "node 0000",
+                "bulk 0000",
+                "node 00001"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_walk_stops_below_undescended_nodes() {
+        assert_eq!(
+            walk(|node| node.path.len() < 4).await,
+            ["planetoid", "bulk ", "node 0", "node 0000"]
+        );
+        assert_eq!(walk(|_| false).await, ["planetoid", "bulk ", "node 0"]);
+    }
+}